@@ -0,0 +1,36 @@
+-- Normalise subscriber emails and enforce uniqueness regardless of case
+-- Keep the address as the subscriber typed it
+ALTER TABLE subscriptions ADD COLUMN email_display TEXT;
+UPDATE subscriptions SET email_display = email;
+ALTER TABLE subscriptions ALTER COLUMN email_display SET NOT NULL;
+
+-- Normalising can make two rows collide, so drop the old constraint first
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+
+-- Same normalisation as `SubscriberEmail::parse` as far as SQL can go: trim and lowercase the domain
+-- IDNA encoding of existing internationalised domains is left to the application
+UPDATE subscriptions
+SET email = split_part(btrim(email), '@', 1) || '@' || lower(split_part(btrim(email), '@', 2))
+WHERE position('@' IN email) > 0;
+
+-- Refuse to go on if existing rows only differ by case - someone has to decide which one to keep
+DO $$
+DECLARE
+    conflicts TEXT;
+BEGIN
+    SELECT string_agg(format('%s: %s', address, ids), E'\n')
+    INTO conflicts
+    FROM (
+        SELECT lower(email) AS address, string_agg(id::text || ' (' || email || ')', ', ' ORDER BY subscribed_at) AS ids
+        FROM subscriptions
+        GROUP BY lower(email)
+        HAVING count(*) > 1
+    ) duplicates;
+
+    IF conflicts IS NOT NULL THEN
+        RAISE EXCEPTION E'Subscriptions with emails differing only by case:\n%', conflicts
+            USING HINT = 'Keep one row per address (e.g. the oldest), delete the others and run the migration again.';
+    END IF;
+END $$;
+
+CREATE UNIQUE INDEX subscriptions_email_lower_key ON subscriptions (lower(email));
@@ -0,0 +1,6 @@
+-- Create Users Table - people allowed to use the `/admin` endpoints
+CREATE TABLE users(
+user_id uuid PRIMARY KEY,
+username TEXT NOT NULL UNIQUE,
+password_hash TEXT NOT NULL
+);
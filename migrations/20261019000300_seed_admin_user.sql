@@ -0,0 +1,8 @@
+-- Seed an initial admin user so a fresh deployment can log in
+-- username: admin, password: everythinghastostartsomewhere - change it straight away
+INSERT INTO users (user_id, username, password_hash)
+VALUES (
+'ddf8994f-d522-4659-8d02-c1d479057be6',
+'admin',
+'$argon2id$v=19$m=15000,t=2,p=1$zbBRXh/5+DYEKvMFIzP7pg$hdkXXassZK+4jcnQ35QJJLdUR9A/2x8PZBmTF5I3kLE'
+);
@@ -0,0 +1,5 @@
+-- Add a status to subscriptions so they can be filtered on
+-- Existing subscribers signed up before any confirmation step existed - treat them as confirmed
+ALTER TABLE subscriptions ADD COLUMN status TEXT NULL;
+UPDATE subscriptions SET status = 'confirmed' WHERE status IS NULL;
+ALTER TABLE subscriptions ALTER COLUMN status SET NOT NULL;
@@ -0,0 +1,8 @@
+-- Keyset pagination walks subscriptions newest first on (subscribed_at, id)
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at DESC, id DESC);
+CREATE INDEX subscriptions_status_subscribed_at_id_idx ON subscriptions (status, subscribed_at DESC, id DESC);
+
+-- Trigram indexes let `ILIKE '%term%'` searches on name and email use an index
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX subscriptions_name_trgm_idx ON subscriptions USING gin (name gin_trgm_ops);
+CREATE INDEX subscriptions_email_trgm_idx ON subscriptions USING gin (email gin_trgm_ops);
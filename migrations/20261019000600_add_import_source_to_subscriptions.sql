@@ -0,0 +1,3 @@
+-- Record where imported subscribers came from, e.g. the provider we migrated away from
+-- NULL for people who signed up through the form
+ALTER TABLE subscriptions ADD COLUMN import_source TEXT NULL;
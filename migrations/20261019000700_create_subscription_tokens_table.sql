@@ -0,0 +1,8 @@
+-- Create Subscription Tokens Table
+-- a token in a link we email proves the person clicking it owns the address
+CREATE TABLE subscription_tokens(
+subscription_token TEXT NOT NULL,
+subscriber_id uuid NOT NULL
+REFERENCES subscriptions (id),
+PRIMARY KEY (subscription_token)
+);
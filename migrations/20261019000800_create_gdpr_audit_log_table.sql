@@ -0,0 +1,11 @@
+-- Create GDPR Audit Log Table
+-- records every data access and erasure - only the subscriber id, never their personal data
+CREATE TABLE gdpr_audit_log(
+id uuid PRIMARY KEY,
+subscriber_id uuid NOT NULL,
+action TEXT NOT NULL,
+-- `subscriber` when they used their token, `admin:<user_id>` for requests handled by us
+performed_by TEXT NOT NULL,
+performed_at timestamptz NOT NULL
+);
+CREATE INDEX gdpr_audit_log_subscriber_id_idx ON gdpr_audit_log (subscriber_id);
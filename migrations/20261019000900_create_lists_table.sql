@@ -0,0 +1,15 @@
+-- Create Lists Table - every subscription belongs to one or more mailing lists
+CREATE TABLE lists(
+id uuid PRIMARY KEY,
+slug TEXT NOT NULL UNIQUE,
+name TEXT NOT NULL,
+-- `POST /subscriptions` signs people up to the default list
+is_default BOOLEAN NOT NULL DEFAULT FALSE,
+created_at timestamptz NOT NULL
+);
+-- At most one default list
+CREATE UNIQUE INDEX lists_is_default_key ON lists (is_default) WHERE is_default;
+
+-- Everybody who subscribed so far did so to the one implicit list
+INSERT INTO lists (id, slug, name, is_default, created_at)
+VALUES ('5a1e1c3d-2b57-4c5e-9a3f-6b1d7c8e9f01', 'newsletter', 'Newsletter', TRUE, now());
@@ -0,0 +1,19 @@
+-- Create List Memberships Table - which subscriber is on which list, each with its own status
+CREATE TABLE list_memberships(
+list_id uuid NOT NULL REFERENCES lists (id),
+subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+status TEXT NOT NULL,
+subscribed_at timestamptz NOT NULL,
+confirmed_at timestamptz NULL,
+unsubscribed_at timestamptz NULL,
+PRIMARY KEY (list_id, subscriber_id)
+);
+CREATE INDEX list_memberships_subscriber_id_idx ON list_memberships (subscriber_id);
+
+-- Move existing subscribers onto the default list, keeping their status
+INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at, confirmed_at)
+SELECT lists.id, subscriptions.id, subscriptions.status, subscriptions.subscribed_at,
+    CASE WHEN subscriptions.status = 'confirmed' THEN subscriptions.subscribed_at END
+FROM subscriptions
+CROSS JOIN lists
+WHERE lists.is_default AND subscriptions.status IN ('pending_confirmation', 'confirmed');
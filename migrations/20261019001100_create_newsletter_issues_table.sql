@@ -0,0 +1,15 @@
+-- Create Newsletter Issues Table
+CREATE TABLE newsletter_issues(
+newsletter_issue_id uuid PRIMARY KEY,
+title TEXT NOT NULL,
+text_content TEXT NOT NULL,
+html_content TEXT NOT NULL,
+published_at timestamptz NOT NULL
+);
+
+-- The lists an issue was sent to
+CREATE TABLE newsletter_issue_lists(
+newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+list_id uuid NOT NULL REFERENCES lists (id),
+PRIMARY KEY (newsletter_issue_id, list_id)
+);
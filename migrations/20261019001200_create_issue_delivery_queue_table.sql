@@ -0,0 +1,9 @@
+-- Create Issue Delivery Queue Table - one row per email still to be sent
+-- the primary key makes sure a subscriber on several of the targeted lists gets the issue once
+CREATE TABLE issue_delivery_queue(
+newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+n_retries SMALLINT NOT NULL DEFAULT 0,
+execute_after timestamptz NOT NULL DEFAULT now(),
+PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
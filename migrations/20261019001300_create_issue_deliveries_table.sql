@@ -0,0 +1,10 @@
+-- Create Issue Deliveries Table - what happened to each email taken off the queue
+CREATE TABLE issue_deliveries(
+newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+-- `sent`, `failed` once retries ran out, or `skipped` when they were no longer subscribed
+status TEXT NOT NULL,
+attempted_at timestamptz NOT NULL,
+PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
+CREATE INDEX issue_deliveries_subscriber_id_idx ON issue_deliveries (subscriber_id);
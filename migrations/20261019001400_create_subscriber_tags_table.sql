@@ -0,0 +1,8 @@
+-- Create Subscriber Tags Table - free-form labels used to target segments
+CREATE TABLE subscriber_tags(
+subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+tag TEXT NOT NULL,
+tagged_at timestamptz NOT NULL,
+PRIMARY KEY (subscriber_id, tag)
+);
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
@@ -0,0 +1,10 @@
+-- When the subscriber first confirmed their address, e.g. for segments like "confirmed in the last 90 days"
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+
+-- The earliest confirmed list membership is the best we know for existing subscribers
+UPDATE subscriptions
+SET confirmed_at = COALESCE(
+    (SELECT MIN(confirmed_at) FROM list_memberships WHERE subscriber_id = subscriptions.id),
+    subscribed_at
+)
+WHERE status = 'confirmed';
@@ -0,0 +1,2 @@
+-- The segment expression an issue was sent to, if any
+ALTER TABLE newsletter_issues ADD COLUMN segment TEXT NULL;
@@ -0,0 +1,13 @@
+-- Create List Fields Table - custom fields a list asks for at signup, e.g. company or country
+CREATE TABLE list_fields(
+list_id uuid NOT NULL REFERENCES lists (id),
+key TEXT NOT NULL,
+label TEXT NOT NULL,
+-- `text`, `number`, `boolean`, `date` or `choice`
+field_type TEXT NOT NULL,
+required BOOLEAN NOT NULL,
+-- e.g. `{"max_length": 100}` or `{"choices": ["fr", "de"]}`
+rules JSONB NOT NULL DEFAULT '{}',
+created_at timestamptz NOT NULL,
+PRIMARY KEY (list_id, key)
+);
@@ -0,0 +1,2 @@
+-- Values of custom fields, keyed by `list_fields.key`
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
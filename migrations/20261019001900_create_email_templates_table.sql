@@ -0,0 +1,37 @@
+-- Create Email Templates Table - reusable issue bodies and the layouts they are wrapped in
+CREATE TABLE email_templates(
+id uuid PRIMARY KEY,
+name TEXT NOT NULL UNIQUE,
+-- `layout` wraps the content of an issue, `issue` is the content itself
+kind TEXT NOT NULL CHECK (kind IN ('layout', 'issue')),
+html TEXT NOT NULL,
+text TEXT NOT NULL,
+created_at timestamptz NOT NULL,
+updated_at timestamptz NOT NULL
+);
+
+-- Every issue is sent in a layout, this one unless another is picked when publishing
+INSERT INTO email_templates (id, name, kind, html, text, created_at, updated_at)
+VALUES (
+'7c0f3b8e-4d2a-4f6b-8e1c-2a9d5b3c7e10',
+'default',
+'layout',
+'<html>
+<body>
+<h1>{{ list.name }}</h1>
+{{ content }}
+<hr>
+<p><a href="{{ unsubscribe_url }}">Unsubscribe</a></p>
+<p>{{ postal_address }}</p>
+</body>
+</html>',
+'{{ list.name }}
+
+{{ content }}
+
+--
+Unsubscribe: {{ unsubscribe_url }}
+{{ postal_address }}',
+now(),
+now()
+);
@@ -0,0 +1,6 @@
+-- The layout an issue is wrapped in, issues published so far get the default one
+ALTER TABLE newsletter_issues
+ADD COLUMN layout_id uuid NOT NULL
+REFERENCES email_templates (id)
+DEFAULT '7c0f3b8e-4d2a-4f6b-8e1c-2a9d5b3c7e10';
+ALTER TABLE newsletter_issues ALTER COLUMN layout_id DROP DEFAULT;
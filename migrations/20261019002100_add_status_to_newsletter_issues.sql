@@ -0,0 +1,25 @@
+-- Issues can be drafted and scheduled ahead of time instead of going out when posted
+-- `draft`, `scheduled`, `sending`, `sent` or `cancelled`
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'sent';
+ALTER TABLE newsletter_issues ALTER COLUMN status DROP DEFAULT;
+-- Published issues with deliveries still queued are not done yet
+UPDATE newsletter_issues SET status = 'sending'
+WHERE EXISTS (
+    SELECT 1 FROM issue_delivery_queue
+    WHERE issue_delivery_queue.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+);
+
+-- When a scheduled issue is due
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_at timestamptz NULL;
+-- Only set once the deliveries are queued
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+-- The source of issues written in Markdown, to edit drafts with
+ALTER TABLE newsletter_issues ADD COLUMN markdown TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN created_at timestamptz NULL;
+ALTER TABLE newsletter_issues ADD COLUMN updated_at timestamptz NULL;
+UPDATE newsletter_issues SET created_at = published_at, updated_at = published_at;
+ALTER TABLE newsletter_issues ALTER COLUMN created_at SET NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN updated_at SET NOT NULL;
+
+-- What the scheduler looks for
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (scheduled_at) WHERE status = 'scheduled';
@@ -0,0 +1,23 @@
+-- Sent issues are public at `/archive/{slug}` unless they are hidden
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL UNIQUE;
+ALTER TABLE newsletter_issues ADD COLUMN hide_from_archive BOOLEAN NOT NULL DEFAULT FALSE;
+-- Slugs are given when an issue starts sending, those already sent get one from their title
+UPDATE newsletter_issues
+SET slug = trim(BOTH '-' FROM regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g'))
+    || '-' || left(newsletter_issue_id::text, 8)
+WHERE status IN ('sending', 'sent');
+
+-- The default layout links to the archived copy, unless it was edited since it was seeded
+UPDATE email_templates
+SET html = replace(
+        html,
+        '<body>
+<h1>{{ list.name }}</h1>',
+        '<body>
+{% if view_in_browser_url %}<p><a href="{{ view_in_browser_url }}">View in browser</a></p>{% endif %}
+<h1>{{ list.name }}</h1>'
+    ),
+    text = '{% if view_in_browser_url %}View in browser: {{ view_in_browser_url }}
+
+{% endif %}' || text
+WHERE id = '7c0f3b8e-4d2a-4f6b-8e1c-2a9d5b3c7e10' AND updated_at = created_at;
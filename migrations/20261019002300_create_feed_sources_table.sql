@@ -0,0 +1,35 @@
+-- Digest templates render the entries of a feed, see `feed_poller`
+ALTER TABLE email_templates DROP CONSTRAINT email_templates_kind_check;
+ALTER TABLE email_templates ADD CONSTRAINT email_templates_kind_check
+    CHECK (kind IN ('layout', 'issue', 'digest'));
+
+-- Create Feed Sources Table - a blog feed whose new entries become issues of the list
+CREATE TABLE feed_sources(
+id uuid PRIMARY KEY,
+list_id uuid NOT NULL UNIQUE REFERENCES lists (id),
+url TEXT NOT NULL,
+-- A `digest` template, and the layout to wrap it in
+template_id uuid NOT NULL REFERENCES email_templates (id),
+layout_id uuid NOT NULL REFERENCES email_templates (id),
+-- Send the digest right away, or leave it as a draft for review
+auto_send BOOLEAN NOT NULL,
+-- NULL until the first poll, which only takes note of the entries already there
+last_polled_at timestamptz NULL,
+last_error TEXT NULL,
+created_at timestamptz NOT NULL,
+updated_at timestamptz NOT NULL
+);
+
+-- Create Feed Entries Table - entries already turned into an issue, by the id the feed gives them
+CREATE TABLE feed_entries(
+feed_source_id uuid NOT NULL REFERENCES feed_sources (id),
+guid TEXT NOT NULL,
+title TEXT NOT NULL,
+-- NULL for the entries found by the first poll, and once a digest draft is deleted
+newsletter_issue_id uuid NULL REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE SET NULL,
+processed_at timestamptz NOT NULL,
+PRIMARY KEY (feed_source_id, guid)
+);
+
+-- `{"feed": {...}, "entries": [...]}` of a digest issue, rendered with its template
+ALTER TABLE newsletter_issues ADD COLUMN digest JSONB NULL;
@@ -0,0 +1,18 @@
+-- Lists can opt out of open and click tracking
+ALTER TABLE lists ADD COLUMN tracking BOOLEAN NOT NULL DEFAULT TRUE;
+-- Whether the email carried the tracking pixel and links - rates only count those that did
+ALTER TABLE issue_deliveries ADD COLUMN tracked BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Create Tracking Events Table - opens and clicks, nothing about the reader's device or network
+CREATE TABLE tracking_events(
+id uuid PRIMARY KEY,
+newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+-- `open` or `click`
+kind TEXT NOT NULL CHECK (kind IN ('open', 'click')),
+-- The original link, for clicks
+url TEXT NULL,
+occurred_at timestamptz NOT NULL
+);
+CREATE INDEX tracking_events_newsletter_issue_id_idx ON tracking_events (newsletter_issue_id);
+CREATE INDEX tracking_events_subscriber_id_idx ON tracking_events (subscriber_id);
@@ -0,0 +1,16 @@
+-- Soft bounces in a row, reset by a delivery - too many and the address counts as bounced
+ALTER TABLE subscriptions ADD COLUMN soft_bounce_count INTEGER NOT NULL DEFAULT 0;
+
+-- Create Email Events Table - what the email provider told us about our emails, as it was sent
+CREATE TABLE email_events(
+id uuid PRIMARY KEY,
+-- The provider's id of the event, a replayed webhook is recognised by it
+provider_event_id TEXT NOT NULL UNIQUE,
+kind TEXT NOT NULL CHECK (kind IN ('hard_bounce', 'soft_bounce', 'complaint', 'delivery', 'other')),
+email TEXT NULL,
+-- `NULL` when the address is not one of our subscribers
+subscriber_id uuid NULL REFERENCES subscriptions (id),
+payload JSONB NOT NULL,
+received_at timestamptz NOT NULL
+);
+CREATE INDEX email_events_subscriber_id_idx ON email_events (subscriber_id);
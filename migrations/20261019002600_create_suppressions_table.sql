@@ -0,0 +1,16 @@
+-- Create Suppressions Table - addresses and domains we never email, whoever they belong to
+CREATE TABLE suppressions(
+id uuid PRIMARY KEY,
+-- `email` for one address, `domain` for every address at the domain or its subdomains
+kind TEXT NOT NULL CHECK (kind IN ('email', 'domain')),
+-- Lowercased, e.g. `ada@example.com` or `test.example`
+value TEXT NOT NULL,
+-- Why, e.g. `Legal request #42` or `complaint`
+reason TEXT NOT NULL,
+-- Who added it - `admin`, `import` or `webhook`
+source TEXT NOT NULL CHECK (source IN ('admin', 'import', 'webhook')),
+created_at timestamptz NOT NULL,
+UNIQUE (kind, value)
+);
+
+-- Deliveries the suppression list stopped are recorded as `suppressed` in `issue_deliveries.status`
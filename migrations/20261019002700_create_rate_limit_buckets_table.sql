@@ -0,0 +1,10 @@
+-- Create Rate Limit Buckets Table - token buckets shared by every replica, when `rate_limit.store` is `postgres`
+CREATE TABLE rate_limit_buckets(
+-- e.g. `/subscriptions:ip:203.0.113.7`
+key TEXT PRIMARY KEY,
+tokens DOUBLE PRECISION NOT NULL,
+updated_at timestamptz NOT NULL,
+-- Once past, the bucket is as good as new and the row can go
+full_at timestamptz NOT NULL
+);
+CREATE INDEX rate_limit_buckets_full_at_idx ON rate_limit_buckets (full_at);
@@ -0,0 +1,3 @@
+-- Add Captcha To Lists
+-- Signups to these lists must pass a CAPTCHA
+ALTER TABLE lists ADD COLUMN captcha BOOLEAN NOT NULL DEFAULT FALSE;
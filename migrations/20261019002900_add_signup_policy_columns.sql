@@ -0,0 +1,5 @@
+-- Add Signup Policy Columns
+-- What the signup policy noticed about an accepted address, e.g. `{role_account}`
+ALTER TABLE subscriptions ADD COLUMN policy_flags TEXT[] NOT NULL DEFAULT '{}';
+-- Lists for internal use only take addresses at these domains, empty for any domain
+ALTER TABLE lists ADD COLUMN allowed_domains TEXT[] NOT NULL DEFAULT '{}';
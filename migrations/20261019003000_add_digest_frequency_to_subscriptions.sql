@@ -0,0 +1,4 @@
+-- Add Digest Frequency To Subscriptions
+-- Chosen in the preference center - `daily` and `weekly` hold deliveries back until the next slot
+ALTER TABLE subscriptions ADD COLUMN digest_frequency TEXT NOT NULL DEFAULT 'immediately'
+    CHECK (digest_frequency IN ('immediately', 'daily', 'weekly'));
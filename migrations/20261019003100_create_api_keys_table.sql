@@ -0,0 +1,19 @@
+-- Create API Keys Table - machine-to-machine access to `/admin`, e.g. for a CMS
+CREATE TABLE api_keys(
+id uuid PRIMARY KEY,
+-- Who minted it - the key acts on their behalf
+user_id uuid NOT NULL REFERENCES users (user_id),
+-- What it is for, e.g. `CMS`
+name TEXT NOT NULL,
+-- The start of the key, to tell keys apart without the secret, e.g. `nlk_4fGh9aQ2`
+prefix TEXT NOT NULL,
+-- SHA-256 of the whole key, hex encoded - the key itself is only shown once
+secret_hash TEXT NOT NULL UNIQUE,
+-- e.g. `{newsletters:write,newsletters:publish}`
+scopes TEXT[] NOT NULL,
+created_at timestamptz NOT NULL,
+-- Never, when NULL
+expires_at timestamptz,
+last_used_at timestamptz,
+revoked_at timestamptz
+);
@@ -0,0 +1,8 @@
+-- Add Roles to Users - what each admin user may do, see `Role`
+-- users from before roles keep doing everything
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner'
+    CHECK (role IN ('owner', 'editor', 'viewer', 'analyst'));
+-- New users are given a role explicitly
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+-- Deactivated users can no longer sign in, nor can their API keys - the row stays for the audit trail
+ALTER TABLE users ADD COLUMN deactivated_at timestamptz;
@@ -0,0 +1,7 @@
+-- Add Purpose and Expiry to Subscription Tokens - see `TokenPurpose`
+-- tokens from before only manage the subscription, data access takes a new link
+ALTER TABLE subscription_tokens ADD COLUMN purpose TEXT NOT NULL DEFAULT 'preferences'
+    CHECK (purpose IN ('preferences', 'data_access'));
+ALTER TABLE subscription_tokens ALTER COLUMN purpose DROP DEFAULT;
+ALTER TABLE subscription_tokens ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '365 days';
+ALTER TABLE subscription_tokens ALTER COLUMN expires_at DROP DEFAULT;
@@ -0,0 +1,8 @@
+-- Add Next Poll At to Feed Sources - when the poller fetches the feed again, failed or not
+-- `last_polled_at` is only set by a successful poll from now on, so a feed is only seeded once it was read
+ALTER TABLE feed_sources ADD COLUMN next_poll_at timestamptz NOT NULL DEFAULT now();
+UPDATE feed_sources SET next_poll_at = last_polled_at + interval '15 minutes'
+WHERE last_polled_at IS NOT NULL;
+-- Failed polls used to set it too - without any entry recorded, the feed may never have been read
+UPDATE feed_sources SET last_polled_at = NULL
+WHERE NOT EXISTS (SELECT 1 FROM feed_entries WHERE feed_entries.feed_source_id = feed_sources.id);
@@ -0,0 +1,7 @@
+-- Create Used Form Tokens Table - the nonce of every form token a signup went through with,
+-- so each token signs up one address; rows older than `max_form_age_hours` are dropped now and then
+CREATE TABLE used_form_tokens(
+nonce TEXT PRIMARY KEY,
+used_at timestamptz NOT NULL
+);
+CREATE INDEX used_form_tokens_used_at_idx ON used_form_tokens (used_at);
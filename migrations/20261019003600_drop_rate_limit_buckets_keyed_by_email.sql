@@ -0,0 +1,3 @@
+-- Drop Rate Limit Buckets Keyed By Email
+-- Buckets are keyed by a hash of the address now, the ones holding it in plain text go
+DELETE FROM rate_limit_buckets WHERE key LIKE '%:email:%@%';
@@ -0,0 +1,4 @@
+-- Rename Digest Frequency To Delivery Window
+-- Issues are held back until a window, not merged into a digest
+ALTER TABLE subscriptions RENAME COLUMN digest_frequency TO delivery_window;
+ALTER TABLE subscriptions RENAME CONSTRAINT subscriptions_digest_frequency_check TO subscriptions_delivery_window_check;
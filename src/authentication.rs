@@ -0,0 +1,349 @@
+use crate::domain::{Role, Scope};
+use actix_web::body::BoxBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpResponse};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use base64::Engine;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Every key starts with it, so leaked keys are easy to search for, e.g. by secret scanners
+const API_KEY_PREFIX: &str = "nlk_";
+
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+// The authenticated user, inserted into the request extensions by `reject_anonymous_users`
+// handlers get it back with `web::ReqData<UserId>`
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::ops::Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// An API key that authenticated a request, inserted next to its `UserId` and `Role`
+#[derive(Clone, Debug)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: Vec<Scope>,
+    // Of the user who minted it
+    pub role: Role,
+}
+
+// Middleware for the `/admin` scope - every request must carry valid `Basic` credentials
+// or an API key as a `Bearer` token, the route's scope is then checked by `require_scope`
+// handlers get the role back with `web::ReqData<Role>`
+pub async fn reject_anonymous_users(
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .expect("The database pool is not registered as application data.")
+        .clone();
+    let api_key = match bearer_token(req.headers()) {
+        Some(key) => match validate_api_key(&key, &pool).await {
+            Ok(Some(api_key)) => Some(api_key),
+            Ok(None) => {
+                tracing::warn!("Rejected request with an unknown, expired or revoked API key.");
+                return Ok(req.into_response(bearer_error(
+                    HttpResponse::Unauthorized(),
+                    r#"Bearer realm="admin", error="invalid_token""#.into(),
+                )));
+            }
+            Err(_) => return Ok(req.into_response(HttpResponse::InternalServerError().finish())),
+        },
+        None => None,
+    };
+    let (user_id, role) = match &api_key {
+        Some(api_key) => (api_key.user_id, api_key.role),
+        None => {
+            let credentials = match basic_authentication(req.headers()) {
+                Ok(credentials) => credentials,
+                Err(e) => {
+                    tracing::warn!("Rejected request without valid credentials: {}", e);
+                    return Ok(req.into_response(unauthorized()));
+                }
+            };
+            match validate_credentials(credentials, &pool).await {
+                Ok(Some(user)) => user,
+                Ok(None) => return Ok(req.into_response(unauthorized())),
+                Err(_) => {
+                    return Ok(req.into_response(HttpResponse::InternalServerError().finish()))
+                }
+            }
+        }
+    };
+
+    req.extensions_mut().insert(UserId(user_id));
+    req.extensions_mut().insert(role);
+    if let Some(api_key) = api_key {
+        req.extensions_mut().insert(api_key);
+    }
+    next.call(req).await
+}
+
+// Middleware `Routes::requiring` wraps each of its resources in - the role, and key, that
+// `reject_anonymous_users` found must grant `scope`. It runs once actix has routed the request,
+// so it holds for any path that reaches the handler, e.g. a percent-encoded one
+pub async fn require_scope(
+    scope: Scope,
+    req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let (role, api_key) = {
+        let extensions = req.extensions();
+        (
+            extensions.get::<Role>().copied(),
+            extensions.get::<ApiKey>().cloned(),
+        )
+    };
+    // Without a role the request was never authenticated - refuse rather than let it through
+    let allowed = role.is_some_and(|role| role.allows(scope))
+        && api_key
+            .as_ref()
+            .is_none_or(|api_key| api_key.scopes.contains(&scope));
+    if allowed {
+        return next.call(req).await;
+    }
+    tracing::warn!(
+        role = role.map(|role| role.as_str()),
+        scope = scope.as_str(),
+        "Rejected request outside the scopes of its user."
+    );
+    let response = match api_key {
+        Some(_) => bearer_error(
+            HttpResponse::Forbidden(),
+            format!(
+                r#"Bearer realm="admin", error="insufficient_scope", scope="{}""#,
+                scope.as_str()
+            ),
+        ),
+        None => HttpResponse::Forbidden().finish(),
+    };
+    Ok(req.into_response(response))
+}
+
+fn bearer_error(mut response: actix_web::HttpResponseBuilder, challenge: String) -> HttpResponse {
+    match HeaderValue::try_from(challenge) {
+        Ok(challenge) => response.insert_header(("WWW-Authenticate", challenge)),
+        Err(_) => &mut response,
+    }
+    .finish()
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((
+            "WWW-Authenticate",
+            HeaderValue::from_static(r#"Basic realm="admin""#),
+        ))
+        .finish()
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, String> {
+    let header_value = headers
+        .get("Authorization")
+        .ok_or("The 'Authorization' header was missing.")?
+        .to_str()
+        .map_err(|_| "The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .ok_or("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .map_err(|_| "Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .map_err(|_| "The decoded credential string is not valid UTF8.")?;
+
+    // Split into two segments, using ':' as delimiter
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .ok_or("A password must be provided in 'Basic' auth.")?;
+
+    Ok(Credentials {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+// `Ok(None)` means the credentials are wrong or the user deactivated, `Err` that we could not check them
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, Role)>, sqlx::Error> {
+    // Verify against a dummy hash when the user does not exist
+    // so the response time does not tell whether a username is valid
+    let mut user = None;
+    let mut expected_password_hash = "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+        .to_string();
+
+    if let Some((stored_user_id, stored_password_hash, role)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user = Some((stored_user_id, role));
+        expected_password_hash = stored_password_hash;
+    }
+
+    // Hashing is CPU-bound - keep it off the async executor threads
+    let current_span = tracing::Span::current();
+    let password_matches = tokio::task::spawn_blocking(move || {
+        current_span.in_scope(|| verify_password_hash(expected_password_hash, credentials.password))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Failed to spawn blocking task: {:?}", e);
+        false
+    });
+
+    Ok(user.filter(|_| password_matches))
+}
+
+#[tracing::instrument(name = "Verify password hash", skip_all)]
+fn verify_password_hash(expected_password_hash: String, password_candidate: String) -> bool {
+    let expected_password_hash = match PasswordHash::new(&expected_password_hash) {
+        Ok(hash) => hash,
+        Err(e) => {
+            tracing::error!("Failed to parse hash in PHC string format: {:?}", e);
+            return false;
+        }
+    };
+    Argon2::default()
+        .verify_password(password_candidate.as_bytes(), &expected_password_hash)
+        .is_ok()
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, String, Role)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash, role
+        FROM users
+        WHERE username = $1 AND deactivated_at IS NULL
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .and_then(|row| {
+        Some((
+            row.user_id,
+            row.password_hash,
+            Role::try_from(row.role).ok()?,
+        ))
+    });
+    Ok(row)
+}
+
+// Same parameters as the seeded admin user
+pub fn compute_password_hash(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let params = Params::new(15000, 2, 1, None)?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+// e.g. `nlk_4fGh9aQ2...` - 40 random characters, too many to guess, so a fast hash will do
+pub fn generate_api_key() -> String {
+    let mut rng = thread_rng();
+    let secret: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    format!("{}{}", API_KEY_PREFIX, secret)
+}
+
+// What `api_keys.prefix` shows of a key, e.g. `nlk_4fGh9aQ2`
+pub fn api_key_prefix(key: &str) -> &str {
+    &key[..API_KEY_PREFIX.len() + 8]
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+// `Ok(None)` means the key is unknown, expired or revoked, or its user deactivated
+// a valid one is marked as used
+#[tracing::instrument(name = "Validate API key", skip(key, pool))]
+pub async fn validate_api_key(key: &str, pool: &PgPool) -> Result<Option<ApiKey>, sqlx::Error> {
+    if !key.starts_with(API_KEY_PREFIX) {
+        return Ok(None);
+    }
+    let now = Utc::now();
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_keys
+        SET last_used_at = $2
+        FROM users
+        WHERE api_keys.secret_hash = $1
+            AND api_keys.revoked_at IS NULL
+            AND (api_keys.expires_at IS NULL OR api_keys.expires_at > $2)
+            AND users.user_id = api_keys.user_id
+            AND users.deactivated_at IS NULL
+        RETURNING api_keys.id, api_keys.user_id, api_keys.scopes, users.role
+        "#,
+        hash_api_key(key),
+        now,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(row.and_then(|row| {
+        Some(ApiKey {
+            id: row.id,
+            user_id: row.user_id,
+            // Scopes we no longer know of grant nothing
+            scopes: row
+                .scopes
+                .into_iter()
+                .filter_map(|scope| Scope::try_from(scope).ok())
+                .collect(),
+            role: Role::try_from(row.role).ok()?,
+        })
+    }))
+}
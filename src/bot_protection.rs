@@ -0,0 +1,283 @@
+use crate::configuration::BotProtectionSettings;
+use crate::routes::MailingList;
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use reqwest::Client;
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// The signed time the signup form was rendered at and a nonce, see `sign_form_token`
+pub const FORM_TOKEN_FIELD: &str = "form_token";
+
+/// Tells a person who filled in the form from a human being, e.g. hCaptcha or Turnstile.
+#[async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    // Whether the response the widget put in the form is a pass - `Err` when we could not tell
+    async fn verify(&self, response: &str) -> Result<bool, String>;
+}
+
+// The `siteverify` API hCaptcha and Turnstile have in common
+pub struct HttpCaptchaVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret_key: String,
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+}
+
+impl HttpCaptchaVerifier {
+    pub fn new(verify_url: String, secret_key: String, timeout: std::time::Duration) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            verify_url,
+            secret_key,
+        }
+    }
+}
+
+#[async_trait]
+impl CaptchaVerifier for HttpCaptchaVerifier {
+    async fn verify(&self, response: &str) -> Result<bool, String> {
+        let verified: VerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&[("secret", self.secret_key.as_str()), ("response", response)])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(verified.success)
+    }
+}
+
+// Why a signup was taken for a bot's
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BotCheck {
+    Honeypot,
+    // A missing `form_token` when one is required, or one we did not sign
+    FormToken,
+    // A `form_token` another signup already went through with
+    ReusedFormToken,
+    TooFast,
+    StaleForm,
+    Captcha,
+}
+
+impl BotCheck {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BotCheck::Honeypot => "honeypot",
+            BotCheck::FormToken => "form_token",
+            BotCheck::ReusedFormToken => "reused_form_token",
+            BotCheck::TooFast => "too_fast",
+            BotCheck::StaleForm => "stale_form",
+            BotCheck::Captcha => "captcha",
+        }
+    }
+}
+
+/// The checks every signup goes through before it is validated, see `check`.
+pub struct BotProtection {
+    settings: BotProtectionSettings,
+    hmac_secret: String,
+    verifier: Box<dyn CaptchaVerifier>,
+    // For the nonces of used form tokens
+    pool: PgPool,
+    // Signups taken for bots since the process started, by check
+    rejections: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl BotProtection {
+    pub fn new(
+        settings: &BotProtectionSettings,
+        hmac_secret: String,
+        verifier: Box<dyn CaptchaVerifier>,
+        pool: PgPool,
+    ) -> Self {
+        Self {
+            settings: settings.clone(),
+            hmac_secret,
+            verifier,
+            pool,
+            rejections: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn settings(&self) -> &BotProtectionSettings {
+        &self.settings
+    }
+
+    // For the signup form to post back as `form_token`
+    pub fn form_token(&self) -> String {
+        sign_form_token(Utc::now(), &self.hmac_secret)
+    }
+
+    // Takes the honeypot, the form token and the CAPTCHA response out of the submitted fields,
+    // so only the list's custom fields are left, and returns the check a bot failed, if any
+    pub async fn check(
+        &self,
+        list: &MailingList,
+        fields: &mut HashMap<String, Value>,
+    ) -> Result<Option<BotCheck>, String> {
+        let honeypot = take_text(fields, &self.settings.honeypot_field);
+        let form_token = take_text(fields, FORM_TOKEN_FIELD);
+        let captcha_response = take_text(fields, &self.settings.captcha.response_field);
+
+        let form_token = form_token
+            .as_deref()
+            .map(|form_token| verify_form_token(form_token, &self.hmac_secret));
+        let failed = if honeypot.is_some() {
+            Some(BotCheck::Honeypot)
+        } else if let Some(failed) = self.check_form_token(form_token.as_ref()) {
+            Some(failed)
+        } else if list.captcha {
+            // Last, so obvious bots never cost a request to the CAPTCHA provider
+            match captcha_response {
+                Some(response) => match self.verifier.verify(&response).await? {
+                    true => None,
+                    false => Some(BotCheck::Captcha),
+                },
+                None => Some(BotCheck::Captcha),
+            }
+        } else {
+            None
+        };
+        // Only once everything else passed, so a failed CAPTCHA does not use the token up
+        let failed = match (failed, form_token) {
+            (None, Some(Some((_, nonce)))) => match self.use_nonce(&nonce).await {
+                Ok(true) => None,
+                Ok(false) => Some(BotCheck::ReusedFormToken),
+                Err(e) => return Err(format!("Failed to record the form token: {}", e)),
+            },
+            (failed, _) => failed,
+        };
+        if let Some(failed) = failed {
+            *self
+                .rejections
+                .lock()
+                .unwrap()
+                .entry(failed.as_str())
+                .or_default() += 1;
+        }
+        Ok(failed)
+    }
+
+    // `form_token` as `verify_form_token` left it, `None` when the form had none
+    fn check_form_token(
+        &self,
+        form_token: Option<&Option<(DateTime<Utc>, String)>>,
+    ) -> Option<BotCheck> {
+        let Some(form_token) = form_token else {
+            return self
+                .settings
+                .require_form_token
+                .then_some(BotCheck::FormToken);
+        };
+        let Some((rendered_at, _)) = form_token else {
+            return Some(BotCheck::FormToken);
+        };
+        let age = Utc::now() - *rendered_at;
+        if age < chrono::Duration::seconds(self.settings.min_fill_seconds as i64) {
+            Some(BotCheck::TooFast)
+        } else if age > chrono::Duration::hours(self.settings.max_form_age_hours as i64) {
+            Some(BotCheck::StaleForm)
+        } else {
+            None
+        }
+    }
+
+    // `false` when a signup already went through with the token
+    async fn use_nonce(&self, nonce: &str) -> Result<bool, sqlx::Error> {
+        let now = Utc::now();
+        let used = sqlx::query!(
+            r#"
+            INSERT INTO used_form_tokens (nonce, used_at) VALUES ($1, $2)
+            ON CONFLICT (nonce) DO NOTHING
+            "#,
+            nonce,
+            now
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            == 0;
+        // Now and then, forget the tokens that are too old to be taken anyway
+        if thread_rng().gen_ratio(1, 100) {
+            let max_age = chrono::Duration::hours(self.settings.max_form_age_hours as i64);
+            sqlx::query!(
+                "DELETE FROM used_form_tokens WHERE used_at < $1",
+                now - max_age
+            )
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(!used)
+    }
+
+    pub fn rejections(&self) -> Vec<(&'static str, u64)> {
+        let rejections = self.rejections.lock().unwrap();
+        rejections
+            .iter()
+            .map(|(check, count)| (*check, *count))
+            .collect()
+    }
+}
+
+// Empty fields count as left out - that is what a person leaves the honeypot as
+fn take_text(fields: &mut HashMap<String, Value>, name: &str) -> Option<String> {
+    match fields.remove(name)? {
+        Value::Null => None,
+        Value::String(text) if text.trim().is_empty() => None,
+        Value::String(text) => Some(text),
+        other => Some(other.to_string()),
+    }
+}
+
+/// When the form was rendered, a random nonce and the HMAC of both, e.g. `1767225600.aZ3k....q7X0...`
+///
+/// Nobody can make one up for a form they never loaded, or for a time long past - and the
+/// nonce is recorded once a signup goes through with it, so it signs up one address only.
+pub fn sign_form_token(rendered_at: DateTime<Utc>, secret: &str) -> String {
+    let nonce: String = std::iter::repeat_with(|| thread_rng().sample(Alphanumeric))
+        .map(char::from)
+        .take(16)
+        .collect();
+    let payload = format!("{}.{}", rendered_at.timestamp(), nonce);
+    let signature = URL_SAFE_NO_PAD.encode(mac(secret, &payload).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+}
+
+// When the form was rendered, and the nonce
+fn verify_form_token(token: &str, secret: &str) -> Option<(DateTime<Utc>, String)> {
+    let (payload, signature) = token.trim().rsplit_once('.')?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    // Compared in constant time
+    mac(secret, payload).verify_slice(&signature).ok()?;
+    let (timestamp, nonce) = payload.split_once('.')?;
+    let rendered_at = DateTime::from_timestamp(timestamp.parse().ok()?, 0)?;
+    Some((rendered_at, nonce.to_string()))
+}
+
+// Tracking tokens are signed with the same secret - the prefix keeps one from passing for the other
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(b"form_token:");
+    mac.update(payload.as_bytes());
+    mac
+}
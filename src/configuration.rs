@@ -1,11 +1,18 @@
+use crate::bot_protection::HttpCaptchaVerifier;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
 use serde_aux::field_attributes::deserialize_number_from_string;
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
+    pub email_client: EmailClientSettings,
+    pub rate_limit: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub signup_policy: SignupPolicySettings,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
     pub password: String,
@@ -15,11 +22,161 @@ pub struct DatabaseSettings {
     pub database_name: String,
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    // Where subscribers reach us - used to build the links we email them
+    pub base_url: String,
+    // Shown in the footer of every email, as anti-spam laws require
+    pub postal_address: String,
+    // Signs the links we hand out, e.g. for open and click tracking
+    pub hmac_secret: String,
+    // Serves the interactive API docs at `/docs` - `/openapi.json` is always there
+    #[serde(default)]
+    pub api_docs: bool,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailClientSettings {
+    pub base_url: String,
+    pub sender_email: String,
+    pub authorization_token: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    // Shared with the provider, who signs the bounce and complaint webhooks with it
+    pub webhook_secret: String,
+    // This many soft bounces in a row and the address is treated as bounced
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_soft_bounces: i32,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub store: RateLimitStore,
+    // Proxies we take the `X-Forwarded-For` of, e.g. `127.0.0.1` or `10.0.0.0/8`
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    #[serde(default)]
+    pub routes: Vec<RouteRateLimit>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStore {
+    // Per process - each replica counts on its own
+    Memory,
+    // Shared by every replica using the database
+    Postgres,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RouteRateLimit {
+    // A route pattern, e.g. `/lists/{slug}/subscriptions`
+    pub path: String,
+    #[serde(default = "default_rate_limited_method")]
+    pub method: String,
+    // Per client IP address
+    pub per_ip: Option<TokenBucketSettings>,
+    // Per address in the `email` field of the body, whoever submits it
+    pub per_email: Option<TokenBucketSettings>,
+}
+
+fn default_rate_limited_method() -> String {
+    "POST".into()
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct TokenBucketSettings {
+    // Requests allowed in a burst
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    // How many requests are allowed again every minute
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub per_minute: f64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    // A field of the signup form hidden from people - only bots fill it in
+    pub honeypot_field: String,
+    // Forms sent back sooner than this after they were rendered were not filled in by a person
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_fill_seconds: u64,
+    // Forms older than this must be reloaded
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_hours: u64,
+    // Turn away signups without a `form_token` - API clients posting JSON must fetch one too
+    pub require_form_token: bool,
+    // Only asked for on the lists that turn it on
+    pub captcha: CaptchaSettings,
+}
+
+// An hCaptcha or Turnstile-style verification endpoint
+#[derive(serde::Deserialize, Clone)]
+pub struct CaptchaSettings {
+    pub verify_url: String,
+    pub secret_key: String,
+    // The form field the widget puts its response in, e.g. `h-captcha-response`
+    pub response_field: String,
+    // Public, for the widget on the signup page
+    pub site_key: String,
+    pub script_url: String,
+    // The element the script turns into the widget, e.g. `h-captcha` or `cf-turnstile`
+    pub widget_class: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+// Which addresses a signup is accepted from, on top of them being valid
+#[derive(serde::Deserialize, Clone)]
+pub struct SignupPolicySettings {
+    // One domain per line, `#` starts a comment - relative to the working directory
+    pub disposable_domains_file: String,
+    pub disposable_domains: PolicyAction,
+    // Local parts of addresses nobody in particular reads, e.g. `noreply`
+    pub role_local_parts: Vec<String>,
+    pub role_accounts: PolicyAction,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    Allow,
+    // Accepted, but recorded in `subscriptions.policy_flags`
+    Flag,
+    Reject,
+}
+
+impl CaptchaSettings {
+    pub fn verifier(&self) -> HttpCaptchaVerifier {
+        HttpCaptchaVerifier::new(
+            self.verify_url.clone(),
+            self.secret_key.clone(),
+            std::time::Duration::from_millis(self.timeout_milliseconds),
+        )
+    }
+}
+
+impl EmailClientSettings {
+    pub fn client(&self) -> EmailClient {
+        let sender = self.sender().expect("Invalid sender email address.");
+        EmailClient::new(
+            self.base_url.clone(),
+            sender,
+            self.authorization_token.clone(),
+            self.timeout(),
+        )
+    }
+
+    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+        SubscriberEmail::parse(self.sender_email.clone())
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
 }
 
 impl DatabaseSettings {
@@ -0,0 +1,140 @@
+use crate::routes::message_page;
+use crate::startup::HmacSecret;
+use actix_web::body::BoxBody;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sha2::Sha256;
+
+// A random value per browser, only ever sent back to us
+pub const CSRF_COOKIE: &str = "csrf";
+// The hidden field every form posts back
+pub const CSRF_FIELD: &str = "csrf_token";
+
+/// The browser's CSRF cookie, or a new one for `add_to` to hand out.
+///
+/// Forms carry the HMAC of the cookie and of the path they post to, e.g. `/lists/weekly/signup` -
+/// another site can make a browser post to us, but can neither read the cookie nor sign for it.
+pub struct CsrfCookie {
+    value: String,
+    is_new: bool,
+}
+
+impl CsrfCookie {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        match request.cookie(CSRF_COOKIE) {
+            Some(cookie) if !cookie.value().is_empty() => Self {
+                value: cookie.value().to_string(),
+                is_new: false,
+            },
+            _ => {
+                let mut rng = thread_rng();
+                Self {
+                    value: std::iter::repeat_with(|| rng.sample(Alphanumeric))
+                        .map(char::from)
+                        .take(32)
+                        .collect(),
+                    is_new: true,
+                }
+            }
+        }
+    }
+
+    // The `csrf_token` of a form posting to `action`, a path with its query string
+    pub fn token(&self, action: &str, secret: &str) -> String {
+        URL_SAFE_NO_PAD.encode(mac(&self.value, action, secret).finalize().into_bytes())
+    }
+
+    // Embedded forms are posted from another site, which only `SameSite=None` cookies survive -
+    // and browsers only take those over HTTPS
+    pub fn add_to(&self, response: &mut HttpResponse, base_url: &str) {
+        if !self.is_new {
+            return;
+        }
+        let secure = base_url.starts_with("https://");
+        let cookie = Cookie::build(CSRF_COOKIE, self.value.clone())
+            .path("/")
+            .http_only(true)
+            .secure(secure)
+            .same_site(if secure {
+                SameSite::None
+            } else {
+                SameSite::Lax
+            })
+            .finish();
+        if let Err(e) = response.add_cookie(&cookie) {
+            tracing::error!("Failed to set the CSRF cookie: {:?}", e);
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct CsrfField {
+    csrf_token: String,
+}
+
+// Middleware for the resources serving forms - every `POST` must come from a page we rendered
+pub async fn require_csrf_token(
+    mut req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    if req.method() != Method::POST {
+        return next.call(req).await;
+    }
+    let secret = req
+        .app_data::<web::Data<HmacSecret>>()
+        .expect("The HMAC secret is not registered as application data.")
+        .clone();
+    // The handler still needs the body, so it goes back once we had a look
+    let body = req.extract::<web::Bytes>().await?;
+    let token = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|body| web::Query::<CsrfField>::from_query(body).ok())
+        .map(|field| field.into_inner().csrf_token);
+    req.set_payload(Payload::from(body));
+    let action = req
+        .uri()
+        .path_and_query()
+        .map_or_else(|| req.path().to_string(), |action| action.to_string());
+    let verified = match (req.cookie(CSRF_COOKIE), token) {
+        (Some(cookie), Some(token)) => verify(cookie.value(), &token, &action, &secret.0),
+        _ => false,
+    };
+    if !verified {
+        tracing::warn!(action, "Rejected a form without a valid CSRF token.");
+        let response = message_page(
+            StatusCode::FORBIDDEN,
+            "This form has expired",
+            "Please reload the page and try again.",
+            None,
+        );
+        return Ok(req.into_response(response));
+    }
+    next.call(req).await
+}
+
+fn verify(cookie: &str, token: &str, action: &str, secret: &str) -> bool {
+    let Ok(token) = URL_SAFE_NO_PAD.decode(token.trim()) else {
+        return false;
+    };
+    // Compared in constant time
+    mac(cookie, action, secret).verify_slice(&token).is_ok()
+}
+
+// Tracking and form tokens are signed with the same secret - the prefix keeps them apart
+fn mac(cookie: &str, action: &str, secret: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(b"csrf:");
+    mac.update(action.as_bytes());
+    mac.update(b":");
+    mac.update(cookie.as_bytes());
+    mac
+}
@@ -0,0 +1,258 @@
+use chrono::NaiveDate;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+const MAX_KEY_LENGTH: usize = 64;
+// Applies to text fields without their own `max_length`
+const DEFAULT_MAX_TEXT_LENGTH: usize = 500;
+// Signup form fields that are not custom fields
+const RESERVED_KEYS: [&str; 5] = ["email", "name", "tags", "form_token", "csrf_token"];
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Text,
+    Number,
+    Boolean,
+    // `YYYY-MM-DD`
+    Date,
+    // One of `FieldRules::choices`
+    Choice,
+}
+
+impl FieldType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Number => "number",
+            FieldType::Boolean => "boolean",
+            FieldType::Date => "date",
+            FieldType::Choice => "choice",
+        }
+    }
+}
+
+impl TryFrom<String> for FieldType {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "number" => Ok(Self::Number),
+            "boolean" => Ok(Self::Boolean),
+            "date" => Ok(Self::Date),
+            "choice" => Ok(Self::Choice),
+            other => Err(format!(
+                "{} is not a supported field type: use text, number, boolean, date or choice.",
+                other
+            )),
+        }
+    }
+}
+
+/// Validation rules of a custom field, only the ones that make sense for its type may be set.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FieldRules {
+    // Text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    // Number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    // Choice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub choices: Option<Vec<String>>,
+}
+
+/// A field a list asks for at signup on top of the email and name, e.g. `company`.
+///
+/// Values are stored in `subscriptions.attributes` under `key`.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct CustomField {
+    pub key: String,
+    pub label: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    pub required: bool,
+    pub rules: FieldRules,
+}
+
+impl CustomField {
+    pub fn parse(
+        key: String,
+        label: Option<String>,
+        field_type: FieldType,
+        required: bool,
+        rules: FieldRules,
+    ) -> Result<CustomField, String> {
+        let key = key.trim().to_string();
+        let is_valid_key = key.len() <= MAX_KEY_LENGTH
+            && key.starts_with(|c: char| c.is_ascii_lowercase())
+            && key
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !is_valid_key {
+            return Err(format!(
+                "`{}` is not a valid field key: use up to {} lowercase letters, digits and `_`, starting with a letter.",
+                key, MAX_KEY_LENGTH
+            ));
+        }
+        if RESERVED_KEYS.contains(&key.as_str()) {
+            return Err(format!("`{}` is reserved for the signup form.", key));
+        }
+
+        let allowed = match field_type {
+            FieldType::Text => {
+                rules.min.is_none() && rules.max.is_none() && rules.choices.is_none()
+            }
+            FieldType::Number => rules.max_length.is_none() && rules.choices.is_none(),
+            FieldType::Boolean | FieldType::Date => rules == FieldRules::default(),
+            FieldType::Choice => {
+                rules.max_length.is_none() && rules.min.is_none() && rules.max.is_none()
+            }
+        };
+        if !allowed {
+            return Err(format!(
+                "These rules do not apply to {} fields.",
+                field_type.as_str()
+            ));
+        }
+        if let (Some(min), Some(max)) = (rules.min, rules.max) {
+            if min > max {
+                return Err("`min` must not be greater than `max`.".into());
+            }
+        }
+        if field_type == FieldType::Choice
+            && rules
+                .choices
+                .as_ref()
+                .is_none_or(|choices| choices.is_empty())
+        {
+            return Err("Choice fields need a non-empty `choices` list.".into());
+        }
+
+        let label = label
+            .map(|label| label.trim().to_string())
+            .filter(|label| !label.is_empty())
+            .unwrap_or_else(|| key.clone());
+        Ok(Self {
+            key,
+            label,
+            field_type,
+            required,
+            rules,
+        })
+    }
+
+    // Check a submitted value and convert it to the JSON type stored for the field
+    // form submissions only have strings, so e.g. `"42"` is accepted for a number
+    pub fn validate(&self, value: &Value) -> Result<Value, String> {
+        let invalid = |expected: &str| format!("`{}` must be {}.", self.label, expected);
+        match self.field_type {
+            FieldType::Text => {
+                let text = value.as_str().ok_or_else(|| invalid("text"))?.trim();
+                let max_length = self.rules.max_length.unwrap_or(DEFAULT_MAX_TEXT_LENGTH);
+                if text.chars().count() > max_length {
+                    return Err(format!(
+                        "`{}` must not be longer than {} characters.",
+                        self.label, max_length
+                    ));
+                }
+                Ok(Value::String(text.to_string()))
+            }
+            FieldType::Number => {
+                let number = match value {
+                    Value::Number(number) => number.as_f64(),
+                    Value::String(s) => s.trim().parse::<f64>().ok(),
+                    _ => None,
+                }
+                .filter(|number| number.is_finite())
+                .ok_or_else(|| invalid("a number"))?;
+                if self.rules.min.is_some_and(|min| number < min) {
+                    return Err(format!(
+                        "`{}` must be at least {}.",
+                        self.label,
+                        self.rules.min.unwrap()
+                    ));
+                }
+                if self.rules.max.is_some_and(|max| number > max) {
+                    return Err(format!(
+                        "`{}` must be at most {}.",
+                        self.label,
+                        self.rules.max.unwrap()
+                    ));
+                }
+                // Whole numbers are stored as integers, e.g. `42` rather than `42.0`
+                if number.fract() == 0.0 && number.abs() < i64::MAX as f64 {
+                    Ok(serde_json::json!(number as i64))
+                } else {
+                    Ok(serde_json::json!(number))
+                }
+            }
+            FieldType::Boolean => match value {
+                Value::Bool(b) => Ok(Value::Bool(*b)),
+                // A checked checkbox is sent as `on`
+                Value::String(s) => match s.trim().to_lowercase().as_str() {
+                    "true" | "on" | "yes" | "1" => Ok(Value::Bool(true)),
+                    "false" | "off" | "no" | "0" => Ok(Value::Bool(false)),
+                    _ => Err(invalid("true or false")),
+                },
+                _ => Err(invalid("true or false")),
+            },
+            FieldType::Date => {
+                let date = value
+                    .as_str()
+                    .and_then(|s| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok())
+                    .ok_or_else(|| invalid("a date like 2025-01-31"))?;
+                Ok(Value::String(date.format("%Y-%m-%d").to_string()))
+            }
+            FieldType::Choice => {
+                let choices = self.rules.choices.as_deref().unwrap_or_default();
+                let choice = value
+                    .as_str()
+                    .map(str::trim)
+                    .filter(|choice| choices.iter().any(|c| c == choice))
+                    .ok_or_else(|| invalid(&format!("one of {}", choices.join(", "))))?;
+                Ok(Value::String(choice.to_string()))
+            }
+        }
+    }
+}
+
+// Validate everything submitted against the fields of a list
+// returns every problem at once, so a form can show them all
+pub fn validate_attributes(
+    fields: &[CustomField],
+    mut submitted: HashMap<String, Value>,
+) -> Result<Map<String, Value>, Vec<String>> {
+    let mut attributes = Map::new();
+    let mut errors = Vec::new();
+    for field in fields {
+        // An empty form input is the same as leaving the field out
+        let value = submitted
+            .remove(&field.key)
+            .filter(|value| !value.is_null() && value.as_str() != Some(""));
+        match value {
+            None if field.required => errors.push(format!("`{}` is required.", field.label)),
+            None => {}
+            Some(value) => match field.validate(&value) {
+                Ok(value) => {
+                    attributes.insert(field.key.clone(), value);
+                }
+                Err(e) => errors.push(e),
+            },
+        }
+    }
+    let mut unknown: Vec<_> = submitted.into_keys().collect();
+    unknown.sort();
+    for key in unknown {
+        errors.push(format!("`{}` is not a field of this list.", key));
+    }
+    if errors.is_empty() {
+        Ok(attributes)
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,43 @@
+/// When a subscriber wants our emails, stored as text in `subscriptions.delivery_window`.
+///
+/// `Daily` and `Weekly` hold each delivery back until the next midnight, or Monday midnight,
+/// UTC, so they all arrive together. Issues are never merged - each is still its own email.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryWindow {
+    Immediately,
+    Daily,
+    Weekly,
+}
+
+impl DeliveryWindow {
+    pub const ALL: [DeliveryWindow; 3] = [
+        DeliveryWindow::Immediately,
+        DeliveryWindow::Daily,
+        DeliveryWindow::Weekly,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryWindow::Immediately => "immediately",
+            DeliveryWindow::Daily => "daily",
+            DeliveryWindow::Weekly => "weekly",
+        }
+    }
+}
+
+impl TryFrom<String> for DeliveryWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "immediately" => Ok(Self::Immediately),
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            other => Err(format!(
+                "{} is not a delivery window: use immediately, daily or weekly.",
+                other
+            )),
+        }
+    }
+}
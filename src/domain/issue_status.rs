@@ -0,0 +1,40 @@
+/// Where a newsletter issue is in its life, stored as text in `newsletter_issues.status`.
+///
+/// `Draft` -> `Scheduled` -> `Sending` -> `Sent`, a scheduled issue can be `Cancelled`
+/// until the scheduler picks it up. Publishing right away goes straight to `Sending`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueStatus {
+    Draft,
+    Scheduled,
+    // Deliveries are queued, some of them not attempted yet
+    Sending,
+    Sent,
+    Cancelled,
+}
+
+impl IssueStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Sending => "sending",
+            IssueStatus::Sent => "sent",
+            IssueStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl TryFrom<String> for IssueStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "draft" => Ok(Self::Draft),
+            "scheduled" => Ok(Self::Scheduled),
+            "sending" => Ok(Self::Sending),
+            "sent" => Ok(Self::Sent),
+            "cancelled" => Ok(Self::Cancelled),
+            other => Err(format!("{} is not a supported issue status.", other)),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+pub mod custom_field;
+pub mod delivery_window;
+pub mod issue_status;
+pub mod new_subscriber;
+pub mod role;
+pub mod scope;
+pub mod subscriber_email;
+pub mod subscription_status;
+pub mod suppression_target;
+pub mod tag;
+pub mod token_purpose;
+
+// Re-export the types so callers can use `crate::domain::SubscriberEmail`
+pub use custom_field::*;
+pub use delivery_window::*;
+pub use issue_status::*;
+pub use new_subscriber::*;
+pub use role::*;
+pub use scope::*;
+pub use subscriber_email::*;
+pub use subscription_status::*;
+pub use suppression_target::*;
+pub use tag::*;
+pub use token_purpose::*;
@@ -0,0 +1,15 @@
+use crate::domain::SubscriberEmail;
+
+// A subscriber that passed validation and is ready to be stored
+pub struct NewSubscriber {
+    pub email: SubscriberEmail,
+    pub name: String,
+}
+
+impl NewSubscriber {
+    // The rules every way of adding a subscriber goes through - the signup form, imports, ...
+    pub fn parse(email: String, name: String) -> Result<NewSubscriber, String> {
+        let email = SubscriberEmail::parse(email)?;
+        Ok(Self { email, name })
+    }
+}
@@ -0,0 +1,80 @@
+use crate::domain::Scope;
+
+/// What an admin user may do, stored as text in `users.role`.
+///
+/// A role grants scopes, the same an API key carries - a key only ever has the scopes of its
+/// role as well, so a key minted by an editor cannot publish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Everything, including managing the other users
+    Owner,
+    // Works on drafts, lists and subscribers - only owners send anything out
+    Editor,
+    // Reads, but changes nothing
+    Viewer,
+    // Reads the numbers, not the people behind them
+    Analyst,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+            Role::Analyst => "analyst",
+        }
+    }
+
+    pub fn scopes(&self) -> &'static [Scope] {
+        match self {
+            Role::Owner => &Scope::ALL,
+            Role::Editor => &[
+                Scope::SubscribersRead,
+                Scope::SubscribersWrite,
+                Scope::ListsRead,
+                Scope::ListsWrite,
+                Scope::NewslettersRead,
+                Scope::NewslettersWrite,
+                Scope::StatsRead,
+                Scope::RoutesRead,
+                Scope::ApiKeysManage,
+            ],
+            Role::Viewer => &[
+                Scope::SubscribersRead,
+                Scope::ListsRead,
+                Scope::NewslettersRead,
+                Scope::StatsRead,
+                Scope::ApiKeysManage,
+            ],
+            Role::Analyst => &[
+                Scope::ListsRead,
+                Scope::NewslettersRead,
+                Scope::StatsRead,
+                Scope::ApiKeysManage,
+            ],
+        }
+    }
+
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes().contains(&scope)
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "owner" => Ok(Self::Owner),
+            "editor" => Ok(Self::Editor),
+            "viewer" => Ok(Self::Viewer),
+            "analyst" => Ok(Self::Analyst),
+            other => Err(format!(
+                "{} is not a role: use owner, editor, viewer or analyst.",
+                other
+            )),
+        }
+    }
+}
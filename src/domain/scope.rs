@@ -0,0 +1,91 @@
+/// What an API key may do, stored as text in `api_keys.scopes`, e.g. `subscribers:read`.
+///
+/// Every admin route requires one, see `Routes::requiring`, and every `Role` grants some.
+/// `ApiKeysManage` and `UsersManage` cannot be granted to a key - only people manage access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+pub enum Scope {
+    #[serde(rename = "subscribers:read")]
+    SubscribersRead,
+    #[serde(rename = "subscribers:write")]
+    SubscribersWrite,
+    #[serde(rename = "lists:read")]
+    ListsRead,
+    #[serde(rename = "lists:write")]
+    ListsWrite,
+    #[serde(rename = "newsletters:read")]
+    NewslettersRead,
+    // Drafts and templates, nothing goes out to the list
+    #[serde(rename = "newsletters:write")]
+    NewslettersWrite,
+    #[serde(rename = "newsletters:publish")]
+    NewslettersPublish,
+    #[serde(rename = "stats:read")]
+    StatsRead,
+    #[serde(rename = "routes:read")]
+    RoutesRead,
+    #[serde(rename = "api_keys:manage")]
+    ApiKeysManage,
+    #[serde(rename = "users:manage")]
+    UsersManage,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 11] = [
+        Scope::SubscribersRead,
+        Scope::SubscribersWrite,
+        Scope::ListsRead,
+        Scope::ListsWrite,
+        Scope::NewslettersRead,
+        Scope::NewslettersWrite,
+        Scope::NewslettersPublish,
+        Scope::StatsRead,
+        Scope::RoutesRead,
+        Scope::ApiKeysManage,
+        Scope::UsersManage,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::SubscribersRead => "subscribers:read",
+            Scope::SubscribersWrite => "subscribers:write",
+            Scope::ListsRead => "lists:read",
+            Scope::ListsWrite => "lists:write",
+            Scope::NewslettersRead => "newsletters:read",
+            Scope::NewslettersWrite => "newsletters:write",
+            Scope::NewslettersPublish => "newsletters:publish",
+            Scope::StatsRead => "stats:read",
+            Scope::RoutesRead => "routes:read",
+            Scope::ApiKeysManage => "api_keys:manage",
+            Scope::UsersManage => "users:manage",
+        }
+    }
+
+    // The scopes a new key asks for, e.g. `["newsletters:write", "newsletters:publish"]`
+    pub fn parse_grantable(scopes: &[String]) -> Result<Vec<Scope>, String> {
+        if scopes.is_empty() {
+            return Err("An API key needs at least one scope.".into());
+        }
+        let mut parsed = Vec::new();
+        for scope in scopes {
+            let scope = Scope::try_from(scope.clone())?;
+            if matches!(scope, Scope::ApiKeysManage | Scope::UsersManage) {
+                return Err(format!("API keys cannot be granted {}.", scope.as_str()));
+            }
+            if !parsed.contains(&scope) {
+                parsed.push(scope);
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("{} is not a scope.", s))
+    }
+}
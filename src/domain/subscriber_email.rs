@@ -0,0 +1,80 @@
+// Limits from RFC 5321 - a longer address can not be delivered anyway
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// An email address submitted by a subscriber, validated and normalised.
+///
+/// `as_ref` gives the normalised form that is stored in `subscriptions.email` and used for
+/// uniqueness: whitespace trimmed, the domain lowercased and IDNA (punycode) encoded.
+/// `display` keeps the address as the subscriber typed it, e.g. `Ursula@Bücher.de`, and is
+/// what we send to - it is stored in `subscriptions.email_display`.
+#[derive(Debug, Clone)]
+pub struct SubscriberEmail {
+    normalized: String,
+    display: String,
+}
+
+impl SubscriberEmail {
+    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
+        let display = s.trim().to_string();
+        let invalid = || format!("{} is not a valid subscriber email.", display);
+
+        // Quoted local parts may contain `@`, we do not accept those so split on the last one
+        let (local_part, domain) = display.rsplit_once('@').ok_or_else(invalid)?;
+        if local_part.is_empty()
+            || local_part.len() > MAX_LOCAL_PART_LENGTH
+            || local_part.contains('@')
+            || local_part
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(invalid());
+        }
+
+        // `domain_to_ascii` lowercases and converts internationalised labels to `xn--` form
+        let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+        if domain.is_empty()
+            || !domain.contains('.')
+            || domain.starts_with('.')
+            || domain.ends_with('.')
+            || domain.contains("..")
+        {
+            return Err(invalid());
+        }
+
+        let normalized = format!("{}@{}", local_part, domain);
+        if normalized.len() > MAX_EMAIL_LENGTH {
+            return Err(invalid());
+        }
+
+        Ok(SubscriberEmail {
+            normalized,
+            display,
+        })
+    }
+
+    pub fn display(&self) -> &str {
+        &self.display
+    }
+
+    pub fn local_part(&self) -> &str {
+        self.normalized.rsplit_once('@').unwrap().0
+    }
+
+    // Lowercased and IDNA encoded, e.g. `xn--bcher-kva.de`
+    pub fn domain(&self) -> &str {
+        self.normalized.rsplit_once('@').unwrap().1
+    }
+}
+
+impl AsRef<str> for SubscriberEmail {
+    fn as_ref(&self) -> &str {
+        &self.normalized
+    }
+}
+
+impl std::fmt::Display for SubscriberEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.normalized.fmt(f)
+    }
+}
@@ -0,0 +1,45 @@
+/// The lifecycle state of a subscriber, stored as text in `subscriptions.status`.
+///
+/// List memberships use the same values in `list_memberships.status`, except `Erased`,
+/// `Bounced` and `Complained` - those are about the address, whatever the list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    // The address does not take our emails, as the email provider told us
+    Bounced,
+    // They marked one of our emails as spam
+    Complained,
+    // Personal data was removed on request, the row only remains for aggregate counts
+    Erased,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+            SubscriptionStatus::Bounced => "bounced",
+            SubscriptionStatus::Complained => "complained",
+            SubscriptionStatus::Erased => "erased",
+        }
+    }
+}
+
+impl TryFrom<String> for SubscriptionStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "pending_confirmation" => Ok(Self::PendingConfirmation),
+            "confirmed" => Ok(Self::Confirmed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            "bounced" => Ok(Self::Bounced),
+            "complained" => Ok(Self::Complained),
+            "erased" => Ok(Self::Erased),
+            other => Err(format!("{} is not a supported subscription status.", other)),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::domain::SubscriberEmail;
+
+/// An address, or a whole domain, that must never be emailed.
+///
+/// Parsed from e.g. `ada@example.com` or `test.example` - `@test.example` works too.
+/// Both are stored lowercased, and a domain covers its subdomains as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SuppressionTarget {
+    Email(String),
+    Domain(String),
+}
+
+impl SuppressionTarget {
+    pub fn parse(s: &str) -> Result<SuppressionTarget, String> {
+        let s = s.trim();
+        if let Some(domain) = s.strip_prefix('@').or((!s.contains('@')).then_some(s)) {
+            let invalid = || format!("{} is not a valid domain.", s);
+            let domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+            let is_valid = domain.contains('.')
+                && domain.split('.').all(|label| {
+                    !label.is_empty()
+                        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                });
+            return if is_valid {
+                Ok(Self::Domain(domain))
+            } else {
+                Err(invalid())
+            };
+        }
+        let email = SubscriberEmail::parse(s.into())?;
+        Ok(Self::Email(email.as_ref().to_lowercase()))
+    }
+
+    // As stored in `suppressions.kind`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SuppressionTarget::Email(_) => "email",
+            SuppressionTarget::Domain(_) => "domain",
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            SuppressionTarget::Email(value) | SuppressionTarget::Domain(value) => value,
+        }
+    }
+}
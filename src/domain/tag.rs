@@ -0,0 +1,50 @@
+const MAX_TAG_LENGTH: usize = 64;
+// More than this in a single signup is not a form we built
+const MAX_TAGS_IN_LIST: usize = 20;
+
+/// A label attached to subscribers, e.g. `beta`, used to target segments.
+///
+/// Tags are lowercased, so `Beta` and `beta` are the same tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag(String);
+
+impl Tag {
+    pub fn parse(s: &str) -> Result<Tag, String> {
+        let tag = s.trim().to_lowercase();
+        let is_valid = !tag.is_empty()
+            && tag.len() <= MAX_TAG_LENGTH
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if is_valid {
+            Ok(Self(tag))
+        } else {
+            Err(format!(
+                "`{}` is not a valid tag: use up to {} letters, digits, `-` and `_`.",
+                s.trim(),
+                MAX_TAG_LENGTH
+            ))
+        }
+    }
+
+    // A comma-separated list, as sent by a hidden `tags` field of a signup form
+    pub fn parse_list(s: &str) -> Result<Vec<Tag>, String> {
+        let mut tags = Vec::new();
+        for tag in s.split(',').filter(|t| !t.trim().is_empty()) {
+            let tag = Tag::parse(tag)?;
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        if tags.len() > MAX_TAGS_IN_LIST {
+            return Err(format!("At most {} tags can be given.", MAX_TAGS_IN_LIST));
+        }
+        Ok(tags)
+    }
+}
+
+impl AsRef<str> for Tag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
@@ -0,0 +1,31 @@
+use chrono::Duration;
+
+/// What a subscription token unlocks, stored as text in `subscription_tokens.purpose`.
+///
+/// Tokens in every newsletter are forwarded along with it, so they only manage the
+/// subscription - reading or erasing someone's data takes a `DataAccess` token, emailed on
+/// request and short-lived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    // Confirming, unsubscribing and the preference center
+    Preferences,
+    // `GET /subscriptions/me/data` and `POST /subscriptions/me/erase`
+    DataAccess,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::Preferences => "preferences",
+            TokenPurpose::DataAccess => "data_access",
+        }
+    }
+
+    // How long a token works after it was stored
+    pub fn lifetime(&self) -> Duration {
+        match self {
+            TokenPurpose::Preferences => Duration::days(365),
+            TokenPurpose::DataAccess => Duration::hours(1),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::routes::{SubscriberFilters, SubscriberRecord};
+use actix_web::web::Bytes;
+use futures_util::{Stream, TryStreamExt};
+use sqlx::{PgPool, QueryBuilder};
+
+// Rows are collected into chunks of roughly this many bytes before being handed out
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    // One JSON object per line
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Database(sqlx::Error),
+    Serialization(String),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Database(e) => write!(f, "Failed to read subscribers: {}", e),
+            ExportError::Serialization(e) => write!(f, "Failed to serialize a subscriber: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+// Stream the subscribers matching `filters`, oldest first, already serialized
+// rows are read off a database cursor as the stream is polled, so memory use stays flat
+pub fn export_subscribers(
+    pool: PgPool,
+    filters: SubscriberFilters,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<Bytes, ExportError>> + 'static {
+    async_stream::try_stream! {
+        let mut query = QueryBuilder::new(
+            "SELECT id, email, email_display, name, status, subscribed_at, import_source, policy_flags FROM subscriptions WHERE TRUE",
+        );
+        filters.push_conditions(&mut query);
+        query.push(" ORDER BY subscribed_at, id");
+        let mut rows = query.build_query_as::<SubscriberRecord>().fetch(&pool);
+
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        if format == ExportFormat::Csv {
+            write_csv_row(&mut chunk, &["id", "email", "email_display", "name", "status", "subscribed_at", "import_source", "policy_flags"])?;
+        }
+        while let Some(subscriber) = rows.try_next().await.map_err(ExportError::Database)? {
+            match format {
+                ExportFormat::Csv => write_csv_row(
+                    &mut chunk,
+                    &[
+                        &subscriber.id.to_string(),
+                        &subscriber.email,
+                        &subscriber.email_display,
+                        &subscriber.name,
+                        &subscriber.status,
+                        &subscriber.subscribed_at.to_rfc3339(),
+                        subscriber.import_source.as_deref().unwrap_or_default(),
+                        // One cell, e.g. `disposable;role_account`
+                        &subscriber.policy_flags.join(";"),
+                    ],
+                )?,
+                ExportFormat::Jsonl => {
+                    serde_json::to_writer(&mut chunk, &subscriber)
+                        .map_err(|e| ExportError::Serialization(e.to_string()))?;
+                    chunk.push(b'\n');
+                }
+            }
+            if chunk.len() >= CHUNK_SIZE {
+                yield Bytes::from(std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_SIZE)));
+            }
+        }
+        if !chunk.is_empty() {
+            yield Bytes::from(chunk);
+        }
+    }
+}
+
+fn write_csv_row(buffer: &mut Vec<u8>, fields: &[&str]) -> Result<(), ExportError> {
+    let mut writer = csv::Writer::from_writer(buffer);
+    writer
+        .write_record(fields)
+        .and_then(|_| writer.flush().map_err(csv::Error::from))
+        .map_err(|e| ExportError::Serialization(e.to_string()))
+}
@@ -0,0 +1,340 @@
+use crate::configuration::Settings;
+use crate::domain::IssueStatus;
+use crate::routes::{insert_issue, start_sending, PreparedIssue};
+use crate::templates::{Digest, DigestEntry, EmailTemplate, FeedVariables};
+use chrono::{DateTime, Utc};
+use feed_rs::model::{Entry, Feed, Link, Text};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use uuid::Uuid;
+
+// A feed is fetched again this long after the last time
+const POLL_INTERVAL_MINUTES: i64 = 15;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+// Summaries are often the whole post, a digest only needs a teaser
+const MAX_SUMMARY_LENGTH: usize = 500;
+
+// Keep turning new feed entries into issues - runs next to the HTTP server in `serve`
+pub async fn run_poller_until_stopped(configuration: Settings) -> Result<(), std::io::Error> {
+    let pool = PgPool::connect_lazy(&configuration.database.connection_string())
+        .expect("Failed to connect to Postgres.");
+    poller_loop(pool, http_client()).await
+}
+
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .expect("Failed to build the feed client.")
+}
+
+async fn poller_loop(pool: PgPool, http_client: reqwest::Client) -> Result<(), std::io::Error> {
+    loop {
+        // Errors are logged by `poll_due_feeds`, we try again on the next tick
+        let _ = poll_due_feeds(&pool, &http_client).await;
+        tokio::time::sleep(Duration::from_secs(60)).await;
+    }
+}
+
+// Fetch every feed that was not polled for a while, returns how many issues were made
+// a feed that cannot be fetched or parsed is skipped, with the error kept on its source
+#[tracing::instrument(skip_all, err)]
+pub async fn poll_due_feeds(
+    pool: &PgPool,
+    http_client: &reqwest::Client,
+) -> Result<u64, sqlx::Error> {
+    let mut issues = 0;
+    while let Some(issue) = try_poll_due_feed(pool, http_client).await? {
+        if issue.is_some() {
+            issues += 1;
+        }
+    }
+    Ok(issues)
+}
+
+struct FeedSource {
+    id: Uuid,
+    list_id: Uuid,
+    list_name: String,
+    url: String,
+    template_html: String,
+    template_text: String,
+    layout_id: Uuid,
+    auto_send: bool,
+    last_polled_at: Option<DateTime<Utc>>,
+}
+
+// `None` once no feed is due, the id of the issue made from the feed otherwise, if any
+// like the scheduler, the row lock makes one replica claim the feed and the others skip it -
+// claiming moves `next_poll_at`, so no lock is held while the feed is fetched
+async fn try_poll_due_feed(
+    pool: &PgPool,
+    http_client: &reqwest::Client,
+) -> Result<Option<Option<Uuid>>, sqlx::Error> {
+    let now = Utc::now();
+    let claimed = sqlx::query_scalar!(
+        r#"
+        UPDATE feed_sources
+        SET next_poll_at = $2
+        WHERE id = (
+            SELECT id FROM feed_sources
+            WHERE next_poll_at <= $1
+            ORDER BY next_poll_at
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+        )
+        RETURNING id
+        "#,
+        now,
+        now + chrono::Duration::minutes(POLL_INTERVAL_MINUTES)
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(feed_source_id) = claimed else {
+        return Ok(None);
+    };
+    let Some(source) = get_feed_source(pool, feed_source_id).await? else {
+        return Ok(Some(None));
+    };
+    let feed = match fetch_feed(http_client, &source.url).await {
+        Ok(feed) => feed,
+        // Tried again at `next_poll_at` - `last_polled_at` stays as it was, so a feed that
+        // was never read is still seeded by the first poll that reads it
+        Err(e) => {
+            tracing::warn!(feed_source_id = %source.id, error = %e, "Failed to poll a feed.");
+            sqlx::query!(
+                "UPDATE feed_sources SET last_error = $2 WHERE id = $1",
+                source.id,
+                e
+            )
+            .execute(pool)
+            .await?;
+            return Ok(Some(None));
+        }
+    };
+
+    let mut transaction = pool.begin().await?;
+    // It may have been removed, or pointed elsewhere, while we were fetching it
+    let last_polled_at = sqlx::query!(
+        "SELECT last_polled_at FROM feed_sources WHERE id = $1 AND url = $2 FOR UPDATE",
+        source.id,
+        source.url
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(last_polled_at) = last_polled_at else {
+        return Ok(Some(None));
+    };
+    let source = FeedSource {
+        last_polled_at: last_polled_at.last_polled_at,
+        ..source
+    };
+    let issue = process_feed(&mut transaction, &source, feed).await?;
+    sqlx::query!(
+        r#"
+        UPDATE feed_sources
+        SET last_polled_at = $2, last_error = NULL
+        WHERE id = $1
+        "#,
+        source.id,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(Some(issue))
+}
+
+async fn get_feed_source(
+    pool: &PgPool,
+    feed_source_id: Uuid,
+) -> Result<Option<FeedSource>, sqlx::Error> {
+    sqlx::query_as!(
+        FeedSource,
+        r#"
+        SELECT feed_sources.id, list_id, lists.name AS list_name, url,
+            email_templates.html AS template_html, email_templates.text AS template_text,
+            layout_id, auto_send, last_polled_at
+        FROM feed_sources
+        JOIN lists ON lists.id = feed_sources.list_id
+        JOIN email_templates ON email_templates.id = feed_sources.template_id
+        WHERE feed_sources.id = $1
+        "#,
+        feed_source_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn fetch_feed(http_client: &reqwest::Client, url: &str) -> Result<Feed, String> {
+    let body = http_client
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| format!("Failed to fetch the feed: {}", e))?
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to fetch the feed: {}", e))?;
+    feed_rs::parser::parse(&body[..]).map_err(|e| format!("The feed does not parse: {}", e))
+}
+
+// Make a digest issue of the entries we have not seen yet, and record them so they never
+// make another one - the first poll only records what is already in the feed
+async fn process_feed(
+    transaction: &mut Transaction<'_, Postgres>,
+    source: &FeedSource,
+    feed: Feed,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let guids: Vec<String> = feed.entries.iter().map(|entry| entry.id.clone()).collect();
+    let seen = sqlx::query_scalar!(
+        r#"
+        SELECT guid FROM feed_entries
+        WHERE feed_source_id = $1 AND guid = ANY($2)
+        "#,
+        source.id,
+        &guids
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    let mut new_entries: Vec<&Entry> = Vec::new();
+    for entry in &feed.entries {
+        let is_new = !seen.contains(&entry.id) && !new_entries.iter().any(|e| e.id == entry.id);
+        if is_new {
+            new_entries.push(entry);
+        }
+    }
+    if new_entries.is_empty() {
+        return Ok(None);
+    }
+
+    let digest = Digest {
+        feed: FeedVariables {
+            title: feed
+                .title
+                .as_ref()
+                .map(plain_text)
+                .filter(|title| !title.is_empty())
+                .unwrap_or_else(|| source.list_name.clone()),
+            url: alternate_link(&feed.links),
+        },
+        entries: new_entries
+            .iter()
+            .map(|entry| digest_entry(entry))
+            .collect(),
+    };
+    let newsletter_issue_id = match source.last_polled_at {
+        None => None,
+        Some(_) => {
+            let issue = PreparedIssue {
+                title: digest_title(&digest),
+                content: EmailTemplate {
+                    html: source.template_html.clone(),
+                    text: source.template_text.clone(),
+                },
+                markdown: None,
+                layout_id: source.layout_id,
+                list_ids: vec![source.list_id],
+                segment: None,
+                hide_from_archive: false,
+                digest: Some(digest.clone()),
+            };
+            let newsletter_issue_id = insert_issue(transaction, &issue, IssueStatus::Draft).await?;
+            if source.auto_send {
+                start_sending(transaction, newsletter_issue_id).await?;
+            }
+            tracing::info!(
+                %newsletter_issue_id,
+                entries = digest.entries.len(),
+                auto_send = source.auto_send,
+                "Made a digest issue from a feed."
+            );
+            Some(newsletter_issue_id)
+        }
+    };
+    let guids: Vec<String> = new_entries.iter().map(|entry| entry.id.clone()).collect();
+    let titles: Vec<String> = digest
+        .entries
+        .iter()
+        .map(|entry| entry.title.clone())
+        .collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO feed_entries (feed_source_id, guid, title, newsletter_issue_id, processed_at)
+        SELECT $1, guid, title, $4, $5 FROM UNNEST($2::text[], $3::text[]) AS entries(guid, title)
+        "#,
+        source.id,
+        &guids,
+        &titles,
+        newsletter_issue_id,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(newsletter_issue_id)
+}
+
+fn digest_entry(entry: &Entry) -> DigestEntry {
+    let summary = entry
+        .summary
+        .as_ref()
+        .map(plain_text)
+        .or_else(|| {
+            let content = entry.content.as_ref()?;
+            let body = content.body.as_deref()?;
+            Some(to_plain_text(body, content.content_type.subty().as_str()))
+        })
+        .map(|summary| truncate(&summary, MAX_SUMMARY_LENGTH))
+        .filter(|summary| !summary.is_empty());
+    DigestEntry {
+        title: entry
+            .title
+            .as_ref()
+            .map(plain_text)
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| "Untitled".into()),
+        url: alternate_link(&entry.links),
+        summary,
+        published_at: entry.published.or(entry.updated).map(|at| at.to_rfc3339()),
+    }
+}
+
+// The title of the entry when there is one, e.g. `Engineering blog: 3 new posts` otherwise
+fn digest_title(digest: &Digest) -> String {
+    match digest.entries.as_slice() {
+        [entry] => entry.title.clone(),
+        entries => format!("{}: {} new posts", digest.feed.title, entries.len()),
+    }
+}
+
+// Links without a `rel` are alternates too
+fn alternate_link(links: &[Link]) -> Option<String> {
+    links
+        .iter()
+        .find(|link| link.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+        .map(|link| link.href.clone())
+}
+
+fn plain_text(text: &Text) -> String {
+    to_plain_text(&text.content, text.content_type.subty().as_str())
+}
+
+// Feeds may carry HTML in titles and summaries, templates escape what they render themselves
+// `subtype` is that of the media type, `html` for `text/html`
+fn to_plain_text(content: &str, subtype: &str) -> String {
+    let text = if matches!(subtype, "html" | "xhtml") {
+        let text = ammonia::Builder::empty().clean(content).to_string();
+        html_escape::decode_html_entities(&text).into_owned()
+    } else {
+        content.to_string()
+    };
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn truncate(text: &str, max_length: usize) -> String {
+    match text.char_indices().nth(max_length) {
+        None => text.into(),
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+    }
+}
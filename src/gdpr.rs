@@ -0,0 +1,297 @@
+use crate::domain::SubscriptionStatus;
+use crate::routes::SubscriberRecord;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// Who asked for the data access or the erasure
+#[derive(Debug, Clone, Copy)]
+pub enum Actor {
+    // The subscriber, proven by a subscription token
+    Subscriber,
+    // One of us, e.g. handling a request that came in by post
+    Admin(Uuid),
+}
+
+impl Actor {
+    fn as_audit_string(&self) -> String {
+        match self {
+            Actor::Subscriber => "subscriber".into(),
+            Actor::Admin(user_id) => format!("admin:{}", user_id),
+        }
+    }
+}
+
+// Everything we store about a subscriber
+// every table holding personal data has to show up here, and in `erase_subscriber`
+#[derive(serde::Serialize)]
+pub struct SubscriberData {
+    pub subscription: SubscriberRecord,
+    // Values of custom fields, e.g. `{"company": "Acme"}`
+    pub attributes: serde_json::Value,
+    pub list_memberships: Vec<ListMembership>,
+    pub tags: Vec<String>,
+    // Newsletter issues we sent, or tried to send, them
+    pub deliveries: Vec<Delivery>,
+    // When they opened our emails and what they clicked
+    pub tracking_events: Vec<TrackingEvent>,
+    // Bounces, complaints and deliveries the email provider reported
+    pub email_events: Vec<EmailEvent>,
+    // Their previous data access and erasure requests
+    pub privacy_requests: Vec<PrivacyRequest>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ListMembership {
+    pub list: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub unsubscribed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Delivery {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct TrackingEvent {
+    pub newsletter_issue_id: Uuid,
+    pub kind: String,
+    pub url: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct EmailEvent {
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub received_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PrivacyRequest {
+    pub action: String,
+    pub performed_by: String,
+    pub performed_at: DateTime<Utc>,
+}
+
+// `Ok(None)` when there is no such subscriber
+#[tracing::instrument(name = "Collecting subscriber data", skip(pool))]
+pub async fn collect_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    actor: Actor,
+) -> Result<Option<SubscriberData>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscription = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT id, email, email_display, name, status, subscribed_at, import_source,
+            policy_flags
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(subscription) = subscription else {
+        return Ok(None);
+    };
+    let attributes = sqlx::query_scalar!(
+        "SELECT attributes FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    let list_memberships = sqlx::query_as!(
+        ListMembership,
+        r#"
+        SELECT lists.slug AS list, list_memberships.status, list_memberships.subscribed_at,
+            list_memberships.confirmed_at, list_memberships.unsubscribed_at
+        FROM list_memberships
+        JOIN lists ON lists.id = list_memberships.list_id
+        WHERE list_memberships.subscriber_id = $1
+        ORDER BY list_memberships.subscribed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let tags = sqlx::query_scalar!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag",
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let deliveries = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT issue_deliveries.newsletter_issue_id, newsletter_issues.title,
+            issue_deliveries.status, issue_deliveries.attempted_at
+        FROM issue_deliveries
+        JOIN newsletter_issues USING (newsletter_issue_id)
+        WHERE issue_deliveries.subscriber_id = $1
+        ORDER BY issue_deliveries.attempted_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let tracking_events = sqlx::query_as!(
+        TrackingEvent,
+        r#"
+        SELECT newsletter_issue_id, kind, url, occurred_at
+        FROM tracking_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let email_events = sqlx::query_as!(
+        EmailEvent,
+        r#"
+        SELECT kind, payload, received_at
+        FROM email_events
+        WHERE subscriber_id = $1
+        ORDER BY received_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    record_audit_entry(&mut transaction, subscriber_id, "access", actor).await?;
+    let privacy_requests = sqlx::query_as!(
+        PrivacyRequest,
+        r#"
+        SELECT action, performed_by, performed_at
+        FROM gdpr_audit_log
+        WHERE subscriber_id = $1
+        ORDER BY performed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(Some(SubscriberData {
+        subscription,
+        attributes,
+        list_memberships,
+        tags,
+        deliveries,
+        tracking_events,
+        email_events,
+        privacy_requests,
+    }))
+}
+
+// Irreversibly remove the personal data of a subscriber
+// the `subscriptions` row stays, anonymised, so counts of sign-ups over time do not change
+// returns `false` when there is no such subscriber
+#[tracing::instrument(name = "Erasing subscriber data", skip(pool))]
+pub async fn erase_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    actor: Actor,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // The placeholder address keeps the unique index on `lower(email)` satisfied
+    let erased = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET email = 'erased-' || id || '@erased.invalid',
+            email_display = 'erased-' || id || '@erased.invalid',
+            name = '',
+            status = $2,
+            import_source = NULL,
+            attributes = '{}',
+            policy_flags = '{}'
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        SubscriptionStatus::Erased.as_str(),
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected()
+        > 0;
+    if !erased {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscriber_tags WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // Opens and clicks stay with the anonymised row, so the stats of past issues do not change
+    // Bounces and complaints too, without the address and the provider's payload that repeats it
+    sqlx::query!(
+        "UPDATE email_events SET email = NULL, payload = '{}' WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // Nothing queued for them goes out any more
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = $2, unsubscribed_at = COALESCE(unsubscribed_at, $3)
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed.as_str(),
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    record_audit_entry(&mut transaction, subscriber_id, "erasure", actor).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+async fn record_audit_entry(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    action: &str,
+    actor: Actor,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO gdpr_audit_log (id, subscriber_id, action, performed_by, performed_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        action,
+        actor.as_audit_string(),
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
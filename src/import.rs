@@ -0,0 +1,486 @@
+use crate::domain::{NewSubscriber, SubscriptionStatus};
+use crate::routes::{get_default_list, get_list_by_slug, MailingList};
+use crate::signup_policy::{PolicyFlag, SignupPolicy};
+use crate::suppression::get_suppressed_emails;
+use actix_web::web::Bytes;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// Rows are written to the database in batches of this size
+const BATCH_SIZE: usize = 1000;
+
+// What to do with a row whose email already belongs to a subscriber
+#[derive(serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    // Leave the existing subscriber untouched
+    #[default]
+    Skip,
+    // Overwrite the existing subscriber's name (and confirm them if the import is confirmed)
+    // anyone who unsubscribed, bounced or complained is left alone
+    Update,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    // Header of the CSV column holding the email address
+    pub email_column: String,
+    // Header of the CSV column holding the name
+    pub name_column: String,
+    pub on_duplicate: DuplicatePolicy,
+    // Imported subscribers are `confirmed` instead of `pending_confirmation`
+    pub mark_confirmed: bool,
+    // Stored in `subscriptions.import_source`
+    pub source: String,
+    // Slug of the list the subscribers are added to, the default list when `None`
+    pub list: Option<String>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            email_column: "email".into(),
+            name_column: "name".into(),
+            on_duplicate: DuplicatePolicy::Skip,
+            mark_confirmed: false,
+            source: "csv".into(),
+            list: None,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct ImportReport {
+    // New subscribers
+    pub accepted: u64,
+    // Existing subscribers overwritten because of `DuplicatePolicy::Update`
+    pub updated: u64,
+    // Rows skipped because the email already exists, in the database or earlier in the file
+    pub duplicates: Vec<ImportedRow>,
+    pub rejected: Vec<ImportedRow>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ImportedRow {
+    // Line in the CSV file, the header is line 1
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    // The file can not be imported at all, e.g. a mapped column is missing
+    InvalidFile(String),
+    // There is no list with this slug
+    UnknownList(String),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::InvalidFile(e) => write!(f, "{}", e),
+            ImportError::UnknownList(slug) => write!(f, "There is no list with slug `{}`.", slug),
+            ImportError::Database(e) => write!(f, "Failed to store imported subscribers: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for ImportError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+// A row as parsed off the CSV, before it is checked against the database
+struct ParsedRow {
+    line: u64,
+    subscriber: Result<NewSubscriber, String>,
+}
+
+// A row the signup policy accepted, waiting for its batch to be written
+struct AcceptedRow {
+    line: u64,
+    subscriber: NewSubscriber,
+    policy_flags: Vec<PolicyFlag>,
+}
+
+// Import subscribers from a CSV stream, with the same signup policy as `subscribe`
+// everything is written in a single transaction, so a failure leaves the database untouched
+#[tracing::instrument(name = "Importing subscribers", skip(pool, reader, signup_policy))]
+pub async fn import_subscribers<R>(
+    pool: &PgPool,
+    reader: R,
+    signup_policy: &SignupPolicy,
+    options: ImportOptions,
+) -> Result<ImportReport, ImportError>
+where
+    R: Read + Send + 'static,
+{
+    // `csv` is a blocking parser - run it on its own thread and receive rows as they are parsed
+    let (row_sender, mut rows) = mpsc::channel(BATCH_SIZE);
+    let columns = (options.email_column.clone(), options.name_column.clone());
+    let parser = tokio::task::spawn_blocking(move || parse_rows(reader, columns, row_sender));
+
+    let mut report = ImportReport::default();
+    let mut transaction = pool.begin().await?;
+    let list = match &options.list {
+        Some(slug) => get_list_by_slug(&mut *transaction, slug)
+            .await?
+            .ok_or_else(|| ImportError::UnknownList(slug.clone()))?,
+        None => get_default_list(&mut *transaction).await?,
+    };
+    let mut seen_emails = HashSet::new();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while let Some(row) = rows.recv().await {
+        match row.subscriber {
+            Err(reason) => report.rejected.push(ImportedRow {
+                line: row.line,
+                reason,
+            }),
+            Ok(subscriber) => {
+                let policy_flags = match signup_policy.evaluate(&list, &subscriber.email) {
+                    Ok(policy_flags) => policy_flags,
+                    Err(reason) => {
+                        report.rejected.push(ImportedRow {
+                            line: row.line,
+                            reason,
+                        });
+                        continue;
+                    }
+                };
+                // Same rule as the unique index - emails differing by case are the same subscriber
+                if !seen_emails.insert(subscriber.email.as_ref().to_lowercase()) {
+                    report.duplicates.push(ImportedRow {
+                        line: row.line,
+                        reason: "The email appears earlier in the file.".into(),
+                    });
+                    continue;
+                }
+                batch.push(AcceptedRow {
+                    line: row.line,
+                    subscriber,
+                    policy_flags,
+                });
+                if batch.len() == BATCH_SIZE {
+                    insert_batch(&mut transaction, &mut batch, &list, &options, &mut report)
+                        .await?;
+                }
+            }
+        }
+    }
+    // The channel is closed once the parser is done, find out how it went
+    parser
+        .await
+        .map_err(|e| ImportError::InvalidFile(format!("Failed to parse the file: {}", e)))??;
+    insert_batch(&mut transaction, &mut batch, &list, &options, &mut report).await?;
+    transaction.commit().await?;
+    // Rows turned away by the database come in per batch, put them back in the order of the file
+    report.duplicates.sort_by_key(|row| row.line);
+    report.rejected.sort_by_key(|row| row.line);
+
+    tracing::info!(
+        accepted = report.accepted,
+        updated = report.updated,
+        duplicates = report.duplicates.len(),
+        rejected = report.rejected.len(),
+        "Subscribers imported"
+    );
+    Ok(report)
+}
+
+fn parse_rows<R: Read>(
+    reader: R,
+    (email_column, name_column): (String, String),
+    rows: mpsc::Sender<ParsedRow>,
+) -> Result<(), ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let headers = reader
+        .headers()
+        .map_err(|e| ImportError::InvalidFile(format!("Failed to read the CSV header: {}", e)))?
+        .clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case(name))
+            .ok_or_else(|| ImportError::InvalidFile(format!("The CSV has no `{}` column.", name)))
+    };
+    let email_index = column(&email_column)?;
+    let name_index = column(&name_column)?;
+
+    for record in reader.records() {
+        let row = match record {
+            Ok(record) => ParsedRow {
+                line: record.position().map(|p| p.line()).unwrap_or_default(),
+                subscriber: match (record.get(email_index), record.get(name_index)) {
+                    (Some(email), Some(name)) => NewSubscriber::parse(email.into(), name.into()),
+                    _ => Err("The row is missing the email or the name.".into()),
+                },
+            },
+            // Reading the underlying stream failed - we can not tell where the next row starts
+            Err(e) if e.is_io_error() => {
+                return Err(ImportError::InvalidFile(format!(
+                    "Failed to read the CSV: {}",
+                    e
+                )))
+            }
+            Err(e) => ParsedRow {
+                line: e.position().map(|p| p.line()).unwrap_or_default(),
+                subscriber: Err(format!("The row is not valid CSV: {}", e)),
+            },
+        };
+        // The receiving end is gone when the import failed - no point in parsing the rest
+        if rows.blocking_send(row).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Saving a batch of imported subscribers", skip_all, fields(batch_size = batch.len()))]
+async fn insert_batch(
+    transaction: &mut Transaction<'_, Postgres>,
+    batch: &mut Vec<AcceptedRow>,
+    list: &MailingList,
+    options: &ImportOptions,
+    report: &mut ImportReport,
+) -> Result<(), sqlx::Error> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    // `subscribe` ignores them without a word, but whoever imports should know
+    let addresses: Vec<String> = batch
+        .iter()
+        .map(|row| row.subscriber.email.as_ref().to_string())
+        .collect();
+    let suppressed = get_suppressed_emails(&mut **transaction, &addresses).await?;
+    batch.retain(|row| {
+        if !suppressed.contains(&row.subscriber.email.as_ref().to_lowercase()) {
+            return true;
+        }
+        report.rejected.push(ImportedRow {
+            line: row.line,
+            reason: "The address is on the suppression list.".into(),
+        });
+        false
+    });
+    let status = if options.mark_confirmed {
+        SubscriptionStatus::Confirmed
+    } else {
+        SubscriptionStatus::PendingConfirmation
+    };
+    let ids: Vec<Uuid> = batch.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = batch
+        .iter()
+        .map(|row| row.subscriber.email.as_ref().to_string())
+        .collect();
+    let email_displays: Vec<String> = batch
+        .iter()
+        .map(|row| row.subscriber.email.display().to_string())
+        .collect();
+    let names: Vec<String> = batch
+        .iter()
+        .map(|row| row.subscriber.name.clone())
+        .collect();
+    // Arrays of arrays must all have the same length in Postgres, so each row's flags are joined
+    let policy_flags: Vec<String> = batch
+        .iter()
+        .map(|row| {
+            let flags: Vec<&str> = row.policy_flags.iter().map(|f| f.as_str()).collect();
+            flags.join(",")
+        })
+        .collect();
+
+    // Only one of the two statements runs; both report the emails they wrote
+    // `xmax = 0` tells a freshly inserted row apart from one updated by `ON CONFLICT`
+    let written: Vec<(Uuid, String, bool)> = match options.on_duplicate {
+        DuplicatePolicy::Skip => sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, email_display, name, subscribed_at, status, import_source, confirmed_at, policy_flags)
+            SELECT id, email, email_display, name, $5, $6, $7, CASE WHEN $6 = 'confirmed' THEN $5::timestamptz END,
+                string_to_array(policy_flags, ',')
+            FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[], $8::text[]) AS t(id, email, email_display, name, policy_flags)
+            ON CONFLICT ((lower(email))) DO NOTHING
+            RETURNING id, email
+            "#,
+            &ids,
+            &emails,
+            &email_displays,
+            &names,
+            Utc::now(),
+            status.as_str(),
+            options.source,
+            &policy_flags,
+        )
+        .fetch_all(&mut **transaction)
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.email, true))
+        .collect(),
+        DuplicatePolicy::Update => sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, email_display, name, subscribed_at, status, import_source, confirmed_at, policy_flags)
+            SELECT id, email, email_display, name, $5, $6, $7, CASE WHEN $6 = 'confirmed' THEN $5::timestamptz END,
+                string_to_array(policy_flags, ',')
+            FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[], $10::text[]) AS t(id, email, email_display, name, policy_flags)
+            ON CONFLICT ((lower(email))) DO UPDATE
+            SET name = EXCLUDED.name,
+                email_display = EXCLUDED.email_display,
+                -- Added to the flags from earlier signups, as `subscribe` does
+                policy_flags = ARRAY(SELECT DISTINCT unnest(subscriptions.policy_flags || EXCLUDED.policy_flags) ORDER BY 1),
+                -- Never downgrade someone who already confirmed
+                status = CASE WHEN EXCLUDED.status = 'confirmed' THEN 'confirmed' ELSE subscriptions.status END,
+                confirmed_at = COALESCE(subscriptions.confirmed_at, EXCLUDED.confirmed_at)
+            -- An import must not subscribe again anyone who left, bounced or complained
+            WHERE subscriptions.status IN ($8, $9)
+            RETURNING id, email, (xmax = 0) AS "inserted!"
+            "#,
+            &ids,
+            &emails,
+            &email_displays,
+            &names,
+            Utc::now(),
+            status.as_str(),
+            options.source,
+            SubscriptionStatus::PendingConfirmation.as_str(),
+            SubscriptionStatus::Confirmed.as_str(),
+            &policy_flags,
+        )
+        .fetch_all(&mut **transaction)
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.email, row.inserted))
+        .collect(),
+    };
+
+    let written_ids: Vec<Uuid> = written.iter().map(|(id, _, _)| *id).collect();
+    add_list_memberships(transaction, list, &written_ids, status).await?;
+
+    let mut written_emails = HashSet::new();
+    for (_, email, inserted) in written {
+        if inserted {
+            report.accepted += 1;
+        } else {
+            report.updated += 1;
+        }
+        written_emails.insert(email.to_lowercase());
+    }
+    // An existing row may have a different case, but the insert returns what is stored
+    // so compare the lowercased forms to find the rows that were skipped
+    let skipped: Vec<(u64, String)> = batch
+        .drain(..)
+        .map(|row| (row.line, row.subscriber.email.as_ref().to_lowercase()))
+        .filter(|(_, email)| !written_emails.contains(email))
+        .collect();
+    let left_alone = match options.on_duplicate {
+        DuplicatePolicy::Skip => HashMap::new(),
+        DuplicatePolicy::Update => get_statuses_left_alone(transaction, &skipped).await?,
+    };
+    for (line, email) in skipped {
+        let reason = match left_alone.get(&email) {
+            Some(status) => format!(
+                "The subscriber with this email is {}, so it was left as it is.",
+                status
+            ),
+            None => "A subscriber with this email already exists.".into(),
+        };
+        report.duplicates.push(ImportedRow { line, reason });
+    }
+    Ok(())
+}
+
+// The status of existing subscribers an update skipped, by lowercased email
+async fn get_statuses_left_alone(
+    transaction: &mut Transaction<'_, Postgres>,
+    skipped: &[(u64, String)],
+) -> Result<HashMap<String, String>, sqlx::Error> {
+    let emails: Vec<String> = skipped.iter().map(|(_, email)| email.clone()).collect();
+    let statuses = sqlx::query!(
+        r#"
+        SELECT lower(email) AS "email!", status FROM subscriptions
+        WHERE lower(email) = ANY($1) AND status NOT IN ($2, $3)
+        "#,
+        &emails,
+        SubscriptionStatus::PendingConfirmation.as_str(),
+        SubscriptionStatus::Confirmed.as_str(),
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    Ok(statuses
+        .into_iter()
+        .map(|row| (row.email, row.status))
+        .collect())
+}
+
+// Rows skipped as duplicates are left off the list too
+// and only a pending membership is confirmed - one that was unsubscribed from stays that way
+async fn add_list_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    list: &MailingList,
+    subscriber_ids: &[Uuid],
+    status: SubscriptionStatus,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at, confirmed_at)
+        SELECT $1, subscriber_id, $3, $4, CASE WHEN $3 = 'confirmed' THEN $4::timestamptz END
+        FROM UNNEST($2::uuid[]) AS subscriber_id
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = CASE
+                WHEN EXCLUDED.status = 'confirmed' AND list_memberships.status = $5 THEN 'confirmed'
+                ELSE list_memberships.status
+            END,
+            confirmed_at = CASE
+                WHEN list_memberships.status = $5 THEN EXCLUDED.confirmed_at
+                ELSE list_memberships.confirmed_at
+            END
+        "#,
+        list.id,
+        subscriber_ids,
+        status.as_str(),
+        Utc::now(),
+        SubscriptionStatus::PendingConfirmation.as_str(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+// Adapts the chunks of a streamed request body to `std::io::Read` for the blocking CSV parser
+// the parser thread waits on the channel while the request handler forwards the chunks
+pub struct ChannelReader {
+    chunks: mpsc::Receiver<std::io::Result<Bytes>>,
+    current: Bytes,
+}
+
+impl ChannelReader {
+    pub fn new(chunks: mpsc::Receiver<std::io::Result<Bytes>>) -> Self {
+        Self {
+            chunks,
+            current: Bytes::new(),
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = chunk?,
+                // The sender is dropped at the end of the body
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current.split_to(n));
+        Ok(n)
+    }
+}
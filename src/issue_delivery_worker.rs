@@ -0,0 +1,416 @@
+use crate::configuration::Settings;
+use crate::domain::TokenPurpose;
+use crate::email_client::{EmailClient, EmailHeader};
+use crate::routes::{generate_subscription_token, preferences_path, store_token};
+use crate::suppression::is_suppressed;
+use crate::templates::{render, Digest, EmailTemplate, ListVariables, MergeVariables};
+use crate::tracking::add_tracking;
+use chrono::Utc;
+use serde_json::{Map, Value};
+use sqlx::types::Json;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+// A failing delivery is attempted this many times before we give up on it
+const MAX_RETRIES: i16 = 5;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+// Keep taking deliveries off the queue - runs next to the HTTP server in `serve`
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), std::io::Error> {
+    let pool = PgPool::connect_lazy(&configuration.database.connection_string())
+        .expect("Failed to connect to Postgres.");
+    let email_client = configuration.email_client.client();
+    worker_loop(
+        pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.application.postal_address,
+        configuration.application.hmac_secret,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    postal_address: String,
+    hmac_secret: String,
+) -> Result<(), std::io::Error> {
+    loop {
+        let outcome = try_execute_task(
+            &pool,
+            &email_client,
+            &base_url,
+            &postal_address,
+            &hmac_secret,
+        )
+        .await;
+        match outcome {
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            // e.g. the database is down - back off a little
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+// Take one due delivery off the queue and send it
+// the row stays locked until we are done, so several workers never send the same email
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id=tracing::field::Empty, subscriber_id=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    postal_address: &str,
+    hmac_secret: &str,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    let Some((mut transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record("subscriber_id", display(task.subscriber_id));
+
+    let mut tracked = false;
+    let status = match get_recipient(&mut transaction, &task).await? {
+        // They may have unsubscribed, or asked to be erased, since the issue was published
+        None => "skipped",
+        // Checked on every send, so a suppression added mid-issue stops the rest of it
+        Some(recipient) if is_suppressed(&mut *transaction, &recipient.email).await? => {
+            "suppressed"
+        }
+        Some(recipient) => {
+            let issue = get_issue(&mut *transaction, task.newsletter_issue_id)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+            let subscription_token = generate_subscription_token();
+            store_token(
+                &mut *transaction,
+                task.subscriber_id,
+                &subscription_token,
+                TokenPurpose::Preferences,
+            )
+            .await?;
+            tracked = recipient.tracking;
+            let mut email = match personalize(
+                issue,
+                recipient,
+                base_url,
+                &subscription_token,
+                postal_address,
+            ) {
+                Ok(email) => email,
+                // Templates are compiled before they are saved, but e.g. a filter can still fail on a value
+                Err(e) => {
+                    tracing::error!(error.cause_chain = ?e, "Failed to render the issue, giving up.");
+                    record_delivery(&mut transaction, &task, "failed", false).await?;
+                    delete_task(transaction, &task).await?;
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+            };
+            if tracked {
+                email.html = add_tracking(
+                    &email.html,
+                    base_url,
+                    hmac_secret,
+                    task.newsletter_issue_id,
+                    task.subscriber_id,
+                );
+            }
+            // Mail clients show their own unsubscribe button, which posts to the link (RFC 8058)
+            let list_unsubscribe = format!("<{}>", email.unsubscribe_url);
+            let headers = [
+                EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: &list_unsubscribe,
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click",
+                },
+            ];
+            match email_client
+                .send_email_with_headers(
+                    &email.recipient,
+                    &email.subject,
+                    &email.html,
+                    &email.text,
+                    &headers,
+                )
+                .await
+            {
+                Ok(()) => "sent",
+                Err(e) if task.n_retries + 1 < MAX_RETRIES => {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        "Failed to deliver issue to a confirmed subscriber, retrying later.",
+                    );
+                    retry_later(transaction, &task).await?;
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        "Failed to deliver issue to a confirmed subscriber, giving up.",
+                    );
+                    "failed"
+                }
+            }
+        }
+    };
+    record_delivery(&mut transaction, &task, status, tracked).await?;
+    delete_task(transaction, &task).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+pub struct PersonalizedEmail {
+    pub recipient: String,
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+    pub unsubscribe_url: String,
+}
+
+pub fn unsubscribe_url(base_url: &str, list_slug: &str, subscription_token: &str) -> String {
+    format!(
+        "{}/lists/{}/subscriptions/unsubscribe?subscription_token={}",
+        base_url, list_slug, subscription_token
+    )
+}
+
+pub fn archive_url(base_url: &str, issue_slug: &str) -> String {
+    format!("{}/archive/{}", base_url, issue_slug)
+}
+
+// Render the issue, in its layout, with the recipient's merge variables
+pub fn personalize(
+    issue: NewsletterIssue,
+    recipient: Recipient,
+    base_url: &str,
+    subscription_token: &str,
+    postal_address: &str,
+) -> Result<PersonalizedEmail, minijinja::Error> {
+    let unsubscribe_url = unsubscribe_url(base_url, &recipient.list_slug, subscription_token);
+    let variables = MergeVariables {
+        name: Some(recipient.name),
+        email: Some(recipient.email_display.clone()),
+        attribute: recipient.attributes.0,
+        title: issue.title.clone(),
+        unsubscribe_url: Some(unsubscribe_url.clone()),
+        preferences_url: Some(format!(
+            "{}{}",
+            base_url,
+            preferences_path(subscription_token)
+        )),
+        view_in_browser_url: issue.archive_slug.map(|slug| archive_url(base_url, &slug)),
+        list: ListVariables {
+            name: recipient.list_name,
+            slug: recipient.list_slug,
+        },
+        postal_address: postal_address.into(),
+        digest: issue.digest.map(|digest| digest.0),
+    };
+    let content = EmailTemplate {
+        html: issue.html_content,
+        text: issue.text_content,
+    };
+    let layout = EmailTemplate {
+        html: issue.layout_html,
+        text: issue.layout_text,
+    };
+    let email = render(&layout, &content, &variables)?;
+    Ok(PersonalizedEmail {
+        recipient: recipient.email_display,
+        subject: issue.title,
+        html: email.html,
+        text: email.text,
+        unsubscribe_url,
+    })
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, Task)>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT newsletter_issue_id, subscriber_id, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+/// Who an issue is rendered for.
+pub struct Recipient {
+    // Normalised, for the suppression list
+    pub email: String,
+    // As they typed it, what the issue is sent to
+    pub email_display: String,
+    pub name: String,
+    pub attributes: Json<Map<String, Value>>,
+    // One of the issue's lists they are confirmed on, for the unsubscribe link
+    pub list_slug: String,
+    pub list_name: String,
+    // Whether opens and clicks are tracked - not if any of their lists for the issue opted out
+    pub tracking: bool,
+}
+
+// `None` unless the subscriber is still confirmed, on at least one of the issue's lists
+async fn get_recipient(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+) -> Result<Option<Recipient>, sqlx::Error> {
+    sqlx::query_as!(
+        Recipient,
+        r#"
+        SELECT subscriptions.email, subscriptions.email_display, subscriptions.name,
+            subscriptions.attributes AS "attributes: Json<Map<String, Value>>",
+            lists.slug AS list_slug, lists.name AS list_name,
+            NOT EXISTS (
+                SELECT 1 FROM list_memberships AS memberships
+                JOIN newsletter_issue_lists AS issue_lists
+                    ON issue_lists.list_id = memberships.list_id
+                JOIN lists AS untracked ON untracked.id = memberships.list_id
+                WHERE memberships.subscriber_id = subscriptions.id
+                    AND issue_lists.newsletter_issue_id = $2
+                    AND memberships.status = 'confirmed'
+                    AND NOT untracked.tracking
+            ) AS "tracking!"
+        FROM subscriptions
+        JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+        JOIN newsletter_issue_lists ON newsletter_issue_lists.list_id = list_memberships.list_id
+        JOIN lists ON lists.id = list_memberships.list_id
+        WHERE subscriptions.id = $1
+            AND newsletter_issue_lists.newsletter_issue_id = $2
+            AND subscriptions.status = 'confirmed'
+            AND list_memberships.status = 'confirmed'
+        ORDER BY lists.slug
+        LIMIT 1
+        "#,
+        task.subscriber_id,
+        task.newsletter_issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+/// An issue and the layout it is wrapped in, as stored.
+pub struct NewsletterIssue {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub layout_html: String,
+    pub layout_text: String,
+    // Set once the issue is sent, unless it is hidden from the archive
+    pub archive_slug: Option<String>,
+    pub digest: Option<Json<Digest>>,
+}
+
+pub async fn get_issue(
+    executor: impl PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content,
+            email_templates.html AS layout_html, email_templates.text AS layout_text,
+            CASE WHEN hide_from_archive THEN NULL ELSE slug END AS archive_slug,
+            digest AS "digest: Json<Digest>"
+        FROM newsletter_issues
+        JOIN email_templates ON email_templates.id = newsletter_issues.layout_id
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+// Exponential backoff - 1, 2, 4, 8 minutes
+async fn retry_later(
+    mut transaction: Transaction<'_, Postgres>,
+    task: &Task,
+) -> Result<(), sqlx::Error> {
+    let execute_after = Utc::now() + chrono::Duration::minutes(1 << task.n_retries);
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1, execute_after = $3
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        execute_after
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await
+}
+
+async fn record_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    task: &Task,
+    status: &str,
+    tracked: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_id, status, tracked, attempted_at
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        status,
+        tracked,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+async fn delete_task(
+    mut transaction: Transaction<'_, Postgres>,
+    task: &Task,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await
+}
@@ -0,0 +1,77 @@
+use crate::configuration::Settings;
+use crate::routes::start_sending;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::time::Duration;
+
+// Keep promoting scheduled issues once they are due - runs next to the HTTP server in `serve`
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), std::io::Error> {
+    let pool = PgPool::connect_lazy(&configuration.database.connection_string())
+        .expect("Failed to connect to Postgres.");
+    scheduler_loop(pool).await
+}
+
+async fn scheduler_loop(pool: PgPool) -> Result<(), std::io::Error> {
+    loop {
+        // Errors are logged by `promote_due_issues`, we try again on the next tick
+        let _ = promote_due_issues(&pool).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+// Queue the deliveries of every scheduled issue that is due, returns how many there were
+// then mark the issues whose deliveries were all attempted as sent
+#[tracing::instrument(skip_all, err)]
+pub async fn promote_due_issues(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let mut promoted = 0;
+    while try_promote_due_issue(pool).await? {
+        promoted += 1;
+    }
+    mark_sent_issues(pool).await?;
+    Ok(promoted)
+}
+
+// Every replica runs a scheduler: the row lock makes one of them promote the issue,
+// `SKIP LOCKED` sends the others on to the next due issue, and once the lock is released
+// the issue is `sending` and no longer matches
+async fn try_promote_due_issue(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let newsletter_issue_id = sqlx::query_scalar!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND scheduled_at <= $1
+        ORDER BY scheduled_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+        Utc::now()
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(newsletter_issue_id) = newsletter_issue_id else {
+        return Ok(false);
+    };
+    let recipients = start_sending(&mut transaction, newsletter_issue_id).await?;
+    transaction.commit().await?;
+    tracing::info!(%newsletter_issue_id, recipients, "Queued a scheduled issue.");
+    Ok(true)
+}
+
+async fn mark_sent_issues(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'sent', updated_at = $1
+        WHERE status = 'sending' AND NOT EXISTS (
+            SELECT 1 FROM issue_delivery_queue
+            WHERE issue_delivery_queue.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+        )
+        "#,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
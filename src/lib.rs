@@ -1,4 +1,24 @@
+pub mod authentication;
+pub mod bot_protection;
 pub mod configuration;
+pub mod csrf;
+pub mod domain;
+pub mod email_client;
+pub mod export;
+pub mod feed_poller;
+pub mod gdpr;
+pub mod import;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod markdown;
+pub mod openapi;
+pub mod rate_limit;
+pub mod route_table;
 pub mod routes;
+pub mod segment;
+pub mod signup_policy;
 pub mod startup;
+pub mod suppression;
 pub mod telemetry;
+pub mod templates;
+pub mod tracking;
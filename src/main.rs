@@ -1,15 +1,123 @@
 #![allow(dead_code)]
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
 use env_logger::Env;
+use futures_util::TryStreamExt;
 use rust_news_letter_server::configuration::get_configuration;
+use rust_news_letter_server::export::{export_subscribers, ExportFormat};
+use rust_news_letter_server::feed_poller::run_poller_until_stopped;
+use rust_news_letter_server::import::{import_subscribers, DuplicatePolicy, ImportOptions};
+use rust_news_letter_server::issue_delivery_worker::run_worker_until_stopped;
+use rust_news_letter_server::issue_scheduler::run_scheduler_until_stopped;
+use rust_news_letter_server::routes::SubscriberFilters;
+use rust_news_letter_server::signup_policy::SignupPolicy;
 use rust_news_letter_server::startup::{run, run_0, run_1};
 use rust_news_letter_server::telemetry::{get_subscriber, init_subscriber};
 // use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use std::io::Write;
 use std::net::TcpListener;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(about = "A newsletter server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP server - what happens when no command is given
+    Serve,
+    /// Import subscribers from a CSV file, printing a JSON report to stdout
+    ImportSubscribers {
+        /// The CSV file, with a header row
+        path: PathBuf,
+        /// Header of the column holding the email address
+        #[arg(long, default_value = "email")]
+        email_column: String,
+        /// Header of the column holding the name
+        #[arg(long, default_value = "name")]
+        name_column: String,
+        /// What to do with emails that are already subscribed
+        #[arg(long, value_enum, default_value_t = DuplicatePolicy::Skip)]
+        on_duplicate: DuplicatePolicy,
+        /// Mark imported subscribers as confirmed
+        #[arg(long)]
+        confirmed: bool,
+        /// Recorded as the import source of the new subscribers
+        #[arg(long, default_value = "csv")]
+        source: String,
+        /// Slug of the list to add the subscribers to, the default list if left out
+        #[arg(long)]
+        list: Option<String>,
+    },
+    /// Export subscribers, with the same filters as `GET /admin/subscribers`
+    ExportSubscribers {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        #[arg(long)]
+        status: Option<String>,
+        /// RFC 3339 timestamp, e.g. 2025-01-01T00:00:00Z
+        #[arg(long)]
+        subscribed_after: Option<DateTime<Utc>>,
+        /// RFC 3339 timestamp, e.g. 2025-01-01T00:00:00Z
+        #[arg(long)]
+        subscribed_before: Option<DateTime<Utc>>,
+        /// Only subscribers whose name or email contains this
+        #[arg(long)]
+        search: Option<String>,
+    },
+}
 
 // Apply to this crate,including the lib
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve().await,
+        Command::ImportSubscribers {
+            path,
+            email_column,
+            name_column,
+            on_duplicate,
+            confirmed,
+            source,
+            list,
+        } => {
+            let options = ImportOptions {
+                email_column,
+                name_column,
+                on_duplicate,
+                mark_confirmed: confirmed,
+                source,
+                list,
+            };
+            import_subscribers_from_file(path, options).await
+        }
+        Command::ExportSubscribers {
+            format,
+            output,
+            status,
+            subscribed_after,
+            subscribed_before,
+            search,
+        } => {
+            let filters = SubscriberFilters {
+                status,
+                subscribed_after,
+                subscribed_before,
+                search,
+            };
+            export_subscribers_to_file(filters, format, output).await
+        }
+    }
+}
+
+async fn serve() -> std::io::Result<()> {
     // Use `tracing` and suits
     let subscriber = get_subscriber("rust-newsletter".into(), "info".into(), std::io::stdout);
     init_subscriber(subscriber);
@@ -34,8 +142,68 @@ async fn main() -> std::io::Result<()> {
         configuration.application.host, configuration.application.port
     );
     let listener = TcpListener::bind(address)?;
-    run(listener, connection)?.await
+    let server = run(listener, connection, &configuration)?;
+    // Newsletter issues are scheduled and delivered in the background - stop when any part does
+    tokio::select! {
+        outcome = server => outcome,
+        outcome = run_scheduler_until_stopped(configuration.clone()) => outcome,
+        outcome = run_poller_until_stopped(configuration.clone()) => outcome,
+        outcome = run_worker_until_stopped(configuration) => outcome,
+    }
+}
+
+async fn import_subscribers_from_file(
+    path: PathBuf,
+    options: ImportOptions,
+) -> std::io::Result<()> {
+    // stdout is for the report - logs go to stderr
+    let subscriber = get_subscriber("rust-newsletter".into(), "info".into(), std::io::stderr);
+    init_subscriber(subscriber);
+
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    let connection = PgPool::connect(&configuration.database.connection_string())
+        .await
+        .expect("Failed to connect to Postgres.");
+
+    let signup_policy = SignupPolicy::load(&configuration.signup_policy)?;
+
+    let file = std::fs::File::open(&path)?;
+    let report = import_subscribers(&connection, file, &signup_policy, options)
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("Failed to serialize the report.")
+    );
+    Ok(())
+}
+
+async fn export_subscribers_to_file(
+    filters: SubscriberFilters,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> std::io::Result<()> {
+    // stdout may be the export itself - logs go to stderr
+    let subscriber = get_subscriber("rust-newsletter".into(), "info".into(), std::io::stderr);
+    init_subscriber(subscriber);
+    filters.validate().map_err(std::io::Error::other)?;
+
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    let connection = PgPool::connect(&configuration.database.connection_string())
+        .await
+        .expect("Failed to connect to Postgres.");
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut chunks = std::pin::pin!(export_subscribers(connection, filters, format));
+    while let Some(chunk) = chunks.try_next().await.map_err(std::io::Error::other)? {
+        writer.write_all(&chunk)?;
+    }
+    writer.flush()
 }
+
 async fn main_2() -> std::io::Result<()> {
     // `init` call `set_logger`
     // print all logs at level `info` and above if `RUST_LOG` is not set
@@ -58,7 +226,7 @@ async fn main_2() -> std::io::Result<()> {
     // Use port from config file, not a random one
     let address = format!("127.0.0.1:{}", configuration.application.port);
     let listener = TcpListener::bind(address)?;
-    run(listener, connection)?.await
+    run(listener, connection, &configuration)?.await
 }
 
 async fn main_1() -> std::io::Result<()> {
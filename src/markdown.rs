@@ -0,0 +1,295 @@
+use crate::templates::EmailTemplate;
+use pulldown_cmark::{html, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+// Many email clients drop `<style>` blocks, so every element carries its own styles
+const FONT: &str = "font-family:Helvetica,Arial,sans-serif;color:#222222;";
+const STYLES: [(&str, &str); 18] = [
+    ("p", "margin:0 0 16px;font-size:16px;line-height:24px;"),
+    ("h1", "margin:0 0 16px;font-size:28px;line-height:34px;"),
+    ("h2", "margin:0 0 16px;font-size:22px;line-height:28px;"),
+    ("h3", "margin:0 0 12px;font-size:18px;line-height:24px;"),
+    ("h4", "margin:0 0 12px;font-size:16px;line-height:22px;"),
+    ("h5", "margin:0 0 12px;font-size:16px;line-height:22px;"),
+    ("h6", "margin:0 0 12px;font-size:16px;line-height:22px;"),
+    ("ul", "margin:0 0 16px;padding:0 0 0 24px;font-size:16px;line-height:24px;"),
+    ("ol", "margin:0 0 16px;padding:0 0 0 24px;font-size:16px;line-height:24px;"),
+    ("li", "margin:0 0 8px;"),
+    ("a", "color:#1a73e8;text-decoration:underline;"),
+    ("blockquote", "margin:0 0 16px;padding:0 0 0 16px;border-left:4px solid #dddddd;color:#555555;"),
+    ("pre", "margin:0 0 16px;padding:12px;background-color:#f4f4f4;font-family:Menlo,Consolas,monospace;font-size:14px;line-height:20px;white-space:pre-wrap;"),
+    ("code", "font-family:Menlo,Consolas,monospace;font-size:14px;"),
+    ("table", "margin:0 0 16px;border-collapse:collapse;"),
+    ("th", "padding:8px;border:1px solid #dddddd;text-align:left;font-size:16px;"),
+    ("td", "padding:8px;border:1px solid #dddddd;font-size:16px;"),
+    ("img", "display:block;max-width:100%;height:auto;border:0;"),
+];
+// Stands in for a merge variable while rendering, followed by its index and an `x`
+// only letters and digits, so neither Markdown, the URL escaping nor the sanitizer touch it
+const MERGE_VARIABLE_PLACEHOLDER: &str = "mergevariableplaceholder";
+
+/// Render the Markdown of an issue into email-safe HTML and a plain text alternative.
+///
+/// The HTML is sanitized, so e.g. `<script>` or `<iframe>` in the Markdown are dropped,
+/// styles are inlined and the content sits in a 600px wide table, which is what every
+/// email client lays out reliably. In the text, links become references listed at the end:
+///
+/// `Read the [docs](https://example.com)` -> `Read the docs [1]` ... `[1] https://example.com`
+///
+/// Merge variables like `{{ name }}` are left as they are for the template to fill in,
+/// in links too: `[Unsubscribe]({{ unsubscribe_url }})`. Only plain variables are, anything
+/// else between `{{` and `}}` is rendered and sanitized like the rest of the text.
+pub fn render(markdown: &str) -> EmailTemplate {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let (markdown, variables) = protect_merge_variables(markdown);
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(&markdown, options));
+    EmailTemplate {
+        html: restore_merge_variables(wrap_in_layout_table(&sanitize(&unsafe_html)), &variables),
+        text: restore_merge_variables(to_text(Parser::new_ext(&markdown, options)), &variables),
+    }
+}
+
+// A link destination can not hold spaces and has `{` and `}` percent-encoded,
+// so plain merge variables are swapped for placeholders until the HTML is sanitized
+fn protect_merge_variables(markdown: &str) -> (String, Vec<&str>) {
+    let mut protected = String::with_capacity(markdown.len());
+    let mut variables = Vec::new();
+    let mut rest = markdown;
+    while let Some(start) = rest.find("{{") {
+        let Some(length) = rest[start..].find("}}").map(|end| end + 2) else {
+            break;
+        };
+        let variable = &rest[start..start + length];
+        protected.push_str(&rest[..start]);
+        if is_plain_variable(&variable[2..length - 2]) {
+            protected.push_str(&format!(
+                "{}{}x",
+                MERGE_VARIABLE_PLACEHOLDER,
+                variables.len()
+            ));
+            variables.push(variable);
+        } else {
+            protected.push_str(variable);
+        }
+        rest = &rest[start + length..];
+    }
+    protected.push_str(rest);
+    (protected, variables)
+}
+
+// e.g. `name` or `attribute.company` - no filters or strings, nothing that could carry markup
+fn is_plain_variable(expression: &str) -> bool {
+    let expression = expression.trim();
+    !expression.is_empty()
+        && expression
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn restore_merge_variables(rendered: String, variables: &[&str]) -> String {
+    variables
+        .iter()
+        .enumerate()
+        .fold(rendered, |rendered, (index, variable)| {
+            rendered.replace(
+                &format!("{}{}x", MERGE_VARIABLE_PLACEHOLDER, index),
+                variable,
+            )
+        })
+}
+
+// Drop anything that could run or load content, and replace the styles with ours
+fn sanitize(html: &str) -> String {
+    let styles: Vec<(&str, String)> = STYLES
+        .iter()
+        .map(|(tag, style)| (*tag, format!("{}{}", FONT, style)))
+        .collect();
+    let mut builder = ammonia::Builder::default();
+    for (tag, style) in &styles {
+        builder.set_tag_attribute_value(*tag, "style", style.as_str());
+    }
+    builder.clean(html).to_string()
+}
+
+fn wrap_in_layout_table(html: &str) -> String {
+    format!(
+        "<table role=\"presentation\" width=\"100%\" cellpadding=\"0\" cellspacing=\"0\" border=\"0\">\
+        <tr><td align=\"center\">\
+        <table role=\"presentation\" width=\"600\" cellpadding=\"0\" cellspacing=\"0\" border=\"0\" style=\"width:100%;max-width:600px;\">\
+        <tr><td style=\"padding:24px;text-align:left;{}\">\n{}</td></tr>\
+        </table>\
+        </td></tr>\
+        </table>",
+        FONT, html
+    )
+}
+
+fn to_text<'a>(events: impl Iterator<Item = Event<'a>>) -> String {
+    let mut writer = TextWriter::default();
+    for event in events {
+        writer.event(event);
+    }
+    writer.finish()
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    // Link references, `[1]` is `links[0]`
+    links: Vec<String>,
+    // Destinations of the links and images we are in
+    open_links: Vec<String>,
+    // One entry per nested list, the next number of an ordered one
+    lists: Vec<Option<u64>>,
+    quote_depth: usize,
+    in_code_block: bool,
+    // Where the heading we are in starts, to underline it
+    heading: Option<(usize, HeadingLevel)>,
+    // A list item marker was just written, its first paragraph goes on the same line
+    after_item_marker: bool,
+    first_cell: bool,
+}
+
+impl TextWriter {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) if self.in_code_block => {
+                for line in text.lines() {
+                    self.push(&format!("    {}", line));
+                    self.out.push('\n');
+                }
+            }
+            Event::Text(text) | Event::Code(text) => self.push(&text),
+            Event::SoftBreak | Event::HardBreak => self.out.push('\n'),
+            Event::Rule => {
+                self.blank_line();
+                self.push("---");
+            }
+            // Raw HTML has no place in the text version
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.blank_line(),
+            Tag::Heading { level, .. } => {
+                self.blank_line();
+                self.heading = Some((self.out.len(), level));
+            }
+            Tag::BlockQuote(_) => {
+                self.blank_line();
+                self.quote_depth += 1;
+            }
+            Tag::CodeBlock(_) => {
+                self.blank_line();
+                self.in_code_block = true;
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.new_line();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}{}. ", indent, *number - 1)
+                    }
+                    _ => format!("{}- ", indent),
+                };
+                self.push(&marker);
+                self.after_item_marker = true;
+            }
+            Tag::Table(_) => self.blank_line(),
+            Tag::TableHead | Tag::TableRow => {
+                self.new_line();
+                self.first_cell = true;
+            }
+            Tag::TableCell => {
+                if !self.first_cell {
+                    self.push(" | ");
+                }
+                self.first_cell = false;
+            }
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                self.open_links.push(dest_url.to_string())
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Heading(_) => {
+                if let Some((start, level)) = self.heading.take() {
+                    let width = self.out[start..].trim().chars().count();
+                    let underline = if level == HeadingLevel::H1 { "=" } else { "-" };
+                    self.out.push('\n');
+                    self.push(&underline.repeat(width));
+                }
+            }
+            TagEnd::BlockQuote(_) => self.quote_depth -= 1,
+            TagEnd::CodeBlock => self.in_code_block = false,
+            TagEnd::List(_) => {
+                self.lists.pop();
+            }
+            TagEnd::Link | TagEnd::Image => {
+                if let Some(url) = self.open_links.pop() {
+                    let number = match self.links.iter().position(|link| *link == url) {
+                        Some(index) => index + 1,
+                        None => {
+                            self.links.push(url);
+                            self.links.len()
+                        }
+                    };
+                    self.push(&format!(" [{}]", number));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        if self.out.is_empty() || self.out.ends_with('\n') {
+            self.out.push_str(&"> ".repeat(self.quote_depth));
+        }
+        self.out.push_str(text);
+        self.after_item_marker = false;
+    }
+
+    fn new_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    // Blocks are separated by an empty line
+    fn blank_line(&mut self) {
+        if self.after_item_marker {
+            return;
+        }
+        let trimmed = self.out.trim_end().len();
+        self.out.truncate(trimmed);
+        if !self.out.is_empty() {
+            self.out.push_str("\n\n");
+        }
+    }
+
+    fn finish(mut self) -> String {
+        let trimmed = self.out.trim_end().len();
+        self.out.truncate(trimmed);
+        if !self.links.is_empty() {
+            self.out.push_str("\n\n");
+            for (index, link) in self.links.iter().enumerate() {
+                self.out.push_str(&format!("[{}] {}\n", index + 1, link));
+            }
+        }
+        self.out
+    }
+}
@@ -0,0 +1,92 @@
+use crate::domain::{Role, Scope};
+use crate::route_table::{Access, RouteInfo};
+use crate::routes::{
+    __path_create_api_key, __path_create_list, __path_deactivate_user, __path_get_form_token,
+    __path_health_check, __path_invite_user, __path_list_api_keys, __path_list_lists,
+    __path_list_routes, __path_list_users, __path_revoke_api_key, __path_set_user_role,
+    __path_subscribe, __path_subscribe_to_list, ApiKeyListing, ApiKeySummary, CreatedApiKey,
+    FormData, FormToken, InviteBody, InvitedUser, ListListing, ListSummary, NewApiKeyBody,
+    NewListBody, RoleBody, RouteListing, UserListing, UserSummary,
+};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+/// The OpenAPI 3.1 document served at `/openapi.json`, generated from the `#[utoipa::path]`
+/// of the handlers - a handler is only in it once it is listed under `paths`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Newsletter API",
+        description = "Sign people up to mailing lists, and run the lists as an admin."
+    ),
+    paths(
+        health_check,
+        subscribe,
+        get_form_token,
+        subscribe_to_list,
+        list_routes,
+        list_lists,
+        create_list,
+        list_api_keys,
+        create_api_key,
+        revoke_api_key,
+        list_users,
+        invite_user,
+        set_user_role,
+        deactivate_user,
+    ),
+    components(schemas(
+        FormData,
+        FormToken,
+        RouteListing,
+        RouteInfo,
+        Access,
+        Scope,
+        Role,
+        ListListing,
+        ListSummary,
+        NewListBody,
+        ApiKeyListing,
+        ApiKeySummary,
+        NewApiKeyBody,
+        CreatedApiKey,
+        UserListing,
+        UserSummary,
+        InviteBody,
+        InvitedUser,
+        RoleBody
+    )),
+    modifiers(&AdminCredentials, &WithoutLicense),
+    tags(
+        (name = "health", description = "Whether the application is up"),
+        (name = "subscriptions", description = "Signing up, for forms and integrations"),
+        (name = "admin", description = "Requires the credentials of a user in the `users` table whose role grants the scope, or an API key with it"),
+    )
+)]
+pub struct ApiDoc;
+
+// The `admin` and `api_key` schemes the `/admin` paths refer to, checked by `reject_anonymous_users`
+struct AdminCredentials;
+
+impl Modify for AdminCredentials {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+// `Cargo.toml` names no license, which utoipa would turn into one with an empty name
+struct WithoutLicense;
+
+impl Modify for WithoutLicense {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+    }
+}
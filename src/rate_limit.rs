@@ -0,0 +1,407 @@
+use crate::configuration::{RateLimitSettings, RateLimitStore, TokenBucketSettings};
+use crate::domain::SubscriberEmail;
+use actix_web::body::BoxBody;
+use actix_web::dev::{Payload, ResourceDef, ServiceRequest, ServiceResponse};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+// At most this many buckets in memory - past it, the oldest is dropped for each new one
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+// Every this many new buckets, the ones that filled up again are dropped
+const SWEEP_EVERY: u64 = 1_000;
+
+/// Token buckets for the routes listed in `rate_limit.routes`.
+///
+/// Every request to a limited route takes a token from the bucket of its client IP, and
+/// from the bucket of the email address it submits; an empty bucket means a 429.
+pub struct RateLimiter {
+    routes: Vec<LimitedRoute>,
+    trusted_proxies: Vec<TrustedProxy>,
+    buckets: Buckets,
+}
+
+struct LimitedRoute {
+    pattern: ResourceDef,
+    method: Method,
+    per_ip: Option<TokenBucketSettings>,
+    per_email: Option<TokenBucketSettings>,
+}
+
+enum Buckets {
+    Memory(Mutex<MemoryBuckets>),
+    Postgres(PgPool),
+}
+
+impl RateLimiter {
+    // Panics on invalid settings, like the rest of the configuration
+    pub fn new(settings: &RateLimitSettings, pool: PgPool) -> Self {
+        let routes = settings
+            .routes
+            .iter()
+            .map(|route| {
+                for limit in [route.per_ip, route.per_email].iter().flatten() {
+                    assert!(
+                        limit.capacity > 0 && limit.per_minute > 0.0,
+                        "The rate limit of `{}` must let some requests through.",
+                        route.path
+                    );
+                }
+                LimitedRoute {
+                    pattern: ResourceDef::new(route.path.as_str()),
+                    method: Method::from_bytes(route.method.to_uppercase().as_bytes())
+                        .expect("Invalid rate limited method."),
+                    per_ip: route.per_ip,
+                    per_email: route.per_email,
+                }
+            })
+            .collect();
+        let trusted_proxies = settings
+            .trusted_proxies
+            .iter()
+            .map(|proxy| TrustedProxy::parse(proxy).expect("Invalid trusted proxy."))
+            .collect();
+        let buckets = match settings.store {
+            RateLimitStore::Memory => Buckets::Memory(Mutex::new(MemoryBuckets::default())),
+            RateLimitStore::Postgres => Buckets::Postgres(pool),
+        };
+        Self {
+            routes,
+            trusted_proxies,
+            buckets,
+        }
+    }
+
+    // Matched against the percent-decoded path actix routes on, so `/%73ubscriptions` counts
+    fn route(&self, request: &ServiceRequest) -> Option<&LimitedRoute> {
+        let path = request.match_info().as_str();
+        self.routes
+            .iter()
+            .find(|route| route.method == request.method() && route.pattern.is_match(path))
+    }
+
+    // The peer, unless it is one of our proxies - then the first hop in `X-Forwarded-For`,
+    // from the right, that is not one of them
+    fn client_ip(&self, request: &ServiceRequest) -> Option<IpAddr> {
+        let mut client = request.peer_addr()?.ip();
+        let hops: Vec<&str> = request
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in hops.iter().rev() {
+            if !self.is_trusted(client) {
+                break;
+            }
+            // Whoever made up the header is the client, as far as we can tell
+            let Ok(hop) = hop.trim().parse() else {
+                break;
+            };
+            client = hop;
+        }
+        Some(client)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|proxy| proxy.contains(ip))
+    }
+
+    // `Err` holds how many seconds until the bucket has a token again
+    async fn take(
+        &self,
+        key: &str,
+        limit: &TokenBucketSettings,
+    ) -> Result<Result<(), u64>, sqlx::Error> {
+        let now = Utc::now();
+        match &self.buckets {
+            Buckets::Memory(buckets) => Ok(buckets.lock().unwrap().take(key, limit, now)),
+            Buckets::Postgres(pool) => take_from_postgres(pool, key, limit, now).await,
+        }
+    }
+}
+
+// The row lock makes concurrent requests of every replica take their tokens one after the other
+async fn take_from_postgres(
+    pool: &PgPool,
+    key: &str,
+    limit: &TokenBucketSettings,
+    now: DateTime<Utc>,
+) -> Result<Result<(), u64>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at)
+        VALUES ($1, $2, $3, $3)
+        ON CONFLICT (key) DO NOTHING
+        "#,
+        key,
+        limit.capacity as f64,
+        now
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let stored = sqlx::query!(
+        "SELECT tokens, updated_at FROM rate_limit_buckets WHERE key = $1 FOR UPDATE",
+        key
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let mut bucket = Bucket {
+        tokens: stored.tokens,
+        updated_at: stored.updated_at,
+        full_at: now,
+    };
+    let outcome = bucket.take(limit, now);
+    sqlx::query!(
+        r#"
+        UPDATE rate_limit_buckets SET tokens = $2, updated_at = $3, full_at = $4
+        WHERE key = $1
+        "#,
+        key,
+        bucket.tokens,
+        bucket.updated_at,
+        bucket.full_at
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // Now and then, forget the buckets that filled up again - they are as good as new
+    if rand::thread_rng().gen_ratio(1, 100) {
+        sqlx::query!("DELETE FROM rate_limit_buckets WHERE full_at <= $1", now)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(outcome)
+}
+
+// The buckets of this replica, capped so random addresses cannot grow them without end
+#[derive(Default)]
+struct MemoryBuckets {
+    buckets: HashMap<String, MemoryBucket>,
+    // Keys oldest first, with the `created` of their bucket - stale once it was dropped
+    order: VecDeque<(u64, String)>,
+    created: u64,
+}
+
+struct MemoryBucket {
+    created: u64,
+    bucket: Bucket,
+}
+
+impl MemoryBuckets {
+    fn take(
+        &mut self,
+        key: &str,
+        limit: &TokenBucketSettings,
+        now: DateTime<Utc>,
+    ) -> Result<(), u64> {
+        if !self.buckets.contains_key(key) {
+            self.created += 1;
+            if self.created.is_multiple_of(SWEEP_EVERY) {
+                self.sweep(now);
+            }
+            // Forgetting a bucket gives its client a full one again, the price of the cap
+            while self.buckets.len() >= MAX_MEMORY_BUCKETS && self.evict_oldest() {}
+            self.order.push_back((self.created, key.to_string()));
+        }
+        let created = self.created;
+        let bucket = self
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| MemoryBucket {
+                created,
+                bucket: Bucket::full(limit, now),
+            });
+        bucket.bucket.take(limit, now)
+    }
+
+    // Drops the buckets that filled up again - they are as good as new
+    fn sweep(&mut self, now: DateTime<Utc>) {
+        self.buckets.retain(|_, stored| stored.bucket.full_at > now);
+        let buckets = &self.buckets;
+        self.order.retain(|(created, key)| {
+            buckets
+                .get(key)
+                .is_some_and(|stored| stored.created == *created)
+        });
+    }
+
+    // `false` once there is nothing left to drop
+    fn evict_oldest(&mut self) -> bool {
+        while let Some((created, key)) = self.order.pop_front() {
+            if self
+                .buckets
+                .get(&key)
+                .is_some_and(|stored| stored.created == created)
+            {
+                self.buckets.remove(&key);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+    // When it holds `capacity` tokens again, if nothing takes any
+    full_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn full(limit: &TokenBucketSettings, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: limit.capacity as f64,
+            updated_at: now,
+            full_at: now,
+        }
+    }
+
+    // Top the bucket up for the time that passed, then take a token if there is one
+    fn take(&mut self, limit: &TokenBucketSettings, now: DateTime<Utc>) -> Result<(), u64> {
+        let capacity = limit.capacity as f64;
+        let minutes = (now - self.updated_at).num_milliseconds().max(0) as f64 / 60_000.0;
+        self.tokens = (self.tokens + minutes * limit.per_minute).min(capacity);
+        self.updated_at = now;
+        let outcome = if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let seconds = (1.0 - self.tokens) / limit.per_minute * 60.0;
+            Err(seconds.ceil() as u64)
+        };
+        let refill_ms = (capacity - self.tokens) / limit.per_minute * 60_000.0;
+        self.full_at = now + chrono::Duration::milliseconds(refill_ms.ceil() as i64);
+        outcome
+    }
+}
+
+// An address, or a network in CIDR notation
+struct TrustedProxy {
+    network: IpAddr,
+    prefix_length: u32,
+}
+
+impl TrustedProxy {
+    fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("`{}` is neither an IP address nor a network.", s);
+        let (address, prefix_length) = match s.trim().split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (s.trim(), None),
+        };
+        let network: IpAddr = address.parse().map_err(|_| invalid())?;
+        let max_length = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match prefix_length {
+            Some(length) => length.parse().map_err(|_| invalid())?,
+            None => max_length,
+        };
+        if prefix_length > max_length {
+            return Err(invalid());
+        }
+        Ok(Self {
+            network,
+            prefix_length,
+        })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_length).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_length).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct EmailField {
+    email: String,
+}
+
+// The SHA-256 of the `email` of a form or JSON body, normalised like the subscriber's will be
+// bucket keys are stored and logged, and must not keep an address around after it is erased
+// `None` when there is none, or it is not an address - the handler rejects those anyway
+fn submitted_email(request: &ServiceRequest, body: &[u8]) -> Option<String> {
+    let is_json = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+    let field = if is_json {
+        serde_json::from_slice::<EmailField>(body).ok()?
+    } else {
+        web::Query::<EmailField>::from_query(std::str::from_utf8(body).ok()?)
+            .ok()?
+            .into_inner()
+    };
+    let email = SubscriberEmail::parse(field.email).ok()?;
+    Some(format!(
+        "{:x}",
+        Sha256::digest(email.as_ref().to_lowercase().as_bytes())
+    ))
+}
+
+fn too_many_requests(retry_after: u64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.max(1).to_string()))
+        .body("Too many requests, please try again later.")
+}
+
+// Middleware for the whole app - only the routes in `rate_limit.routes` are limited
+pub async fn rate_limit(
+    mut req: ServiceRequest,
+    next: Next<BoxBody>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .expect("The rate limiter is not registered as application data.")
+        .clone();
+    let Some(route) = limiter.route(&req) else {
+        return next.call(req).await;
+    };
+    let path = route.pattern.pattern().unwrap_or_default();
+    let mut buckets = Vec::new();
+    if let Some(limit) = route.per_ip {
+        let client_ip = limiter
+            .client_ip(&req)
+            .map_or_else(|| "unknown".into(), |ip| ip.to_string());
+        buckets.push((format!("{}:ip:{}", path, client_ip), limit));
+    }
+    if let Some(limit) = route.per_email {
+        // The handler still needs the body, so it goes back once we had a look
+        let body = req.extract::<web::Bytes>().await?;
+        let email = submitted_email(&req, &body);
+        req.set_payload(Payload::from(body));
+        if let Some(email) = email {
+            buckets.push((format!("{}:email:{}", path, email), limit));
+        }
+    }
+    for (key, limit) in buckets {
+        match limiter.take(&key, &limit).await {
+            Ok(Ok(())) => {}
+            Ok(Err(retry_after)) => {
+                tracing::warn!(key, retry_after, "Rate limited a request.");
+                return Ok(req.into_response(too_many_requests(retry_after)));
+            }
+            // A broken store must not take signups down with it
+            Err(e) => tracing::error!("Failed to check the rate limit: {:?}", e),
+        }
+    }
+    next.call(req).await
+}
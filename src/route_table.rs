@@ -0,0 +1,224 @@
+use crate::authentication::require_scope;
+use crate::domain::Scope;
+use crate::routes::message_page;
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ResourceDef, ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, Accept, Header, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{guard, web, App, FromRequest, Handler, HttpRequest, HttpResponse, Responder};
+
+// Who may call a route
+#[derive(serde::Serialize, utoipa::ToSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    Public,
+    // Users in the `users` table, see `reject_anonymous_users`
+    Admin,
+}
+
+/// One route `run` serves, e.g. `GET /admin/lists`, as listed by `GET /admin/routes`.
+#[derive(serde::Serialize, utoipa::ToSchema, Debug, Clone)]
+pub struct RouteInfo {
+    pub method: String,
+    pub path: String,
+    pub access: Access,
+    /// e.g. `routes::admin::lists::list_lists`
+    pub handler: &'static str,
+    /// What an API key needs to call it - every admin route requires a scope
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Scope>,
+}
+
+/// Every route of the app, recorded while registering them - so the listing cannot drift.
+pub struct RouteTable {
+    routes: Vec<RouteInfo>,
+    patterns: Vec<ResourceDef>,
+}
+
+impl RouteTable {
+    // Runs `register` on an app that is thrown away, only to see what goes in
+    // panics on an admin route without a scope, so none can be added unguarded
+    pub fn collect(register: impl FnOnce(&mut Routes)) -> Self {
+        let mut routes = Vec::new();
+        let _ = App::new().configure(|config| register(&mut Routes::new(config, &mut routes)));
+        if let Some(route) = routes
+            .iter()
+            .find(|route| route.access == Access::Admin && route.scope.is_none())
+        {
+            panic!(
+                "{} {} is an admin route without a scope, register it with `Routes::requiring`.",
+                route.method, route.path
+            );
+        }
+        let patterns = routes
+            .iter()
+            .map(|route| ResourceDef::new(route.path.as_str()))
+            .collect();
+        Self { routes, patterns }
+    }
+
+    pub fn routes(&self) -> &[RouteInfo] {
+        &self.routes
+    }
+
+    // The methods some route takes at `path`, e.g. `["GET", "POST"]`
+    pub fn allowed_methods(&self, path: &str) -> Vec<&str> {
+        let mut methods: Vec<&str> = Vec::new();
+        for (route, pattern) in self.routes.iter().zip(&self.patterns) {
+            if pattern.is_match(path) && !methods.contains(&route.method.as_str()) {
+                methods.push(&route.method);
+            }
+        }
+        methods
+    }
+}
+
+/// Registers routes and records them in the `RouteTable` at the same time.
+pub struct Routes<'a> {
+    config: &'a mut web::ServiceConfig,
+    table: &'a mut Vec<RouteInfo>,
+    prefix: String,
+    access: Access,
+    scope: Option<Scope>,
+}
+
+impl<'a> Routes<'a> {
+    pub fn new(config: &'a mut web::ServiceConfig, table: &'a mut Vec<RouteInfo>) -> Self {
+        Self {
+            config,
+            table,
+            prefix: String::new(),
+            access: Access::Public,
+            scope: None,
+        }
+    }
+
+    pub fn route<F, Args>(&mut self, method: Method, path: &str, handler: F) -> &mut Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.table.push(RouteInfo {
+            method: method.to_string(),
+            path: format!("{}{}", self.prefix, path),
+            access: self.access,
+            handler: handler_name::<F>(),
+            scope: self.scope,
+        });
+        let route = web::method(method.clone()).to(handler);
+        match self.scope {
+            // Checked on the resource itself, not by looking its path up again
+            Some(scope) => self.config.service(
+                web::resource(path)
+                    .guard(guard::Method(method))
+                    .route(route)
+                    .wrap(from_fn(move |req: ServiceRequest, next: Next<BoxBody>| {
+                        require_scope(scope, req, next)
+                    })),
+            ),
+            None => self.config.route(path, route),
+        };
+        self
+    }
+
+    // Routes under `path` that share a middleware, added by `wrap` - e.g. `require_csrf_token`
+    pub fn scope<T, B>(
+        &mut self,
+        path: &str,
+        access: Access,
+        wrap: impl FnOnce(actix_web::Scope) -> actix_web::Scope<T>,
+        register: impl FnOnce(&mut Routes),
+    ) -> &mut Self
+    where
+        T: ServiceFactory<
+                ServiceRequest,
+                Config = (),
+                Response = ServiceResponse<B>,
+                Error = actix_web::Error,
+                InitError = (),
+            > + 'static,
+        B: MessageBody + 'static,
+    {
+        let prefix = format!("{}{}", self.prefix, path);
+        let table = &mut *self.table;
+        let scope = wrap(web::scope(path)).configure(|config| {
+            register(&mut Routes {
+                config,
+                table,
+                prefix,
+                access,
+                scope: None,
+            })
+        });
+        self.config.service(scope);
+        self
+    }
+
+    // Routes the role, and API key, must grant `scope` for, e.g. `Scope::SubscribersRead`
+    // each is wrapped in `require_scope`
+    pub fn requiring(&mut self, scope: Scope, register: impl FnOnce(&mut Routes)) -> &mut Self {
+        let outer = self.scope.replace(scope);
+        register(self);
+        self.scope = outer;
+        self
+    }
+}
+
+// The handler's path in the crate, e.g. `routes::health_check::health_check`
+fn handler_name<F>() -> &'static str {
+    let name = std::any::type_name::<F>();
+    name.strip_prefix(concat!(env!("CARGO_CRATE_NAME"), "::"))
+        .unwrap_or(name)
+}
+
+// The default service, for paths no route matches and methods a route does not take
+// browsers get a page, everybody else JSON - the path is never echoed back
+pub async fn no_route(request: HttpRequest, route_table: web::Data<RouteTable>) -> HttpResponse {
+    let allowed = route_table.allowed_methods(request.path());
+    let (status, error, message) = if allowed.is_empty() {
+        (
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "There is nothing at this address.".to_string(),
+        )
+    } else {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            format!("This address does not take {} requests.", request.method()),
+        )
+    };
+    let mut response = if prefers_html(&request) {
+        let heading = status.canonical_reason().unwrap_or_default();
+        message_page(status, heading, &message, None)
+    } else {
+        HttpResponse::build(status).json(serde_json::json!({
+            "error": error,
+            "message": message,
+        }))
+    };
+    if !allowed.is_empty() {
+        // Methods are tokens, always a valid header value
+        let allow = HeaderValue::from_str(&allowed.join(", ")).unwrap();
+        response.headers_mut().insert(header::ALLOW, allow);
+    }
+    response
+}
+
+// Whichever of HTML and JSON comes first in `Accept` - JSON without one
+fn prefers_html(request: &HttpRequest) -> bool {
+    let Ok(accept) = Accept::parse(request) else {
+        return false;
+    };
+    accept
+        .ranked()
+        .iter()
+        .find_map(|mime| match mime.essence_str() {
+            "text/html" | "application/xhtml+xml" => Some(true),
+            "application/json" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
@@ -0,0 +1,203 @@
+use crate::authentication::{api_key_prefix, generate_api_key, hash_api_key, UserId};
+use crate::domain::{Role, Scope};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// e.g. `{"name": "CMS", "scopes": ["newsletters:write", "newsletters:publish"]}`
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewApiKeyBody {
+    name: String,
+    /// e.g. `["newsletters:write", "newsletters:publish"]` - any your role grants, except
+    /// `api_keys:manage` and `users:manage`
+    scopes: Vec<String>,
+    /// Never, when missing
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct CreatedApiKey {
+    id: Uuid,
+    name: String,
+    prefix: String,
+    scopes: Vec<Scope>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    /// Goes in `Authorization: Bearer` - shown this once, only its hash is stored
+    key: String,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ApiKeySummary {
+    id: Uuid,
+    name: String,
+    /// The start of the key, e.g. `nlk_4fGh9aQ2`
+    prefix: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ApiKeyListing {
+    api_keys: Vec<ApiKeySummary>,
+}
+
+// The key acts on behalf of whoever minted it, with no more than the scopes of their role
+#[utoipa::path(
+    post,
+    path = "/admin/api-keys",
+    tag = "admin",
+    security(("admin" = [])),
+    request_body = NewApiKeyBody,
+    responses(
+        (status = 201, description = "The new key, with its secret", body = CreatedApiKey),
+        (status = 400, description = "Why the key was turned away", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid credentials"),
+    )
+)]
+#[tracing::instrument(name = "Minting an API key", skip(body, pool, user_id, role), fields(user_id = %*user_id))]
+pub async fn create_api_key(
+    body: web::Json<NewApiKeyBody>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+) -> HttpResponse {
+    let NewApiKeyBody {
+        name,
+        scopes,
+        expires_at,
+    } = body.into_inner();
+    let name = name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().body("The key name must not be empty.");
+    }
+    let scopes = match Scope::parse_grantable(&scopes) {
+        Ok(scopes) => scopes,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    if let Some(scope) = scopes.iter().find(|scope| !role.allows(**scope)) {
+        return HttpResponse::BadRequest().body(format!(
+            "The role {} does not grant {}.",
+            role.as_str(),
+            scope.as_str()
+        ));
+    }
+    let created_at = Utc::now();
+    if expires_at.is_some_and(|expires_at| expires_at <= created_at) {
+        return HttpResponse::BadRequest().body("The key must expire in the future.");
+    }
+    let id = Uuid::new_v4();
+    let key = generate_api_key();
+    let prefix = api_key_prefix(&key).to_string();
+    let stored_scopes: Vec<String> = scopes.iter().map(|scope| scope.as_str().into()).collect();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO api_keys (id, user_id, name, prefix, secret_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        id,
+        **user_id,
+        name,
+        prefix,
+        hash_api_key(&key),
+        &stored_scopes,
+        created_at,
+        expires_at,
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(_) => HttpResponse::Created().json(CreatedApiKey {
+            id,
+            name: name.to_string(),
+            prefix,
+            scopes,
+            created_at,
+            expires_at,
+            key,
+        }),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// The keys of whoever asks, newest first - revoked ones too, never their secrets
+#[utoipa::path(
+    get,
+    path = "/admin/api-keys",
+    tag = "admin",
+    security(("admin" = [])),
+    responses(
+        (status = 200, description = "Your keys, newest first", body = ApiKeyListing),
+        (status = 401, description = "Missing or invalid credentials"),
+    )
+)]
+#[tracing::instrument(name = "Listing API keys", skip(pool, user_id), fields(user_id = %*user_id))]
+pub async fn list_api_keys(pool: web::Data<PgPool>, user_id: web::ReqData<UserId>) -> HttpResponse {
+    let result = sqlx::query_as!(
+        ApiKeySummary,
+        r#"
+        SELECT id, name, prefix, scopes, created_at, expires_at, last_used_at, revoked_at
+        FROM api_keys
+        WHERE user_id = $1
+        ORDER BY created_at DESC, id
+        "#,
+        **user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+    match result {
+        Ok(api_keys) => HttpResponse::Ok().json(ApiKeyListing { api_keys }),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Takes effect with the next request - the key stays listed, with its `revoked_at`
+#[utoipa::path(
+    delete,
+    path = "/admin/api-keys/{key_id}",
+    tag = "admin",
+    security(("admin" = [])),
+    params(("key_id" = Uuid, Path, description = "The `id` of one of your keys")),
+    responses(
+        (status = 204, description = "The key no longer works"),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 404, description = "You have no such key, or it is already revoked"),
+    )
+)]
+#[tracing::instrument(name = "Revoking an API key", skip(pool, user_id), fields(user_id = %*user_id))]
+pub async fn revoke_api_key(
+    key_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> HttpResponse {
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_keys
+        SET revoked_at = $3
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        key_id.into_inner(),
+        **user_id,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
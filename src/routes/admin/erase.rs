@@ -0,0 +1,23 @@
+use crate::authentication::UserId;
+use crate::gdpr::{erase_subscriber, Actor};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// For erasure requests that reach us outside the subscriber's own link, e.g. by post
+#[tracing::instrument(name = "Erasing a subscriber", skip(pool, user_id), fields(user_id = %*user_id))]
+pub async fn erase_subscriber_by_id(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> HttpResponse {
+    let actor = Actor::Admin(*user_id.into_inner());
+    match erase_subscriber(&pool, subscriber_id.into_inner(), actor).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to erase subscriber data: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
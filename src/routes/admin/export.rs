@@ -0,0 +1,43 @@
+use crate::export::{export_subscribers, ExportFormat};
+use crate::routes::SubscriberFilters;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{web, HttpResponse};
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+
+// Takes the same filters as the listing, e.g. `?format=jsonl&status=confirmed`
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportQuery {
+    #[serde(flatten)]
+    filters: SubscriberFilters,
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+// The file is sent as a chunked response while it is read from the database
+#[tracing::instrument(name = "Exporting subscribers", skip(query, pool), fields(format = ?query.format, filters = ?query.filters))]
+pub async fn export_subscriber_list(
+    query: web::Query<ExportQuery>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let ExportQuery { filters, format } = query.into_inner();
+    if let Err(e) = filters.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    // Headers are already sent when a row fails, all we can do is abort the response
+    let body = export_subscribers(pool.get_ref().clone(), filters, format).map_err(|e| {
+        tracing::error!("Failed to export subscribers: {:?}", e);
+        actix_web::error::ErrorInternalServerError(e)
+    });
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "subscribers.{}",
+                format.file_extension()
+            ))],
+        })
+        .streaming(body)
+}
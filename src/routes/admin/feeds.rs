@@ -0,0 +1,162 @@
+use crate::routes::{get_list_by_slug, resolve_template, PublishError};
+use crate::templates::TemplateKind;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// e.g. `{"url": "https://blog.example.com/feed.xml", "template": "blog-digest", "auto_send": true}`
+#[derive(serde::Deserialize)]
+pub struct FeedSourceBody {
+    url: String,
+    // Name of a `digest` template
+    template: String,
+    // Name of the layout template to wrap the digest in, `default` when left out
+    layout: Option<String>,
+    // Send new digests right away, rather than leaving them as drafts
+    #[serde(default)]
+    auto_send: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct FeedSourceDetails {
+    url: String,
+    template: String,
+    layout: String,
+    auto_send: bool,
+    // The last time it was read - failed polls leave it alone
+    last_polled_at: Option<DateTime<Utc>>,
+    // Why the last poll failed, if it did
+    last_error: Option<String>,
+}
+
+// A list has at most one feed, setting it again replaces it
+// pointing it at another url starts over, as if the feed was new
+#[tracing::instrument(name = "Setting the feed of a list", skip(body, pool))]
+pub async fn set_list_feed(
+    slug: web::Path<String>,
+    body: web::Json<FeedSourceBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let is_http =
+        reqwest::Url::parse(&body.url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+    if !is_http {
+        return HttpResponse::BadRequest().body(format!("`{}` is not an http(s) url.", body.url));
+    }
+    let result: Result<Option<()>, PublishError> = async {
+        let mut transaction = pool.begin().await?;
+        let Some(list) = get_list_by_slug(&mut *transaction, &slug).await? else {
+            return Ok(None);
+        };
+        let template =
+            resolve_template(&mut transaction, body.template, TemplateKind::Digest).await?;
+        let layout_name = body.layout.unwrap_or_else(|| "default".into());
+        let layout = resolve_template(&mut transaction, layout_name, TemplateKind::Layout).await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO feed_sources (
+                id, list_id, url, template_id, layout_id, auto_send, created_at, updated_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $7)
+            ON CONFLICT (list_id) DO UPDATE
+            SET url = EXCLUDED.url, template_id = EXCLUDED.template_id,
+                layout_id = EXCLUDED.layout_id, auto_send = EXCLUDED.auto_send,
+                last_polled_at = CASE
+                    WHEN feed_sources.url = EXCLUDED.url THEN feed_sources.last_polled_at
+                END,
+                next_poll_at = CASE
+                    WHEN feed_sources.url = EXCLUDED.url THEN feed_sources.next_poll_at
+                    ELSE EXCLUDED.next_poll_at
+                END,
+                updated_at = EXCLUDED.updated_at
+            "#,
+            Uuid::new_v4(),
+            list.id,
+            body.url,
+            template.id,
+            layout.id,
+            body.auto_send,
+            Utc::now()
+        )
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(Some(()))
+    }
+    .await;
+    match result {
+        Ok(Some(())) => get_list_feed(slug, pool).await,
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[tracing::instrument(name = "Fetching the feed of a list", skip(pool))]
+pub async fn get_list_feed(slug: web::Path<String>, pool: web::Data<PgPool>) -> HttpResponse {
+    let result = sqlx::query_as!(
+        FeedSourceDetails,
+        r#"
+        SELECT url, templates.name AS template, layouts.name AS layout, auto_send,
+            last_polled_at, last_error
+        FROM feed_sources
+        JOIN lists ON lists.id = feed_sources.list_id
+        JOIN email_templates AS templates ON templates.id = feed_sources.template_id
+        JOIN email_templates AS layouts ON layouts.id = feed_sources.layout_id
+        WHERE lists.slug = $1
+        "#,
+        slug.into_inner()
+    )
+    .fetch_optional(pool.get_ref())
+    .await;
+    match result {
+        Ok(Some(source)) => HttpResponse::Ok().json(source),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// The issues already made from the feed stay as they are
+#[tracing::instrument(name = "Removing the feed of a list", skip(pool))]
+pub async fn delete_list_feed(slug: web::Path<String>, pool: web::Data<PgPool>) -> HttpResponse {
+    let result: Result<bool, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+        let source_id = sqlx::query_scalar!(
+            r#"
+            SELECT feed_sources.id FROM feed_sources
+            JOIN lists ON lists.id = feed_sources.list_id
+            WHERE lists.slug = $1
+            FOR UPDATE OF feed_sources
+            "#,
+            slug.into_inner()
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let Some(source_id) = source_id else {
+            return Ok(false);
+        };
+        sqlx::query!(
+            "DELETE FROM feed_entries WHERE feed_source_id = $1",
+            source_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query!("DELETE FROM feed_sources WHERE id = $1", source_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(true)
+    }
+    .await;
+    match result {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::domain::{CustomField, FieldRules, FieldType};
+use crate::routes::get_list_by_slug;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::types::Json;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+// e.g. `{"key": "country", "label": "Country", "type": "choice", "required": true, "choices": ["fr", "de"]}`
+#[derive(serde::Deserialize)]
+pub struct NewFieldBody {
+    key: String,
+    label: Option<String>,
+    #[serde(rename = "type")]
+    field_type: String,
+    #[serde(default)]
+    required: bool,
+    #[serde(flatten)]
+    rules: FieldRules,
+}
+
+#[tracing::instrument(name = "Adding a custom field to a list", skip(body, pool), fields(key = %body.key))]
+pub async fn create_list_field(
+    slug: web::Path<String>,
+    body: web::Json<NewFieldBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let field = match FieldType::try_from(body.field_type).and_then(|field_type| {
+        CustomField::parse(body.key, body.label, field_type, body.required, body.rules)
+    }) {
+        Ok(field) => field,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let list = match get_list_by_slug(pool.get_ref(), &slug).await {
+        Ok(Some(list)) => list,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO list_fields (list_id, key, label, field_type, required, rules, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (list_id, key) DO NOTHING
+        "#,
+        list.id,
+        field.key,
+        field.label,
+        field.field_type.as_str(),
+        field.required,
+        Json(&field.rules) as _,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::Conflict().body(format!("The list already has a field `{}`.", field.key))
+        }
+        Ok(_) => HttpResponse::Created().json(field),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Listing the custom fields of a list", skip(pool))]
+pub async fn list_list_fields(slug: web::Path<String>, pool: web::Data<PgPool>) -> HttpResponse {
+    let list = match get_list_by_slug(pool.get_ref(), &slug).await {
+        Ok(Some(list)) => list,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match get_list_fields(pool.get_ref(), list.id).await {
+        Ok(fields) => HttpResponse::Ok().json(serde_json::json!({ "fields": fields })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Values already stored in `subscriptions.attributes` are kept
+#[tracing::instrument(name = "Removing a custom field from a list", skip(pool))]
+pub async fn delete_list_field(
+    path: web::Path<(String, String)>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let (slug, key) = path.into_inner();
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM list_fields
+        USING lists
+        WHERE lists.id = list_fields.list_id AND lists.slug = $1 AND list_fields.key = $2
+        "#,
+        slug,
+        key
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Fetching the custom fields of a list", skip(executor))]
+pub async fn get_list_fields(
+    executor: impl PgExecutor<'_>,
+    list_id: Uuid,
+) -> Result<Vec<CustomField>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT key, label, field_type, required, rules AS "rules: Json<FieldRules>"
+        FROM list_fields
+        WHERE list_id = $1
+        ORDER BY created_at, key
+        "#,
+        list_id
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    rows.into_iter()
+        .map(|row| {
+            // The values were validated on the way in
+            let field_type =
+                FieldType::try_from(row.field_type).map_err(|e| sqlx::Error::Decode(e.into()))?;
+            Ok(CustomField {
+                key: row.key,
+                label: row.label,
+                field_type,
+                required: row.required,
+                rules: row.rules.0,
+            })
+        })
+        .collect()
+}
+
+// Keys of the custom fields of every list - what `attribute.<key>` may refer to in a template
+#[tracing::instrument(name = "Fetching the keys of all custom fields", skip(executor))]
+pub async fn get_field_keys(executor: impl PgExecutor<'_>) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(r#"SELECT DISTINCT key AS "key!" FROM list_fields ORDER BY 1"#)
+        .fetch_all(executor)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })
+}
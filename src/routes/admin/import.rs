@@ -0,0 +1,77 @@
+use crate::import::{
+    import_subscribers, ChannelReader, DuplicatePolicy, ImportError, ImportOptions,
+};
+use crate::signup_policy::SignupPolicy;
+use actix_web::{web, HttpResponse};
+use futures_util::StreamExt;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+
+// e.g. `?email_column=Email%20Address&name_column=First%20Name&on_duplicate=update&confirmed=true&source=mailchimp`
+#[derive(serde::Deserialize, Debug)]
+pub struct ImportQuery {
+    email_column: Option<String>,
+    name_column: Option<String>,
+    #[serde(default)]
+    on_duplicate: DuplicatePolicy,
+    #[serde(default)]
+    confirmed: bool,
+    source: Option<String>,
+    // Slug of the list to add the subscribers to
+    list: Option<String>,
+}
+
+impl From<ImportQuery> for ImportOptions {
+    fn from(query: ImportQuery) -> Self {
+        let defaults = ImportOptions::default();
+        Self {
+            email_column: query.email_column.unwrap_or(defaults.email_column),
+            name_column: query.name_column.unwrap_or(defaults.name_column),
+            on_duplicate: query.on_duplicate,
+            mark_confirmed: query.confirmed,
+            source: query.source.unwrap_or(defaults.source),
+            list: query.list,
+        }
+    }
+}
+
+// The CSV is the raw request body, e.g. `curl --data-binary @subscribers.csv`
+// it is parsed while it is being uploaded instead of being buffered in memory first
+#[tracing::instrument(
+    name = "Importing subscribers from an upload",
+    skip(payload, pool, signup_policy)
+)]
+pub async fn import_subscribers_csv(
+    query: web::Query<ImportQuery>,
+    mut payload: web::Payload,
+    pool: web::Data<PgPool>,
+    signup_policy: web::Data<SignupPolicy>,
+) -> HttpResponse {
+    let (sender, receiver) = mpsc::channel(16);
+    let forward_body = async move {
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(|e| std::io::Error::other(e.to_string()));
+            // The parser stopped early - the rest of the body is not needed
+            if sender.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    };
+    let import = import_subscribers(
+        &pool,
+        ChannelReader::new(receiver),
+        &signup_policy,
+        query.into_inner().into(),
+    );
+
+    let (report, _) = tokio::join!(import, forward_body);
+    match report {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(ImportError::InvalidFile(e)) => HttpResponse::BadRequest().body(e),
+        Err(e @ ImportError::UnknownList(_)) => HttpResponse::BadRequest().body(e.to_string()),
+        Err(ImportError::Database(e)) => {
+            tracing::error!("Failed to import subscribers: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
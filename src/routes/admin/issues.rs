@@ -0,0 +1,392 @@
+use crate::domain::IssueStatus;
+use crate::routes::{insert_issue, prepare_issue, update_issue, NewsletterBody, PublishError};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// e.g. `?status=scheduled`
+#[derive(serde::Deserialize, Debug)]
+pub struct IssueFilters {
+    status: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ScheduleBody {
+    // RFC 3339, e.g. `2025-03-01T08:00:00Z`
+    send_at: DateTime<Utc>,
+}
+
+// e.g. `{"hidden": true}`
+#[derive(serde::Deserialize, Debug)]
+pub struct ArchiveBody {
+    hidden: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    scheduled_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct IssueDetails {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    content: serde_json::Value,
+    layout: String,
+    lists: Vec<String>,
+    segment: Option<String>,
+    // Where the issue is in the archive, once it is sent
+    slug: Option<String>,
+    hide_from_archive: bool,
+    scheduled_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+// Same body as publishing, but nothing is sent until the draft is scheduled
+#[tracing::instrument(name = "Creating a draft issue", skip(body, pool))]
+pub async fn create_draft(
+    body: web::Json<NewsletterBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let result: Result<Uuid, PublishError> = async {
+        let mut transaction = pool.begin().await?;
+        let issue = prepare_issue(&mut transaction, body.into_inner()).await?;
+        let newsletter_issue_id =
+            insert_issue(&mut transaction, &issue, IssueStatus::Draft).await?;
+        transaction.commit().await?;
+        Ok(newsletter_issue_id)
+    }
+    .await;
+    match result {
+        Ok(newsletter_issue_id) => HttpResponse::Created().json(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "status": IssueStatus::Draft.as_str(),
+        })),
+        Err(e) => e.into_response(),
+    }
+}
+
+// Replaces the whole draft, only drafts can be edited
+#[tracing::instrument(name = "Updating a draft issue", skip(body, pool))]
+pub async fn update_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<NewsletterBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let result: Result<Option<IssueStatus>, PublishError> = async {
+        let mut transaction = pool.begin().await?;
+        let status = lock_issue(&mut transaction, newsletter_issue_id).await?;
+        if status == Some(IssueStatus::Draft) {
+            let issue = prepare_issue(&mut transaction, body.into_inner()).await?;
+            update_issue(&mut transaction, newsletter_issue_id, &issue).await?;
+            transaction.commit().await?;
+        }
+        Ok(status)
+    }
+    .await;
+    match result {
+        Ok(Some(IssueStatus::Draft)) => HttpResponse::Ok().json(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "status": IssueStatus::Draft.as_str(),
+        })),
+        Ok(Some(status)) => not_a_draft(status),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => e.into_response(),
+    }
+}
+
+// Drafts and cancelled issues never reached anyone, so they can go
+#[tracing::instrument(name = "Deleting an issue", skip(pool))]
+pub async fn delete_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let result: Result<Option<IssueStatus>, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+        let status = lock_issue(&mut transaction, newsletter_issue_id).await?;
+        if matches!(status, Some(IssueStatus::Draft | IssueStatus::Cancelled)) {
+            sqlx::query!(
+                "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+                newsletter_issue_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            sqlx::query!(
+                "DELETE FROM newsletter_issues WHERE newsletter_issue_id = $1",
+                newsletter_issue_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            transaction.commit().await?;
+        }
+        Ok(status)
+    }
+    .await;
+    match result {
+        Ok(Some(IssueStatus::Draft | IssueStatus::Cancelled)) => HttpResponse::NoContent().finish(),
+        Ok(Some(status)) => HttpResponse::Conflict().body(format!(
+            "The issue is {}, only drafts and cancelled issues can be deleted.",
+            status.as_str()
+        )),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to delete the issue: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// The scheduler queues the deliveries once `send_at` has passed
+// a scheduled issue can be given another time until then
+#[tracing::instrument(name = "Scheduling an issue", skip(pool))]
+pub async fn schedule_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<ScheduleBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if body.send_at <= Utc::now() {
+        return HttpResponse::BadRequest().body("`send_at` must be in the future.");
+    }
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let result: Result<Option<IssueStatus>, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+        let status = lock_issue(&mut transaction, newsletter_issue_id).await?;
+        if matches!(status, Some(IssueStatus::Draft | IssueStatus::Scheduled)) {
+            sqlx::query!(
+                r#"
+                UPDATE newsletter_issues
+                SET status = 'scheduled', scheduled_at = $2, updated_at = $3
+                WHERE newsletter_issue_id = $1
+                "#,
+                newsletter_issue_id,
+                body.send_at,
+                Utc::now()
+            )
+            .execute(&mut *transaction)
+            .await?;
+            transaction.commit().await?;
+        }
+        Ok(status)
+    }
+    .await;
+    match result {
+        Ok(Some(IssueStatus::Draft | IssueStatus::Scheduled)) => {
+            HttpResponse::Ok().json(serde_json::json!({
+                "newsletter_issue_id": newsletter_issue_id,
+                "status": IssueStatus::Scheduled.as_str(),
+                "scheduled_at": body.send_at,
+            }))
+        }
+        Ok(Some(status)) => not_a_draft(status),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to schedule the issue: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Only before the scheduler picked the issue up - it waits for the scheduler otherwise,
+// and then finds the issue sending
+#[tracing::instrument(name = "Cancelling a scheduled issue", skip(pool))]
+pub async fn cancel_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let result: Result<Option<IssueStatus>, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+        let status = lock_issue(&mut transaction, newsletter_issue_id).await?;
+        if status == Some(IssueStatus::Scheduled) {
+            sqlx::query!(
+                r#"
+                UPDATE newsletter_issues
+                SET status = 'cancelled', updated_at = $2
+                WHERE newsletter_issue_id = $1
+                "#,
+                newsletter_issue_id,
+                Utc::now()
+            )
+            .execute(&mut *transaction)
+            .await?;
+            transaction.commit().await?;
+        }
+        Ok(status)
+    }
+    .await;
+    match result {
+        Ok(Some(IssueStatus::Scheduled)) => HttpResponse::Ok().json(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "status": IssueStatus::Cancelled.as_str(),
+        })),
+        Ok(Some(status)) => HttpResponse::Conflict().body(format!(
+            "The issue is {}, only scheduled issues can be cancelled.",
+            status.as_str()
+        )),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to cancel the issue: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Listing issues", skip(pool))]
+pub async fn list_issues(
+    filters: web::Query<IssueFilters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let status = match filters
+        .status
+        .clone()
+        .map(IssueStatus::try_from)
+        .transpose()
+    {
+        Ok(status) => status.map(|status| status.as_str()),
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let result = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, status, scheduled_at, published_at, created_at, updated_at
+        FROM newsletter_issues
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY created_at DESC, newsletter_issue_id
+        "#,
+        status
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+    match result {
+        Ok(issues) => HttpResponse::Ok().json(serde_json::json!({ "issues": issues })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Fetching an issue", skip(pool))]
+pub async fn get_issue_details(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let result = sqlx::query!(
+        r#"
+        SELECT newsletter_issues.newsletter_issue_id, title, status, html_content, text_content,
+            markdown, email_templates.name AS layout, segment, slug, hide_from_archive,
+            scheduled_at, published_at,
+            newsletter_issues.created_at, newsletter_issues.updated_at,
+            ARRAY(
+                SELECT lists.slug FROM newsletter_issue_lists
+                JOIN lists ON lists.id = newsletter_issue_lists.list_id
+                WHERE newsletter_issue_lists.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+                ORDER BY lists.slug
+            ) AS "lists!"
+        FROM newsletter_issues
+        JOIN email_templates ON email_templates.id = newsletter_issues.layout_id
+        WHERE newsletter_issues.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id.into_inner()
+    )
+    .fetch_optional(pool.get_ref())
+    .await;
+    match result {
+        Ok(Some(row)) => HttpResponse::Ok().json(IssueDetails {
+            newsletter_issue_id: row.newsletter_issue_id,
+            title: row.title,
+            status: row.status,
+            content: serde_json::json!({
+                "html": row.html_content,
+                "text": row.text_content,
+                "markdown": row.markdown,
+            }),
+            layout: row.layout,
+            lists: row.lists,
+            segment: row.segment,
+            slug: row.slug,
+            hide_from_archive: row.hide_from_archive,
+            scheduled_at: row.scheduled_at,
+            published_at: row.published_at,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Take an issue out of the public archive, or put it back - whatever its status
+#[tracing::instrument(name = "Changing the archive visibility of an issue", skip(pool))]
+pub async fn set_archive_visibility(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<ArchiveBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let result = sqlx::query_scalar!(
+        r#"
+        UPDATE newsletter_issues
+        SET hide_from_archive = $2, updated_at = $3
+        WHERE newsletter_issue_id = $1
+        RETURNING slug
+        "#,
+        newsletter_issue_id.into_inner(),
+        body.hidden,
+        Utc::now()
+    )
+    .fetch_optional(pool.get_ref())
+    .await;
+    match result {
+        Ok(Some(slug)) => HttpResponse::Ok().json(serde_json::json!({
+            "slug": slug,
+            "hide_from_archive": body.hidden,
+        })),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Lock the issue for the rest of the transaction, so its status cannot change under us
+async fn lock_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueStatus>, sqlx::Error> {
+    let status = sqlx::query_scalar!(
+        r#"
+        SELECT status FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    status
+        .map(|status| IssueStatus::try_from(status).map_err(|e| sqlx::Error::Decode(e.into())))
+        .transpose()
+}
+
+fn not_a_draft(status: IssueStatus) -> HttpResponse {
+    HttpResponse::Conflict().body(format!(
+        "The issue is {}, only drafts can be changed.",
+        status.as_str()
+    ))
+}
@@ -0,0 +1,277 @@
+use crate::domain::SuppressionTarget;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewListBody {
+    slug: String,
+    name: String,
+    /// Whether opens and clicks of the list's issues are tracked
+    #[serde(default = "tracking_default")]
+    #[schema(default = true)]
+    tracking: bool,
+    /// Whether signups must pass a CAPTCHA
+    #[serde(default)]
+    captcha: bool,
+    /// e.g. `["ourcompany.com"]` for a list only colleagues may sign up to
+    #[serde(default)]
+    allowed_domains: Vec<String>,
+}
+
+fn tracking_default() -> bool {
+    true
+}
+
+// e.g. `{"enabled": false}` - turns a setting of a list on or off
+#[derive(serde::Deserialize)]
+pub struct EnabledBody {
+    enabled: bool,
+}
+
+// e.g. `{"domains": ["ourcompany.com"]}` - `[]` takes signups from any domain again
+#[derive(serde::Deserialize)]
+pub struct AllowedDomainsBody {
+    domains: Vec<String>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ListSummary {
+    id: Uuid,
+    slug: String,
+    name: String,
+    is_default: bool,
+    tracking: bool,
+    captcha: bool,
+    allowed_domains: Vec<String>,
+    created_at: DateTime<Utc>,
+    /// Member counts by membership status
+    pending_confirmation: i64,
+    confirmed: i64,
+    unsubscribed: i64,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ListListing {
+    lists: Vec<ListSummary>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/lists",
+    tag = "admin",
+    security(("admin" = []), ("api_key" = ["lists:write"])),
+    request_body = NewListBody,
+    responses(
+        (status = 201, description = "The new list, with its `id`"),
+        (status = 400, description = "Why the list was turned away", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "The API key lacks the scope"),
+        (status = 409, description = "A list with this slug already exists", body = String, content_type = "text/plain"),
+    )
+)]
+#[tracing::instrument(name = "Creating a list", skip(body, pool), fields(slug = %body.slug))]
+pub async fn create_list(body: web::Json<NewListBody>, pool: web::Data<PgPool>) -> HttpResponse {
+    let NewListBody {
+        slug,
+        name,
+        tracking,
+        captcha,
+        allowed_domains,
+    } = body.into_inner();
+    if let Err(e) = validate_slug(&slug) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let name = name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().body("The list name must not be empty.");
+    }
+    let allowed_domains = match parse_domains(&allowed_domains) {
+        Ok(allowed_domains) => allowed_domains,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO lists (id, slug, name, tracking, captcha, allowed_domains, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        id,
+        slug,
+        name,
+        tracking,
+        captcha,
+        &allowed_domains,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::Conflict().body(format!("A list with slug `{}` already exists.", slug))
+        }
+        Ok(_) => HttpResponse::Created().json(serde_json::json!({
+            "id": id,
+            "slug": slug,
+            "name": name,
+            "tracking": tracking,
+            "captcha": captcha,
+            "allowed_domains": allowed_domains,
+        })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists",
+    tag = "admin",
+    security(("admin" = []), ("api_key" = ["lists:read"])),
+    responses(
+        (status = 200, description = "Every list, oldest first", body = ListListing),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "The API key lacks the scope"),
+    )
+)]
+#[tracing::instrument(name = "Listing lists", skip(pool))]
+pub async fn list_lists(pool: web::Data<PgPool>) -> HttpResponse {
+    let result = sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT lists.id, lists.slug, lists.name, lists.is_default, lists.tracking,
+            lists.captcha, lists.allowed_domains, lists.created_at,
+            COUNT(*) FILTER (WHERE list_memberships.status = 'pending_confirmation') AS "pending_confirmation!",
+            COUNT(*) FILTER (WHERE list_memberships.status = 'confirmed') AS "confirmed!",
+            COUNT(*) FILTER (WHERE list_memberships.status = 'unsubscribed') AS "unsubscribed!"
+        FROM lists
+        LEFT JOIN list_memberships ON list_memberships.list_id = lists.id
+        GROUP BY lists.id
+        ORDER BY lists.created_at, lists.slug
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+    match result {
+        Ok(lists) => HttpResponse::Ok().json(ListListing { lists }),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Issues already sent keep their pixel and links, turning tracking off stops counting new ones
+// only for the issues sent from now on
+#[tracing::instrument(name = "Setting the tracking of a list", skip(body, pool))]
+pub async fn set_list_tracking(
+    slug: web::Path<String>,
+    body: web::Json<EnabledBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let result = sqlx::query!(
+        "UPDATE lists SET tracking = $2 WHERE slug = $1",
+        slug.into_inner(),
+        body.enabled
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "tracking": body.enabled })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Takes effect with the next signup - the form must show the widget before turning it on
+#[tracing::instrument(name = "Setting the CAPTCHA of a list", skip(body, pool))]
+pub async fn set_list_captcha(
+    slug: web::Path<String>,
+    body: web::Json<EnabledBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let result = sqlx::query!(
+        "UPDATE lists SET captcha = $2 WHERE slug = $1",
+        slug.into_inner(),
+        body.enabled
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "captcha": body.enabled })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Members from other domains stay on the list, it only applies to new signups
+#[tracing::instrument(name = "Setting the allowed domains of a list", skip(body, pool))]
+pub async fn set_list_allowed_domains(
+    slug: web::Path<String>,
+    body: web::Json<AllowedDomainsBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let domains = match parse_domains(&body.domains) {
+        Ok(domains) => domains,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let result = sqlx::query!(
+        "UPDATE lists SET allowed_domains = $2 WHERE slug = $1",
+        slug.into_inner(),
+        &domains
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "allowed_domains": domains })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Normalised like the domains on the suppression list, e.g. `Bücher.de` -> `xn--bcher-kva.de`
+fn parse_domains(domains: &[String]) -> Result<Vec<String>, String> {
+    let mut parsed = Vec::new();
+    for domain in domains {
+        match SuppressionTarget::parse(domain)? {
+            SuppressionTarget::Domain(domain) if !parsed.contains(&domain) => parsed.push(domain),
+            SuppressionTarget::Domain(_) => {}
+            SuppressionTarget::Email(_) => {
+                return Err(format!("{} is not a valid domain.", domain.trim()))
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+// Slugs end up in URLs, e.g. `/lists/security-advisories/subscriptions`
+pub(crate) fn validate_slug(slug: &str) -> Result<(), String> {
+    let is_valid = !slug.is_empty()
+        && slug.len() <= 64
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-');
+    if is_valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{}` is not a valid slug: use up to 64 lowercase letters, digits and inner hyphens.",
+            slug
+        ))
+    }
+}
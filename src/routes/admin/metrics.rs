@@ -0,0 +1,22 @@
+use crate::bot_protection::BotProtection;
+use actix_web::{web, HttpResponse};
+use std::fmt::Write;
+
+// In the Prometheus text format, for a scraper with admin credentials
+// counts start over with every process, as Prometheus expects of counters
+pub async fn get_metrics(bot_protection: web::Data<BotProtection>) -> HttpResponse {
+    let mut body = String::new();
+    body.push_str("# HELP signup_bot_rejections_total Signups ignored as coming from bots, by failed check.\n");
+    body.push_str("# TYPE signup_bot_rejections_total counter\n");
+    for (check, count) in bot_protection.rejections() {
+        writeln!(
+            body,
+            "signup_bot_rejections_total{{check=\"{}\"}} {}",
+            check, count
+        )
+        .unwrap();
+    }
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
@@ -0,0 +1,39 @@
+pub mod api_keys;
+pub mod erase;
+pub mod export;
+pub mod feeds;
+pub mod fields;
+pub mod import;
+pub mod issues;
+pub mod lists;
+pub mod metrics;
+pub mod newsletters;
+pub mod previews;
+pub mod routes;
+pub mod segments;
+pub mod stats;
+pub mod subscribers;
+pub mod suppressions;
+pub mod tags;
+pub mod templates;
+pub mod users;
+
+pub use api_keys::*;
+pub use erase::*;
+pub use export::*;
+pub use feeds::*;
+pub use fields::*;
+pub use import::*;
+pub use issues::*;
+pub use lists::*;
+pub use metrics::*;
+pub use newsletters::*;
+pub use previews::*;
+pub use routes::*;
+pub use segments::*;
+pub use stats::*;
+pub use subscribers::*;
+pub use suppressions::*;
+pub use tags::*;
+pub use templates::*;
+pub use users::*;
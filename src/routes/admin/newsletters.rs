@@ -0,0 +1,408 @@
+use crate::domain::IssueStatus;
+use crate::markdown;
+use crate::routes::{
+    compile, get_default_list, get_list_by_slug, get_template, MailingList, StoredTemplate,
+};
+use crate::segment::Segment;
+use crate::templates::{Digest, EmailTemplate, TemplateKind};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::types::Json;
+use sqlx::{Connection, PgPool, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+
+// Of the archive slug made from an issue's title
+const MAX_SLUG_LENGTH: usize = 80;
+
+#[derive(serde::Deserialize)]
+pub struct NewsletterBody {
+    title: String,
+    // Either the content itself, or the name of an `issue` template to send
+    content: Option<Content>,
+    template: Option<String>,
+    // Name of the layout template to wrap the content in, `default` when left out
+    layout: Option<String>,
+    // Slugs of the lists to send to, the default list when left out
+    lists: Option<Vec<String>>,
+    // Only send to the subscribers on those lists matching this expression, see `Segment`
+    segment: Option<String>,
+    // Keep the issue out of the public archive once it is sent
+    #[serde(default)]
+    hide_from_archive: bool,
+}
+
+// `{"markdown": "..."}`, or both versions written by hand `{"html": "...", "text": "..."}`
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Markdown { markdown: String },
+    Rendered { html: String, text: String },
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewBody {
+    markdown: String,
+}
+
+pub(crate) enum PublishError {
+    // The body does not make an issue, e.g. the content uses an unknown variable
+    Invalid(String),
+    UnknownList(String),
+    UnknownTemplate(String, TemplateKind),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for PublishError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl PublishError {
+    pub(crate) fn into_response(self) -> HttpResponse {
+        match self {
+            PublishError::Invalid(e) => HttpResponse::BadRequest().body(e),
+            PublishError::UnknownList(slug) => {
+                HttpResponse::BadRequest().body(format!("There is no list with slug `{}`.", slug))
+            }
+            PublishError::UnknownTemplate(name, kind) => HttpResponse::BadRequest().body(format!(
+                "There is no {} template named `{}`.",
+                kind.as_str(),
+                name
+            )),
+            PublishError::Database(e) => {
+                tracing::error!("Failed to store the newsletter issue: {:?}", e);
+                HttpResponse::InternalServerError().finish()
+            }
+        }
+    }
+}
+
+/// An issue whose body was checked, ready to be stored.
+pub(crate) struct PreparedIssue {
+    pub(crate) title: String,
+    pub(crate) content: EmailTemplate,
+    pub(crate) markdown: Option<String>,
+    pub(crate) layout_id: Uuid,
+    pub(crate) list_ids: Vec<Uuid>,
+    pub(crate) segment: Option<String>,
+    pub(crate) hide_from_archive: bool,
+    // Only issues made from a feed have one, the content is then a digest template
+    pub(crate) digest: Option<Digest>,
+}
+
+// Store the issue and queue one delivery per confirmed subscriber on any of the lists
+// the emails themselves go out from the background worker, hence `202 Accepted`
+#[tracing::instrument(name = "Publishing a newsletter issue", skip(body, pool), fields(title = %body.title))]
+pub async fn publish_newsletter(
+    body: web::Json<NewsletterBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match publish(&pool, body.into_inner()).await {
+        Ok((newsletter_issue_id, recipients)) => HttpResponse::Accepted().json(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "recipients": recipients,
+        })),
+        Err(e) => e.into_response(),
+    }
+}
+
+// What a Markdown issue will look like, before any merge variables are filled in
+#[tracing::instrument(name = "Previewing a newsletter issue", skip(body))]
+pub async fn preview_newsletter(body: web::Json<PreviewBody>) -> HttpResponse {
+    let EmailTemplate { html, text } = markdown::render(&body.markdown);
+    HttpResponse::Ok().json(serde_json::json!({ "html": html, "text": text }))
+}
+
+async fn publish(pool: &PgPool, body: NewsletterBody) -> Result<(Uuid, u64), PublishError> {
+    let mut transaction = pool.begin().await?;
+    let issue = prepare_issue(&mut transaction, body).await?;
+    let newsletter_issue_id = insert_issue(&mut transaction, &issue, IssueStatus::Draft).await?;
+    let recipients = start_sending(&mut transaction, newsletter_issue_id).await?;
+    transaction.commit().await?;
+    Ok((newsletter_issue_id, recipients))
+}
+
+// Check everything the body refers to exists, and that the content compiles
+pub(crate) async fn prepare_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    body: NewsletterBody,
+) -> Result<PreparedIssue, PublishError> {
+    let title = body.title.trim().to_string();
+    if title.is_empty() {
+        return Err(PublishError::Invalid("The title must not be empty.".into()));
+    }
+    if let Some(segment) = &body.segment {
+        Segment::parse(segment).map_err(PublishError::Invalid)?;
+    }
+    let lists = resolve_lists(transaction, body.lists).await?;
+    let (content, markdown) = match (body.content, body.template) {
+        (Some(content), None) => {
+            let (content, markdown) = match content {
+                Content::Markdown { markdown } => (markdown::render(&markdown), Some(markdown)),
+                Content::Rendered { html, text } => (EmailTemplate { html, text }, None),
+            };
+            compile(&mut **transaction, &content, TemplateKind::Issue)
+                .await?
+                .map_err(PublishError::Invalid)?;
+            (content, markdown)
+        }
+        // Stored templates were compiled when they were saved
+        (None, Some(name)) => {
+            let stored = resolve_template(transaction, name, TemplateKind::Issue).await?;
+            (stored.template, None)
+        }
+        _ => {
+            return Err(PublishError::Invalid(
+                "Send either `content` or `template`.".into(),
+            ))
+        }
+    };
+    let layout_name = body.layout.unwrap_or_else(|| "default".into());
+    let layout = resolve_template(transaction, layout_name, TemplateKind::Layout).await?;
+    Ok(PreparedIssue {
+        title,
+        content,
+        markdown,
+        layout_id: layout.id,
+        list_ids: lists.iter().map(|list| list.id).collect(),
+        segment: body.segment,
+        hide_from_archive: body.hide_from_archive,
+        digest: None,
+    })
+}
+
+pub(crate) async fn insert_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue: &PreparedIssue,
+    status: IssueStatus,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, markdown, segment, layout_id,
+            hide_from_archive, digest, status, created_at, updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11)
+        "#,
+        newsletter_issue_id,
+        issue.title,
+        issue.content.text,
+        issue.content.html,
+        issue.markdown,
+        issue.segment,
+        issue.layout_id,
+        issue.hide_from_archive,
+        issue.digest.as_ref().map(Json) as Option<Json<&Digest>>,
+        status.as_str(),
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    store_issue_lists(transaction, newsletter_issue_id, &issue.list_ids).await?;
+    Ok(newsletter_issue_id)
+}
+
+pub(crate) async fn update_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    issue: &PreparedIssue,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, markdown = $5, segment = $6,
+            layout_id = $7, hide_from_archive = $8, updated_at = $9
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        issue.title,
+        issue.content.text,
+        issue.content.html,
+        issue.markdown,
+        issue.segment,
+        issue.layout_id,
+        issue.hide_from_archive,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    store_issue_lists(transaction, newsletter_issue_id, &issue.list_ids).await
+}
+
+async fn store_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT DISTINCT $1::uuid, list_id FROM UNNEST($2::uuid[]) AS list_id
+        "#,
+        newsletter_issue_id,
+        list_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+// `slug` as it is, unless an issue already has it
+async fn set_slug(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    slug: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET slug = CASE
+            WHEN EXISTS (SELECT 1 FROM newsletter_issues WHERE slug = $2)
+            THEN $2 || '-' || left(newsletter_issue_id::text, 8)
+            ELSE $2
+        END
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        slug
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+// Queue one delivery per confirmed subscriber on any of the issue's lists, matching its segment
+// the caller must hold the issue, so it is only ever queued once
+pub async fn start_sending(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let issue = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'sending', published_at = $2, updated_at = $2
+        WHERE newsletter_issue_id = $1
+        RETURNING title, segment
+        "#,
+        newsletter_issue_id,
+        Utc::now()
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    // The archive address of the issue, e.g. `/archive/our-spring-update`
+    // a title used before gets the start of the issue's id appended
+    let slug = slugify(&issue.title);
+    // In a savepoint, as a failed statement would abort the whole transaction
+    let mut savepoint = transaction.begin().await?;
+    match set_slug(&mut savepoint, newsletter_issue_id, &slug).await {
+        Ok(()) => savepoint.commit().await?,
+        // Another issue with the title took the slug since `EXISTS` looked
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            savepoint.rollback().await?;
+            let suffixed = format!(
+                "{}-{}",
+                slug,
+                &newsletter_issue_id.simple().to_string()[..8]
+            );
+            set_slug(transaction, newsletter_issue_id, &suffixed).await?;
+        }
+        Err(e) => return Err(e),
+    }
+    // It was checked when the issue was stored
+    let segment = issue
+        .segment
+        .as_deref()
+        .map(Segment::parse)
+        .transpose()
+        .map_err(|e| sqlx::Error::Decode(e.into()))?;
+    // `DISTINCT` is what makes someone on two of the lists get the issue once
+    // daily and weekly subscribers get theirs at the next midnight, or Monday midnight, UTC
+    let mut query = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id, execute_after) \
+        SELECT DISTINCT ",
+    );
+    query
+        .push_bind(newsletter_issue_id)
+        .push(
+            "::uuid, list_memberships.subscriber_id, \
+            CASE subscriptions.delivery_window \
+                WHEN 'daily' THEN (date_trunc('day', now() AT TIME ZONE 'UTC') + interval '1 day') AT TIME ZONE 'UTC' \
+                WHEN 'weekly' THEN (date_trunc('week', now() AT TIME ZONE 'UTC') + interval '1 week') AT TIME ZONE 'UTC' \
+                ELSE now() \
+            END \
+            FROM list_memberships \
+            JOIN subscriptions ON subscriptions.id = list_memberships.subscriber_id \
+            WHERE list_memberships.status = 'confirmed' AND subscriptions.status = 'confirmed' \
+            AND list_memberships.list_id IN (\
+                SELECT list_id FROM newsletter_issue_lists WHERE newsletter_issue_id = ",
+        )
+        .push_bind(newsletter_issue_id)
+        .push(")");
+    if let Some(segment) = &segment {
+        query.push(" AND ");
+        segment.push_condition(&mut query);
+    }
+    Ok(query
+        .build()
+        .execute(&mut **transaction)
+        .await?
+        .rows_affected())
+}
+
+async fn resolve_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    slugs: Option<Vec<String>>,
+) -> Result<Vec<MailingList>, PublishError> {
+    let slugs = match slugs {
+        Some(slugs) if !slugs.is_empty() => slugs,
+        _ => return Ok(vec![get_default_list(&mut **transaction).await?]),
+    };
+    let mut lists = Vec::with_capacity(slugs.len());
+    for slug in slugs {
+        match get_list_by_slug(&mut **transaction, &slug).await? {
+            Some(list) => lists.push(list),
+            None => return Err(PublishError::UnknownList(slug)),
+        }
+    }
+    Ok(lists)
+}
+
+pub(crate) async fn resolve_template(
+    transaction: &mut Transaction<'_, Postgres>,
+    name: String,
+    kind: TemplateKind,
+) -> Result<StoredTemplate, PublishError> {
+    match get_template(&mut **transaction, &name).await? {
+        Some(stored) if stored.kind == kind => Ok(stored),
+        _ => Err(PublishError::UnknownTemplate(name, kind)),
+    }
+}
+
+// Lowercase letters, digits and single `-`s, e.g. `Spring update: what's new?` -> `spring-update-what-s-new`
+fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.len() == MAX_SLUG_LENGTH {
+            break;
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "issue".into()
+    } else {
+        slug.into()
+    }
+}
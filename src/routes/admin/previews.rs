@@ -0,0 +1,213 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::{get_issue, personalize, PersonalizedEmail, Recipient};
+use crate::startup::{ApplicationBaseUrl, PostalAddress};
+use actix_web::{web, HttpResponse};
+use serde_json::{Map, Value};
+use sqlx::types::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// A test send is for a few colleagues, not a list
+const MAX_TEST_RECIPIENTS: usize = 10;
+// Merge variables when the issue is not rendered as a particular subscriber
+const PLACEHOLDER_NAME: &str = "Test Subscriber";
+const PLACEHOLDER_EMAIL: &str = "subscriber@example.com";
+// Stands in for the token of the unsubscribe link, the link does not work in previews
+const PREVIEW_TOKEN: &str = "preview";
+
+// e.g. `{"emails": ["editor@example.com"], "subscriber_id": "..."}`
+#[derive(serde::Deserialize)]
+pub struct TestSendBody {
+    emails: Vec<String>,
+    // Render with this subscriber's merge variables, placeholders when left out
+    subscriber_id: Option<Uuid>,
+}
+
+// e.g. `?subscriber_id=...&format=text`
+#[derive(serde::Deserialize, Debug)]
+pub struct PreviewQuery {
+    subscriber_id: Option<Uuid>,
+    #[serde(default)]
+    format: PreviewFormat,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    #[default]
+    Html,
+    Text,
+}
+
+// Send the rendered issue right away, outside of the delivery queue
+// nothing is recorded in `issue_deliveries` and no subscriber token is created
+#[tracing::instrument(
+    name = "Sending a test issue",
+    skip(body, pool, email_client, base_url, postal_address)
+)]
+pub async fn send_test_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<TestSendBody>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    postal_address: web::Data<PostalAddress>,
+) -> HttpResponse {
+    let TestSendBody {
+        emails,
+        subscriber_id,
+    } = body.into_inner();
+    if emails.is_empty() || emails.len() > MAX_TEST_RECIPIENTS {
+        return HttpResponse::BadRequest().body(format!(
+            "Send the test to between 1 and {} addresses.",
+            MAX_TEST_RECIPIENTS
+        ));
+    }
+    let emails = match emails
+        .into_iter()
+        .map(SubscriberEmail::parse)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(emails) => emails,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let email = match render_preview(
+        &pool,
+        newsletter_issue_id.into_inner(),
+        subscriber_id,
+        &base_url.0,
+        &postal_address.0,
+    )
+    .await
+    {
+        Ok(email) => email,
+        Err(response) => return response,
+    };
+    let subject = format!("[TEST] {}", email.subject);
+    for recipient in &emails {
+        if let Err(e) = email_client
+            .send_email(recipient.display(), &subject, &email.html, &email.text)
+            .await
+        {
+            tracing::error!("Failed to send the test issue: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "recipients": emails.iter().map(AsRef::as_ref).collect::<Vec<&str>>(),
+    }))
+}
+
+// The issue as a subscriber would see it in their inbox, to open in a browser
+#[tracing::instrument(name = "Previewing an issue", skip(pool, base_url, postal_address))]
+pub async fn preview_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    query: web::Query<PreviewQuery>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    postal_address: web::Data<PostalAddress>,
+) -> HttpResponse {
+    let email = match render_preview(
+        &pool,
+        newsletter_issue_id.into_inner(),
+        query.subscriber_id,
+        &base_url.0,
+        &postal_address.0,
+    )
+    .await
+    {
+        Ok(email) => email,
+        Err(response) => return response,
+    };
+    match query.format {
+        PreviewFormat::Html => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(email.html),
+        PreviewFormat::Text => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(email.text),
+    }
+}
+
+// The error is the response to give
+async fn render_preview(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Option<Uuid>,
+    base_url: &str,
+    postal_address: &str,
+) -> Result<PersonalizedEmail, HttpResponse> {
+    let internal_error = |e: sqlx::Error| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        HttpResponse::InternalServerError().finish()
+    };
+    let issue = get_issue(pool, newsletter_issue_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| HttpResponse::NotFound().finish())?;
+    let recipient = get_preview_recipient(pool, newsletter_issue_id, subscriber_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| {
+            HttpResponse::BadRequest().body("There is no subscriber with this `subscriber_id`.")
+        })?;
+    personalize(issue, recipient, base_url, PREVIEW_TOKEN, postal_address)
+        .map_err(|e| HttpResponse::BadRequest().body(format!("The issue does not render: {}", e)))
+}
+
+// `None` if there is no such subscriber
+// the list is one of the issue's the subscriber is on, if any, for the header and footer
+async fn get_preview_recipient(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Option<Uuid>,
+) -> Result<Option<Recipient>, sqlx::Error> {
+    let list = sqlx::query!(
+        r#"
+        SELECT lists.slug, lists.name
+        FROM newsletter_issue_lists
+        JOIN lists ON lists.id = newsletter_issue_lists.list_id
+        LEFT JOIN list_memberships ON list_memberships.list_id = lists.id
+            AND list_memberships.subscriber_id = $2
+        WHERE newsletter_issue_lists.newsletter_issue_id = $1
+        ORDER BY list_memberships.subscriber_id IS NULL, lists.slug
+        LIMIT 1
+        "#,
+        newsletter_issue_id,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await?;
+    let Some(subscriber_id) = subscriber_id else {
+        return Ok(Some(Recipient {
+            email: PLACEHOLDER_EMAIL.into(),
+            email_display: PLACEHOLDER_EMAIL.into(),
+            name: PLACEHOLDER_NAME.into(),
+            attributes: Json(Map::new()),
+            list_slug: list.slug,
+            list_name: list.name,
+            // Previews and test sends must not count as opens and clicks
+            tracking: false,
+        }));
+    };
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT email, email_display, name, attributes AS "attributes: Json<Map<String, Value>>"
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscriber.map(|subscriber| Recipient {
+        email: subscriber.email,
+        email_display: subscriber.email_display,
+        name: subscriber.name,
+        attributes: subscriber.attributes,
+        list_slug: list.slug,
+        list_name: list.name,
+        tracking: false,
+    }))
+}
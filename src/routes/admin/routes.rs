@@ -0,0 +1,25 @@
+use crate::route_table::{RouteInfo, RouteTable};
+use actix_web::{web, HttpResponse};
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct RouteListing<'a> {
+    routes: &'a [RouteInfo],
+}
+
+// Every route the app serves, in the order they are matched, to audit what is exposed
+#[utoipa::path(
+    get,
+    path = "/admin/routes",
+    tag = "admin",
+    security(("admin" = []), ("api_key" = ["routes:read"])),
+    responses(
+        (status = 200, description = "Every route, in the order they are matched", body = RouteListing),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "The API key lacks the scope"),
+    )
+)]
+pub async fn list_routes(route_table: web::Data<RouteTable>) -> HttpResponse {
+    HttpResponse::Ok().json(RouteListing {
+        routes: route_table.routes(),
+    })
+}
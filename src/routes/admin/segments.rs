@@ -0,0 +1,34 @@
+use crate::segment::Segment;
+use actix_web::{web, HttpResponse};
+use sqlx::{PgPool, QueryBuilder};
+
+#[derive(serde::Deserialize)]
+pub struct SegmentBody {
+    segment: String,
+}
+
+// How many subscribers an expression matches, to check it before publishing to it
+#[tracing::instrument(name = "Previewing a segment", skip(body, pool), fields(segment = %body.segment))]
+pub async fn preview_segment(
+    body: web::Json<SegmentBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let segment = match Segment::parse(&body.segment) {
+        Ok(segment) => segment,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let mut query =
+        QueryBuilder::new("SELECT COUNT(*) FROM subscriptions WHERE status <> 'erased' AND ");
+    segment.push_condition(&mut query);
+    match query
+        .build_query_scalar::<i64>()
+        .fetch_one(pool.get_ref())
+        .await
+    {
+        Ok(matching) => HttpResponse::Ok().json(serde_json::json!({ "matching": matching })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
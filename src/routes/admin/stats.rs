@@ -0,0 +1,131 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Serialize)]
+pub struct IssueStats {
+    newsletter_issue_id: Uuid,
+    deliveries: DeliveryCounts,
+    opens: EngagementCounts,
+    clicks: EngagementCounts,
+    // Most clicked first
+    links: Vec<LinkClicks>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryCounts {
+    sent: i64,
+    failed: i64,
+    skipped: i64,
+    // Not sent because the address or its domain is on the suppression list
+    suppressed: i64,
+    // Sent with the tracking pixel and links - the rates are out of these
+    tracked: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct EngagementCounts {
+    total: i64,
+    // Subscribers who opened, or clicked, at least once
+    unique: i64,
+    rate: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct LinkClicks {
+    url: String,
+    clicks: i64,
+    unique: i64,
+}
+
+// Counts only, who opened or clicked what stays in `tracking_events`
+#[tracing::instrument(name = "Fetching the stats of an issue", skip(pool))]
+pub async fn get_issue_stats(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let result: Result<Option<IssueStats>, sqlx::Error> = async {
+        let exists = sqlx::query_scalar!(
+            "SELECT 1 FROM newsletter_issues WHERE newsletter_issue_id = $1",
+            newsletter_issue_id
+        )
+        .fetch_optional(pool.get_ref())
+        .await?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+        let deliveries = sqlx::query_as!(
+            DeliveryCounts,
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE status = 'sent') AS "sent!",
+                COUNT(*) FILTER (WHERE status = 'failed') AS "failed!",
+                COUNT(*) FILTER (WHERE status = 'skipped') AS "skipped!",
+                COUNT(*) FILTER (WHERE status = 'suppressed') AS "suppressed!",
+                COUNT(*) FILTER (WHERE status = 'sent' AND tracked) AS "tracked!"
+            FROM issue_deliveries
+            WHERE newsletter_issue_id = $1
+            "#,
+            newsletter_issue_id
+        )
+        .fetch_one(pool.get_ref())
+        .await?;
+        let events = sqlx::query!(
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE kind = 'open') AS "opens!",
+                COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'open') AS "unique_opens!",
+                COUNT(*) FILTER (WHERE kind = 'click') AS "clicks!",
+                COUNT(DISTINCT subscriber_id) FILTER (WHERE kind = 'click') AS "unique_clicks!"
+            FROM tracking_events
+            WHERE newsletter_issue_id = $1
+            "#,
+            newsletter_issue_id
+        )
+        .fetch_one(pool.get_ref())
+        .await?;
+        let links = sqlx::query_as!(
+            LinkClicks,
+            r#"
+            SELECT url AS "url!", COUNT(*) AS "clicks!",
+                COUNT(DISTINCT subscriber_id) AS "unique!"
+            FROM tracking_events
+            WHERE newsletter_issue_id = $1 AND kind = 'click'
+            GROUP BY url
+            ORDER BY COUNT(*) DESC, url
+            "#,
+            newsletter_issue_id
+        )
+        .fetch_all(pool.get_ref())
+        .await?;
+        let rate = |unique: i64| match deliveries.tracked {
+            0 => 0.0,
+            tracked => unique as f64 / tracked as f64,
+        };
+        Ok(Some(IssueStats {
+            newsletter_issue_id,
+            opens: EngagementCounts {
+                total: events.opens,
+                unique: events.unique_opens,
+                rate: rate(events.unique_opens),
+            },
+            clicks: EngagementCounts {
+                total: events.clicks,
+                unique: events.unique_clicks,
+                rate: rate(events.unique_clicks),
+            },
+            deliveries,
+            links,
+        }))
+    }
+    .await;
+    match result {
+        Ok(Some(stats)) => HttpResponse::Ok().json(stats),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
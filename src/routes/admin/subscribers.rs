@@ -0,0 +1,225 @@
+use crate::domain::SubscriptionStatus;
+use actix_web::{web, HttpResponse};
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_aux::field_attributes::deserialize_option_number_from_string;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+// Filters shared by every admin view over `subscriptions`
+// e.g. `?status=confirmed&subscribed_after=2025-01-01T00:00:00Z&search=guin`
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct SubscriberFilters {
+    pub status: Option<String>,
+    pub subscribed_after: Option<DateTime<Utc>>,
+    pub subscribed_before: Option<DateTime<Utc>>,
+    pub search: Option<String>,
+}
+
+impl SubscriberFilters {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(status) = &self.status {
+            SubscriptionStatus::try_from(status.clone())?;
+        }
+        if let (Some(after), Some(before)) = (self.subscribed_after, self.subscribed_before) {
+            if after > before {
+                return Err(
+                    "`subscribed_after` must not be later than `subscribed_before`.".into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Append the filters as `AND ...` conditions - the query must already have a `WHERE` clause
+    pub fn push_conditions(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        if let Some(status) = &self.status {
+            query
+                .push(" AND status = ")
+                .push_bind(status.to_lowercase());
+        }
+        if let Some(after) = self.subscribed_after {
+            query.push(" AND subscribed_at >= ").push_bind(after);
+        }
+        if let Some(before) = self.subscribed_before {
+            query.push(" AND subscribed_at < ").push_bind(before);
+        }
+        if let Some(search) = self.search.as_deref().map(str::trim) {
+            if !search.is_empty() {
+                let pattern = format!("%{}%", escape_like_pattern(search));
+                query
+                    .push(" AND (name ILIKE ")
+                    .push_bind(pattern.clone())
+                    .push(" OR email ILIKE ")
+                    .push_bind(pattern)
+                    .push(")");
+            }
+        }
+    }
+}
+
+// `%` and `_` typed by the user are literal characters, not wildcards
+pub(crate) fn escape_like_pattern(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ListSubscribersQuery {
+    #[serde(flatten)]
+    filters: SubscriberFilters,
+    // `flatten` makes every value a string, so numbers have to be parsed from one
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    limit: Option<i64>,
+    cursor: Option<String>,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub email_display: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub import_source: Option<String>,
+    // e.g. `["role_account"]`, see `signup_policy`
+    pub policy_flags: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberPage {
+    subscribers: Vec<SubscriberRecord>,
+    // Pass back as `cursor` to get the next page, `null` on the last page
+    next_cursor: Option<String>,
+    // Number of subscribers matching the filters, across all pages
+    total: i64,
+}
+
+// The position of a subscriber in the `subscribed_at DESC, id DESC` ordering
+// encoded as an opaque string so clients do not depend on its shape
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let raw = format!(
+            "{}|{}",
+            self.subscribed_at
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            self.id
+        );
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    fn decode(s: &str) -> Result<Cursor, String> {
+        let invalid = || "The cursor is not valid.".to_string();
+        let raw = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let (subscribed_at, id) = raw.split_once('|').ok_or_else(invalid)?;
+        Ok(Cursor {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at)
+                .map_err(|_| invalid())?
+                .with_timezone(&Utc),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[tracing::instrument(name = "Listing subscribers", skip(query, pool), fields(filters = ?query.filters))]
+pub async fn list_subscribers(
+    query: web::Query<ListSubscribersQuery>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let query = query.into_inner();
+    if let Err(e) = query.filters.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return HttpResponse::BadRequest()
+            .body(format!("`limit` must be between 1 and {}.", MAX_PAGE_SIZE));
+    }
+    let cursor = match query.cursor.as_deref().map(Cursor::decode).transpose() {
+        Ok(cursor) => cursor,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let page = fetch_subscriber_page(&pool, &query.filters, cursor, limit).await;
+    let total = count_subscribers(&pool, &query.filters).await;
+    match (page, total) {
+        (Ok((subscribers, next_cursor)), Ok(total)) => HttpResponse::Ok().json(SubscriberPage {
+            subscribers,
+            next_cursor: next_cursor.map(|c| c.encode()),
+            total,
+        }),
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Fetching a page of subscribers", skip(pool, filters, cursor))]
+async fn fetch_subscriber_page(
+    pool: &PgPool,
+    filters: &SubscriberFilters,
+    cursor: Option<Cursor>,
+    limit: i64,
+) -> Result<(Vec<SubscriberRecord>, Option<Cursor>), sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT id, email, email_display, name, status, subscribed_at, import_source, policy_flags FROM subscriptions WHERE TRUE",
+    );
+    filters.push_conditions(&mut query);
+    if let Some(cursor) = cursor {
+        query
+            .push(" AND (subscribed_at, id) < (")
+            .push_bind(cursor.subscribed_at)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+    // Fetch one extra row to know whether there is a next page
+    query
+        .push(" ORDER BY subscribed_at DESC, id DESC LIMIT ")
+        .push_bind(limit + 1);
+
+    let mut subscribers = query
+        .build_query_as::<SubscriberRecord>()
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|last| Cursor {
+            subscribed_at: last.subscribed_at,
+            id: last.id,
+        })
+    } else {
+        None
+    };
+    Ok((subscribers, next_cursor))
+}
+
+#[tracing::instrument(name = "Counting subscribers", skip(pool, filters))]
+async fn count_subscribers(pool: &PgPool, filters: &SubscriberFilters) -> Result<i64, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT count(*) FROM subscriptions WHERE TRUE");
+    filters.push_conditions(&mut query);
+    query
+        .build_query_scalar::<i64>()
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })
+}
@@ -0,0 +1,214 @@
+use crate::domain::SuppressionTarget;
+use crate::import::ImportedRow;
+use crate::routes::escape_like_pattern;
+use crate::suppression::{add_suppression, SuppressionSource};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// e.g. `?search=example.com`
+#[derive(serde::Deserialize, Debug)]
+pub struct SuppressionFilters {
+    search: Option<String>,
+}
+
+// e.g. `{"value": "test.example", "reason": "Internal test domain"}`
+#[derive(serde::Deserialize)]
+pub struct SuppressionBody {
+    // An address, or a domain
+    value: String,
+    reason: String,
+}
+
+// e.g. `?reason=Legal%20request` - for the rows without a reason of their own
+#[derive(serde::Deserialize, Debug)]
+pub struct SuppressionImportQuery {
+    reason: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Suppression {
+    id: Uuid,
+    kind: String,
+    value: String,
+    reason: String,
+    source: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct SuppressionImportReport {
+    accepted: u64,
+    // Already suppressed, or earlier in the file
+    duplicates: Vec<ImportedRow>,
+    rejected: Vec<ImportedRow>,
+}
+
+#[tracing::instrument(name = "Listing suppressions", skip(pool))]
+pub async fn list_suppressions(
+    filters: web::Query<SuppressionFilters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let pattern = filters
+        .search
+        .as_deref()
+        .map(str::trim)
+        .filter(|search| !search.is_empty())
+        .map(|search| format!("%{}%", escape_like_pattern(search)));
+    let result = sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT id, kind, value, reason, source, created_at
+        FROM suppressions
+        WHERE $1::text IS NULL OR value ILIKE $1 OR reason ILIKE $1
+        ORDER BY created_at DESC, value
+        "#,
+        pattern
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+    match result {
+        Ok(suppressions) => {
+            HttpResponse::Ok().json(serde_json::json!({ "suppressions": suppressions }))
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Takes effect right away, also on issues that are still being sent
+#[tracing::instrument(name = "Adding a suppression", skip(body, pool), fields(value = %body.value))]
+pub async fn create_suppression(
+    body: web::Json<SuppressionBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let target = match SuppressionTarget::parse(&body.value) {
+        Ok(target) => target,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let reason = body.reason.trim();
+    if reason.is_empty() {
+        return HttpResponse::BadRequest().body("The reason must not be empty.");
+    }
+    match add_suppression(pool.get_ref(), &target, reason, SuppressionSource::Admin).await {
+        Ok(Some(id)) => HttpResponse::Created().json(serde_json::json!({
+            "id": id,
+            "kind": target.kind(),
+            "value": target.value(),
+            "reason": reason,
+        })),
+        Ok(None) => {
+            HttpResponse::Conflict().body(format!("`{}` is already suppressed.", target.value()))
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Subscribers whose status changed because of a bounce or a complaint stay as they are
+#[tracing::instrument(name = "Removing a suppression", skip(pool))]
+pub async fn delete_suppression(id: web::Path<Uuid>, pool: web::Data<PgPool>) -> HttpResponse {
+    let result = sqlx::query!("DELETE FROM suppressions WHERE id = $1", id.into_inner())
+        .execute(pool.get_ref())
+        .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// A CSV with a `value` column and an optional `reason` one, e.g. `curl --data-binary @legal.csv`
+// all or nothing - a failure leaves the suppression list as it was
+#[tracing::instrument(name = "Importing suppressions", skip(body, pool))]
+pub async fn import_suppressions(
+    query: web::Query<SuppressionImportQuery>,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(&body[..]);
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => {
+            return HttpResponse::BadRequest().body(format!("Failed to read the CSV header: {}", e))
+        }
+    };
+    let column = |name: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(name));
+    let Some(value_index) = column("value") else {
+        return HttpResponse::BadRequest().body("The CSV has no `value` column.");
+    };
+    let reason_index = column("reason");
+    let default_reason = query.reason.as_deref().map(str::trim).unwrap_or_default();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => return HttpResponse::BadRequest().body(format!("Invalid CSV: {}", e)),
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let reason = reason_index
+            .and_then(|index| record.get(index))
+            .filter(|reason| !reason.is_empty())
+            .unwrap_or(default_reason)
+            .to_string();
+        let target = record
+            .get(value_index)
+            .ok_or_else(|| "The row has no value.".to_string())
+            .and_then(SuppressionTarget::parse)
+            .and_then(|target| match reason.is_empty() {
+                true => Err("The row has no reason, and no `reason` was given.".into()),
+                false => Ok(target),
+            });
+        rows.push((line, target, reason));
+    }
+
+    let result: Result<SuppressionImportReport, sqlx::Error> = async {
+        let mut report = SuppressionImportReport::default();
+        let mut transaction = pool.begin().await?;
+        for (line, target, reason) in rows {
+            let target = match target {
+                Ok(target) => target,
+                Err(reason) => {
+                    report.rejected.push(ImportedRow { line, reason });
+                    continue;
+                }
+            };
+            let added = add_suppression(
+                &mut *transaction,
+                &target,
+                &reason,
+                SuppressionSource::Import,
+            )
+            .await?;
+            match added {
+                Some(_) => report.accepted += 1,
+                None => report.duplicates.push(ImportedRow {
+                    line,
+                    reason: format!("`{}` is already suppressed.", target.value()),
+                }),
+            }
+        }
+        transaction.commit().await?;
+        Ok(report)
+    }
+    .await;
+    match result {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            tracing::error!("Failed to import suppressions: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::domain::Tag;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct TagsBody {
+    tags: Vec<String>,
+}
+
+// Tagging is additive - tags the subscriber already has are left as they are
+#[tracing::instrument(name = "Tagging a subscriber", skip(body, pool))]
+pub async fn tag_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<TagsBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let subscriber_id = subscriber_id.into_inner();
+    let tags = match body
+        .tags
+        .iter()
+        .map(|tag| Tag::parse(tag))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(tags) => tags,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    match subscriber_exists(&pool, subscriber_id).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    if add_tags(pool.get_ref(), subscriber_id, &tags)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    match get_tags(&pool, subscriber_id).await {
+        Ok(tags) => HttpResponse::Ok().json(serde_json::json!({ "tags": tags })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Untagging a subscriber", skip(pool))]
+pub async fn untag_subscriber(
+    path: web::Path<(Uuid, String)>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let (subscriber_id, tag) = path.into_inner();
+    let result = sqlx::query!(
+        "DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = $2",
+        subscriber_id,
+        tag.to_lowercase()
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Saving subscriber tags", skip(executor, tags))]
+pub async fn add_tags(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    tags: &[Tag],
+) -> Result<(), sqlx::Error> {
+    let tags: Vec<String> = tags.iter().map(|tag| tag.as_ref().to_string()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag, tagged_at)
+        SELECT $1, tag, $3 FROM UNNEST($2::text[]) AS tag
+        ON CONFLICT (subscriber_id, tag) DO NOTHING
+        "#,
+        subscriber_id,
+        &tags,
+        Utc::now()
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+async fn get_tags(pool: &PgPool, subscriber_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag",
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+async fn subscriber_exists(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM subscriptions WHERE id = $1 AND status <> 'erased') AS "exists!""#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
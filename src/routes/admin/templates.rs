@@ -0,0 +1,202 @@
+use crate::routes::{get_field_keys, validate_slug};
+use crate::templates::{EmailTemplate, TemplateKind};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+// e.g. `{"name": "welcome", "kind": "issue", "html": "<p>Hi {{ name }}</p>", "text": "Hi {{ name }}"}`
+#[derive(serde::Deserialize)]
+pub struct NewTemplateBody {
+    name: String,
+    kind: String,
+    html: String,
+    text: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TemplateBody {
+    html: String,
+    text: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct TemplateSummary {
+    name: String,
+    kind: String,
+    html: String,
+    text: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+/// A template as stored in `email_templates`.
+pub struct StoredTemplate {
+    pub id: Uuid,
+    pub kind: TemplateKind,
+    pub template: EmailTemplate,
+}
+
+#[tracing::instrument(name = "Creating an email template", skip(body, pool), fields(name = %body.name))]
+pub async fn create_template(
+    body: web::Json<NewTemplateBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let NewTemplateBody {
+        name,
+        kind,
+        html,
+        text,
+    } = body.into_inner();
+    if let Err(e) = validate_slug(&name) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let kind = match TemplateKind::try_from(kind) {
+        Ok(kind) => kind,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let template = EmailTemplate { html, text };
+    match compile(pool.get_ref(), &template, kind).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => return HttpResponse::BadRequest().body(e),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO email_templates (id, name, kind, html, text, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $6)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        kind.as_str(),
+        template.html,
+        template.text,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::Conflict().body(format!("A template named `{}` already exists.", name))
+        }
+        Ok(_) => HttpResponse::Created().json(serde_json::json!({
+            "name": name,
+            "kind": kind,
+        })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// The kind of a template is fixed, only its versions can be replaced
+#[tracing::instrument(name = "Updating an email template", skip(body, pool))]
+pub async fn update_template(
+    name: web::Path<String>,
+    body: web::Json<TemplateBody>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let TemplateBody { html, text } = body.into_inner();
+    let stored = match get_template(pool.get_ref(), &name).await {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let template = EmailTemplate { html, text };
+    match compile(pool.get_ref(), &template, stored.kind).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => return HttpResponse::BadRequest().body(e),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    let result = sqlx::query!(
+        r#"
+        UPDATE email_templates
+        SET html = $2, text = $3, updated_at = $4
+        WHERE id = $1
+        "#,
+        stored.id,
+        template.html,
+        template.text,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "name": name.into_inner(),
+            "kind": stored.kind,
+        })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Listing email templates", skip(pool))]
+pub async fn list_templates(pool: web::Data<PgPool>) -> HttpResponse {
+    let result = sqlx::query_as!(
+        TemplateSummary,
+        r#"
+        SELECT name, kind, html, text, created_at, updated_at
+        FROM email_templates
+        ORDER BY kind, name
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+    match result {
+        Ok(templates) => HttpResponse::Ok().json(serde_json::json!({ "templates": templates })),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Check a template against the custom fields that currently exist
+// the outer error is the database's, the inner one is for whoever wrote the template
+pub async fn compile(
+    executor: impl PgExecutor<'_>,
+    template: &EmailTemplate,
+    kind: TemplateKind,
+) -> Result<Result<(), String>, sqlx::Error> {
+    let attribute_keys = get_field_keys(executor).await?;
+    Ok(template.compile(kind, &attribute_keys))
+}
+
+#[tracing::instrument(name = "Fetching an email template", skip(executor))]
+pub async fn get_template(
+    executor: impl PgExecutor<'_>,
+    name: &str,
+) -> Result<Option<StoredTemplate>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, kind, html, text
+        FROM email_templates
+        WHERE name = $1
+        "#,
+        name
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    row.map(|row| {
+        // The kind was validated on the way in
+        let kind = TemplateKind::try_from(row.kind).map_err(|e| sqlx::Error::Decode(e.into()))?;
+        Ok(StoredTemplate {
+            id: row.id,
+            kind,
+            template: EmailTemplate {
+                html: row.html,
+                text: row.text,
+            },
+        })
+    })
+    .transpose()
+}
@@ -0,0 +1,277 @@
+use crate::authentication::{compute_password_hash, UserId};
+use crate::domain::Role;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// e.g. `{"username": "intern", "role": "viewer"}`
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct InviteBody {
+    username: String,
+    /// `owner`, `editor`, `viewer` or `analyst`
+    role: String,
+}
+
+// e.g. `{"role": "editor"}`
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RoleBody {
+    role: String,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct InvitedUser {
+    user_id: Uuid,
+    username: String,
+    role: Role,
+    /// To hand to them - shown this once, only its hash is stored
+    password: String,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct UserSummary {
+    user_id: Uuid,
+    username: String,
+    role: String,
+    deactivated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct UserListing {
+    users: Vec<UserSummary>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "admin",
+    security(("admin" = [])),
+    responses(
+        (status = 200, description = "Every user, deactivated ones too", body = UserListing),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Only owners manage users"),
+    )
+)]
+#[tracing::instrument(name = "Listing users", skip(pool))]
+pub async fn list_users(pool: web::Data<PgPool>) -> HttpResponse {
+    let result = sqlx::query_as!(
+        UserSummary,
+        "SELECT user_id, username, role, deactivated_at FROM users ORDER BY username"
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+    match result {
+        Ok(users) => HttpResponse::Ok().json(UserListing { users }),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Creates the user with a random password, for the owner to pass on
+#[utoipa::path(
+    post,
+    path = "/admin/users",
+    tag = "admin",
+    security(("admin" = [])),
+    request_body = InviteBody,
+    responses(
+        (status = 201, description = "The new user, with their password", body = InvitedUser),
+        (status = 400, description = "Why the user was turned away", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Only owners manage users"),
+        (status = 409, description = "The username is taken", body = String, content_type = "text/plain"),
+    )
+)]
+#[tracing::instrument(name = "Inviting a user", skip(body, pool), fields(username = %body.username))]
+pub async fn invite_user(body: web::Json<InviteBody>, pool: web::Data<PgPool>) -> HttpResponse {
+    let InviteBody { username, role } = body.into_inner();
+    let username = username.trim();
+    if username.is_empty() {
+        return HttpResponse::BadRequest().body("The username must not be empty.");
+    }
+    let role = match Role::try_from(role) {
+        Ok(role) => role,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let password: String = std::iter::repeat_with(|| thread_rng().sample(Alphanumeric))
+        .map(char::from)
+        .take(24)
+        .collect();
+    // Hashing is CPU-bound - keep it off the async executor threads
+    let password_hash = match web::block({
+        let password = password.clone();
+        move || compute_password_hash(&password)
+    })
+    .await
+    {
+        Ok(Ok(password_hash)) => password_hash,
+        Ok(Err(e)) => {
+            tracing::error!("Failed to hash the password: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+        Err(e) => {
+            tracing::error!("Failed to spawn blocking task: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let user_id = Uuid::new_v4();
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        user_id,
+        username,
+        password_hash,
+        role.as_str()
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::Conflict().body(format!("A user named `{}` already exists.", username))
+        }
+        Ok(_) => HttpResponse::Created().json(InvitedUser {
+            user_id,
+            username: username.to_string(),
+            role,
+            password,
+        }),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Takes effect with their next request, API keys included
+#[utoipa::path(
+    put,
+    path = "/admin/users/{user_id}/role",
+    tag = "admin",
+    security(("admin" = [])),
+    params(("user_id" = Uuid, Path, description = "The user to change")),
+    request_body = RoleBody,
+    responses(
+        (status = 200, description = "The role was changed"),
+        (status = 400, description = "Not a role", body = String, content_type = "text/plain"),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Only owners manage users"),
+        (status = 404, description = "There is no such active user"),
+        (status = 409, description = "It would leave no active owner", body = String, content_type = "text/plain"),
+    )
+)]
+#[tracing::instrument(name = "Changing the role of a user", skip(body, pool, actor), fields(actor = %*actor))]
+pub async fn set_user_role(
+    user_id: web::Path<Uuid>,
+    body: web::Json<RoleBody>,
+    pool: web::Data<PgPool>,
+    actor: web::ReqData<UserId>,
+) -> HttpResponse {
+    let role = match Role::try_from(body.into_inner().role) {
+        Ok(role) => role,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    match change_user(&pool, user_id.into_inner(), UserChange::Role(role)).await {
+        Ok(None) => HttpResponse::Ok().json(serde_json::json!({ "role": role })),
+        Ok(Some(response)) => response,
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// They can no longer sign in, nor can their API keys - the user is kept, e.g. for `gdpr_audit_log`
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_id}/deactivate",
+    tag = "admin",
+    security(("admin" = [])),
+    params(("user_id" = Uuid, Path, description = "The user to deactivate")),
+    responses(
+        (status = 204, description = "The user was deactivated"),
+        (status = 401, description = "Missing or invalid credentials"),
+        (status = 403, description = "Only owners manage users"),
+        (status = 404, description = "There is no such active user"),
+        (status = 409, description = "It would leave no active owner", body = String, content_type = "text/plain"),
+    )
+)]
+#[tracing::instrument(name = "Deactivating a user", skip(pool, actor), fields(actor = %*actor))]
+pub async fn deactivate_user(
+    user_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    actor: web::ReqData<UserId>,
+) -> HttpResponse {
+    match change_user(&pool, user_id.into_inner(), UserChange::Deactivate).await {
+        Ok(None) => HttpResponse::NoContent().finish(),
+        Ok(Some(response)) => response,
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+enum UserChange {
+    Role(Role),
+    Deactivate,
+}
+
+// `Ok(Some(response))` when the change was refused - no such active user, or it would remove
+// the last active owner, locked meanwhile so two owners cannot demote each other at once
+async fn change_user(
+    pool: &PgPool,
+    user_id: Uuid,
+    change: UserChange,
+) -> Result<Option<HttpResponse>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let owners = sqlx::query_scalar!(
+        "SELECT user_id FROM users WHERE role = 'owner' AND deactivated_at IS NULL FOR UPDATE"
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    let exists = sqlx::query_scalar!(
+        r#"SELECT 1 AS "exists!" FROM users WHERE user_id = $1 AND deactivated_at IS NULL"#,
+        user_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .is_some();
+    if !exists {
+        return Ok(Some(HttpResponse::NotFound().finish()));
+    }
+    let removes_owner = !matches!(change, UserChange::Role(Role::Owner));
+    if removes_owner && owners == [user_id] {
+        return Ok(Some(
+            HttpResponse::Conflict().body("There must be at least one active owner."),
+        ));
+    }
+    match change {
+        UserChange::Role(role) => {
+            sqlx::query!(
+                "UPDATE users SET role = $2 WHERE user_id = $1",
+                user_id,
+                role.as_str()
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        UserChange::Deactivate => {
+            sqlx::query!(
+                "UPDATE users SET deactivated_at = $2 WHERE user_id = $1",
+                user_id,
+                Utc::now()
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(None)
+}
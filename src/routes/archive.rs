@@ -0,0 +1,337 @@
+use crate::issue_delivery_worker::archive_url;
+use crate::routes::{get_list_by_slug, render_page, MailingList};
+use crate::startup::{ApplicationBaseUrl, PostalAddress};
+use crate::templates::{render_content, Digest, EmailTemplate, ListVariables, MergeVariables};
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde_json::Map;
+use sqlx::types::Json;
+use sqlx::PgPool;
+
+// How many of the latest issues a feed carries
+const FEED_LENGTH: i64 = 20;
+
+// The pages are templates too, named `.html` / `.xml` so minijinja escapes what goes in them
+// our own links are built from the base url and slugs, hence `|safe`
+const INDEX_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{ heading }}</title>
+{% if feed_url %}<link rel="alternate" type="application/atom+xml" title="{{ heading }}" href="{{ feed_url|safe }}">{% endif %}
+</head>
+<body>
+<h1>{{ heading }}</h1>
+{% if issues %}<ul>
+{% for issue in issues %}<li><a href="{{ issue.url|safe }}">{{ issue.title }}</a> <time datetime="{{ issue.published_at }}">{{ issue.date }}</time></li>
+{% endfor %}</ul>
+{% else %}<p>Nothing has been published yet.</p>
+{% endif %}</body>
+</html>"#;
+
+const ISSUE_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{ title }} - {{ list.name }}</title>
+<link rel="alternate" type="application/atom+xml" title="{{ list.name }}" href="{{ feed_url|safe }}">
+</head>
+<body>
+<p><a href="{{ index_url|safe }}">{{ list.name }} archive</a></p>
+<h1>{{ title }}</h1>
+<p><time datetime="{{ published_at }}">{{ date }}</time></p>
+{{ content|safe }}
+</body>
+</html>"#;
+
+const FEED: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{{ list.name }}</title>
+<id>{{ feed_url|safe }}</id>
+<link rel="self" type="application/atom+xml" href="{{ feed_url|safe }}"/>
+<link rel="alternate" type="text/html" href="{{ index_url|safe }}"/>
+<author><name>{{ list.name }}</name></author>
+<updated>{{ updated }}</updated>
+{% for entry in entries %}<entry>
+<title>{{ entry.title }}</title>
+<id>{{ entry.url|safe }}</id>
+<link rel="alternate" type="text/html" href="{{ entry.url|safe }}"/>
+<published>{{ entry.published_at }}</published>
+<updated>{{ entry.published_at }}</updated>
+<content type="html">{{ entry.html }}</content>
+</entry>
+{% endfor %}</feed>"#;
+
+// e.g. `?list=weekly`
+#[derive(serde::Deserialize, Debug)]
+pub struct ArchiveFilters {
+    list: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct IssueLink {
+    title: String,
+    url: String,
+    // RFC 3339 for machines, `date` for people
+    published_at: String,
+    date: String,
+}
+
+/// A sent issue that is not hidden from the archive.
+struct ArchivedIssue {
+    slug: String,
+    title: String,
+    html_content: String,
+    text_content: String,
+    published_at: DateTime<Utc>,
+    digest: Option<Json<Digest>>,
+}
+
+// Every public issue, newest first - or only those sent to `?list=`
+#[tracing::instrument(name = "Showing the archive", skip(pool, base_url))]
+pub async fn archive_index(
+    filters: web::Query<ArchiveFilters>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let list = match &filters.list {
+        None => None,
+        Some(slug) => match get_list_by_slug(pool.get_ref(), slug).await {
+            Ok(Some(list)) => Some(list),
+            Ok(None) => return HttpResponse::NotFound().finish(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+    };
+    let result = sqlx::query!(
+        r#"
+        SELECT slug AS "slug!", title, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE slug IS NOT NULL AND NOT hide_from_archive
+            AND ($1::uuid IS NULL OR EXISTS (
+                SELECT 1 FROM newsletter_issue_lists
+                WHERE newsletter_issue_lists.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+                    AND newsletter_issue_lists.list_id = $1
+            ))
+        ORDER BY published_at DESC, slug
+        "#,
+        list.as_ref().map(|list| list.id)
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+    let issues: Vec<IssueLink> = match result {
+        Ok(rows) => rows
+            .into_iter()
+            .map(|row| IssueLink {
+                url: archive_url(&base_url.0, &row.slug),
+                title: row.title,
+                published_at: row.published_at.to_rfc3339(),
+                date: format_date(row.published_at),
+            })
+            .collect(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let context = minijinja::context! {
+        heading => list.as_ref().map_or("Archive".into(), |list| format!("{} archive", list.name)),
+        feed_url => list.as_ref().map(|list| feed_url(&base_url.0, &list.slug)),
+        issues,
+    };
+    render_page(
+        "archive.html",
+        INDEX_PAGE,
+        context,
+        "text/html; charset=utf-8",
+    )
+}
+
+// One issue, rendered without a recipient - `404` for drafts and hidden issues alike
+#[tracing::instrument(
+    name = "Showing an archived issue",
+    skip(pool, base_url, postal_address)
+)]
+pub async fn archived_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    postal_address: web::Data<PostalAddress>,
+) -> HttpResponse {
+    // The header names one of the lists the issue went to
+    let result = sqlx::query!(
+        r#"
+        SELECT newsletter_issues.slug AS "slug!", title, html_content, text_content,
+            published_at AS "published_at!", digest AS "digest: Json<Digest>",
+            lists.id AS list_id, lists.slug AS list_slug, lists.name AS list_name,
+            lists.captcha AS list_captcha, lists.allowed_domains AS list_allowed_domains
+        FROM newsletter_issues
+        JOIN newsletter_issue_lists
+            ON newsletter_issue_lists.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+        JOIN lists ON lists.id = newsletter_issue_lists.list_id
+        WHERE newsletter_issues.slug = $1 AND NOT hide_from_archive
+        ORDER BY lists.slug
+        LIMIT 1
+        "#,
+        slug.into_inner()
+    )
+    .fetch_optional(pool.get_ref())
+    .await;
+    let (issue, list) = match result {
+        Ok(Some(row)) => (
+            ArchivedIssue {
+                slug: row.slug,
+                title: row.title,
+                html_content: row.html_content,
+                text_content: row.text_content,
+                published_at: row.published_at,
+                digest: row.digest,
+            },
+            MailingList {
+                id: row.list_id,
+                slug: row.list_slug,
+                name: row.list_name,
+                captcha: row.list_captcha,
+                allowed_domains: row.list_allowed_domains,
+            },
+        ),
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let content = match render_archived(&issue, &list, &postal_address.0) {
+        Ok(content) => content,
+        Err(e) => return render_error(e),
+    };
+    let context = minijinja::context! {
+        title => issue.title,
+        list => ListVariables { name: list.name, slug: list.slug.clone() },
+        index_url => format!("{}/archive?list={}", base_url.0, list.slug),
+        feed_url => feed_url(&base_url.0, &list.slug),
+        published_at => issue.published_at.to_rfc3339(),
+        date => format_date(issue.published_at),
+        content => content.html,
+    };
+    render_page(
+        "issue.html",
+        ISSUE_PAGE,
+        context,
+        "text/html; charset=utf-8",
+    )
+}
+
+// An Atom feed of the latest public issues sent to the list, content included
+#[tracing::instrument(name = "Serving a list feed", skip(pool, base_url, postal_address))]
+pub async fn list_feed(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    postal_address: web::Data<PostalAddress>,
+) -> HttpResponse {
+    let list = match get_list_by_slug(pool.get_ref(), &slug).await {
+        Ok(Some(list)) => list,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let result = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT slug AS "slug!", title, html_content, text_content, published_at AS "published_at!",
+            digest AS "digest: Json<Digest>"
+        FROM newsletter_issues
+        JOIN newsletter_issue_lists
+            ON newsletter_issue_lists.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+        WHERE newsletter_issue_lists.list_id = $1
+            AND slug IS NOT NULL AND NOT hide_from_archive
+        ORDER BY published_at DESC, slug
+        LIMIT $2
+        "#,
+        list.id,
+        FEED_LENGTH
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+    let issues = match result {
+        Ok(issues) => issues,
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let mut entries = Vec::with_capacity(issues.len());
+    for issue in &issues {
+        let content = match render_archived(issue, &list, &postal_address.0) {
+            Ok(content) => content,
+            Err(e) => return render_error(e),
+        };
+        entries.push(minijinja::context! {
+            title => issue.title,
+            url => archive_url(&base_url.0, &issue.slug),
+            published_at => issue.published_at.to_rfc3339(),
+            html => content.html,
+        });
+    }
+    // A feed without entries was last updated... now, as far as readers are concerned
+    let updated = issues
+        .first()
+        .map_or_else(Utc::now, |issue| issue.published_at);
+    let context = minijinja::context! {
+        list => ListVariables { name: list.name, slug: list.slug.clone() },
+        feed_url => feed_url(&base_url.0, &list.slug),
+        index_url => format!("{}/archive?list={}", base_url.0, list.slug),
+        updated => updated.to_rfc3339(),
+        entries,
+    };
+    render_page(
+        "feed.xml",
+        FEED,
+        context,
+        "application/atom+xml; charset=utf-8",
+    )
+}
+
+fn feed_url(base_url: &str, list_slug: &str) -> String {
+    format!("{}/lists/{}/feed.xml", base_url, list_slug)
+}
+
+// e.g. `March 1, 2025`
+fn format_date(date: DateTime<Utc>) -> String {
+    date.format("%B %-d, %Y").to_string()
+}
+
+// The archive is public - no name, email or unsubscribe link, see `MergeVariables`
+fn render_archived(
+    issue: &ArchivedIssue,
+    list: &MailingList,
+    postal_address: &str,
+) -> Result<EmailTemplate, minijinja::Error> {
+    let variables = MergeVariables {
+        name: None,
+        email: None,
+        attribute: Map::new(),
+        title: issue.title.clone(),
+        list: ListVariables {
+            name: list.name.clone(),
+            slug: list.slug.clone(),
+        },
+        unsubscribe_url: None,
+        preferences_url: None,
+        view_in_browser_url: None,
+        postal_address: postal_address.into(),
+        digest: issue.digest.as_ref().map(|digest| digest.0.clone()),
+    };
+    let content = EmailTemplate {
+        html: issue.html_content.clone(),
+        text: issue.text_content.clone(),
+    };
+    render_content(&content, &variables)
+}
+
+// Issues are compiled before they are stored, but e.g. a filter can still fail on a value
+fn render_error(e: minijinja::Error) -> HttpResponse {
+    tracing::error!(error.cause_chain = ?e, "Failed to render an archived issue.");
+    HttpResponse::InternalServerError().finish()
+}
@@ -6,6 +6,12 @@ pub async fn greet(req: HttpRequest) -> impl Responder {
     format!("Hello {}!", &name)
 }
 
+#[utoipa::path(
+    get,
+    path = "/health_check",
+    tag = "health",
+    responses((status = 200, description = "The application is up"))
+)]
 pub async fn health_check(_req: HttpRequest) -> HttpResponse {
     // `Ok()` returns a Builder instance
     // `finish()` converts the Builder into a Response instance and sends it back to the client
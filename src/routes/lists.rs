@@ -0,0 +1,315 @@
+use crate::bot_protection::BotProtection;
+use crate::domain::{SubscriptionStatus, TokenPurpose};
+use crate::email_client::EmailClient;
+use crate::routes::{
+    get_subscriber_id_from_token, message_page, preferences_path, render_page, subscribe_to,
+    FormData, TokenParameters,
+};
+use crate::signup_policy::SignupPolicy;
+use crate::startup::ApplicationBaseUrl;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+// Following the link only asks - link scanners and prefetchers must not unsubscribe anyone
+const UNSUBSCRIBE_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Unsubscribe from {{ list_name }}</title>
+</head>
+<body>
+<h1>Unsubscribe from {{ list_name }}</h1>
+<p>You will no longer get emails from {{ list_name }}. Any other lists you are on are not affected.</p>
+<form method="post" action="{{ action_url|safe }}">
+<p><button type="submit">Unsubscribe</button></p>
+</form>
+</body>
+</html>"#;
+
+#[derive(Debug, serde::Serialize)]
+pub struct MailingList {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+    // Signups must pass a CAPTCHA
+    pub captcha: bool,
+    // Signups are only taken from these domains, if any
+    pub allowed_domains: Vec<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/lists/{slug}/subscriptions",
+    tag = "subscriptions",
+    params(("slug" = String, Path, description = "The list to sign up to, e.g. `weekly`")),
+    request_body(content(
+        (FormData = "application/x-www-form-urlencoded"),
+        (FormData = "application/json"),
+    )),
+    responses(
+        (status = 200, description = "A confirmation email is on its way - or the address needs none"),
+        (status = 400, description = "Why the details were turned away", body = String, content_type = "text/plain"),
+        (status = 404, description = "There is no such list"),
+        (status = 429, description = "Too many signups from this client or for this address"),
+    )
+)]
+#[tracing::instrument(
+    name = "Adding a new subscriber to a list",
+    skip(form, pool, email_client, base_url, bot_protection, signup_policy)
+)]
+pub async fn subscribe_to_list(
+    slug: web::Path<String>,
+    form: web::Either<web::Json<FormData>, web::Form<FormData>>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    bot_protection: web::Data<BotProtection>,
+    signup_policy: web::Data<SignupPolicy>,
+) -> HttpResponse {
+    let list = match get_list_by_slug(pool.get_ref(), &slug).await {
+        Ok(Some(list)) => list,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    subscribe_to(
+        &pool,
+        &email_client,
+        &base_url.0,
+        &bot_protection,
+        &signup_policy,
+        &list,
+        form.into_inner(),
+    )
+    .await
+}
+
+// The link in the confirmation email lands here, a page for people who click it
+// confirming a second time is harmless
+#[tracing::instrument(
+    name = "Confirming a list subscription",
+    skip(parameters, pool, base_url)
+)]
+pub async fn confirm_list_subscription(
+    slug: web::Path<String>,
+    parameters: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let (list, subscriber_id) =
+        match resolve_membership(&pool, &slug, &parameters.subscription_token).await {
+            Ok(Some(membership)) => membership,
+            Ok(None) => return invalid_confirmation_link(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    match confirm_membership(&pool, &list, subscriber_id).await {
+        Ok(true) => {
+            let preferences_url = format!(
+                "{}{}",
+                base_url.0,
+                preferences_path(&parameters.subscription_token)
+            );
+            message_page(
+                StatusCode::OK,
+                "You are subscribed",
+                &format!(
+                    "Thank you for confirming your subscription to {}.",
+                    list.name
+                ),
+                Some((&preferences_url, "Manage your email preferences")),
+            )
+        }
+        // The token belongs to someone who never signed up to this list
+        Ok(false) => invalid_confirmation_link(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+fn invalid_confirmation_link() -> HttpResponse {
+    message_page(
+        StatusCode::UNAUTHORIZED,
+        "This link is not valid",
+        "Use the link in the latest confirmation email we sent you.",
+        None,
+    )
+}
+
+// The unsubscribe link in every email, a page with a button to post to `unsubscribe_from_list`
+#[tracing::instrument(name = "Showing the unsubscribe page", skip(parameters, pool))]
+pub async fn unsubscribe_page(
+    slug: web::Path<String>,
+    parameters: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let token = &parameters.subscription_token;
+    // Only a token we issued makes it into the page
+    let list = match resolve_membership(&pool, &slug, token).await {
+        Ok(Some((list, _))) => list,
+        Ok(None) => return invalid_unsubscribe_link(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let context = minijinja::context! {
+        list_name => list.name,
+        action_url => format!(
+            "/lists/{}/subscriptions/unsubscribe?subscription_token={}",
+            list.slug, token
+        ),
+    };
+    render_page(
+        "unsubscribe.html",
+        UNSUBSCRIBE_PAGE,
+        context,
+        "text/html; charset=utf-8",
+    )
+}
+
+fn invalid_unsubscribe_link() -> HttpResponse {
+    message_page(
+        StatusCode::UNAUTHORIZED,
+        "This link is not valid",
+        "Use the unsubscribe link in the latest email we sent you.",
+        None,
+    )
+}
+
+// Posted by the unsubscribe page, and by mail clients as a one-click unsubscribe (RFC 8058)
+// leaves the subscriber on every other list they are on
+#[tracing::instrument(name = "Unsubscribing from a list", skip(parameters, pool))]
+pub async fn unsubscribe_from_list(
+    slug: web::Path<String>,
+    parameters: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let (list, subscriber_id) =
+        match resolve_membership(&pool, &slug, &parameters.subscription_token).await {
+            Ok(Some(membership)) => membership,
+            Ok(None) => return invalid_unsubscribe_link(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    let result = sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = $3, unsubscribed_at = $4
+        WHERE list_id = $1 AND subscriber_id = $2 AND status <> $3
+        "#,
+        list.id,
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed.as_str(),
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await;
+    match result {
+        Ok(_) => message_page(
+            StatusCode::OK,
+            "You are unsubscribed",
+            &format!("You will no longer get emails from {}.", list.name),
+            None,
+        ),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// `Ok(None)` for an unknown list or token - callers answer both with 401
+async fn resolve_membership(
+    pool: &PgPool,
+    slug: &str,
+    subscription_token: &str,
+) -> Result<Option<(MailingList, Uuid)>, sqlx::Error> {
+    let Some(list) = get_list_by_slug(pool, slug).await? else {
+        return Ok(None);
+    };
+    let subscriber_id =
+        get_subscriber_id_from_token(pool, subscription_token, TokenPurpose::Preferences).await?;
+    Ok(subscriber_id.map(|subscriber_id| (list, subscriber_id)))
+}
+
+// Returns `false` when the subscriber is not on the list at all
+#[tracing::instrument(name = "Marking list membership as confirmed", skip(pool))]
+async fn confirm_membership(
+    pool: &PgPool,
+    list: &MailingList,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let confirmed = sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = $3, confirmed_at = COALESCE(confirmed_at, $4)
+        WHERE list_id = $1 AND subscriber_id = $2 AND status <> 'unsubscribed'
+        "#,
+        list.id,
+        subscriber_id,
+        SubscriptionStatus::Confirmed.as_str(),
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .rows_affected()
+        > 0;
+    if !confirmed {
+        return Ok(false);
+    }
+    // Confirming any list proves the address is theirs
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $2, confirmed_at = COALESCE(confirmed_at, $3)
+        WHERE id = $1 AND status IN ('pending_confirmation', 'unsubscribed')
+        "#,
+        subscriber_id,
+        SubscriptionStatus::Confirmed.as_str(),
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Looking up a list", skip(executor))]
+pub async fn get_list_by_slug(
+    executor: impl PgExecutor<'_>,
+    slug: &str,
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT id, slug, name, captcha, allowed_domains FROM lists WHERE slug = $1",
+        slug
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+// The list `POST /subscriptions` signs people up to, seeded by the migration that introduced lists
+#[tracing::instrument(name = "Looking up the default list", skip(executor))]
+pub async fn get_default_list(executor: impl PgExecutor<'_>) -> Result<MailingList, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT id, slug, name, captcha, allowed_domains FROM lists WHERE is_default"
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
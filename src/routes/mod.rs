@@ -1,6 +1,26 @@
+pub mod admin;
+pub mod api_docs;
+pub mod archive;
 pub mod health_check;
+pub mod lists;
+pub mod pages;
+pub mod preferences;
+pub mod signup_form;
+pub mod subscriber_data;
 pub mod subscriptions;
+pub mod tracking;
+pub mod webhooks;
 
 // Re-export the modules to make them available when the crate is imported
+pub use admin::*;
+pub use api_docs::*;
+pub use archive::*;
 pub use health_check::*;
+pub use lists::*;
+pub use pages::*;
+pub use preferences::*;
+pub use signup_form::*;
+pub use subscriber_data::*;
 pub use subscriptions::*;
+pub use tracking::*;
+pub use webhooks::*;
@@ -0,0 +1,65 @@
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use minijinja::Environment;
+
+// What a page has to say after a form was posted or a link followed, e.g. "Check your inbox"
+const MESSAGE_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{ heading }}</title>
+</head>
+<body>
+<h1>{{ heading }}</h1>
+<p>{{ message }}</p>
+{% if link_url %}<p><a href="{{ link_url|safe }}">{{ link_text }}</a></p>
+{% endif %}</body>
+</html>"#;
+
+// The pages are templates compiled on every request - there are few of them, and they are small
+pub fn render_page(
+    name: &str,
+    source: &str,
+    context: minijinja::Value,
+    content_type: &str,
+) -> HttpResponse {
+    let env = Environment::new();
+    match env
+        .template_from_named_str(name, source)
+        .and_then(|template| template.render(context))
+    {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(content_type.to_string())
+            .body(body),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to render {}.", name);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// `link` is the url and the text of a link to go on from there
+pub fn message_page(
+    status: StatusCode,
+    heading: &str,
+    message: &str,
+    link: Option<(&str, &str)>,
+) -> HttpResponse {
+    let context = minijinja::context! {
+        heading,
+        message,
+        link_url => link.map(|(url, _)| url),
+        link_text => link.map(|(_, text)| text),
+    };
+    let mut response = render_page(
+        "message.html",
+        MESSAGE_PAGE,
+        context,
+        "text/html; charset=utf-8",
+    );
+    if response.status().is_success() {
+        *response.status_mut() = status;
+    }
+    response
+}
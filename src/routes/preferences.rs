@@ -0,0 +1,496 @@
+use crate::csrf::{CsrfCookie, CSRF_FIELD};
+use crate::domain::{DeliveryWindow, SubscriberEmail, SubscriptionStatus, TokenPurpose};
+use crate::routes::{get_subscriber_id_from_token, message_page, render_page, TokenParameters};
+use crate::signup_policy::is_within;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Two forms, so unsubscribing from everything is one click and not a matter of unticking boxes
+const PREFERENCES_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Your email preferences</title>
+</head>
+<body>
+<h1>Your email preferences</h1>
+{% if notice %}<p role="status">{{ notice }}</p>
+{% endif %}<p>We send our emails to {{ email }}.</p>
+<form method="post" action="{{ action_url|safe }}">
+<p><label>Name <input type="text" name="name" value="{{ name }}" autocomplete="name"></label></p>
+<p><label>When to send <select name="delivery_window">
+{% for window in windows %}<option value="{{ window.value }}"{% if window.value == delivery_window %} selected{% endif %}>{{ window.label }}</option>
+{% endfor %}</select></label></p>
+<fieldset>
+<legend>Lists</legend>
+{% for list in lists %}<p><label><input type="checkbox" name="list.{{ list.slug }}"{% if list.subscribed %} checked{% endif %}> {{ list.name }}</label></p>
+{% else %}<p>There are no lists to choose from.</p>
+{% endfor %}</fieldset>
+<input type="hidden" name="{{ csrf_field }}" value="{{ csrf_token }}">
+<p><button type="submit">Save</button></p>
+</form>
+<form method="post" action="{{ unsubscribe_url|safe }}">
+<input type="hidden" name="{{ csrf_field }}" value="{{ unsubscribe_csrf_token }}">
+<p><button type="submit">Unsubscribe from everything</button></p>
+</form>
+</body>
+</html>"#;
+
+// Checkboxes are named `list.{slug}` and only posted when ticked
+#[derive(serde::Deserialize)]
+pub struct PreferencesForm {
+    name: String,
+    delivery_window: String,
+    #[serde(flatten)]
+    fields: HashMap<String, String>,
+}
+
+struct Subscriber {
+    email: String,
+    name: String,
+    delivery_window: String,
+}
+
+// A list the subscriber can tick or untick
+#[derive(serde::Serialize)]
+struct ListChoice {
+    #[serde(skip)]
+    id: Uuid,
+    slug: String,
+    name: String,
+    subscribed: bool,
+}
+
+#[derive(serde::Serialize)]
+struct WindowChoice {
+    value: &'static str,
+    label: &'static str,
+}
+
+// The link in every email, see the `preferences_url` merge variable
+#[tracing::instrument(
+    name = "Showing the preference center",
+    skip(request, parameters, pool, hmac_secret, base_url)
+)]
+pub async fn preference_center(
+    request: HttpRequest,
+    parameters: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let token = &parameters.subscription_token;
+    let subscriber_id =
+        match get_subscriber_id_from_token(&pool, token, TokenPurpose::Preferences).await {
+            Ok(Some(subscriber_id)) => subscriber_id,
+            Ok(None) => return invalid_link(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    render_preferences(
+        &request,
+        &pool,
+        subscriber_id,
+        token,
+        &hmac_secret.0,
+        &base_url.0,
+        None,
+    )
+    .await
+}
+
+// Ticking a list joins it without another confirmation email - the token came from one of ours
+// the CSRF token was checked by `require_csrf_token`
+#[tracing::instrument(
+    name = "Saving preferences",
+    skip(request, parameters, form, pool, hmac_secret, base_url)
+)]
+pub async fn save_preferences(
+    request: HttpRequest,
+    parameters: web::Query<TokenParameters>,
+    form: web::Form<PreferencesForm>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let token = &parameters.subscription_token;
+    let subscriber_id =
+        match get_subscriber_id_from_token(&pool, token, TokenPurpose::Preferences).await {
+            Ok(Some(subscriber_id)) => subscriber_id,
+            Ok(None) => return invalid_link(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    let form = form.into_inner();
+    let delivery_window = match DeliveryWindow::try_from(form.delivery_window) {
+        Ok(delivery_window) => delivery_window,
+        Err(e) => {
+            let preferences_url = format!("{}{}", base_url.0, preferences_path(token));
+            return message_page(
+                StatusCode::BAD_REQUEST,
+                "Please check your preferences",
+                &e,
+                Some((&preferences_url, "Back to your preferences")),
+            );
+        }
+    };
+    let result = update_preferences(
+        &pool,
+        subscriber_id,
+        form.name.trim(),
+        delivery_window,
+        &form.fields,
+    )
+    .await;
+    match result {
+        Ok(true) => {}
+        Ok(false) => return invalid_link(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    render_preferences(
+        &request,
+        &pool,
+        subscriber_id,
+        token,
+        &hmac_secret.0,
+        &base_url.0,
+        Some("Your preferences were saved."),
+    )
+    .await
+}
+
+// Leaves every list, and stops whatever is still on its way
+#[tracing::instrument(
+    name = "Unsubscribing from everything",
+    skip(parameters, pool, base_url)
+)]
+pub async fn unsubscribe_from_everything(
+    parameters: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let token = &parameters.subscription_token;
+    let subscriber_id =
+        match get_subscriber_id_from_token(&pool, token, TokenPurpose::Preferences).await {
+            Ok(Some(subscriber_id)) => subscriber_id,
+            Ok(None) => return invalid_link(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    if let Err(e) = unsubscribe_everywhere(&pool, subscriber_id).await {
+        tracing::error!("Failed to execute query: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    let preferences_url = format!("{}{}", base_url.0, preferences_path(token));
+    message_page(
+        StatusCode::OK,
+        "You are unsubscribed",
+        "You will not get any more emails from us.",
+        Some((
+            &preferences_url,
+            "Changed your mind? Pick the lists you want again",
+        )),
+    )
+}
+
+async fn render_preferences(
+    request: &HttpRequest,
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+    hmac_secret: &str,
+    base_url: &str,
+    notice: Option<&str>,
+) -> HttpResponse {
+    let subscriber = match get_subscriber(pool, subscriber_id).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return invalid_link(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let lists = match get_list_choices(pool, subscriber_id, &subscriber.email).await {
+        Ok(lists) => lists,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let action = preferences_path(subscription_token);
+    let unsubscribe_action = format!(
+        "/preferences/unsubscribe?subscription_token={}",
+        subscription_token
+    );
+    let csrf_cookie = CsrfCookie::from_request(request);
+    let windows: Vec<WindowChoice> = DeliveryWindow::ALL
+        .iter()
+        .map(|window| WindowChoice {
+            value: window.as_str(),
+            label: window_label(*window),
+        })
+        .collect();
+    let context = minijinja::context! {
+        notice,
+        email => subscriber.email,
+        name => subscriber.name,
+        delivery_window => subscriber.delivery_window,
+        windows,
+        lists,
+        action_url => format!("{}{}", base_url, action),
+        unsubscribe_url => format!("{}{}", base_url, unsubscribe_action),
+        csrf_field => CSRF_FIELD,
+        csrf_token => csrf_cookie.token(&action, hmac_secret),
+        unsubscribe_csrf_token => csrf_cookie.token(&unsubscribe_action, hmac_secret),
+    };
+    let mut response = render_page(
+        "preferences.html",
+        PREFERENCES_PAGE,
+        context,
+        "text/html; charset=utf-8",
+    );
+    // It shows who the link belongs to
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    csrf_cookie.add_to(&mut response, base_url);
+    response
+}
+
+fn window_label(window: DeliveryWindow) -> &'static str {
+    match window {
+        DeliveryWindow::Immediately => "As soon as they are sent",
+        DeliveryWindow::Daily => "Together, at midnight (UTC)",
+        DeliveryWindow::Weekly => "Together, on Monday at midnight (UTC)",
+    }
+}
+
+pub fn preferences_path(subscription_token: &str) -> String {
+    format!("/preferences?subscription_token={}", subscription_token)
+}
+
+fn invalid_link() -> HttpResponse {
+    message_page(
+        StatusCode::UNAUTHORIZED,
+        "This link is not valid",
+        "Use the link in the latest email we sent you.",
+        None,
+    )
+}
+
+// Erased subscribers lose their tokens, this is in case one is left behind
+async fn get_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        "SELECT email_display AS email, name, delivery_window FROM subscriptions WHERE id = $1 AND status <> $2",
+        subscriber_id,
+        SubscriptionStatus::Erased.as_str()
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+// The lists the subscriber is on, and those they could sign up to with their address
+async fn get_list_choices(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    email: &str,
+) -> Result<Vec<ListChoice>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT lists.id, lists.slug, lists.name, lists.allowed_domains,
+            list_memberships.status AS "status?"
+        FROM lists
+        LEFT JOIN list_memberships
+            ON list_memberships.list_id = lists.id AND list_memberships.subscriber_id = $1
+        ORDER BY lists.name, lists.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let email = SubscriberEmail::parse(email.to_string()).ok();
+    let domain = email.as_ref().map(|email| email.domain());
+    Ok(rows
+        .into_iter()
+        .filter(|row| {
+            row.status.is_some()
+                || row.allowed_domains.is_empty()
+                || domain.is_some_and(|domain| {
+                    row.allowed_domains
+                        .iter()
+                        .any(|allowed| is_within(domain, allowed))
+                })
+        })
+        .map(|row| ListChoice {
+            id: row.id,
+            slug: row.slug,
+            name: row.name,
+            subscribed: row.status.as_deref().is_some_and(|status| {
+                status == SubscriptionStatus::PendingConfirmation.as_str()
+                    || status == SubscriptionStatus::Confirmed.as_str()
+            }),
+        })
+        .collect())
+}
+
+// Returns `false` when the subscriber is gone
+#[tracing::instrument(name = "Updating preferences", skip(pool, name, fields))]
+async fn update_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    name: &str,
+    delivery_window: DeliveryWindow,
+    fields: &HashMap<String, String>,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET name = $2, delivery_window = $3
+        WHERE id = $1 AND status <> $4
+        RETURNING email_display
+        "#,
+        subscriber_id,
+        name,
+        delivery_window.as_str(),
+        SubscriptionStatus::Erased.as_str()
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    let Some(updated) = updated else {
+        return Ok(false);
+    };
+    // Lists that were not on the page are left alone, whatever was posted
+    let lists = get_list_choices(&mut *transaction, subscriber_id, &updated.email_display).await?;
+    for list in lists {
+        let ticked = fields.contains_key(&format!("list.{}", list.slug));
+        if ticked && !list.subscribed {
+            join_list(&mut transaction, list.id, subscriber_id).await?;
+        } else if !ticked && list.subscribed {
+            leave_list(&mut transaction, list.id, subscriber_id).await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(true)
+}
+
+// Like confirming a signup, see `confirm_membership`
+async fn join_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at, confirmed_at)
+        VALUES ($1, $2, $3, $4, $4)
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE
+        SET status = EXCLUDED.status,
+            subscribed_at = CASE WHEN list_memberships.status = 'unsubscribed' THEN EXCLUDED.subscribed_at ELSE list_memberships.subscribed_at END,
+            confirmed_at = COALESCE(list_memberships.confirmed_at, EXCLUDED.confirmed_at),
+            unsubscribed_at = NULL
+        "#,
+        list_id,
+        subscriber_id,
+        SubscriptionStatus::Confirmed.as_str(),
+        now
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $2, confirmed_at = COALESCE(confirmed_at, $3)
+        WHERE id = $1 AND status IN ('pending_confirmation', 'unsubscribed')
+        "#,
+        subscriber_id,
+        SubscriptionStatus::Confirmed.as_str(),
+        now
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+async fn leave_list(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = $3, unsubscribed_at = $4
+        WHERE list_id = $1 AND subscriber_id = $2 AND status <> $3
+        "#,
+        list_id,
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed.as_str(),
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+// Bounced and complained subscribers keep their status, it says more
+async fn unsubscribe_everywhere(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships
+        SET status = $2, unsubscribed_at = $3
+        WHERE subscriber_id = $1 AND status <> $2
+        "#,
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed.as_str(),
+        now
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $2
+        WHERE id = $1 AND status IN ('pending_confirmation', 'confirmed')
+        "#,
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed.as_str()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use crate::bot_protection::{BotProtection, FORM_TOKEN_FIELD};
+use crate::csrf::{CsrfCookie, CSRF_FIELD};
+use crate::email_client::EmailClient;
+use crate::routes::{
+    get_list_by_slug, get_list_fields, message_page, render_page, subscribe_to, FormData,
+};
+use crate::signup_policy::SignupPolicy;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::templates::ListVariables;
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+// Custom fields get the input that matches their type, the rules the browser can check too
+// the honeypot is off screen - people never see it, bots fill it in
+const SIGNUP_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Subscribe to {{ list.name }}</title>
+{% if captcha %}<script src="{{ captcha.script_url }}" async defer></script>
+{% endif %}</head>
+<body>
+<h1>Subscribe to {{ list.name }}</h1>
+<form method="post" action="{{ action_url|safe }}">
+<p><label>Email <input type="email" name="email" autocomplete="email" required></label></p>
+<p><label>Name <input type="text" name="name" autocomplete="name"></label></p>
+{% for field in fields %}<p><label>{{ field.label }}
+{% if field.type == "boolean" %}<input type="checkbox" name="{{ field.key }}"{% if field.required %} required{% endif %}>
+{% elif field.type == "choice" %}<select name="{{ field.key }}"{% if field.required %} required{% endif %}>
+{% if not field.required %}<option value=""></option>
+{% endif %}{% for choice in field.rules.choices %}<option>{{ choice }}</option>
+{% endfor %}</select>
+{% elif field.type == "number" %}<input type="number" step="any" name="{{ field.key }}"{% if field.rules.min is defined %} min="{{ field.rules.min }}"{% endif %}{% if field.rules.max is defined %} max="{{ field.rules.max }}"{% endif %}{% if field.required %} required{% endif %}>
+{% elif field.type == "date" %}<input type="date" name="{{ field.key }}"{% if field.required %} required{% endif %}>
+{% else %}<input type="text" name="{{ field.key }}"{% if field.rules.max_length is defined %} maxlength="{{ field.rules.max_length }}"{% endif %}{% if field.required %} required{% endif %}>
+{% endif %}</label></p>
+{% endfor %}<div style="position: absolute; left: -10000px;" aria-hidden="true">
+<label>Leave this empty <input type="text" name="{{ honeypot_field }}" tabindex="-1" autocomplete="off"></label>
+</div>
+<input type="hidden" name="{{ form_token_field }}" value="{{ form_token }}">
+<input type="hidden" name="{{ csrf_field }}" value="{{ csrf_token }}">
+{% if captcha %}<div class="{{ captcha.widget_class }}" data-sitekey="{{ captcha.site_key }}"></div>
+{% endif %}<p><button type="submit">Subscribe</button></p>
+</form>
+</body>
+</html>"#;
+
+#[derive(serde::Serialize)]
+struct CaptchaWidget<'a> {
+    script_url: &'a str,
+    site_key: &'a str,
+    widget_class: &'a str,
+}
+
+// A page of its own, so it can go in an `<iframe>` on any site
+#[tracing::instrument(
+    name = "Showing a signup form",
+    skip(request, pool, base_url, bot_protection, hmac_secret)
+)]
+pub async fn signup_form(
+    request: HttpRequest,
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    bot_protection: web::Data<BotProtection>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let list = match get_list_by_slug(pool.get_ref(), &slug).await {
+        Ok(Some(list)) => list,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let fields = match get_list_fields(pool.get_ref(), list.id).await {
+        Ok(fields) => fields,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let settings = bot_protection.settings();
+    let action = signup_path(&list.slug);
+    let csrf_cookie = CsrfCookie::from_request(&request);
+    let context = minijinja::context! {
+        list => ListVariables { name: list.name, slug: list.slug.clone() },
+        action_url => format!("{}{}", base_url.0, action),
+        fields,
+        honeypot_field => settings.honeypot_field,
+        form_token_field => FORM_TOKEN_FIELD,
+        form_token => bot_protection.form_token(),
+        csrf_field => CSRF_FIELD,
+        csrf_token => csrf_cookie.token(&action, &hmac_secret.0),
+        captcha => list.captcha.then_some(CaptchaWidget {
+            script_url: &settings.captcha.script_url,
+            site_key: &settings.captcha.site_key,
+            widget_class: &settings.captcha.widget_class,
+        }),
+    };
+    let mut response = render_page(
+        "signup.html",
+        SIGNUP_PAGE,
+        context,
+        "text/html; charset=utf-8",
+    );
+    // The form token in it is only good for a while
+    response
+        .headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    csrf_cookie.add_to(&mut response, &base_url.0);
+    response
+}
+
+// Signs up like `POST /lists/{slug}/subscriptions`, answering with pages instead of status codes
+// the CSRF token was checked by `require_csrf_token`
+#[tracing::instrument(
+    name = "Submitting a signup form",
+    skip(form, pool, email_client, base_url, bot_protection, signup_policy)
+)]
+pub async fn submit_signup_form(
+    slug: web::Path<String>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    bot_protection: web::Data<BotProtection>,
+    signup_policy: web::Data<SignupPolicy>,
+) -> HttpResponse {
+    let list = match get_list_by_slug(pool.get_ref(), &slug).await {
+        Ok(Some(list)) => list,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let mut form = form.into_inner();
+    form.attributes.remove(CSRF_FIELD);
+    let response = subscribe_to(
+        &pool,
+        &email_client,
+        &base_url.0,
+        &bot_protection,
+        &signup_policy,
+        &list,
+        form,
+    )
+    .await;
+    match response.status() {
+        StatusCode::OK => message_page(
+            StatusCode::OK,
+            "Check your inbox",
+            &format!(
+                "We sent you an email with a link to confirm your subscription to {}.",
+                list.name
+            ),
+            None,
+        ),
+        // Validation errors are plain text, meant for the person who filled in the form
+        StatusCode::BAD_REQUEST => {
+            let message = match actix_web::body::to_bytes(response.into_body()).await {
+                Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+            let signup_url = format!("{}{}", base_url.0, signup_path(&list.slug));
+            message_page(
+                StatusCode::BAD_REQUEST,
+                "Please check your details",
+                &message,
+                Some((&signup_url, "Back to the form")),
+            )
+        }
+        _ => response,
+    }
+}
+
+fn signup_path(list_slug: &str) -> String {
+    format!("/lists/{}/signup", list_slug)
+}
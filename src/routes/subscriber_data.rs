@@ -0,0 +1,177 @@
+use crate::domain::{SubscriberEmail, TokenPurpose};
+use crate::email_client::EmailClient;
+use crate::gdpr::{collect_subscriber_data, erase_subscriber, Actor};
+use crate::routes::{generate_subscription_token, get_subscriber_id_from_token, store_token};
+use crate::startup::ApplicationBaseUrl;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct DataAccessRequest {
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TokenParameters {
+    pub subscription_token: String,
+}
+
+// Email a link to `GET /subscriptions/me/data` to the address, if it is subscribed
+// the response is the same either way so nobody can probe who is on the list
+#[tracing::instrument(
+    name = "Requesting access to subscriber data",
+    skip(form, pool, email_client, base_url)
+)]
+pub async fn request_data_access(
+    form: web::Form<DataAccessRequest>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    let subscriber = match get_subscriber_by_email(&pool, &email).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return HttpResponse::Ok().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let subscription_token = generate_subscription_token();
+    if store_token(
+        pool.get_ref(),
+        subscriber.id,
+        &subscription_token,
+        TokenPurpose::DataAccess,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if send_data_access_email(
+        &email_client,
+        &subscriber.email_display,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().finish()
+}
+
+struct Subscriber {
+    id: Uuid,
+    // The address as they typed it, what we send to
+    email_display: String,
+}
+
+#[tracing::instrument(name = "Looking up a subscriber by email", skip(pool, email))]
+async fn get_subscriber_by_email(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Subscriber>, sqlx::Error> {
+    sqlx::query_as!(
+        Subscriber,
+        "SELECT id, email_display FROM subscriptions WHERE lower(email) = lower($1) AND status <> 'erased'",
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(
+    name = "Sending a data access email",
+    skip(email_client, recipient, base_url, subscription_token)
+)]
+async fn send_data_access_email(
+    email_client: &EmailClient,
+    recipient: &str,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), reqwest::Error> {
+    let data_link = format!(
+        "{}/subscriptions/me/data?subscription_token={}",
+        base_url, subscription_token
+    );
+    email_client
+        .send_email(
+            recipient,
+            "The data we hold about you",
+            &format!(
+                "Follow <a href=\"{}\">this link</a> to see everything we store about you.<br />\
+                From there you can also ask us to erase it.",
+                data_link
+            ),
+            &format!(
+                "Visit {} to see everything we store about you.\nFrom there you can also ask us to erase it.",
+                data_link
+            ),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send data access email: {:?}", e);
+            e
+        })
+}
+
+#[tracing::instrument(name = "Returning subscriber data", skip(parameters, pool))]
+pub async fn get_subscriber_data(
+    parameters: web::Query<TokenParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let subscriber_id = match get_subscriber_id_from_token(
+        &pool,
+        &parameters.subscription_token,
+        TokenPurpose::DataAccess,
+    )
+    .await
+    {
+        Ok(Some(subscriber_id)) => subscriber_id,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match collect_subscriber_data(&pool, subscriber_id, Actor::Subscriber).await {
+        Ok(Some(data)) => HttpResponse::Ok().json(data),
+        Ok(None) => HttpResponse::Unauthorized().finish(),
+        Err(e) => {
+            tracing::error!("Failed to collect subscriber data: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// The token is deleted along with the data, so it only works once
+#[tracing::instrument(name = "Erasing subscriber data on request", skip(form, pool))]
+pub async fn erase_my_data(
+    form: web::Form<TokenParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let subscriber_id = match get_subscriber_id_from_token(
+        &pool,
+        &form.subscription_token,
+        TokenPurpose::DataAccess,
+    )
+    .await
+    {
+        Ok(Some(subscriber_id)) => subscriber_id,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    match erase_subscriber(&pool, subscriber_id, Actor::Subscriber).await {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            tracing::error!("Failed to erase subscriber data: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,56 +1,433 @@
+use crate::bot_protection::BotProtection;
+use crate::domain::{validate_attributes, NewSubscriber, SubscriptionStatus, Tag, TokenPurpose};
+use crate::email_client::EmailClient;
+use crate::routes::{add_tags, get_default_list, get_list_fields, MailingList};
+use crate::signup_policy::{PolicyFlag, SignupPolicy};
+use crate::startup::ApplicationBaseUrl;
+use crate::suppression::is_suppressed;
 use actix_web::{web, HttpResponse};
 use chrono::Utc;
-use sqlx::PgPool;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde_json::{Map, Value};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
 use tracing_futures::Instrument;
 use uuid::Uuid;
 
 // Define a struct that represents the data that a user submits
-#[derive(serde::Deserialize)]
+// as a form or as JSON
+// the `///` comments end up in `/openapi.json`
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+#[schema(example = json!({"email": "ursula_le_guin@gmail.com", "name": "le guin"}))]
 pub struct FormData {
     email: String,
     name: String,
+    /// Comma-separated, usually a hidden field of the signup form, e.g. `beta,conference-2025`
+    pub tags: Option<String>,
+    /// Everything else is a value for one of the list's custom fields, or the bot protection's
+    /// `form_token` and honeypot
+    #[serde(flatten)]
+    pub attributes: HashMap<String, Value>,
 }
 
 // `#[tracing_instrument]` creates a span at the beginning of the function invocation
 // automatically attach all arguments passed to the function to the span, e.g. `form`
 // skip the `form` and `pool` arguments, not displaying
+// `/subscriptions` predates mailing lists - it signs people up to the default list
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(content(
+        (FormData = "application/x-www-form-urlencoded"),
+        (FormData = "application/json"),
+    )),
+    responses(
+        (status = 200, description = "A confirmation email is on its way - or the address needs none"),
+        (status = 400, description = "Why the details were turned away", body = String, content_type = "text/plain"),
+        (status = 429, description = "Too many signups from this client or for this address"),
+    )
+)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(form, pool, email_client, base_url, bot_protection, signup_policy)
+)]
+pub async fn subscribe(
+    form: web::Either<web::Json<FormData>, web::Form<FormData>>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    bot_protection: web::Data<BotProtection>,
+    signup_policy: web::Data<SignupPolicy>,
+) -> HttpResponse {
+    let list = match get_default_list(pool.get_ref()).await {
+        Ok(list) => list,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    subscribe_to(
+        &pool,
+        &email_client,
+        &base_url.0,
+        &bot_protection,
+        &signup_policy,
+        &list,
+        form.into_inner(),
+    )
+    .await
+}
+
+// For the signup form to post back as `form_token`, e.g. `{"form_token": "1767225600.aZ3k....q7X0..."}`
+#[utoipa::path(
+    get,
+    path = "/subscriptions/form-token",
+    tag = "subscriptions",
+    responses((status = 200, description = "A token to post back as `form_token`, good for a while", body = FormToken))
+)]
+pub async fn get_form_token(bot_protection: web::Data<BotProtection>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(FormToken {
+            form_token: bot_protection.form_token(),
+        })
+}
+
+// Named after `FORM_TOKEN_FIELD`
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct FormToken {
+    form_token: String,
+}
+
+impl TryFrom<FormData> for NewSubscriber {
+    type Error = String;
+
+    fn try_from(form: FormData) -> Result<Self, Self::Error> {
+        NewSubscriber::parse(form.email, form.name)
+    }
+}
+
+// Add the subscriber to `list` and email them a link to confirm
+// someone already confirmed on the list gets the same response, but no email
+#[tracing::instrument(
+    name = "Subscribing to a list",
+    skip(pool, email_client, base_url, bot_protection, signup_policy, list, form),
     fields(
+    list = %list.slug,
     subscriber_email = %form.email,
     subscriber_name= %form.name
     )
     )]
-pub async fn subscribe(form: web::Form<FormData>, pool: web::Data<PgPool>) -> HttpResponse {
-    match insert_subscriber(&pool, &form).await {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+pub async fn subscribe_to(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    bot_protection: &BotProtection,
+    signup_policy: &SignupPolicy,
+    list: &MailingList,
+    mut form: FormData,
+) -> HttpResponse {
+    // Bots get the response a person would, so they have nothing to learn from
+    match bot_protection.check(list, &mut form.attributes).await {
+        Ok(None) => {}
+        Ok(Some(failed)) => {
+            tracing::warn!(check = failed.as_str(), "Ignored the signup of a bot.");
+            return HttpResponse::Ok().finish();
+        }
+        Err(e) => {
+            tracing::error!("Failed to check the signup for bots: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+    let tags = match Tag::parse_list(form.tags.as_deref().unwrap_or_default()) {
+        Ok(tags) => tags,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let submitted_attributes = std::mem::take(&mut form.attributes);
+    let new_subscriber: NewSubscriber = match form.try_into() {
+        Ok(subscriber) => subscriber,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    // Before the suppression list, so whether an address is turned away says nothing about it
+    let policy_flags = match signup_policy.evaluate(list, &new_subscriber.email) {
+        Ok(policy_flags) => policy_flags,
+        Err(e) => {
+            tracing::info!("Turned away an address against the signup policy: {}", e);
+            return HttpResponse::BadRequest().body(e);
+        }
+    };
+    let fields = match get_list_fields(pool, list.id).await {
+        Ok(fields) => fields,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let attributes = match validate_attributes(&fields, submitted_attributes) {
+        Ok(attributes) => attributes,
+        Err(errors) => return HttpResponse::BadRequest().body(errors.join("\n")),
+    };
+
+    // Same response as a signup, so the form does not tell who is on the suppression list
+    match is_suppressed(pool, new_subscriber.email.as_ref()).await {
+        Ok(false) => {}
+        Ok(true) => {
+            tracing::info!("Ignored the signup of a suppressed address.");
+            return HttpResponse::Ok().finish();
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
     }
+
+    let signup = Signup {
+        new_subscriber: &new_subscriber,
+        tags: &tags,
+        attributes,
+        policy_flags: &policy_flags,
+    };
+    let subscription_token = match add_pending_membership(pool, list, signup).await {
+        Ok(Some(subscription_token)) => subscription_token,
+        Ok(None) => return HttpResponse::Ok().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    if send_confirmation_email(
+        email_client,
+        &new_subscriber,
+        list,
+        base_url,
+        &subscription_token,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().finish()
+}
+
+// A validated signup form
+struct Signup<'a> {
+    new_subscriber: &'a NewSubscriber,
+    tags: &'a [Tag],
+    attributes: Map<String, Value>,
+    policy_flags: &'a [PolicyFlag],
+}
+
+// Returns the token to put in the confirmation link, `None` when there is nothing to confirm
+async fn add_pending_membership(
+    pool: &PgPool,
+    list: &MailingList,
+    signup: Signup<'_>,
+) -> Result<Option<String>, sqlx::Error> {
+    let mut transaction = pool.begin().await.map_err(|e| {
+        tracing::error!(
+            "Failed to acquire a Postgres connection from the pool: {:?}",
+            e
+        );
+        e
+    })?;
+    let subscriber_id = insert_subscriber(&mut *transaction, signup.new_subscriber).await?;
+    let status = upsert_list_membership(&mut transaction, list, subscriber_id).await?;
+    // Anyone can post anyone's address - only an address nobody confirmed yet takes what the
+    // form says about it, tags and attributes would move a confirmed subscriber into segments
+    if is_unconfirmed(&mut transaction, subscriber_id).await? {
+        add_tags(&mut *transaction, subscriber_id, signup.tags).await?;
+        store_attributes(&mut transaction, subscriber_id, signup.attributes).await?;
+        store_policy_flags(&mut transaction, subscriber_id, signup.policy_flags).await?;
+    }
+    if status == SubscriptionStatus::Confirmed.as_str() {
+        transaction.commit().await?;
+        return Ok(None);
+    }
+    let subscription_token = generate_subscription_token();
+    store_token(
+        &mut *transaction,
+        subscriber_id,
+        &subscription_token,
+        TokenPurpose::Preferences,
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(Some(subscription_token))
 }
 
+// Locks the subscriber, so a confirmation cannot slip in before the signup is saved
 #[tracing::instrument(
-    name = "Saving new subscriber details in the database",
-    skip(form, pool)
+    name = "Checking whether the subscriber is unconfirmed",
+    skip(transaction)
 )]
-pub async fn insert_subscriber(pool: &PgPool, form: &FormData) -> Result<(), sqlx::Error> {
+async fn is_unconfirmed(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let status = sqlx::query_scalar!(
+        "SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(status == SubscriptionStatus::PendingConfirmation.as_str())
+}
+
+// Merged into what we already have, e.g. from signing up to another list
+#[tracing::instrument(name = "Saving subscriber attributes", skip(transaction, attributes))]
+async fn store_attributes(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    attributes: Map<String, Value>,
+) -> Result<(), sqlx::Error> {
+    if attributes.is_empty() {
+        return Ok(());
+    }
+    sqlx::query!(
+        "UPDATE subscriptions SET attributes = attributes || $2 WHERE id = $1",
+        subscriber_id,
+        Value::Object(attributes)
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+// Added to the flags from earlier signups - the policy may have changed since
+#[tracing::instrument(name = "Saving signup policy flags", skip(transaction))]
+async fn store_policy_flags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    policy_flags: &[PolicyFlag],
+) -> Result<(), sqlx::Error> {
+    if policy_flags.is_empty() {
+        return Ok(());
+    }
+    let policy_flags: Vec<String> = policy_flags.iter().map(|f| f.as_str().into()).collect();
     sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at)
-    VALUES ($1, $2, $3, $4)
+        UPDATE subscriptions
+        SET policy_flags = ARRAY(SELECT DISTINCT unnest(policy_flags || $2) ORDER BY 1)
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        &policy_flags
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+// An address that only differs by case from an existing one is the same subscriber
+// so we leave the existing row alone and return its id - without telling the caller, to avoid leaking who subscribed
+#[tracing::instrument(
+    name = "Saving new subscriber details in the database",
+    skip(new_subscriber, executor)
+)]
+pub async fn insert_subscriber(
+    executor: impl PgExecutor<'_>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Uuid, sqlx::Error> {
+    // The outer `SELECT` does not see the row inserted by the CTE, so at most one side returns an id
+    sqlx::query_scalar!(
+        r#"
+    WITH inserted AS (
+        INSERT INTO subscriptions (id, email, email_display, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT ((lower(email))) DO NOTHING
+        RETURNING id
+    )
+    SELECT id AS "id!" FROM inserted
+    UNION ALL
+    SELECT id FROM subscriptions WHERE lower(email) = lower($2)
+    LIMIT 1
     "#,
         Uuid::new_v4(),
-        form.email,
-        form.name,
+        new_subscriber.email.as_ref(),
+        new_subscriber.email.display(),
+        new_subscriber.name,
+        Utc::now(),
+        SubscriptionStatus::PendingConfirmation.as_str()
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+// Someone who unsubscribed from the list starts over, otherwise the membership stays as it is
+// returns the membership status after the upsert
+#[tracing::instrument(name = "Adding the subscriber to the list", skip(transaction))]
+async fn upsert_list_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    list: &MailingList,
+    subscriber_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+    INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (list_id, subscriber_id) DO UPDATE
+    SET status = CASE WHEN list_memberships.status = 'unsubscribed' THEN EXCLUDED.status ELSE list_memberships.status END,
+        subscribed_at = CASE WHEN list_memberships.status = 'unsubscribed' THEN EXCLUDED.subscribed_at ELSE list_memberships.subscribed_at END,
+        unsubscribed_at = CASE WHEN list_memberships.status = 'unsubscribed' THEN NULL ELSE list_memberships.unsubscribed_at END
+    RETURNING status
+    "#,
+        list.id,
+        subscriber_id,
+        SubscriptionStatus::PendingConfirmation.as_str(),
         Utc::now()
     )
-    .execute(pool)
+    .fetch_one(&mut **transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
-    })?;
-    Ok(())
+    })
+}
+
+#[tracing::instrument(
+    name = "Sending a confirmation email to a new subscriber",
+    skip(email_client, new_subscriber, base_url, subscription_token)
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    new_subscriber: &NewSubscriber,
+    list: &MailingList,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<(), reqwest::Error> {
+    let confirmation_link = format!(
+        "{}/lists/{}/subscriptions/confirm?subscription_token={}",
+        base_url, list.slug, subscription_token
+    );
+    email_client
+        .send_email(
+            new_subscriber.email.display(),
+            &format!("Confirm your subscription to {}", list.name),
+            &format!(
+                "Welcome to {}!<br />Click <a href=\"{}\">here</a> to confirm your subscription.",
+                html_escape::encode_text(&list.name),
+                confirmation_link
+            ),
+            &format!(
+                "Welcome to {}!\nVisit {} to confirm your subscription.",
+                list.name, confirmation_link
+            ),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send a confirmation email: {:?}", e);
+            e
+        })
 }
 
 // Use `web::Data` to extract value from application state
@@ -73,8 +450,8 @@ pub async fn subscribe_3(form: web::Form<FormData>, pool: web::Data<PgPool>) ->
 
     let res = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO subscriptions (id, email, email_display, name, subscribed_at, status)
+        VALUES ($1, $2, $2, $3, $4, 'confirmed')
         "#,
         request_id,
         form.email,
@@ -120,8 +497,8 @@ pub async fn subscribe_2(
     // Write to database
     let res = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO subscriptions (id, email, email_display, name, subscribed_at, status)
+        VALUES ($1, $2, $2, $3, $4, 'confirmed')
         "#,
         request_id,
         form.email,
@@ -162,3 +539,65 @@ pub async fn subscribe_1(_form: web::Form<FormData>) -> HttpResponse {
 pub async fn subscribe_0() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+// A random 25-characters-long case-sensitive alphanumeric token - ~10^45 possibilities
+pub fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
+
+#[tracing::instrument(
+    name = "Storing subscription token in the database",
+    skip(subscription_token, executor)
+)]
+pub async fn store_token(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    subscription_token: &str,
+    purpose: TokenPurpose,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO subscription_tokens (subscription_token, subscriber_id, purpose, expires_at)
+    VALUES ($1, $2, $3, $4)
+    "#,
+        subscription_token,
+        subscriber_id,
+        purpose.as_str(),
+        Utc::now() + purpose.lifetime()
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+// `None` as well for a token that expired, or was stored for another purpose
+#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
+pub async fn get_subscriber_id_from_token(
+    pool: &PgPool,
+    subscription_token: &str,
+    purpose: TokenPurpose,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT subscriber_id FROM subscription_tokens
+        WHERE subscription_token = $1 AND purpose = $2 AND expires_at > now()
+        "#,
+        subscription_token,
+        purpose.as_str()
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(result.map(|r| r.subscriber_id))
+}
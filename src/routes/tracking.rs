@@ -0,0 +1,83 @@
+use crate::domain::SubscriptionStatus;
+use crate::startup::HmacSecret;
+use crate::tracking::TrackingToken;
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// The smallest transparent GIF there is, 1x1 pixel
+const PIXEL: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+// The pixel at the bottom of a tracked email - loading it counts as an open
+#[tracing::instrument(name = "Recording an open", skip(token, pool, hmac_secret))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let Some(token) = TrackingToken::verify(&token, &hmac_secret.0) else {
+        return HttpResponse::NotFound().finish();
+    };
+    record_event(&pool, &token, "open").await;
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        // Every load should reach us, not a cache along the way
+        .insert_header(CacheControl(vec![
+            CacheDirective::NoCache,
+            CacheDirective::NoStore,
+            CacheDirective::MustRevalidate,
+        ]))
+        .body(PIXEL.to_vec())
+}
+
+// e.g. `/t/c/eyJpIjoi...` - records the click and sends the reader on to the original link
+// only links we signed redirect, anything else is a 404 rather than an open redirect
+#[tracing::instrument(name = "Recording a click", skip(token, pool, hmac_secret))]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let token = TrackingToken::verify(&token, &hmac_secret.0);
+    let Some((token, url)) = token.and_then(|token| {
+        let url = token.url.clone()?;
+        Some((token, url))
+    }) else {
+        return HttpResponse::NotFound().finish();
+    };
+    record_event(&pool, &token, "click").await;
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+// A reader should get their image or their page even if we fail to count it
+// nor is there anything to count once the subscriber or the issue is gone, e.g. after an erasure
+async fn record_event(pool: &PgPool, token: &TrackingToken, kind: &str) {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (id, newsletter_issue_id, subscriber_id, kind, url, occurred_at)
+        SELECT $1, newsletter_issue_id, subscriptions.id, $4, $5, $6
+        FROM newsletter_issues, subscriptions
+        WHERE newsletter_issue_id = $2 AND subscriptions.id = $3 AND subscriptions.status <> $7
+        "#,
+        Uuid::new_v4(),
+        token.newsletter_issue_id,
+        token.subscriber_id,
+        kind,
+        token.url,
+        Utc::now(),
+        SubscriptionStatus::Erased.as_str()
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to execute query: {:?}", e);
+    }
+}
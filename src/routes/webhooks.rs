@@ -0,0 +1,268 @@
+use crate::domain::{SubscriberEmail, SubscriptionStatus, SuppressionTarget};
+use crate::startup::{MaxSoftBounces, WebhookSecret};
+use crate::suppression::{add_suppression, SuppressionSource};
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// base64 of the HMAC-SHA256 of the body, keyed with `email_client.webhook_secret`
+const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+// The fields we use of a Postmark-style event, e.g.
+// `{"RecordType": "Bounce", "ID": 42, "Type": "HardBounce", "Email": "ada@example.com", ...}`
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProviderEvent {
+    record_type: String,
+    #[serde(rename = "ID")]
+    id: Option<u64>,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    // The kind of bounce
+    r#type: Option<String>,
+    // Bounces and complaints name the address `Email`, deliveries `Recipient`
+    email: Option<String>,
+    recipient: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EventKind {
+    HardBounce,
+    SoftBounce,
+    Complaint,
+    Delivery,
+    // Kept for the record, e.g. an auto-responder, but changes nothing
+    Other,
+}
+
+impl EventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::HardBounce => "hard_bounce",
+            EventKind::SoftBounce => "soft_bounce",
+            EventKind::Complaint => "complaint",
+            EventKind::Delivery => "delivery",
+            EventKind::Other => "other",
+        }
+    }
+}
+
+impl ProviderEvent {
+    fn kind(&self) -> EventKind {
+        match (self.record_type.as_str(), self.r#type.as_deref()) {
+            ("Bounce", Some("HardBounce" | "BadEmailAddress")) => EventKind::HardBounce,
+            ("Bounce", Some("SoftBounce" | "Transient" | "DnsError")) => EventKind::SoftBounce,
+            ("Bounce", Some("SpamComplaint")) | ("SpamComplaint", _) => EventKind::Complaint,
+            ("Delivery", _) => EventKind::Delivery,
+            _ => EventKind::Other,
+        }
+    }
+
+    // Lowercased, like the addresses on the suppression list
+    fn email(&self) -> Option<String> {
+        self.email
+            .as_ref()
+            .or(self.recipient.as_ref())
+            .map(|email| email.trim().to_lowercase())
+            // We send to the display form, e.g. `ada@bücher.de` - look it up as it is stored
+            .map(|email| match SubscriberEmail::parse(email.clone()) {
+                Ok(parsed) => parsed.as_ref().to_string(),
+                Err(_) => email,
+            })
+    }
+
+    // e.g. `Bounce:42` - the provider sends the same event again until we answer with a 200
+    fn provider_event_id(&self) -> Option<String> {
+        let id = self
+            .id
+            .map(|id| id.to_string())
+            .or_else(|| self.message_id.clone())?;
+        Some(format!("{}:{}", self.record_type, id))
+    }
+}
+
+// One event per request - a replayed event is acknowledged, but only counted the first time
+#[tracing::instrument(name = "Receiving an email event", skip_all)]
+pub async fn receive_email_event(
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    webhook_secret: web::Data<WebhookSecret>,
+    max_soft_bounces: web::Data<MaxSoftBounces>,
+) -> HttpResponse {
+    let is_signed = request
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|signature| signature.to_str().ok())
+        .is_some_and(|signature| verify_signature(&body, signature, &webhook_secret.0));
+    if !is_signed {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let payload: Value = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid JSON: {}", e)),
+    };
+    let event: ProviderEvent = match serde_json::from_value(payload.clone()) {
+        Ok(event) => event,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid event: {}", e)),
+    };
+    let Some(provider_event_id) = event.provider_event_id() else {
+        return HttpResponse::BadRequest().body("The event has neither an `ID` nor a `MessageID`.");
+    };
+    let result: Result<bool, sqlx::Error> = async {
+        let mut transaction = pool.begin().await?;
+        let Some(subscriber_id) =
+            store_event(&mut transaction, &event, &provider_event_id, payload).await?
+        else {
+            return Ok(false);
+        };
+        let kind = event.kind();
+        let suppress = match subscriber_id {
+            // Only when it changed their status - an erased subscriber's address is not stored again
+            Some(subscriber_id) => {
+                update_lifecycle(&mut transaction, subscriber_id, kind, max_soft_bounces.0).await?
+            }
+            // Not one of our subscribers, the address never gets another email all the same
+            None => matches!(kind, EventKind::HardBounce | EventKind::Complaint),
+        };
+        let target = event
+            .email()
+            .and_then(|email| SuppressionTarget::parse(&email).ok());
+        if let (true, Some(target)) = (suppress, target) {
+            let reason = match kind {
+                EventKind::Complaint => "Spam complaint",
+                EventKind::SoftBounce => "Repeated soft bounces",
+                _ => "Hard bounce",
+            };
+            add_suppression(
+                &mut *transaction,
+                &target,
+                reason,
+                SuppressionSource::Webhook,
+            )
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(true)
+    }
+    .await;
+    match result {
+        Ok(is_new) => {
+            tracing::info!(
+                provider_event_id,
+                kind = event.kind().as_str(),
+                replayed = !is_new,
+                "Received an email event."
+            );
+            HttpResponse::Ok().finish()
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn verify_signature(body: &[u8], signature: &str, secret: &str) -> bool {
+    let Ok(signature) = STANDARD.decode(signature) else {
+        return false;
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    // Compared in constant time
+    mac.verify_slice(&signature).is_ok()
+}
+
+// `None` if we stored the event before, the subscriber it is about otherwise, if any
+async fn store_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &ProviderEvent,
+    provider_event_id: &str,
+    payload: Value,
+) -> Result<Option<Option<Uuid>>, sqlx::Error> {
+    let stored = sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            id, provider_event_id, kind, email, subscriber_id, payload, received_at
+        )
+        SELECT $1, $2, $3, $4, (SELECT id FROM subscriptions WHERE lower(email) = $4), $5, $6
+        ON CONFLICT (provider_event_id) DO NOTHING
+        RETURNING subscriber_id
+        "#,
+        Uuid::new_v4(),
+        provider_event_id,
+        event.kind().as_str(),
+        event.email(),
+        payload,
+        Utc::now()
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(stored.map(|stored| stored.subscriber_id))
+}
+
+// Hard bounces and complaints stop every future send, soft bounces only once they pile up
+// an erased subscriber stays erased, and a complaint is never downgraded to a bounce
+// returns whether the subscriber was just made bounced or complained
+async fn update_lifecycle(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    kind: EventKind,
+    max_soft_bounces: i32,
+) -> Result<bool, sqlx::Error> {
+    let status = match kind {
+        EventKind::HardBounce => Some(SubscriptionStatus::Bounced),
+        EventKind::Complaint => Some(SubscriptionStatus::Complained),
+        EventKind::SoftBounce => {
+            let soft_bounces = sqlx::query_scalar!(
+                r#"
+                UPDATE subscriptions SET soft_bounce_count = soft_bounce_count + 1
+                WHERE id = $1
+                RETURNING soft_bounce_count
+                "#,
+                subscriber_id
+            )
+            .fetch_one(&mut **transaction)
+            .await?;
+            (soft_bounces >= max_soft_bounces).then_some(SubscriptionStatus::Bounced)
+        }
+        EventKind::Delivery => {
+            sqlx::query!(
+                "UPDATE subscriptions SET soft_bounce_count = 0 WHERE id = $1",
+                subscriber_id
+            )
+            .execute(&mut **transaction)
+            .await?;
+            None
+        }
+        EventKind::Other => None,
+    };
+    let Some(status) = status else {
+        return Ok(false);
+    };
+    let updated = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $2
+        WHERE id = $1 AND status NOT IN ($3, $4)
+        "#,
+        subscriber_id,
+        status.as_str(),
+        SubscriptionStatus::Erased.as_str(),
+        SubscriptionStatus::Complained.as_str()
+    )
+    .execute(&mut **transaction)
+    .await?
+    .rows_affected()
+        > 0;
+    if updated {
+        tracing::info!(%subscriber_id, status = status.as_str(), "Suppressed a subscriber.");
+    }
+    Ok(updated)
+}
@@ -0,0 +1,513 @@
+use crate::domain::{SubscriptionStatus, Tag};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::{Postgres, QueryBuilder};
+
+// Keep expressions small enough to read, and the parser's recursion shallow
+const MAX_EXPRESSION_LENGTH: usize = 2000;
+const MAX_NESTING_DEPTH: usize = 32;
+
+/// A subset of subscribers described by an expression, e.g.
+///
+/// `tag = beta and confirmed_at >= 90 days ago and not (status = unsubscribed or tag = "churned")`
+///
+/// - `tag = x` / `tag != x` - whether the subscriber has the tag
+/// - `status = x` / `status != x` - one of the `SubscriptionStatus` values
+/// - `subscribed_at` / `confirmed_at` compared with `<`, `<=`, `>`, `>=` to a date (`2025-01-01`),
+///   an RFC 3339 timestamp or `N minutes|hours|days|weeks ago`
+/// - `attribute.<key>` compared to a value of a custom field, e.g. `attribute.employees >= 50`
+/// - `and` binds tighter than `or`, `not` negates, parentheses group
+///
+/// Subscribers who never confirmed have no `confirmed_at` and match no comparison on it.
+/// Attribute comparisons are false for subscribers without the attribute, `!=` included.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+    Not(Box<Segment>),
+    HasTag(Tag),
+    Status(SubscriptionStatus),
+    Date {
+        field: DateField,
+        comparison: Comparison,
+        value: DateValue,
+    },
+    Attribute {
+        key: String,
+        comparison: Comparison,
+        value: Value,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateField {
+    SubscribedAt,
+    ConfirmedAt,
+}
+
+impl DateField {
+    fn column(&self) -> &'static str {
+        match self {
+            DateField::SubscribedAt => "subscriptions.subscribed_at",
+            DateField::ConfirmedAt => "subscriptions.confirmed_at",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Comparison::Equal => " = ",
+            Comparison::NotEqual => " <> ",
+            Comparison::Less => " < ",
+            Comparison::LessOrEqual => " <= ",
+            Comparison::Greater => " > ",
+            Comparison::GreaterOrEqual => " >= ",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DateValue {
+    At(DateTime<Utc>),
+    // Resolved against the clock when the segment is turned into SQL, not when it is parsed
+    Ago(Duration),
+}
+
+impl DateValue {
+    fn resolve(&self) -> DateTime<Utc> {
+        match self {
+            DateValue::At(at) => *at,
+            // `parse_date` made sure it fits, which only an `ago` in the future can outgrow
+            DateValue::Ago(duration) => Utc::now()
+                .checked_sub_signed(*duration)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        }
+    }
+}
+
+impl Segment {
+    pub fn parse(expression: &str) -> Result<Segment, String> {
+        if expression.len() > MAX_EXPRESSION_LENGTH {
+            return Err(format!(
+                "The segment must not be longer than {} characters.",
+                MAX_EXPRESSION_LENGTH
+            ));
+        }
+        let tokens = tokenize(expression)?;
+        if tokens.is_empty() {
+            return Err("The segment is empty.".into());
+        }
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+        let segment = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(segment),
+            Some(token) => Err(format!(
+                "Unexpected `{}` at character {}.",
+                token.text, token.offset
+            )),
+        }
+    }
+
+    // Append the segment as one parenthesised condition on the `subscriptions` table
+    // every value is a bind parameter, nothing from the expression ends up in the SQL itself
+    pub fn push_condition(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Segment::And(left, right) | Segment::Or(left, right) => {
+                let operator = if matches!(self, Segment::And(..)) {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                query.push("(");
+                left.push_condition(query);
+                query.push(operator);
+                right.push_condition(query);
+                query.push(")");
+            }
+            Segment::Not(inner) => {
+                query.push("(NOT ");
+                inner.push_condition(query);
+                query.push(")");
+            }
+            Segment::HasTag(tag) => {
+                query
+                    .push("EXISTS (SELECT 1 FROM subscriber_tags WHERE subscriber_tags.subscriber_id = subscriptions.id AND subscriber_tags.tag = ")
+                    .push_bind(tag.as_ref().to_string())
+                    .push(")");
+            }
+            Segment::Status(status) => {
+                query
+                    .push("(subscriptions.status = ")
+                    .push_bind(status.as_str())
+                    .push(")");
+            }
+            Segment::Date {
+                field,
+                comparison,
+                value,
+            } => {
+                query
+                    .push("(")
+                    .push(field.column())
+                    .push(comparison.as_sql())
+                    .push_bind(value.resolve())
+                    .push(")");
+            }
+            Segment::Attribute {
+                key,
+                comparison,
+                value,
+            } => {
+                // `COALESCE` turns the `NULL` of a missing attribute into `FALSE`, so `NOT` works as expected
+                query.push("COALESCE(");
+                match (comparison, value) {
+                    (Comparison::Equal | Comparison::NotEqual, value) => {
+                        query
+                            .push("(subscriptions.attributes -> ")
+                            .push_bind(key.clone())
+                            .push(")")
+                            .push(comparison.as_sql())
+                            .push_bind(Json(value.clone()));
+                    }
+                    (_, Value::Number(number)) => {
+                        query
+                            .push("(CASE WHEN jsonb_typeof(subscriptions.attributes -> ")
+                            .push_bind(key.clone())
+                            .push(") = 'number' THEN (subscriptions.attributes ->> ")
+                            .push_bind(key.clone())
+                            .push(")::float8 END)")
+                            .push(comparison.as_sql())
+                            .push_bind(number.as_f64().unwrap_or_default());
+                    }
+                    (_, value) => {
+                        query
+                            .push("(CASE WHEN jsonb_typeof(subscriptions.attributes -> ")
+                            .push_bind(key.clone())
+                            .push(") = 'string' THEN subscriptions.attributes ->> ")
+                            .push_bind(key.clone())
+                            .push(" END)")
+                            .push(comparison.as_sql())
+                            .push_bind(value.as_str().unwrap_or_default().to_string());
+                    }
+                }
+                query.push(", FALSE)");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    OpenParen,
+    CloseParen,
+    Comparison(Comparison),
+    Word,
+    // A double-quoted string, e.g. a value with spaces
+    Quoted,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    // The word or the content of the quoted string
+    text: String,
+    // Position in the expression, for error messages
+    offset: usize,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let (kind, text) = match c {
+            c if c.is_whitespace() => continue,
+            '(' => (TokenKind::OpenParen, "(".to_string()),
+            ')' => (TokenKind::CloseParen, ")".to_string()),
+            '=' => (TokenKind::Comparison(Comparison::Equal), "=".to_string()),
+            '!' | '<' | '>' => {
+                let followed_by_equals = chars.next_if(|(_, next)| *next == '=').is_some();
+                let comparison = match (c, followed_by_equals) {
+                    ('!', true) => Comparison::NotEqual,
+                    ('<', false) => Comparison::Less,
+                    ('<', true) => Comparison::LessOrEqual,
+                    ('>', false) => Comparison::Greater,
+                    ('>', true) => Comparison::GreaterOrEqual,
+                    _ => return Err(format!("Unexpected `!` at character {}.", offset)),
+                };
+                let text = if followed_by_equals {
+                    format!("{}=", c)
+                } else {
+                    c.to_string()
+                };
+                (TokenKind::Comparison(comparison), text)
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => text.push(c),
+                        None => {
+                            return Err(format!(
+                                "The string starting at character {} is not closed.",
+                                offset
+                            ))
+                        }
+                    }
+                }
+                (TokenKind::Quoted, text)
+            }
+            c if is_word_character(c) => {
+                let mut text = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, c)| is_word_character(*c)) {
+                    text.push(c);
+                }
+                (TokenKind::Word, text)
+            }
+            c => return Err(format!("Unexpected `{}` at character {}.", c, offset)),
+        };
+        tokens.push(Token { kind, text, offset });
+    }
+    Ok(tokens)
+}
+
+// Enough for identifiers, dates and RFC 3339 timestamps, e.g. `2025-01-01T00:00:00+02:00`
+fn is_word_character(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.' | '+')
+}
+
+// A recursive descent parser, one method per precedence level
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| "The segment ends unexpectedly.".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        self.peek().is_some_and(|token| {
+            token.kind == TokenKind::Word && token.text.eq_ignore_ascii_case(keyword)
+        })
+    }
+
+    fn parse_or(&mut self) -> Result<Segment, String> {
+        let mut segment = self.parse_and()?;
+        while self.next_is_keyword("or") {
+            self.position += 1;
+            segment = Segment::Or(Box::new(segment), Box::new(self.parse_and()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.parse_unary()?;
+        while self.next_is_keyword("and") {
+            self.position += 1;
+            segment = Segment::And(Box::new(segment), Box::new(self.parse_unary()?));
+        }
+        Ok(segment)
+    }
+
+    fn parse_unary(&mut self) -> Result<Segment, String> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING_DEPTH {
+            return Err(format!(
+                "The segment must not nest deeper than {} levels.",
+                MAX_NESTING_DEPTH
+            ));
+        }
+        let segment = if self.next_is_keyword("not") {
+            self.position += 1;
+            Segment::Not(Box::new(self.parse_unary()?))
+        } else if self.peek().map(|t| &t.kind) == Some(&TokenKind::OpenParen) {
+            self.position += 1;
+            let segment = self.parse_or()?;
+            let token = self.next()?;
+            if token.kind != TokenKind::CloseParen {
+                return Err(format!(
+                    "Expected `)` at character {}, found `{}`.",
+                    token.offset, token.text
+                ));
+            }
+            segment
+        } else {
+            self.parse_condition()?
+        };
+        self.depth -= 1;
+        Ok(segment)
+    }
+
+    fn parse_condition(&mut self) -> Result<Segment, String> {
+        let field = self.next()?;
+        if field.kind != TokenKind::Word {
+            return Err(format!(
+                "Expected a field at character {}, found `{}`.",
+                field.offset, field.text
+            ));
+        }
+        let operator = self.next()?;
+        let TokenKind::Comparison(comparison) = operator.kind else {
+            return Err(format!(
+                "Expected a comparison after `{}` at character {}, found `{}`.",
+                field.text, operator.offset, operator.text
+            ));
+        };
+        let value = self.next()?;
+        if !matches!(value.kind, TokenKind::Word | TokenKind::Quoted) {
+            return Err(format!(
+                "Expected a value at character {}, found `{}`.",
+                value.offset, value.text
+            ));
+        }
+
+        if let Some(key) = field.text.to_lowercase().strip_prefix("attribute.") {
+            return parse_attribute_condition(key, comparison, value);
+        }
+        let condition = match field.text.to_lowercase().as_str() {
+            "tag" => Segment::HasTag(Tag::parse(&value.text)?),
+            "status" => Segment::Status(SubscriptionStatus::try_from(value.text.clone())?),
+            "subscribed_at" | "confirmed_at" => {
+                let field = if field.text.eq_ignore_ascii_case("subscribed_at") {
+                    DateField::SubscribedAt
+                } else {
+                    DateField::ConfirmedAt
+                };
+                if matches!(comparison, Comparison::Equal | Comparison::NotEqual) {
+                    return Err(format!(
+                        "Compare dates with `<`, `<=`, `>` or `>=` (character {}).",
+                        operator.offset
+                    ));
+                }
+                let value = self.parse_date(value)?;
+                return Ok(Segment::Date {
+                    field,
+                    comparison,
+                    value,
+                });
+            }
+            other => {
+                return Err(format!(
+                    "Unknown field `{}` at character {}: use `tag`, `status`, `subscribed_at`, `confirmed_at` or `attribute.<key>`.",
+                    other, field.offset
+                ))
+            }
+        };
+        match comparison {
+            Comparison::Equal => Ok(condition),
+            Comparison::NotEqual => Ok(Segment::Not(Box::new(condition))),
+            _ => Err(format!(
+                "`{}` can only be compared with `=` or `!=` (character {}).",
+                field.text, operator.offset
+            )),
+        }
+    }
+
+    // `value` is the first token of the date, relative dates take two more
+    fn parse_date(&mut self, value: Token) -> Result<DateValue, String> {
+        if let Ok(amount) = value.text.parse::<i64>() {
+            let unit = self.next()?;
+            let duration = match unit.text.to_lowercase().trim_end_matches('s') {
+                "minute" => Duration::try_minutes(amount),
+                "hour" => Duration::try_hours(amount),
+                "day" => Duration::try_days(amount),
+                "week" => Duration::try_weeks(amount),
+                _ => {
+                    return Err(format!(
+                        "Unknown unit `{}` at character {}: use minutes, hours, days or weeks.",
+                        unit.text, unit.offset
+                    ))
+                }
+            }
+            .filter(|duration| Utc::now().checked_sub_signed(*duration).is_some())
+            .ok_or_else(|| format!("`{}` is too far back.", value.text))?;
+            let ago = self.next()?;
+            if !ago.text.eq_ignore_ascii_case("ago") {
+                return Err(format!(
+                    "Expected `ago` at character {}, found `{}`.",
+                    ago.offset, ago.text
+                ));
+            }
+            return Ok(DateValue::Ago(duration));
+        }
+        if let Ok(at) = DateTime::parse_from_rfc3339(&value.text) {
+            return Ok(DateValue::At(at.with_timezone(&Utc)));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(&value.text, "%Y-%m-%d") {
+            return Ok(DateValue::At(date.and_time(Default::default()).and_utc()));
+        }
+        Err(format!(
+            "`{}` at character {} is not a date: use e.g. `2025-01-01`, an RFC 3339 timestamp or `90 days ago`.",
+            value.text, value.offset
+        ))
+    }
+}
+
+fn parse_attribute_condition(
+    key: &str,
+    comparison: Comparison,
+    value: Token,
+) -> Result<Segment, String> {
+    let is_valid_key = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !is_valid_key {
+        return Err(format!("`{}` is not a valid attribute key.", key));
+    }
+    // Unquoted values are typed the way the attribute was stored, e.g. `42` or `true`
+    let typed_value = match value.kind {
+        TokenKind::Quoted => Value::String(value.text.clone()),
+        _ => match value.text.as_str() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            text => text
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite())
+                .map(|number| serde_json::json!(number))
+                .unwrap_or_else(|| Value::String(value.text.clone())),
+        },
+    };
+    let is_ordering = !matches!(comparison, Comparison::Equal | Comparison::NotEqual);
+    if is_ordering && typed_value.is_boolean() {
+        return Err(format!(
+            "`{}` at character {} can only be compared with `=` or `!=`.",
+            value.text, value.offset
+        ));
+    }
+    Ok(Segment::Attribute {
+        key: key.to_string(),
+        comparison,
+        value: typed_value,
+    })
+}
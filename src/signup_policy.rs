@@ -0,0 +1,118 @@
+use crate::configuration::{PolicyAction, SignupPolicySettings};
+use crate::domain::SubscriberEmail;
+use crate::routes::MailingList;
+use std::collections::HashSet;
+
+// What we noticed about an address we accepted, as stored in `subscriptions.policy_flags`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyFlag {
+    Disposable,
+    RoleAccount,
+}
+
+impl PolicyFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PolicyFlag::Disposable => "disposable",
+            PolicyFlag::RoleAccount => "role_account",
+        }
+    }
+}
+
+/// Which addresses signups are accepted from: no disposable ones, no role accounts,
+/// and only the domains a list allows, if it limits them.
+pub struct SignupPolicy {
+    disposable_domains: HashSet<String>,
+    disposable_action: PolicyAction,
+    role_local_parts: HashSet<String>,
+    role_action: PolicyAction,
+}
+
+impl SignupPolicy {
+    // Reads `disposable_domains_file`
+    pub fn load(settings: &SignupPolicySettings) -> std::io::Result<Self> {
+        let domains = std::fs::read_to_string(&settings.disposable_domains_file)?;
+        Ok(Self::new(settings, &domains))
+    }
+
+    pub fn new(settings: &SignupPolicySettings, disposable_domains: &str) -> Self {
+        let disposable_domains = disposable_domains
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|domain| !domain.is_empty())
+            .map(|domain| domain.trim_start_matches('@').to_lowercase())
+            .collect();
+        let role_local_parts = settings
+            .role_local_parts
+            .iter()
+            .map(|local_part| local_part.trim().to_lowercase())
+            .collect();
+        Self {
+            disposable_domains,
+            disposable_action: settings.disposable_domains,
+            role_local_parts,
+            role_action: settings.role_accounts,
+        }
+    }
+
+    // The flags to record on the subscriber, or why the address is turned away
+    // rejections only depend on the address and the list, never on who is subscribed,
+    // so the reason is safe to show
+    pub fn evaluate(
+        &self,
+        list: &MailingList,
+        email: &SubscriberEmail,
+    ) -> Result<Vec<PolicyFlag>, String> {
+        let domain = email.domain();
+        if !list.allowed_domains.is_empty()
+            && !list
+                .allowed_domains
+                .iter()
+                .any(|allowed| is_within(domain, allowed))
+        {
+            return Err(format!(
+                "This list only accepts addresses at {}.",
+                list.allowed_domains.join(", ")
+            ));
+        }
+
+        let mut flags = Vec::new();
+        let is_disposable = self
+            .disposable_domains
+            .iter()
+            .any(|disposable| is_within(domain, disposable));
+        if is_disposable {
+            match self.disposable_action {
+                PolicyAction::Allow => {}
+                PolicyAction::Flag => flags.push(PolicyFlag::Disposable),
+                PolicyAction::Reject => {
+                    return Err("Disposable email addresses are not accepted.".into())
+                }
+            }
+        }
+        // `noreply+news@` is still `noreply@`
+        let local_part = email.local_part().to_lowercase();
+        let local_part = local_part.split('+').next().unwrap_or_default();
+        if self.role_local_parts.contains(local_part) {
+            match self.role_action {
+                PolicyAction::Allow => {}
+                PolicyAction::Flag => flags.push(PolicyFlag::RoleAccount),
+                PolicyAction::Reject => {
+                    return Err(format!(
+                        "Role addresses like {}@ are not accepted, please use a personal one.",
+                        local_part
+                    ))
+                }
+            }
+        }
+        Ok(flags)
+    }
+}
+
+// The domain itself, or one of its subdomains
+pub(crate) fn is_within(domain: &str, parent: &str) -> bool {
+    domain == parent
+        || domain
+            .strip_suffix(parent)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
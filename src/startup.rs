@@ -1,28 +1,336 @@
-use crate::routes::{greet, health_check, subscribe, subscribe_0, subscribe_1};
-use actix_web::{dev::Server, middleware::Logger, web, App, HttpServer};
+use crate::authentication::reject_anonymous_users;
+use crate::bot_protection::BotProtection;
+use crate::configuration::Settings;
+use crate::csrf::require_csrf_token;
+use crate::domain::Scope;
+use crate::rate_limit::{rate_limit, RateLimiter};
+use crate::route_table::{no_route, Access, RouteTable, Routes};
+use crate::routes::{
+    api_docs_page, archive_index, archived_issue, cancel_issue, confirm_list_subscription,
+    create_api_key, create_draft, create_list, create_list_field, create_suppression,
+    create_template, deactivate_user, delete_issue, delete_list_feed, delete_list_field,
+    delete_suppression, erase_my_data, erase_subscriber_by_id, export_subscriber_list,
+    get_form_token, get_issue_details, get_issue_stats, get_list_feed, get_metrics,
+    get_subscriber_data, greet, health_check, import_subscribers_csv, import_suppressions,
+    invite_user, list_api_keys, list_feed, list_issues, list_list_fields, list_lists, list_routes,
+    list_subscribers, list_suppressions, list_templates, list_users, openapi_json,
+    preference_center, preview_issue, preview_newsletter, preview_segment, publish_newsletter,
+    receive_email_event, request_data_access, revoke_api_key, save_preferences, schedule_issue,
+    send_test_issue, set_archive_visibility, set_list_allowed_domains, set_list_captcha,
+    set_list_feed, set_list_tracking, set_user_role, signup_form, submit_signup_form, subscribe,
+    subscribe_0, subscribe_1, subscribe_to_list, tag_subscriber, track_click, track_open,
+    unsubscribe_from_everything, unsubscribe_from_list, unsubscribe_page, untag_subscriber,
+    update_draft, update_template,
+};
+use crate::signup_policy::SignupPolicy;
+use actix_web::{
+    dev::Server,
+    http::Method,
+    middleware::{from_fn, Logger},
+    web, App, HttpServer,
+};
 use sqlx::PgPool;
 use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
 
-pub fn run(listener: TcpListener, db_pool: PgPool) -> std::io::Result<Server> {
+// A wrapper type so handlers can tell the base url apart from other `String`s in the application data
+pub struct ApplicationBaseUrl(pub String);
+// The sender's postal address, for the footer of emails rendered outside the delivery worker
+pub struct PostalAddress(pub String);
+// The key tracking links are signed with
+pub struct HmacSecret(pub String);
+// What the email provider signs its webhooks with
+pub struct WebhookSecret(pub String);
+// Soft bounces in a row before an address counts as bounced
+pub struct MaxSoftBounces(pub i32);
+
+// Takes the whole configuration - the application data outgrew a parameter each
+pub fn run(
+    listener: TcpListener,
+    db_pool: PgPool,
+    configuration: &Settings,
+) -> std::io::Result<Server> {
+    let rate_limiter = web::Data::new(RateLimiter::new(&configuration.rate_limit, db_pool.clone()));
     let db_pool = web::Data::new(db_pool);
+    let email_client = web::Data::new(configuration.email_client.client());
+    let base_url = web::Data::new(ApplicationBaseUrl(
+        configuration.application.base_url.clone(),
+    ));
+    let postal_address = web::Data::new(PostalAddress(
+        configuration.application.postal_address.clone(),
+    ));
+    let hmac_secret = web::Data::new(HmacSecret(configuration.application.hmac_secret.clone()));
+    let webhook_secret = web::Data::new(WebhookSecret(
+        configuration.email_client.webhook_secret.clone(),
+    ));
+    let max_soft_bounces =
+        web::Data::new(MaxSoftBounces(configuration.email_client.max_soft_bounces));
+    let signup_policy = web::Data::new(
+        SignupPolicy::load(&configuration.signup_policy)
+            .expect("Failed to read the disposable domains file."),
+    );
+    let bot_protection = web::Data::new(BotProtection::new(
+        &configuration.bot_protection,
+        configuration.application.hmac_secret.clone(),
+        Box::new(configuration.bot_protection.captcha.verifier()),
+        db_pool.get_ref().clone(),
+    ));
+    let api_docs = configuration.application.api_docs;
+    let route_table = web::Data::new(RouteTable::collect(|routes| {
+        register_routes(routes, api_docs)
+    }));
     let server = HttpServer::new(move || {
         App::new()
+            // Inside the logger, so rejected requests are logged too
+            .wrap(from_fn(rate_limit))
             // Instead of `Logger::default()`, we use `TracingLogger::default()`
             .wrap(TracingLogger::default())
-            .route("/", web::get().to(greet))
-            .route("/health_check", web::get().to(health_check))
-            .route("/{name}", web::get().to(greet))
-            .route("/subscriptions", web::post().to(subscribe))
+            // The same routes `route_table` was collected from
+            .configure(|config| {
+                register_routes(&mut Routes::new(config, &mut Vec::new()), api_docs)
+            })
+            // Unknown paths get a 404, and known ones a 405 for methods they do not take
+            .default_service(web::to(no_route))
             // attach a copy of pointer to database connection to the application - i.e. only connection but multi copies across threads
             // .app_data(connection.clone())
             .app_data(db_pool.clone())
+            .app_data(email_client.clone())
+            .app_data(base_url.clone())
+            .app_data(postal_address.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(webhook_secret.clone())
+            .app_data(max_soft_bounces.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(bot_protection.clone())
+            .app_data(signup_policy.clone())
+            .app_data(route_table.clone())
     })
     .listen(listener)?
     .run();
 
     Ok(server)
 }
+// Every route of `run`, recorded in the `RouteTable` as they are registered
+// there is no greeting at `/{name}` - unknown paths go to `no_route`
+fn register_routes(routes: &mut Routes, api_docs: bool) {
+    routes
+        .route(Method::GET, "/health_check", health_check)
+        .route(Method::GET, "/openapi.json", openapi_json);
+    if api_docs {
+        routes.route(Method::GET, "/docs", api_docs_page);
+    }
+    routes
+        .route(Method::GET, "/archive", archive_index)
+        .route(Method::GET, "/archive/{slug}", archived_issue)
+        // The forms post back to where they were served from, with a CSRF token
+        .scope(
+            "/preferences",
+            Access::Public,
+            |scope| scope.wrap(from_fn(require_csrf_token)),
+            |preferences| {
+                preferences
+                    .route(Method::GET, "", preference_center)
+                    .route(Method::POST, "", save_preferences)
+                    .route(Method::POST, "/unsubscribe", unsubscribe_from_everything);
+            },
+        )
+        .route(Method::POST, "/subscriptions", subscribe)
+        .route(Method::GET, "/subscriptions/form-token", get_form_token)
+        .route(
+            Method::POST,
+            "/subscriptions/me/access",
+            request_data_access,
+        )
+        .route(Method::GET, "/subscriptions/me/data", get_subscriber_data)
+        .route(Method::POST, "/subscriptions/me/erase", erase_my_data)
+        .route(
+            Method::POST,
+            "/lists/{slug}/subscriptions",
+            subscribe_to_list,
+        )
+        .route(
+            Method::GET,
+            "/lists/{slug}/subscriptions/confirm",
+            confirm_list_subscription,
+        )
+        .route(
+            Method::GET,
+            "/lists/{slug}/subscriptions/unsubscribe",
+            unsubscribe_page,
+        )
+        .route(
+            Method::POST,
+            "/lists/{slug}/subscriptions/unsubscribe",
+            unsubscribe_from_list,
+        )
+        .scope(
+            "/lists/{slug}/signup",
+            Access::Public,
+            |scope| scope.wrap(from_fn(require_csrf_token)),
+            |signup| {
+                signup.route(Method::GET, "", signup_form).route(
+                    Method::POST,
+                    "",
+                    submit_signup_form,
+                );
+            },
+        )
+        .route(Method::GET, "/lists/{slug}/feed.xml", list_feed)
+        .route(Method::GET, "/t/o/{token}", track_open)
+        .route(Method::GET, "/t/c/{token}", track_click)
+        .route(Method::POST, "/webhooks/email-events", receive_email_event)
+        // Everything under `/admin` requires credentials of a user in the `users` table
+        .scope(
+            "/admin",
+            Access::Admin,
+            |scope| scope.wrap(from_fn(reject_anonymous_users)),
+            register_admin_routes,
+        );
+}
+
+// Grouped by the scope a user's role, and an API key, must grant - see `Role::scopes`
+fn register_admin_routes(admin: &mut Routes) {
+    admin
+        .requiring(Scope::RoutesRead, |admin| {
+            admin.route(Method::GET, "/routes", list_routes);
+        })
+        .requiring(Scope::ApiKeysManage, |admin| {
+            admin
+                .route(Method::GET, "/api-keys", list_api_keys)
+                .route(Method::POST, "/api-keys", create_api_key)
+                .route(Method::DELETE, "/api-keys/{key_id}", revoke_api_key);
+        })
+        .requiring(Scope::UsersManage, |admin| {
+            admin
+                .route(Method::GET, "/users", list_users)
+                .route(Method::POST, "/users", invite_user)
+                .route(Method::PUT, "/users/{user_id}/role", set_user_role)
+                .route(Method::POST, "/users/{user_id}/deactivate", deactivate_user);
+        })
+        .requiring(Scope::SubscribersRead, |admin| {
+            admin
+                .route(Method::GET, "/subscribers", list_subscribers)
+                .route(Method::GET, "/subscribers/export", export_subscriber_list)
+                .route(Method::GET, "/suppressions", list_suppressions)
+                .route(Method::POST, "/segments/preview", preview_segment);
+        })
+        .requiring(Scope::SubscribersWrite, |admin| {
+            admin
+                .route(Method::POST, "/subscribers/import", import_subscribers_csv)
+                .route(
+                    Method::POST,
+                    "/subscribers/{subscriber_id}/erase",
+                    erase_subscriber_by_id,
+                )
+                .route(
+                    Method::POST,
+                    "/subscribers/{subscriber_id}/tags",
+                    tag_subscriber,
+                )
+                .route(
+                    Method::DELETE,
+                    "/subscribers/{subscriber_id}/tags/{tag}",
+                    untag_subscriber,
+                )
+                .route(Method::POST, "/suppressions", create_suppression)
+                .route(Method::POST, "/suppressions/import", import_suppressions)
+                .route(
+                    Method::DELETE,
+                    "/suppressions/{suppression_id}",
+                    delete_suppression,
+                );
+        })
+        .requiring(Scope::StatsRead, |admin| {
+            admin.route(Method::GET, "/metrics", get_metrics).route(
+                Method::GET,
+                "/newsletters/{newsletter_issue_id}/stats",
+                get_issue_stats,
+            );
+        })
+        .requiring(Scope::ListsRead, |admin| {
+            admin
+                .route(Method::GET, "/lists", list_lists)
+                .route(Method::GET, "/lists/{slug}/feed", get_list_feed)
+                .route(Method::GET, "/lists/{slug}/fields", list_list_fields);
+        })
+        .requiring(Scope::ListsWrite, |admin| {
+            admin
+                .route(Method::POST, "/lists", create_list)
+                .route(Method::PUT, "/lists/{slug}/feed", set_list_feed)
+                .route(Method::DELETE, "/lists/{slug}/feed", delete_list_feed)
+                .route(Method::PUT, "/lists/{slug}/tracking", set_list_tracking)
+                .route(Method::PUT, "/lists/{slug}/captcha", set_list_captcha)
+                .route(
+                    Method::PUT,
+                    "/lists/{slug}/allowed-domains",
+                    set_list_allowed_domains,
+                )
+                .route(Method::POST, "/lists/{slug}/fields", create_list_field)
+                .route(
+                    Method::DELETE,
+                    "/lists/{slug}/fields/{key}",
+                    delete_list_field,
+                );
+        })
+        .requiring(Scope::NewslettersRead, |admin| {
+            admin
+                .route(Method::GET, "/templates", list_templates)
+                .route(Method::GET, "/newsletters", list_issues)
+                .route(Method::POST, "/newsletters/preview", preview_newsletter)
+                .route(
+                    Method::GET,
+                    "/newsletters/{newsletter_issue_id}",
+                    get_issue_details,
+                )
+                .route(
+                    Method::GET,
+                    "/newsletters/{newsletter_issue_id}/preview",
+                    preview_issue,
+                );
+        })
+        .requiring(Scope::NewslettersWrite, |admin| {
+            admin
+                .route(Method::POST, "/templates", create_template)
+                .route(Method::PUT, "/templates/{name}", update_template)
+                .route(Method::POST, "/newsletters/drafts", create_draft)
+                .route(
+                    Method::PUT,
+                    "/newsletters/{newsletter_issue_id}",
+                    update_draft,
+                )
+                .route(
+                    Method::DELETE,
+                    "/newsletters/{newsletter_issue_id}",
+                    delete_issue,
+                )
+                .route(
+                    Method::POST,
+                    "/newsletters/{newsletter_issue_id}/test",
+                    send_test_issue,
+                );
+        })
+        // Anything that reaches subscribers
+        .requiring(Scope::NewslettersPublish, |admin| {
+            admin
+                .route(Method::POST, "/newsletters", publish_newsletter)
+                .route(
+                    Method::POST,
+                    "/newsletters/{newsletter_issue_id}/schedule",
+                    schedule_issue,
+                )
+                .route(
+                    Method::POST,
+                    "/newsletters/{newsletter_issue_id}/cancel",
+                    cancel_issue,
+                )
+                .route(
+                    Method::PUT,
+                    "/newsletters/{newsletter_issue_id}/archive",
+                    set_archive_visibility,
+                );
+        });
+}
+
 // `HttpServer`does two jobs given an address - bind it and start the app
 pub fn run_0(address: &str) -> std::io::Result<Server> {
     let server = HttpServer::new(|| {
@@ -0,0 +1,98 @@
+use crate::domain::SuppressionTarget;
+use chrono::Utc;
+use sqlx::PgExecutor;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+// Who added a suppression, as stored in `suppressions.source`
+#[derive(Debug, Clone, Copy)]
+pub enum SuppressionSource {
+    Admin,
+    Import,
+    // The email provider reported a hard bounce or a complaint
+    Webhook,
+}
+
+impl SuppressionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionSource::Admin => "admin",
+            SuppressionSource::Import => "import",
+            SuppressionSource::Webhook => "webhook",
+        }
+    }
+}
+
+// Whether the address, or its domain, is on the suppression list
+#[tracing::instrument(name = "Checking the suppression list", skip(executor))]
+pub async fn is_suppressed(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<bool, sqlx::Error> {
+    // Domains are checked to only hold letters, digits, `-` and `.`, so they are safe in `LIKE`
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM suppressions
+            WHERE (kind = 'email' AND value = lower($1))
+                OR (kind = 'domain' AND (
+                    value = split_part(lower($1), '@', 2)
+                    OR split_part(lower($1), '@', 2) LIKE '%.' || value
+                ))
+        ) AS "suppressed!"
+        "#,
+        email.trim()
+    )
+    .fetch_one(executor)
+    .await
+}
+
+// The lowercased addresses among `emails` that are on the suppression list, as `is_suppressed` checks them
+pub async fn get_suppressed_emails(
+    executor: impl PgExecutor<'_>,
+    emails: &[String],
+) -> Result<HashSet<String>, sqlx::Error> {
+    let emails: Vec<String> = emails.iter().map(|e| e.trim().to_lowercase()).collect();
+    let suppressed = sqlx::query_scalar!(
+        r#"
+        SELECT email AS "email!" FROM UNNEST($1::text[]) AS email
+        WHERE EXISTS (
+            SELECT 1 FROM suppressions
+            WHERE (kind = 'email' AND value = email)
+                OR (kind = 'domain' AND (
+                    value = split_part(email, '@', 2)
+                    OR split_part(email, '@', 2) LIKE '%.' || value
+                ))
+        )
+        "#,
+        &emails
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(suppressed.into_iter().collect())
+}
+
+// Returns the id of the new suppression, `None` if it was already there
+pub async fn add_suppression(
+    executor: impl PgExecutor<'_>,
+    target: &SuppressionTarget,
+    reason: &str,
+    source: SuppressionSource,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO suppressions (id, kind, value, reason, source, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (kind, value) DO NOTHING
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        target.kind(),
+        target.value(),
+        reason,
+        source.as_str(),
+        Utc::now()
+    )
+    .fetch_optional(executor)
+    .await
+}
@@ -0,0 +1,268 @@
+use minijinja::{Environment, Value};
+use serde_json::Map;
+use std::collections::BTreeSet;
+
+// Available in every template, `{{ name }}`, `{{ list.name }}` ...
+const VARIABLES: [&str; 7] = [
+    "name",
+    "email",
+    "title",
+    "unsubscribe_url",
+    "preferences_url",
+    "view_in_browser_url",
+    "postal_address",
+];
+const LIST_VARIABLES: [&str; 2] = ["name", "slug"];
+// Digests also have `entries`, each with `title`, `url`, `summary` and `published_at`
+const FEED_VARIABLES: [&str; 2] = ["title", "url"];
+// A layout has to place the issue, and the footer every email must carry
+const LAYOUT_REQUIRED: [&str; 3] = ["content", "unsubscribe_url", "postal_address"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateKind {
+    // Wraps the content of an issue - header, `{{ content }}`, footer
+    Layout,
+    // The content of an issue
+    Issue,
+    // The content of an issue made from new feed entries, see `feed_poller`
+    Digest,
+}
+
+impl TemplateKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateKind::Layout => "layout",
+            TemplateKind::Issue => "issue",
+            TemplateKind::Digest => "digest",
+        }
+    }
+}
+
+impl TryFrom<String> for TemplateKind {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "layout" => Ok(Self::Layout),
+            "issue" => Ok(Self::Issue),
+            "digest" => Ok(Self::Digest),
+            other => Err(format!(
+                "{} is not a template kind: use layout, issue or digest.",
+                other
+            )),
+        }
+    }
+}
+
+/// The HTML and plain text versions of an email, written with Jinja syntax, e.g.
+///
+/// `<p>Hi {{ name }}, how is {{ attribute.company }} doing?</p>`
+///
+/// - `name`, `email` and `attribute.<key>` (a custom field of any list) are the recipient's
+/// - `title` is the issue's, `list.name` and `list.slug` the list it is sent through
+/// - `unsubscribe_url` and `postal_address` are for the footer
+/// - `view_in_browser_url` links to the issue in the archive, unless it is hidden from it
+/// - a layout also has `content`, the rendered issue
+/// - a digest also has `entries` and `feed.title` / `feed.url`, see `Digest`
+///
+/// Values are HTML escaped in the HTML version, a missing attribute renders as nothing.
+/// The archive has no recipient, so `name`, `email` and the links are missing there too:
+/// `Hi {{ name | default("there") }}` reads well in both.
+#[derive(Debug, Clone)]
+pub struct EmailTemplate {
+    pub html: String,
+    pub text: String,
+}
+
+impl EmailTemplate {
+    // Parse both versions and make sure they only use variables we can fill in
+    // so a typo is caught when the template is saved, not when half the list got the email
+    pub fn compile(&self, kind: TemplateKind, attribute_keys: &[String]) -> Result<(), String> {
+        let env = environment();
+        for (file_name, source) in [("html", &self.html), ("text", &self.text)] {
+            let template = env
+                .template_from_named_str(file_name, source)
+                .map_err(|e| format!("The {} version does not compile: {}", file_name, e))?;
+            let used = template.undeclared_variables(true);
+            let unknown: BTreeSet<_> = used
+                .iter()
+                .filter(|variable| !is_known(&env, kind, variable, attribute_keys))
+                .collect();
+            if !unknown.is_empty() {
+                let unknown: Vec<_> = unknown.iter().map(|v| format!("`{}`", v)).collect();
+                return Err(format!(
+                    "The {} version uses unknown variables: {}.",
+                    file_name,
+                    unknown.join(", ")
+                ));
+            }
+            if kind == TemplateKind::Layout {
+                let roots = template.undeclared_variables(false);
+                if let Some(missing) = LAYOUT_REQUIRED.iter().find(|v| !roots.contains(**v)) {
+                    return Err(format!(
+                        "The {} version of a layout must use `{{{{ {} }}}}`.",
+                        file_name, missing
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What one recipient's copy of an issue is rendered with.
+#[derive(serde::Serialize, Debug)]
+pub struct MergeVariables {
+    // `None` is left undefined rather than rendered as `none`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub attribute: Map<String, serde_json::Value>,
+    pub title: String,
+    pub list: ListVariables,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsubscribe_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferences_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub view_in_browser_url: Option<String>,
+    pub postal_address: String,
+    // `entries` and `feed`, for digest issues
+    #[serde(flatten)]
+    pub digest: Option<Digest>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ListVariables {
+    pub name: String,
+    pub slug: String,
+}
+
+/// The feed entries a digest issue was made from, stored with the issue, e.g.
+///
+/// `{% for entry in entries %}<a href="{{ entry.url }}">{{ entry.title }}</a>{% endfor %}`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Digest {
+    pub feed: FeedVariables,
+    pub entries: Vec<DigestEntry>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct FeedVariables {
+    pub title: String,
+    // The website the feed is about
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct DigestEntry {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    // Plain text, the markup of the feed is dropped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    // RFC 3339
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_at: Option<String>,
+}
+
+// Render the issue for one recipient, then the layout around it
+pub fn render(
+    layout: &EmailTemplate,
+    content: &EmailTemplate,
+    variables: &MergeVariables,
+) -> Result<EmailTemplate, minijinja::Error> {
+    let env = environment();
+    let context = Value::from_serialize(variables);
+    let html_context = html_context(variables, &context);
+    let EmailTemplate { html, text } = render_with(&env, content, &html_context, &context)?;
+    // The rendered issue is already escaped, the layout must not escape it again
+    let html = env
+        .template_from_named_str("html", &layout.html)?
+        .render(minijinja::context! { content => Value::from_safe_string(html), ..html_context })?;
+    let text = env
+        .template_from_named_str("text", &layout.text)?
+        .render(minijinja::context! { content => text, ..context })?;
+    Ok(EmailTemplate { html, text })
+}
+
+// Render the issue alone, e.g. for the archive, which has a page of its own around it
+pub fn render_content(
+    content: &EmailTemplate,
+    variables: &MergeVariables,
+) -> Result<EmailTemplate, minijinja::Error> {
+    let context = Value::from_serialize(variables);
+    render_with(
+        &environment(),
+        content,
+        &html_context(variables, &context),
+        &context,
+    )
+}
+
+fn render_with(
+    env: &Environment,
+    template: &EmailTemplate,
+    html_context: &Value,
+    text_context: &Value,
+) -> Result<EmailTemplate, minijinja::Error> {
+    let html = env
+        .template_from_named_str("html", &template.html)?
+        .render(html_context)?;
+    let text = env
+        .template_from_named_str("text", &template.text)?
+        .render(text_context)?;
+    Ok(EmailTemplate { html, text })
+}
+
+// Escaping the `/`s of a link we built ourselves would only garble it
+fn html_context(variables: &MergeVariables, context: &Value) -> Value {
+    let links: Value = [
+        ("unsubscribe_url", &variables.unsubscribe_url),
+        ("preferences_url", &variables.preferences_url),
+        ("view_in_browser_url", &variables.view_in_browser_url),
+    ]
+    .into_iter()
+    .filter_map(|(name, url)| Some((name, Value::from_safe_string(url.clone()?))))
+    .collect();
+    minijinja::context! { ..links, ..context.clone() }
+}
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    // Only the HTML version is escaped, the name of a template is its version
+    env.set_auto_escape_callback(|name| match name {
+        "html" => minijinja::AutoEscape::Html,
+        _ => minijinja::AutoEscape::None,
+    });
+    env
+}
+
+// `variable` is dotted, e.g. `list.name` or `attribute.company`
+fn is_known(
+    env: &Environment,
+    kind: TemplateKind,
+    variable: &str,
+    attribute_keys: &[String],
+) -> bool {
+    let (root, field) = match variable.split_once('.') {
+        Some((root, field)) => (root, Some(field)),
+        None => (variable, None),
+    };
+    match (root, field) {
+        ("attribute", Some(key)) => attribute_keys.iter().any(|k| k == key),
+        ("list", Some(field)) => LIST_VARIABLES.contains(&field),
+        ("attribute" | "list", None) => true,
+        ("content", None) => kind == TemplateKind::Layout,
+        ("entries", None) => kind == TemplateKind::Digest,
+        ("feed", None) => kind == TemplateKind::Digest,
+        ("feed", Some(field)) => kind == TemplateKind::Digest && FEED_VARIABLES.contains(&field),
+        (root, None) if VARIABLES.contains(&root) => true,
+        // Functions like `range` show up as variables too
+        (root, _) => env.globals().any(|(name, _)| name == root),
+    }
+}
@@ -0,0 +1,121 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// What a tracking link stands for, carried in the link itself rather than stored, e.g.
+///
+/// `/t/c/eyJpIjoi...In0.3q2-7w...` - the JSON of the token, then its HMAC
+///
+/// The signature is what stops anyone from turning `/t/c/` into an open redirect,
+/// or making up opens and clicks for someone else.
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+pub struct TrackingToken {
+    #[serde(rename = "i")]
+    pub newsletter_issue_id: Uuid,
+    #[serde(rename = "s")]
+    pub subscriber_id: Uuid,
+    // Where a click goes, `None` for the open pixel
+    #[serde(rename = "u", default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl TrackingToken {
+    pub fn sign(&self, secret: &str) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap());
+        let signature = URL_SAFE_NO_PAD.encode(mac(secret, &payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    // `None` unless we signed the token
+    pub fn verify(token: &str, secret: &str) -> Option<Self> {
+        let (payload, signature) = token.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        // Compared in constant time
+        mac(secret, payload).verify_slice(&signature).ok()?;
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+}
+
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Route the links of one recipient's HTML through `/t/c/` and add the open pixel.
+///
+/// Our own links - unsubscribe, preferences, view in browser - are left alone, and so is the text
+/// version: there is nothing to load in it, and rewritten links would only make it unreadable.
+pub fn add_tracking(
+    html: &str,
+    base_url: &str,
+    secret: &str,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> String {
+    let token = |url: Option<String>| {
+        TrackingToken {
+            newsletter_issue_id,
+            subscriber_id,
+            url,
+        }
+        .sign(secret)
+    };
+    let mut html = rewrite_links(html, |url| {
+        let is_external = (url.starts_with("http://") || url.starts_with("https://"))
+            && !url.starts_with(base_url);
+        is_external.then(|| format!("{}/t/c/{}", base_url, token(Some(url.into()))))
+    });
+    let pixel = format!(
+        "<img src=\"{}/t/o/{}\" width=\"1\" height=\"1\" alt=\"\" style=\"display:block;border:0;\">",
+        base_url,
+        token(None)
+    );
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(end) => html.insert_str(end, &pixel),
+        None => html.push_str(&pixel),
+    }
+    html
+}
+
+// Replace the value of every `href` attribute `rewrite` returns a new one for
+// `rewrite` gets the link with HTML entities decoded, e.g. `&amp;` as `&`
+fn rewrite_links(html: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    const HREF: &str = "href=";
+    // Same byte offsets as `html`, lowercasing ASCII keeps the length
+    let lowercase = html.to_ascii_lowercase();
+    let mut rewritten = String::with_capacity(html.len());
+    let mut copied = 0;
+    let mut from = 0;
+    while let Some(found) = lowercase[from..].find(HREF) {
+        let attribute = from + found;
+        let value = attribute + HREF.len();
+        from = value;
+        // `href=` on its own, not e.g. `data-href=`
+        if !html[..attribute].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let Some(quote) = html[value..]
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+        else {
+            continue;
+        };
+        let Some(length) = html[value + 1..].find(quote) else {
+            break;
+        };
+        let (start, end) = (value + 1, value + 1 + length);
+        if let Some(url) = rewrite(&html_escape::decode_html_entities(&html[start..end])) {
+            rewritten.push_str(&html[copied..start]);
+            rewritten.push_str(&url);
+            copied = end;
+        }
+        from = end;
+    }
+    rewritten.push_str(&html[copied..]);
+    rewritten
+}
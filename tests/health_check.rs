@@ -1,13 +1,30 @@
 // Apply to `tests` crate
 #![allow(dead_code)]
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use chrono::{Datelike, Duration, Utc};
 use once_cell::sync::Lazy;
 use rust_news_letter_server::{
-    configuration::{get_configuration, DatabaseSettings},
+    bot_protection::sign_form_token,
+    configuration::{
+        get_configuration, DatabaseSettings, PolicyAction, RateLimitStore, RouteRateLimit,
+        Settings, TokenBucketSettings,
+    },
+    email_client::EmailClient,
+    feed_poller::{http_client, poll_due_feeds},
+    import::{import_subscribers, DuplicatePolicy, ImportOptions},
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    issue_scheduler::promote_due_issues,
+    signup_policy::SignupPolicy,
     startup::{run, run_0, run_1},
     telemetry::{get_subscriber, init_subscriber},
+    tracking::TrackingToken,
 };
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::collections::HashMap;
 use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
 #[actix_rt::test]
 // `actix_rt::test` starts a new `tokio` runtime for each test function and shuts it down after the test function is done.
@@ -81,6 +98,236 @@ fn spawn_app_1() -> String {
 pub struct TestApp {
     pub address: String,
     pub db_pool: PgPool,
+    pub test_user: TestUser,
+    // Stands in for the email provider's API
+    pub email_server: MockServer,
+    // Pointed at `email_server`, for running the delivery worker in tests
+    pub email_client: EmailClient,
+    pub postal_address: String,
+    pub hmac_secret: String,
+    pub webhook_secret: String,
+}
+
+impl TestApp {
+    pub async fn post_subscriber_import(&self, query: &str, csv: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/admin/subscribers/import?{}",
+                self.address, query
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_string())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscriber_export(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                self.address, query
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_data_access_request(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/me/access", self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Extract the link embedded in the body of an email sent through the mock API
+    pub fn get_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let links: Vec<_> = linkify::LinkFinder::new()
+            .links(body["TextBody"].as_str().unwrap())
+            .filter(|l| *l.kind() == linkify::LinkKind::Url)
+            .collect();
+        assert_eq!(links.len(), 1);
+        let link = reqwest::Url::parse(links[0].as_str()).unwrap();
+        // Make sure we don't call random APIs on the web
+        assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+        link
+    }
+
+    pub async fn post_admin_json(&self, path: &str, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin{}", self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_admin_json(&self, path: &str, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin{}", self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_list_subscription(&self, slug: &str, body: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/lists/{}/subscriptions", self.address, slug))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_string())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Run the delivery worker until the queue is empty
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.address,
+                &self.postal_address,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    // One tick of the scheduler, returns how many issues it queued
+    pub async fn run_scheduler(&self) -> u64 {
+        promote_due_issues(&self.db_pool).await.unwrap()
+    }
+
+    // Poll the feeds that are due, returns how many issues were made from them
+    pub async fn poll_feeds(&self) -> u64 {
+        poll_due_feeds(&self.db_pool, &http_client()).await.unwrap()
+    }
+
+    pub async fn get_admin(&self, path: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin{}", self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Another admin user, e.g. an `editor`
+    pub async fn store_user(&self, role: &'static str) -> TestUser {
+        let user = TestUser::with_role(role);
+        user.store(&self.db_pool).await;
+        user
+    }
+
+    pub async fn admin_request_as(
+        &self,
+        user: &TestUser,
+        method: reqwest::Method,
+        path: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .request(method, format!("{}/admin{}", self.address, path))
+            .basic_auth(&user.username, Some(&user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Mints an API key for the test user and returns its secret
+    pub async fn mint_api_key(&self, scopes: &[&str]) -> String {
+        let response = self
+            .post_admin_json(
+                "/api-keys",
+                &serde_json::json!({ "name": "CMS", "scopes": scopes }),
+            )
+            .await;
+        assert_eq!(201, response.status().as_u16());
+        let body: serde_json::Value = response.json().await.unwrap();
+        body["key"].as_str().unwrap().to_string()
+    }
+
+    pub async fn admin_request_with_key(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .request(method, format!("{}/admin{}", self.address, path))
+            .bearer_auth(key)
+            .json(&serde_json::json!({ "slug": "from-the-cms", "name": "From the CMS" }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers?{}", self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+}
+
+// A user of the admin endpoints, with a random password stored hashed like a real one
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+    pub role: &'static str,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self::with_role("owner")
+    }
+
+    pub fn with_role(role: &'static str) -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+            role,
+        }
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut OsRng);
+        // The cheapest parameters - some tests sign in hundreds of times
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(Params::MIN_M_COST, Params::MIN_T_COST, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash, role) VALUES ($1, $2, $3, $4)",
+            self.user_id,
+            self.username,
+            password_hash,
+            self.role,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
 }
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
@@ -99,6 +346,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 });
 
 async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// For tests that need settings of their own, e.g. tighter rate limits
+async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     // The first time `initialize` is invoked, the code in `TRACING` is executed
     // all other invocation will skip the code in `TRACING`
     Lazy::force(&TRACING);
@@ -107,17 +359,35 @@ async fn spawn_app() -> TestApp {
     let port = listener.local_addr().unwrap().port();
     let address = format!("http://127.0.0.1:{}", port);
 
+    let email_server = MockServer::start().await;
     let mut configuration = get_configuration().expect("Failed to read configuration.");
     configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.email_client.base_url = email_server.uri();
+    // The CAPTCHA provider is stubbed on the same mock server
+    configuration.bot_protection.captcha.verify_url = format!("{}/siteverify", email_server.uri());
+    // Most tests sign up like an API client that has no form token
+    configuration.bot_protection.require_form_token = false;
+    customise(&mut configuration);
     let connection_pool = configurate_database(&configuration.database).await;
 
-    let server = run(listener, connection_pool.clone()).expect("Failed to bind address");
+    configuration.application.base_url = address.clone();
+    let server =
+        run(listener, connection_pool.clone(), &configuration).expect("Failed to bind address");
     // when `tokio` runtime is shut down, all tasks spawned on it are dropped automatically.
     tokio::spawn(server);
 
+    let test_user = TestUser::generate();
+    test_user.store(&connection_pool).await;
+
     TestApp {
         address,
         db_pool: connection_pool,
+        test_user,
+        email_server,
+        email_client: configuration.email_client.client(),
+        postal_address: configuration.application.postal_address.clone(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        webhook_secret: configuration.email_client.webhook_secret.clone(),
     }
 }
 // Allow spawn app that configurates a random data base for a test
@@ -135,17 +405,27 @@ async fn spawn_app3() -> TestApp {
     let port = listener.local_addr().unwrap().port();
     let address = format!("http://127.0.0.1:{}", port);
 
+    let email_server = MockServer::start().await;
     let mut configuration = get_configuration().expect("Failed to read configuration.");
     configuration.database.database_name = Uuid::new_v4().to_string();
+    configuration.email_client.base_url = email_server.uri();
     let connection_pool = configurate_database(&configuration.database).await;
 
-    let server = run(listener, connection_pool.clone()).expect("Failed to bind address");
+    configuration.application.base_url = address.clone();
+    let server =
+        run(listener, connection_pool.clone(), &configuration).expect("Failed to bind address");
     // when `tokio` runtime is shut down, all tasks spawned on it are dropped automatically.
     tokio::spawn(server);
 
     TestApp {
         address,
         db_pool: connection_pool,
+        test_user: TestUser::generate(),
+        email_server,
+        email_client: configuration.email_client.client(),
+        postal_address: configuration.application.postal_address.clone(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        webhook_secret: configuration.email_client.webhook_secret.clone(),
     }
 }
 pub async fn configurate_database(config: &DatabaseSettings) -> PgPool {
@@ -176,18 +456,28 @@ async fn spawn_app_2() -> TestApp {
     let port = listener.local_addr().unwrap().port();
     let address = format!("http://127.0.0.1:{}", port);
 
-    let configuration = get_configuration().expect("Failed to read configuration.");
+    let email_server = MockServer::start().await;
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.email_client.base_url = email_server.uri();
     let connection_pool = PgPool::connect(&configuration.database.connection_string())
         .await
         .expect("Failed to connect to Postgres.");
 
-    let server = run(listener, connection_pool.clone()).expect("Failed to bind address");
+    configuration.application.base_url = address.clone();
+    let server =
+        run(listener, connection_pool.clone(), &configuration).expect("Failed to bind address");
     // when `tokio` runtime is shut down, all tasks spawned on it are dropped automatically.
     tokio::spawn(server);
 
     TestApp {
         address,
         db_pool: connection_pool,
+        test_user: TestUser::generate(),
+        email_server,
+        email_client: configuration.email_client.client(),
+        postal_address: configuration.application.postal_address.clone(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        webhook_secret: configuration.email_client.webhook_secret.clone(),
     }
 }
 
@@ -196,13 +486,13 @@ async fn spawn_app_2() -> TestApp {
 // Otherwise, one test is dependent on the state of the database after the other test
 async fn subscribe_returns_a_200_for_valid_form_data() {
     // let address = spawn_app_1();
-    let address = spawn_app().await.address;
-    let configuration = get_configuration().expect("Failed to read configuration");
-    let connection_string = configuration.database.connection_string();
-    // connection must be mutable for us to query
-    let mut connection = PgConnection::connect(&connection_string)
-        .await
-        .expect("Failed to connect to Postgres.");
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let address = app.address.clone();
 
     let client = reqwest::Client::new();
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
@@ -224,8 +514,9 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
     // `query` requires to know where to find the database by DATABASE_URL env var
     // `configuration` file is for runtime, i.e., after compiled
     // for `test` and dev we can provide env var in a top level `.env` file - easier
+    // Query the randomly named database of this test, not the one from `configuration`
     let saved = sqlx::query!("SELECT email, name FROM subscriptions",)
-        .fetch_one(&mut connection)
+        .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
@@ -260,3 +551,5862 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
         );
     }
 }
+
+#[actix_rt::test]
+async fn subscribe_normalizes_the_email_and_keeps_the_display_form() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let client = reqwest::Client::new();
+    let test_cases = vec![
+        (
+            "%20%20Ursula_Le_Guin%40Gmail.COM%20",
+            "Ursula_Le_Guin@gmail.com",
+            "Ursula_Le_Guin@Gmail.COM",
+        ),
+        (
+            "le_guin%40B%C3%BCcher.de",
+            "le_guin@xn--bcher-kva.de",
+            "le_guin@Bücher.de",
+        ),
+    ];
+
+    for (email, normalized, display) in test_cases {
+        let response = client
+            .post(format!("{}/subscriptions", app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(format!("name=le%20guin&email={}", email))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        assert_eq!(200, response.status().as_u16());
+
+        let saved = sqlx::query!(
+            "SELECT email, email_display FROM subscriptions WHERE email = $1",
+            normalized
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+        assert_eq!(saved.email_display, display);
+        // The confirmation goes to the address as they typed it
+        let confirmation = sent_emails(&app).await.pop().unwrap();
+        assert_eq!(confirmation["To"], display);
+    }
+}
+
+#[actix_rt::test]
+async fn subscribe_treats_emails_differing_by_case_as_the_same_subscriber() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let client = reqwest::Client::new();
+
+    for email in [
+        "Ursula%40Gmail.com",
+        "ursula%40gmail.com",
+        "URSULA%40GMAIL.COM",
+    ] {
+        let response = client
+            .post(format!("{}/subscriptions", app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(format!("name=le%20guin&email={}", email))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        // Duplicates are not reported back to avoid leaking who is subscribed
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let saved = sqlx::query!("SELECT email, email_display FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email_display, "Ursula@Gmail.com");
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_400_when_the_email_is_invalid() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let test_cases = vec![
+        ("name=le%20guin&email=", "empty email"),
+        (
+            "name=le%20guin&email=ursula_le_guin",
+            "missing the @ symbol",
+        ),
+        (
+            "name=le%20guin&email=%40gmail.com",
+            "missing the local part",
+        ),
+        ("name=le%20guin&email=ursula%40", "missing the domain"),
+        (
+            "name=le%20guin&email=ursula%20le%40gmail.com",
+            "whitespace in the local part",
+        ),
+        (
+            "name=le%20guin&email=ursula%40gmail..com",
+            "empty domain label",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = client
+            .post(format!("{}/subscriptions", app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for {}",
+            description
+        );
+    }
+}
+
+// Insert subscribers directly, `minutes_ago` apart, so the ordering of the listing is known
+async fn insert_subscribers(app: &TestApp, subscribers: &[(&str, &str, &str, i64)]) {
+    for (email, name, status, minutes_ago) in subscribers {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, email_display, name, status, subscribed_at)
+            VALUES ($1, $2, $2, $3, $4, $5)
+            "#,
+            Uuid::new_v4(),
+            email,
+            name,
+            status,
+            Utc::now() - Duration::minutes(*minutes_ago),
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to insert subscriber.");
+    }
+}
+
+fn listed_emails(body: &serde_json::Value) -> Vec<&str> {
+    body["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[actix_rt::test]
+async fn admin_requests_without_valid_credentials_are_rejected() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/admin/subscribers", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+
+    let response = client
+        .get(format!("{}/admin/subscribers", app.address))
+        .basic_auth(&app.test_user.username, Some(Uuid::new_v4().to_string()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(401, response.status().as_u16());
+
+    let response = client
+        .get(format!("{}/admin/subscribers", app.address))
+        .basic_auth(Uuid::new_v4().to_string(), Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn listing_subscribers_walks_every_page_with_the_cursor() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("a@example.com", "a", "confirmed", 5),
+            ("b@example.com", "b", "confirmed", 4),
+            ("c@example.com", "c", "confirmed", 3),
+            ("d@example.com", "d", "confirmed", 2),
+            ("e@example.com", "e", "confirmed", 1),
+        ],
+    )
+    .await;
+
+    let mut emails = vec![];
+    let mut query = "limit=2".to_string();
+    loop {
+        let response = app.get_admin_subscribers(&query).await;
+        assert_eq!(200, response.status().as_u16());
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["total"], 5);
+        emails.extend(listed_emails(&body).into_iter().map(String::from));
+        match body["next_cursor"].as_str() {
+            Some(cursor) => query = format!("limit=2&cursor={}", cursor),
+            None => break,
+        }
+    }
+
+    // Newest first
+    assert_eq!(
+        emails,
+        vec![
+            "e@example.com",
+            "d@example.com",
+            "c@example.com",
+            "b@example.com",
+            "a@example.com"
+        ]
+    );
+}
+
+#[actix_rt::test]
+async fn listing_subscribers_applies_filters_and_search() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            (
+                "ursula@example.com",
+                "Ursula Le Guin",
+                "confirmed",
+                60 * 24 * 10,
+            ),
+            (
+                "octavia@example.com",
+                "Octavia Butler",
+                "pending_confirmation",
+                60 * 24 * 5,
+            ),
+            ("ted@example.com", "Ted Chiang", "confirmed", 60),
+            ("under_score@example.com", "Under Score", "confirmed", 30),
+        ],
+    )
+    .await;
+
+    let test_cases = vec![
+        (
+            "status=confirmed",
+            vec![
+                "under_score@example.com",
+                "ted@example.com",
+                "ursula@example.com",
+            ],
+        ),
+        ("status=pending_confirmation", vec!["octavia@example.com"]),
+        ("search=BUTLER", vec!["octavia@example.com"]),
+        ("search=ted%40", vec!["ted@example.com"]),
+        // `_` is not a wildcard
+        ("search=a_p", vec![]),
+        ("search=a_p&status=confirmed", vec![]),
+        ("search=under_", vec!["under_score@example.com"]),
+    ];
+    for (query, expected) in test_cases {
+        let response = app.get_admin_subscribers(query).await;
+        assert_eq!(200, response.status().as_u16(), "Failed for `{}`", query);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(listed_emails(&body), expected, "Failed for `{}`", query);
+        assert_eq!(body["total"], expected.len(), "Failed for `{}`", query);
+    }
+
+    // Date range - everything from a week ago until two hours ago
+    let after = (Utc::now() - Duration::days(7)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let before =
+        (Utc::now() - Duration::hours(2)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let response = app
+        .get_admin_subscribers(&format!(
+            "subscribed_after={}&subscribed_before={}",
+            after, before
+        ))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(listed_emails(&body), vec!["octavia@example.com"]);
+}
+
+#[actix_rt::test]
+async fn listing_subscribers_returns_a_400_for_invalid_parameters() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("status=sleeping", "an unknown status"),
+        ("limit=0", "a limit that is too small"),
+        ("limit=100000", "a limit that is too large"),
+        ("cursor=not-a-cursor", "an invalid cursor"),
+        ("subscribed_after=yesterday", "an invalid date"),
+        (
+            "subscribed_after=2025-02-01T00:00:00Z&subscribed_before=2025-01-01T00:00:00Z",
+            "an empty date range",
+        ),
+    ];
+    for (query, description) in test_cases {
+        let response = app.get_admin_subscribers(query).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for {}",
+            description
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn importing_subscribers_reports_accepted_rejected_and_duplicate_rows() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[("existing@example.com", "existing", "confirmed", 1)],
+    )
+    .await;
+    let csv = "\
+Email Address,First Name,Country
+ursula@example.com,Ursula,US
+not-an-email,Broken,US
+URSULA@Example.com,Ursula again,US
+existing@EXAMPLE.com,Existing,UK
+octavia@example.com,Octavia,US
+";
+
+    let response = app
+        .post_subscriber_import(
+            "email_column=Email%20Address&name_column=First%20Name&source=mailchimp",
+            csv,
+        )
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 2);
+    assert_eq!(report["updated"], 0);
+    assert_eq!(report["rejected"].as_array().unwrap().len(), 1);
+    assert_eq!(report["rejected"][0]["line"], 3);
+    let duplicate_lines: Vec<_> = report["duplicates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(duplicate_lines, vec![4, 5]);
+
+    let imported = sqlx::query!(
+        "SELECT email, status, import_source FROM subscriptions WHERE import_source IS NOT NULL ORDER BY email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch imported subscribers.");
+    assert_eq!(imported.len(), 2);
+    assert_eq!(imported[0].email, "octavia@example.com");
+    assert_eq!(imported[1].email, "ursula@example.com");
+    for subscriber in imported {
+        assert_eq!(subscriber.status, "pending_confirmation");
+        assert_eq!(subscriber.import_source.as_deref(), Some("mailchimp"));
+    }
+}
+
+#[actix_rt::test]
+async fn importing_subscribers_can_update_existing_ones_and_confirm_them() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[("pending@example.com", "Old name", "pending_confirmation", 1)],
+    )
+    .await;
+    let csv = "email,name\nPending@example.com,New name\nnew@example.com,New\n";
+
+    let response = app
+        .post_subscriber_import("on_duplicate=update&confirmed=true", csv)
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 1);
+    assert_eq!(report["updated"], 1);
+    assert_eq!(report["duplicates"].as_array().unwrap().len(), 0);
+
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscribers.");
+    assert_eq!(saved[0].email, "new@example.com");
+    assert_eq!(saved[0].status, "confirmed");
+    // The existing row keeps its email, everything else comes from the file
+    assert_eq!(saved[1].email, "pending@example.com");
+    assert_eq!(saved[1].name, "New name");
+    assert_eq!(saved[1].status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn importing_subscribers_never_subscribes_again_anyone_who_left() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("gone@example.com", "Gone", "unsubscribed", 1),
+            ("bounced@example.com", "Bounced", "bounced", 1),
+            ("left@example.com", "Left", "confirmed", 1),
+        ],
+    )
+    .await;
+    // Still subscribed, but not to the default list any more
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+        SELECT lists.id, subscriptions.id, 'unsubscribed', now()
+        FROM lists, subscriptions
+        WHERE lists.is_default AND subscriptions.email = 'left@example.com'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let csv = "email,name
+gone@example.com,Gone
+bounced@example.com,Bounced
+left@example.com,Left
+";
+
+    let response = app
+        .post_subscriber_import("on_duplicate=update&confirmed=true", csv)
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["updated"], 1);
+    let duplicates = report["duplicates"].as_array().unwrap();
+    assert_eq!(duplicates.len(), 2);
+    assert_eq!(duplicates[0]["line"], 2);
+    assert!(duplicates[0]["reason"]
+        .as_str()
+        .unwrap()
+        .contains("unsubscribed"));
+    assert_eq!(duplicates[1]["line"], 3);
+    assert!(duplicates[1]["reason"]
+        .as_str()
+        .unwrap()
+        .contains("bounced"));
+
+    let saved = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let statuses: Vec<_> = saved
+        .iter()
+        .map(|s| (s.email.as_str(), s.status.as_str()))
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            ("bounced@example.com", "bounced"),
+            ("gone@example.com", "unsubscribed"),
+            ("left@example.com", "confirmed"),
+        ]
+    );
+    let membership = sqlx::query_scalar!(
+        r#"
+        SELECT list_memberships.status FROM list_memberships
+        JOIN subscriptions ON subscriptions.id = list_memberships.subscriber_id
+        WHERE subscriptions.email = 'left@example.com'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(membership, "unsubscribed");
+}
+
+#[actix_rt::test]
+async fn importing_subscribers_returns_a_400_when_a_mapped_column_is_missing() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "",
+            "email,full_name\nursula@example.com,Ursula\n",
+            "the default name column",
+        ),
+        (
+            "email_column=mail",
+            "email,name\nursula@example.com,Ursula\n",
+            "a custom email column",
+        ),
+        ("", "", "an empty file"),
+    ];
+
+    for (query, csv, description) in test_cases {
+        let response = app.post_subscriber_import(query, csv).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for {}",
+            description
+        );
+    }
+    let count = sqlx::query_scalar!("SELECT count(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count, Some(0));
+}
+
+// The CLI goes through the same function with a file instead of a request body
+#[actix_rt::test]
+async fn importing_subscribers_from_a_reader_spans_several_batches() {
+    let app = spawn_app().await;
+    let mut csv = "email,name\n".to_string();
+    for i in 0..2500 {
+        csv.push_str(&format!("subscriber{}@example.com,Subscriber {}\n", i, i));
+    }
+
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    let signup_policy = SignupPolicy::load(&configuration.signup_policy).unwrap();
+
+    let report = import_subscribers(
+        &app.db_pool,
+        std::io::Cursor::new(csv.into_bytes()),
+        &signup_policy,
+        ImportOptions {
+            on_duplicate: DuplicatePolicy::Skip,
+            mark_confirmed: true,
+            source: "cli".into(),
+            ..ImportOptions::default()
+        },
+    )
+    .await
+    .expect("Failed to import subscribers.");
+
+    assert_eq!(report.accepted, 2500);
+    assert!(report.rejected.is_empty());
+    let count = sqlx::query_scalar!(
+        "SELECT count(*) FROM subscriptions WHERE status = 'confirmed' AND import_source = 'cli'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(count, Some(2500));
+}
+
+#[actix_rt::test]
+async fn exporting_subscribers_as_csv_honours_the_listing_filters() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("ursula@example.com", "Le Guin, Ursula", "confirmed", 3),
+            ("octavia@example.com", "Octavia", "pending_confirmation", 2),
+            ("ted@example.com", "Ted", "confirmed", 1),
+        ],
+    )
+    .await;
+    sqlx::query!(
+        "UPDATE subscriptions SET policy_flags = '{disposable,role_account}' WHERE email = 'ted@example.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app
+        .get_subscriber_export("format=csv&status=confirmed")
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        "text/csv; charset=utf-8",
+        response.headers()["Content-Type"]
+    );
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .contains("subscribers.csv"));
+    let body = response.text().await.unwrap();
+    let mut reader = csv::Reader::from_reader(body.as_bytes());
+    assert_eq!(
+        reader.headers().unwrap(),
+        vec![
+            "id",
+            "email",
+            "email_display",
+            "name",
+            "status",
+            "subscribed_at",
+            "import_source",
+            "policy_flags"
+        ]
+    );
+    let rows: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+    // Oldest first, the comma in the name is quoted
+    assert_eq!(rows.len(), 2);
+    assert_eq!(&rows[0][1], "ursula@example.com");
+    assert_eq!(&rows[0][3], "Le Guin, Ursula");
+    assert_eq!(&rows[0][7], "");
+    assert_eq!(&rows[1][1], "ted@example.com");
+    assert_eq!(&rows[1][4], "confirmed");
+    assert_eq!(&rows[1][7], "disposable;role_account");
+}
+
+#[actix_rt::test]
+async fn exporting_subscribers_as_json_lines_returns_one_object_per_line() {
+    let app = spawn_app().await;
+    let mut subscribers = vec![];
+    for i in 0..1500 {
+        subscribers.push((format!("subscriber{}@example.com", i), i as i64));
+    }
+    let subscribers: Vec<_> = subscribers
+        .iter()
+        .map(|(email, minutes_ago)| (email.as_str(), "name", "confirmed", *minutes_ago))
+        .collect();
+    insert_subscribers(&app, &subscribers).await;
+
+    let response = app.get_subscriber_export("format=jsonl").await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("application/x-ndjson", response.headers()["Content-Type"]);
+    let body = response.text().await.unwrap();
+    let lines: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // More than one chunk's worth of rows
+    assert_eq!(lines.len(), 1500);
+    assert_eq!(lines[0]["email"], "subscriber1499@example.com");
+    assert_eq!(lines[0]["status"], "confirmed");
+    assert!(lines[0]["subscribed_at"].is_string());
+}
+
+#[actix_rt::test]
+async fn exporting_subscribers_returns_a_400_for_invalid_parameters() {
+    let app = spawn_app().await;
+    for query in ["format=xml", "status=sleeping"] {
+        let response = app.get_subscriber_export(query).await;
+        assert_eq!(400, response.status().as_u16(), "Failed for `{}`", query);
+    }
+}
+
+// Goes through the emailed link, as a subscriber would
+async fn get_data_access_link(app: &TestApp, email: &str) -> reqwest::Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_data_access_request(email).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_link(email_request)
+}
+
+fn subscription_token(link: &reqwest::Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "subscription_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+#[actix_rt::test]
+async fn the_data_access_link_returns_everything_stored_about_the_subscriber() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ursula@example.com", "confirmed")]).await;
+
+    let link = get_data_access_link(&app, "Ursula@Example.com").await;
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(data["subscription"]["email"], "ursula@example.com");
+    assert_eq!(data["subscription"]["name"], "Ursula");
+    assert_eq!(data["subscription"]["status"], "confirmed");
+    assert_eq!(data["list_memberships"][0]["list"], "newsletter");
+    // Looking at the data is itself recorded
+    assert_eq!(data["privacy_requests"][0]["action"], "access");
+    assert_eq!(data["privacy_requests"][0]["performed_by"], "subscriber");
+}
+
+#[actix_rt::test]
+async fn requesting_data_access_for_an_unknown_email_sends_nothing() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_data_access_request("nobody@example.com").await;
+
+    // Same answer as for a subscriber, so nobody can find out who is on the list
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn subscriber_data_is_rejected_without_a_valid_token() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/me/data?subscription_token=made-up",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn erasing_subscriber_data_anonymizes_the_row_and_revokes_the_token() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("ursula@example.com", "Ursula", "confirmed", 2),
+            ("octavia@example.com", "Octavia", "confirmed", 1),
+        ],
+    )
+    .await;
+    let link = get_data_access_link(&app, "ursula@example.com").await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/me/erase", app.address))
+        .form(&[("subscription_token", subscription_token(&link))])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    // The row is kept for counts, without anything that identifies the person
+    let rows = sqlx::query!(
+        "SELECT id, email, email_display, name, status FROM subscriptions ORDER BY subscribed_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].status, "erased");
+    assert_eq!(rows[0].name, "");
+    assert!(!rows[0].email.contains("ursula"));
+    assert!(!rows[0].email_display.contains("ursula"));
+    assert_eq!(rows[1].email, "octavia@example.com");
+
+    let audit = sqlx::query!(
+        "SELECT action, performed_by FROM gdpr_audit_log WHERE subscriber_id = $1 ORDER BY performed_at",
+        rows[0].id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audit.last().unwrap().action, "erasure");
+    assert_eq!(audit.last().unwrap().performed_by, "subscriber");
+
+    // The link stops working
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn admins_can_erase_a_subscriber() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!(
+            "{}/admin/subscribers/{}/erase",
+            app.address, subscriber_id
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "erased");
+    assert_eq!(saved.name, "");
+    let performed_by = sqlx::query_scalar!("SELECT performed_by FROM gdpr_audit_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(performed_by, format!("admin:{}", app.test_user.user_id));
+
+    let response = client
+        .post(format!(
+            "{}/admin/subscribers/{}/erase",
+            app.address,
+            Uuid::new_v4()
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(404, response.status().as_u16());
+}
+
+// Put already-inserted subscribers on a list, with the given membership status
+async fn add_to_list(app: &TestApp, slug: &str, members: &[(&str, &str)]) {
+    for (email, status) in members {
+        sqlx::query!(
+            r#"
+            INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+            SELECT lists.id, subscriptions.id, $3, now()
+            FROM lists, subscriptions
+            WHERE lists.slug = $1 AND subscriptions.email = $2
+            "#,
+            slug,
+            email,
+            status
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to add subscriber to the list.");
+    }
+}
+
+async fn create_list(app: &TestApp, slug: &str, name: &str) {
+    let response = app
+        .post_admin_json("/lists", &serde_json::json!({ "slug": slug, "name": name }))
+        .await;
+    assert_eq!(201, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn subscribe_sends_a_confirmation_link_for_the_default_list() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[("name", "le guin"), ("email", "ursula@example.com")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_link(email_request);
+    assert_eq!(link.path(), "/lists/newsletter/subscriptions/confirm");
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT subscriptions.status, list_memberships.status AS membership_status, list_memberships.confirmed_at
+        FROM subscriptions
+        JOIN list_memberships ON list_memberships.subscriber_id = subscriptions.id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.membership_status, "confirmed");
+    assert!(saved.confirmed_at.is_some());
+}
+
+#[actix_rt::test]
+async fn subscribing_to_an_unknown_list_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_list_subscription("nope", "name=le%20guin&email=ursula%40example.com")
+        .await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn list_subscriptions_are_confirmed_and_unsubscribed_per_list() {
+    let app = spawn_app().await;
+    create_list(&app, "security-advisories", "Security advisories").await;
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ursula@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_list_subscription(
+            "security-advisories",
+            "name=Ursula&email=Ursula%40Example.com",
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_link(email_request);
+    assert_eq!(
+        link.path(),
+        "/lists/security-advisories/subscriptions/confirm"
+    );
+    assert_eq!(
+        200,
+        reqwest::get(link.clone()).await.unwrap().status().as_u16()
+    );
+
+    let unsubscribe = format!(
+        "{}/lists/security-advisories/subscriptions/unsubscribe?subscription_token={}",
+        app.address,
+        subscription_token(&link)
+    );
+    // Following the link only asks, so a link scanner unsubscribes nobody
+    let response = reqwest::get(&unsubscribe).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let page = response.text().await.unwrap();
+    assert!(page.contains("<form method=\"post\""), "{}", page);
+    assert_eq!(
+        list_membership_status(&app, "security-advisories").await,
+        "confirmed"
+    );
+    let response = reqwest::Client::new()
+        .post(&unsubscribe)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let memberships = sqlx::query!(
+        r#"
+        SELECT lists.slug, list_memberships.status
+        FROM list_memberships JOIN lists ON lists.id = list_memberships.list_id
+        ORDER BY lists.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(memberships.len(), 2);
+    assert_eq!(memberships[0].slug, "newsletter");
+    assert_eq!(memberships[0].status, "confirmed");
+    assert_eq!(memberships[1].slug, "security-advisories");
+    assert_eq!(memberships[1].status, "unsubscribed");
+}
+
+async fn list_membership_status(app: &TestApp, slug: &str) -> String {
+    sqlx::query_scalar!(
+        r#"
+        SELECT list_memberships.status
+        FROM list_memberships JOIN lists ON lists.id = list_memberships.list_id
+        WHERE lists.slug = $1
+        "#,
+        slug
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[actix_rt::test]
+async fn issues_carry_a_one_click_unsubscribe_header() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Hello",
+                "content": { "html": "<p>Hi</p>", "text": "Hi" },
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email = &sent_emails(&app).await[0];
+    let headers: HashMap<&str, &str> = email["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| (h["Name"].as_str().unwrap(), h["Value"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        headers["List-Unsubscribe-Post"],
+        "List-Unsubscribe=One-Click"
+    );
+    let unsubscribe = headers["List-Unsubscribe"]
+        .strip_prefix('<')
+        .and_then(|url| url.strip_suffix('>'))
+        .unwrap();
+    assert!(unsubscribe.starts_with(&format!(
+        "{}/lists/newsletter/subscriptions/unsubscribe?subscription_token=",
+        app.address
+    )));
+
+    // What a mail client posts
+    let response = reqwest::Client::new()
+        .post(unsubscribe)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        list_membership_status(&app, "newsletter").await,
+        "unsubscribed"
+    );
+}
+
+#[actix_rt::test]
+async fn confirming_a_list_subscription_without_a_valid_token_returns_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/lists/newsletter/subscriptions/confirm?subscription_token=made-up",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn admins_can_create_and_list_lists() {
+    let app = spawn_app().await;
+    create_list(&app, "engineering-blog", "Engineering blog").await;
+
+    let response = app
+        .post_admin_json(
+            "/lists",
+            &serde_json::json!({ "slug": "engineering-blog", "name": "Again" }),
+        )
+        .await;
+    assert_eq!(409, response.status().as_u16());
+    let response = app
+        .post_admin_json(
+            "/lists",
+            &serde_json::json!({ "slug": "Not A Slug", "name": "Invalid" }),
+        )
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    insert_subscribers(
+        &app,
+        &[
+            ("ursula@example.com", "Ursula", "confirmed", 2),
+            ("octavia@example.com", "Octavia", "pending_confirmation", 1),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "engineering-blog",
+        &[
+            ("ursula@example.com", "confirmed"),
+            ("octavia@example.com", "pending_confirmation"),
+        ],
+    )
+    .await;
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/lists", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let lists = body["lists"].as_array().unwrap();
+    assert_eq!(lists.len(), 2);
+    assert_eq!(lists[0]["slug"], "newsletter");
+    assert_eq!(lists[0]["is_default"], true);
+    assert_eq!(lists[1]["slug"], "engineering-blog");
+    assert_eq!(lists[1]["confirmed"], 1);
+    assert_eq!(lists[1]["pending_confirmation"], 1);
+}
+
+#[actix_rt::test]
+async fn publishing_to_several_lists_delivers_the_issue_once_per_confirmed_subscriber() {
+    let app = spawn_app().await;
+    create_list(&app, "product-updates", "Product updates").await;
+    create_list(&app, "engineering-blog", "Engineering blog").await;
+    insert_subscribers(
+        &app,
+        &[
+            ("both@example.com", "Both", "confirmed", 4),
+            ("product@example.com", "Product", "confirmed", 3),
+            ("pending@example.com", "Pending", "pending_confirmation", 2),
+            ("left@example.com", "Left", "confirmed", 1),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "product-updates",
+        &[
+            ("both@example.com", "confirmed"),
+            ("product@example.com", "confirmed"),
+            ("pending@example.com", "pending_confirmation"),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "engineering-blog",
+        &[
+            ("both@example.com", "confirmed"),
+            ("left@example.com", "unsubscribed"),
+        ],
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Release notes",
+                "content": { "html": "<p>Shipped!</p>", "text": "Shipped!" },
+                "lists": ["product-updates", "engineering-blog"],
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["recipients"], 2);
+    app.dispatch_all_pending_emails().await;
+
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["To"].as_str().unwrap().to_string()
+        })
+        .collect();
+    recipients.sort();
+    assert_eq!(recipients, ["both@example.com", "product@example.com"]);
+    let sent = sqlx::query_scalar!("SELECT COUNT(*) FROM issue_deliveries WHERE status = 'sent'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(sent, Some(2));
+}
+
+#[actix_rt::test]
+async fn publishing_to_an_unknown_list_returns_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Release notes",
+                "content": { "html": "<p>Shipped!</p>", "text": "Shipped!" },
+                "lists": ["nope"],
+            }),
+        )
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let issues = sqlx::query_scalar!("SELECT COUNT(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues, Some(0));
+}
+
+#[actix_rt::test]
+async fn importing_subscribers_adds_them_to_the_requested_list() {
+    let app = spawn_app().await;
+    create_list(&app, "engineering-blog", "Engineering blog").await;
+
+    let response = app
+        .post_subscriber_import(
+            "list=engineering-blog&confirmed=true",
+            "email,name\nursula@example.com,Ursula\n",
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let membership = sqlx::query!(
+        r#"
+        SELECT lists.slug, list_memberships.status
+        FROM list_memberships JOIN lists ON lists.id = list_memberships.list_id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(membership.slug, "engineering-blog");
+    assert_eq!(membership.status, "confirmed");
+
+    let response = app
+        .post_subscriber_import("list=nope", "email,name\noctavia@example.com,Octavia\n")
+        .await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn importing_subscribers_applies_the_signup_policy() {
+    let app = spawn_app().await;
+    let response = app
+        .post_admin_json(
+            "/lists",
+            &serde_json::json!({
+                "slug": "staff",
+                "name": "Staff updates",
+                "allowed_domains": ["ourcompany.com"],
+            }),
+        )
+        .await;
+    assert_eq!(201, response.status().as_u16());
+
+    let response = app
+        .post_subscriber_import(
+            "list=staff",
+            "email,name\nada@ourcompany.com,Ada\nursula@example.com,Ursula\n",
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 1);
+    assert_eq!(report["rejected"][0]["line"], 3);
+    assert_eq!(
+        report["rejected"][0]["reason"],
+        "This list only accepts addresses at ourcompany.com."
+    );
+
+    // Disposable addresses are rejected and role addresses flagged, as configured in base.yaml
+    let response = app
+        .post_subscriber_import(
+            "",
+            "email,name\nsomeone@mailinator.com,Someone\nnoreply@example.com,Mail\n",
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 1);
+    assert_eq!(report["rejected"][0]["line"], 2);
+    assert_eq!(
+        report["rejected"][0]["reason"],
+        "Disposable email addresses are not accepted."
+    );
+    let saved = sqlx::query!("SELECT email, policy_flags FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].email, "ada@ourcompany.com");
+    assert!(saved[0].policy_flags.is_empty());
+    assert_eq!(saved[1].email, "noreply@example.com");
+    assert_eq!(saved[1].policy_flags, vec!["role_account"]);
+}
+
+#[actix_rt::test]
+async fn importing_subscribers_reports_suppressed_addresses_as_rejected() {
+    let app = spawn_app().await;
+    for value in ["blocked@example.com", "blocked.test"] {
+        assert_eq!(201, suppress(&app, value).await.status().as_u16());
+    }
+    let csv = "\
+email,name
+Blocked@Example.com,Blocked
+ada@example.com,Ada
+someone@mail.blocked.test,Someone
+";
+
+    let response = app.post_subscriber_import("confirmed=true", csv).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 1);
+    let rejected: Vec<_> = report["rejected"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| (r["line"].as_u64().unwrap(), r["reason"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        rejected,
+        vec![
+            (2, "The address is on the suppression list."),
+            (4, "The address is on the suppression list."),
+        ]
+    );
+    let emails = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(emails, vec!["ada@example.com"]);
+}
+
+async fn tag_subscribers(app: &TestApp, tags: &[(&str, &str)]) {
+    for (email, tag) in tags {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriber_tags (subscriber_id, tag, tagged_at)
+            SELECT id, $2, now() FROM subscriptions WHERE email = $1
+            "#,
+            email,
+            tag
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to tag subscriber.");
+    }
+}
+
+async fn preview_segment(app: &TestApp, segment: &str) -> reqwest::Response {
+    app.post_admin_json(
+        "/segments/preview",
+        &serde_json::json!({ "segment": segment }),
+    )
+    .await
+}
+
+#[actix_rt::test]
+async fn subscribe_stores_tags_from_a_hidden_form_field() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula@example.com"),
+            ("tags", "Beta, conference-2025"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let tags = sqlx::query_scalar!("SELECT tag FROM subscriber_tags ORDER BY tag")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tags, ["beta", "conference-2025"]);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[
+            ("name", "octavia"),
+            ("email", "octavia@example.com"),
+            ("tags", "not a tag"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn admins_can_tag_and_untag_subscribers() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    let subscriber_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_admin_json(
+            &format!("/subscribers/{}/tags", subscriber_id),
+            &serde_json::json!({ "tags": ["beta", "VIP"] }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["tags"], serde_json::json!(["beta", "vip"]));
+
+    let response = reqwest::Client::new()
+        .delete(format!(
+            "{}/admin/subscribers/{}/tags/beta",
+            app.address, subscriber_id
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(204, response.status().as_u16());
+    let tags = sqlx::query_scalar!("SELECT tag FROM subscriber_tags")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tags, ["vip"]);
+
+    let response = app
+        .post_admin_json(
+            &format!("/subscribers/{}/tags", Uuid::new_v4()),
+            &serde_json::json!({ "tags": ["beta"] }),
+        )
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn segment_preview_counts_the_matching_subscribers() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("recent-beta@example.com", "Recent", "confirmed", 4),
+            ("old-beta@example.com", "Old", "confirmed", 3),
+            (
+                "pending-beta@example.com",
+                "Pending",
+                "pending_confirmation",
+                2,
+            ),
+            ("other@example.com", "Other", "confirmed", 1),
+        ],
+    )
+    .await;
+    tag_subscribers(
+        &app,
+        &[
+            ("recent-beta@example.com", "beta"),
+            ("old-beta@example.com", "beta"),
+            ("pending-beta@example.com", "beta"),
+            ("other@example.com", "churned"),
+        ],
+    )
+    .await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET confirmed_at = CASE WHEN email = 'old-beta@example.com' THEN now() - interval '200 days' ELSE now() END
+        WHERE status = 'confirmed'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let test_cases = [
+        ("tag = beta and confirmed_at >= 90 days ago", 1),
+        ("tag = beta", 3),
+        ("TAG = \"beta\" AND status != pending_confirmation", 2),
+        ("not tag = beta", 1),
+        (
+            "tag = churned or (tag = beta and status = pending_confirmation)",
+            2,
+        ),
+        ("confirmed_at < 2000-01-01", 0),
+    ];
+    for (segment, expected) in test_cases {
+        let response = preview_segment(&app, segment).await;
+        assert_eq!(200, response.status().as_u16(), "for {}", segment);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["matching"], expected, "for {}", segment);
+    }
+}
+
+#[actix_rt::test]
+async fn segment_preview_returns_a_400_for_invalid_expressions() {
+    let app = spawn_app().await;
+    let test_cases = [
+        ("", "an empty segment"),
+        ("tag = beta and", "a dangling `and`"),
+        ("(tag = beta", "an unclosed parenthesis"),
+        ("tag > beta", "a tag compared with `>`"),
+        ("status = sleeping", "an unknown status"),
+        ("confirmed_at = 2025-01-01", "a date compared with `=`"),
+        ("confirmed_at > 90 fortnights ago", "an unknown unit"),
+        (
+            "confirmed_at >= 100000000 days ago",
+            "a date before the calendar",
+        ),
+        ("country = fr", "an unknown field"),
+        (
+            "tag = \"beta'; DROP TABLE subscriptions; --\"",
+            "an invalid tag",
+        ),
+    ];
+    for (segment, description) in test_cases {
+        let response = preview_segment(&app, segment).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for {}",
+            description
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn publishing_to_a_segment_only_reaches_the_matching_subscribers() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("beta@example.com", "Beta", "confirmed", 2),
+            ("other@example.com", "Other", "confirmed", 1),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "newsletter",
+        &[
+            ("beta@example.com", "confirmed"),
+            ("other@example.com", "confirmed"),
+        ],
+    )
+    .await;
+    tag_subscribers(&app, &[("beta@example.com", "beta")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Beta news",
+                "content": { "html": "<p>New!</p>", "text": "New!" },
+                "segment": "tag = beta",
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "beta@example.com");
+}
+
+async fn create_fields(app: &TestApp, slug: &str, fields: &[serde_json::Value]) {
+    for field in fields {
+        let response = app
+            .post_admin_json(&format!("/lists/{}/fields", slug), field)
+            .await;
+        assert_eq!(201, response.status().as_u16(), "for {}", field);
+    }
+}
+
+fn company_fields() -> Vec<serde_json::Value> {
+    vec![
+        serde_json::json!({ "key": "company", "label": "Company", "type": "text", "max_length": 20 }),
+        serde_json::json!({ "key": "country", "type": "choice", "required": true, "choices": ["fr", "de"] }),
+        serde_json::json!({ "key": "employees", "type": "number", "min": 1 }),
+        serde_json::json!({ "key": "newsletter_opt_in", "type": "boolean" }),
+    ]
+}
+
+#[actix_rt::test]
+async fn admins_can_define_custom_fields_per_list() {
+    let app = spawn_app().await;
+    create_fields(&app, "newsletter", &company_fields()).await;
+
+    let test_cases = [
+        (
+            serde_json::json!({ "key": "Company Name", "type": "text" }),
+            "an invalid key",
+        ),
+        (
+            serde_json::json!({ "key": "email", "type": "text" }),
+            "a reserved key",
+        ),
+        (
+            serde_json::json!({ "key": "size", "type": "shoe" }),
+            "an unknown type",
+        ),
+        (
+            serde_json::json!({ "key": "plan", "type": "choice" }),
+            "a choice without choices",
+        ),
+        (
+            serde_json::json!({ "key": "age", "type": "number", "min": 10, "max": 1 }),
+            "min greater than max",
+        ),
+        (
+            serde_json::json!({ "key": "vip", "type": "boolean", "max_length": 3 }),
+            "a rule that does not apply",
+        ),
+    ];
+    for (field, description) in test_cases {
+        let response = app
+            .post_admin_json("/lists/newsletter/fields", &field)
+            .await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for {}",
+            description
+        );
+    }
+    let response = app
+        .post_admin_json(
+            "/lists/newsletter/fields",
+            &serde_json::json!({ "key": "company", "type": "text" }),
+        )
+        .await;
+    assert_eq!(409, response.status().as_u16());
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/lists/newsletter/fields", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    let keys: Vec<_> = body["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["key"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        keys,
+        ["company", "country", "employees", "newsletter_opt_in"]
+    );
+    assert_eq!(body["fields"][1]["label"], "country");
+    assert_eq!(body["fields"][1]["rules"]["choices"][1], "de");
+}
+
+#[actix_rt::test]
+async fn subscribe_stores_custom_attributes_from_a_form_or_json() {
+    let app = spawn_app().await;
+    create_fields(&app, "newsletter", &company_fields()).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[
+            ("name", "Ursula"),
+            ("email", "ursula@example.com"),
+            ("company", " Earthsea "),
+            ("country", "fr"),
+            ("employees", "42"),
+            ("newsletter_opt_in", "on"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let response = client
+        .post(format!("{}/subscriptions", app.address))
+        .json(&serde_json::json!({
+            "name": "Octavia",
+            "email": "octavia@example.com",
+            "country": "de",
+            "employees": 7,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT email, attributes FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved[0].email, "octavia@example.com");
+    assert_eq!(
+        saved[0].attributes,
+        serde_json::json!({ "country": "de", "employees": 7 })
+    );
+    assert_eq!(
+        saved[1].attributes,
+        serde_json::json!({
+            "company": "Earthsea",
+            "country": "fr",
+            "employees": 42,
+            "newsletter_opt_in": true,
+        })
+    );
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_400_with_a_message_for_invalid_attributes() {
+    // The same address over and over would run into its rate limit
+    let app = spawn_app_with(|configuration| configuration.rate_limit.routes.clear()).await;
+    create_fields(&app, "newsletter", &company_fields()).await;
+    let test_cases = [
+        (vec![], "`country` is required."),
+        (vec![("country", "es")], "`country` must be one of fr, de."),
+        (
+            vec![("country", "fr"), ("employees", "many")],
+            "`employees` must be a number.",
+        ),
+        (
+            vec![("country", "fr"), ("employees", "0")],
+            "`employees` must be at least 1.",
+        ),
+        (
+            vec![("country", "fr"), ("company", "A company with a long name")],
+            "`Company` must not be longer than 20 characters.",
+        ),
+        (
+            vec![("country", "fr"), ("favourite_colour", "blue")],
+            "`favourite_colour` is not a field of this list.",
+        ),
+    ];
+
+    for (attributes, message) in test_cases {
+        let mut form = vec![("name", "Ursula"), ("email", "ursula@example.com")];
+        form.extend(attributes);
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", app.address))
+            .form(&form)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(400, response.status().as_u16(), "for {}", message);
+        assert_eq!(response.text().await.unwrap(), message);
+    }
+}
+
+#[actix_rt::test]
+async fn segments_can_compare_custom_attributes() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("big-fr@example.com", "Big", "confirmed", 3),
+            ("small-de@example.com", "Small", "confirmed", 2),
+            ("unknown@example.com", "Unknown", "confirmed", 1),
+        ],
+    )
+    .await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET attributes = CASE email
+            WHEN 'big-fr@example.com' THEN '{"country": "fr", "employees": 500, "since": "2020-05-01"}'::jsonb
+            WHEN 'small-de@example.com' THEN '{"country": "de", "employees": 5, "since": "2024-01-01"}'::jsonb
+            ELSE '{}'::jsonb
+        END
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let test_cases = [
+        ("attribute.employees >= 50", 1),
+        ("attribute.country = fr", 1),
+        ("attribute.country != fr", 1),
+        ("not attribute.country = fr", 2),
+        ("attribute.since < \"2023-01-01\"", 1),
+        (
+            "attribute.employees < 1000 and attribute.country = \"de\"",
+            1,
+        ),
+    ];
+    for (segment, expected) in test_cases {
+        let response = preview_segment(&app, segment).await;
+        assert_eq!(200, response.status().as_u16(), "for {}", segment);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["matching"], expected, "for {}", segment);
+    }
+}
+
+// The bodies of every email sent so far, in order
+async fn sent_emails(app: &TestApp) -> Vec<serde_json::Value> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect()
+}
+
+#[actix_rt::test]
+async fn templates_referencing_unknown_variables_are_rejected() {
+    let app = spawn_app().await;
+    let template = |name: &str, kind: &str, html: &str, text: &str| serde_json::json!({ "name": name, "kind": kind, "html": html, "text": text });
+
+    let test_cases = [
+        (
+            template("welcome", "issue", "<p>Hi {{ nmae }}</p>", "Hi {{ name }}"),
+            "`nmae`",
+        ),
+        (
+            template("welcome", "issue", "<p>Hi</p>", "{{ attribute.company }}"),
+            "`attribute.company`",
+        ),
+        (
+            template("welcome", "issue", "<p>{{ content }}</p>", "Hi"),
+            "`content`",
+        ),
+        (
+            template("welcome", "issue", "<p>{% if name %}</p>", "Hi"),
+            "does not compile",
+        ),
+        (
+            template(
+                "plain",
+                "layout",
+                "{{ content }} {{ postal_address }}",
+                "{{ content }} {{ unsubscribe_url }} {{ postal_address }}",
+            ),
+            "`{{ unsubscribe_url }}`",
+        ),
+        (template("welcome", "email", "Hi", "Hi"), "template kind"),
+        (
+            template("Welcome!", "issue", "Hi", "Hi"),
+            "not a valid slug",
+        ),
+    ];
+    for (body, expected) in test_cases {
+        let response = app.post_admin_json("/templates", &body).await;
+        assert_eq!(400, response.status().as_u16(), "for {}", body);
+        let message = response.text().await.unwrap();
+        assert!(message.contains(expected), "{} for {}", message, body);
+    }
+
+    // Attributes become known once a list has the field
+    create_fields(&app, "newsletter", &company_fields()).await;
+    let welcome = template(
+        "welcome",
+        "issue",
+        "<p>Hi {{ name }} from {{ attribute.company }}</p>{% for i in range(2) %}!{% endfor %}",
+        "Hi {{ name | default(\"there\") }}",
+    );
+    let response = app.post_admin_json("/templates", &welcome).await;
+    assert_eq!(201, response.status().as_u16());
+    let response = app.post_admin_json("/templates", &welcome).await;
+    assert_eq!(409, response.status().as_u16());
+
+    let response = app
+        .put_admin_json(
+            "/templates/welcome",
+            &serde_json::json!({ "html": "<p>{{ nmae }}</p>", "text": "Hi" }),
+        )
+        .await;
+    assert_eq!(400, response.status().as_u16());
+    let response = app
+        .put_admin_json(
+            "/templates/missing",
+            &serde_json::json!({ "html": "Hi", "text": "Hi" }),
+        )
+        .await;
+    assert_eq!(404, response.status().as_u16());
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/templates", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    let names: Vec<_> = body["templates"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| (t["kind"].as_str().unwrap(), t["name"].as_str().unwrap()))
+        .collect();
+    assert_eq!(names, [("issue", "welcome"), ("layout", "default")]);
+}
+
+#[actix_rt::test]
+async fn published_issues_are_personalized_escaped_and_wrapped_in_the_layout() {
+    let app = spawn_app().await;
+    create_fields(&app, "newsletter", &company_fields()).await;
+    insert_subscribers(
+        &app,
+        &[("tom@example.com", "Tom <&> Jerry", "confirmed", 1)],
+    )
+    .await;
+    add_to_list(&app, "newsletter", &[("tom@example.com", "confirmed")]).await;
+    sqlx::query!(r#"UPDATE subscriptions SET attributes = '{"company": "ACME"}'::jsonb"#)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Hello",
+                "content": {
+                    "html": "<p>Hi {{ name }} from {{ attribute.company }}</p>",
+                    "text": "Hi {{ name }} from {{ attribute.company }}",
+                },
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email = &sent_emails(&app).await[0];
+    let html = email["HtmlBody"].as_str().unwrap();
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(
+        html.contains("<p>Hi Tom &lt;&amp;&gt; Jerry from ACME</p>"),
+        "{}",
+        html
+    );
+    assert!(text.contains("Hi Tom <&> Jerry from ACME"), "{}", text);
+    assert!(html.contains("<h1>Newsletter</h1>"));
+    assert!(text.starts_with(&format!(
+        "View in browser: {}/archive/hello\n\nNewsletter",
+        app.address
+    )));
+    for body in [html, text] {
+        assert!(body.contains(&app.postal_address));
+        assert!(body.contains("/lists/newsletter/subscriptions/unsubscribe?subscription_token="));
+    }
+}
+
+#[actix_rt::test]
+async fn publishing_can_use_stored_templates_and_layouts() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    for body in [
+        serde_json::json!({
+            "name": "digest", "kind": "issue",
+            "html": "<p>Your digest, {{ name }}</p>", "text": "Your digest, {{ name }}",
+        }),
+        serde_json::json!({
+            "name": "minimal", "kind": "layout",
+            "html": "<main>{{ content }}</main><footer><a href=\"{{ unsubscribe_url }}\">Leave</a> {{ postal_address }}</footer>",
+            "text": "{{ content }}\n{{ unsubscribe_url }}\n{{ postal_address }}",
+        }),
+    ] {
+        let response = app.post_admin_json("/templates", &body).await;
+        assert_eq!(201, response.status().as_u16());
+    }
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({ "title": "Digest", "template": "digest", "layout": "minimal" }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email = &sent_emails(&app).await[0];
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(
+        html.starts_with("<main><p>Your digest, Ada</p></main><footer>"),
+        "{}",
+        html
+    );
+}
+
+#[actix_rt::test]
+async fn publishing_returns_a_400_for_unknown_variables_or_templates() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+
+    let test_cases = [
+        serde_json::json!({
+            "title": "Hello",
+            "content": { "html": "<p>{{ first_name }}</p>", "text": "Hi" },
+        }),
+        serde_json::json!({ "title": "Hello", "template": "missing" }),
+        // The default layout is not an issue template
+        serde_json::json!({ "title": "Hello", "template": "default" }),
+        serde_json::json!({
+            "title": "Hello",
+            "content": { "html": "Hi", "text": "Hi" },
+            "layout": "missing",
+        }),
+        serde_json::json!({ "title": "Hello" }),
+    ];
+    for body in test_cases {
+        let response = app.post_admin_json("/newsletters", &body).await;
+        assert_eq!(400, response.status().as_u16(), "for {}", body);
+    }
+    let queued = sqlx::query_scalar!("SELECT COUNT(*) FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued, Some(0));
+}
+
+#[actix_rt::test]
+async fn previewing_markdown_returns_email_safe_html_and_plain_text() {
+    let app = spawn_app().await;
+    let markdown = "# Release notes\n\n\
+        Read the [docs](https://example.com/docs) or [the blog](https://example.com/blog), \
+        the [docs](https://example.com/docs) again.\n\n\
+        - one\n- two\n  1. nested\n\n\
+        > quoted\n\n\
+        <script>alert(1)</script><iframe src=\"https://evil.example.com\"></iframe>\
+        <img src=\"https://example.com/a.png\" onerror=\"alert(2)\">\n\n\
+        Hi {{ name }}";
+
+    let response = app
+        .post_admin_json(
+            "/newsletters/preview",
+            &serde_json::json!({ "markdown": markdown }),
+        )
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let html = body["html"].as_str().unwrap();
+    assert!(html.starts_with("<table role=\"presentation\""), "{}", html);
+    assert!(html.contains("<h1 style=\""), "{}", html);
+    assert!(
+        html.contains("href=\"https://example.com/docs\""),
+        "{}",
+        html
+    );
+    assert!(
+        html.contains("<img src=\"https://example.com/a.png\""),
+        "{}",
+        html
+    );
+    assert!(html.contains("Hi {{ name }}"), "{}", html);
+    for unsafe_markup in ["<script", "alert(1)", "iframe", "onerror"] {
+        assert!(!html.contains(unsafe_markup), "{}", html);
+    }
+    assert_eq!(
+        body["text"],
+        "Release notes\n\
+        =============\n\n\
+        Read the docs [1] or the blog [2], the docs [1] again.\n\n\
+        - one\n\
+        - two\n  \
+          1. nested\n\n\
+        > quoted\n\n\
+        Hi {{ name }}\n\n\
+        [1] https://example.com/docs\n\
+        [2] https://example.com/blog\n"
+    );
+}
+
+#[actix_rt::test]
+async fn publishing_markdown_sends_both_renderings() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Links would go through the click tracking redirect otherwise
+    app.put_admin_json(
+        "/lists/newsletter/tracking",
+        &serde_json::json!({ "enabled": false }),
+    )
+    .await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Hello",
+                "content": { "markdown": "Hi **{{ name }}**, see [the docs](https://example.com/docs)." },
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email = &sent_emails(&app).await[0];
+    let html = email["HtmlBody"].as_str().unwrap();
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(
+        html.contains("Hi <strong>Ada</strong>, see <a href=\"https://example.com/docs\""),
+        "{}",
+        html
+    );
+    assert!(
+        text.contains("Hi Ada, see the docs [1].\n\n[1] https://example.com/docs"),
+        "{}",
+        text
+    );
+}
+
+#[actix_rt::test]
+async fn merge_variables_in_markdown_links_are_filled_in() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.put_admin_json(
+        "/lists/newsletter/tracking",
+        &serde_json::json!({ "enabled": false }),
+    )
+    .await;
+    let markdown =
+        "Hi {{ name }}, [Unsubscribe]({{ unsubscribe_url }}) or [here]({{unsubscribe_url}}).";
+
+    let response = app
+        .post_admin_json(
+            "/newsletters/preview",
+            &serde_json::json!({ "markdown": markdown }),
+        )
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let html = body["html"].as_str().unwrap();
+    assert!(html.contains("href=\"{{ unsubscribe_url }}\""), "{}", html);
+    assert!(html.contains("href=\"{{unsubscribe_url}}\""), "{}", html);
+    assert!(
+        body["text"]
+            .as_str()
+            .unwrap()
+            .ends_with("[1] {{ unsubscribe_url }}\n[2] {{unsubscribe_url}}\n"),
+        "{}",
+        body["text"]
+    );
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({ "title": "Hello", "content": { "markdown": markdown } }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email = &sent_emails(&app).await[0];
+    let html = email["HtmlBody"].as_str().unwrap();
+    let unsubscribe_link = format!(
+        "Hi Ada, <a href=\"{}/lists/newsletter/subscriptions/unsubscribe?subscription_token=",
+        app.address
+    );
+    assert!(html.contains(&unsubscribe_link), "{}", html);
+    assert!(!html.contains("%7B"), "{}", html);
+}
+
+async fn create_draft(app: &TestApp, body: &serde_json::Value) -> String {
+    let response = app.post_admin_json("/newsletters/drafts", body).await;
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "draft");
+    body["newsletter_issue_id"].as_str().unwrap().to_string()
+}
+
+async fn issue_status(app: &TestApp, newsletter_issue_id: &str) -> String {
+    let response = app
+        .get_admin(&format!("/newsletters/{}", newsletter_issue_id))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["status"].as_str().unwrap().to_string()
+}
+
+async fn queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query_scalar!("SELECT COUNT(*) FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+// Make the scheduled issue due without waiting for it
+async fn make_due(app: &TestApp, newsletter_issue_id: &str) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_at = now() - interval '1 minute' WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(newsletter_issue_id).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[actix_rt::test]
+async fn drafts_can_be_created_edited_and_deleted() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    let id = create_draft(
+        &app,
+        &serde_json::json!({ "title": "Draft", "content": { "markdown": "Hi **{{ name }}**" } }),
+    )
+    .await;
+    assert_eq!(queued_deliveries(&app).await, 0);
+
+    let response = app.get_admin(&format!("/newsletters/{}", id)).await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["title"], "Draft");
+    assert_eq!(body["content"]["markdown"], "Hi **{{ name }}**");
+    assert_eq!(body["lists"], serde_json::json!(["newsletter"]));
+    assert_eq!(body["layout"], "default");
+    assert!(body["published_at"].is_null());
+
+    let response = app
+        .put_admin_json(
+            &format!("/newsletters/{}", id),
+            &serde_json::json!({ "title": "Final", "content": { "html": "<p>Hi</p>", "text": "Hi" } }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let response = app
+        .put_admin_json(
+            &format!("/newsletters/{}", id),
+            &serde_json::json!({ "title": "Final", "content": { "html": "{{ nmae }}", "text": "Hi" } }),
+        )
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    let body: serde_json::Value = app
+        .get_admin("/newsletters?status=draft")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["issues"][0]["title"], "Final");
+    assert_eq!(
+        400,
+        app.get_admin("/newsletters?status=bogus")
+            .await
+            .status()
+            .as_u16()
+    );
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/newsletters/{}", app.address, id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(204, response.status().as_u16());
+    let response = app.get_admin(&format!("/newsletters/{}", id)).await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn scheduled_issues_are_queued_exactly_once_when_due() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let id = create_draft(
+        &app,
+        &serde_json::json!({ "title": "Later", "content": { "html": "<p>Hi</p>", "text": "Hi" } }),
+    )
+    .await;
+    let schedule = format!("/newsletters/{}/schedule", id);
+
+    let response = app
+        .post_admin_json(
+            &schedule,
+            &serde_json::json!({ "send_at": Utc::now() - Duration::hours(1) }),
+        )
+        .await;
+    assert_eq!(400, response.status().as_u16());
+    let response = app
+        .post_admin_json(
+            &schedule,
+            &serde_json::json!({ "send_at": Utc::now() + Duration::hours(1) }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(issue_status(&app, &id).await, "scheduled");
+    assert_eq!(app.run_scheduler().await, 0);
+    assert_eq!(queued_deliveries(&app).await, 0);
+
+    // Two replicas ticking at the same time
+    make_due(&app, &id).await;
+    let (first, second) = tokio::join!(app.run_scheduler(), app.run_scheduler());
+    assert_eq!(first + second, 1);
+    assert_eq!(queued_deliveries(&app).await, 1);
+    assert_eq!(issue_status(&app, &id).await, "sending");
+
+    // It is too late to change or cancel it
+    let response = app
+        .post_admin_json(
+            &format!("/newsletters/{}/cancel", id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(409, response.status().as_u16());
+    let response = app
+        .put_admin_json(
+            &format!("/newsletters/{}", id),
+            &serde_json::json!({ "title": "Changed", "content": { "html": "Hi", "text": "Hi" } }),
+        )
+        .await;
+    assert_eq!(409, response.status().as_u16());
+
+    app.dispatch_all_pending_emails().await;
+    app.run_scheduler().await;
+    assert_eq!(issue_status(&app, &id).await, "sent");
+}
+
+#[actix_rt::test]
+async fn cancelled_issues_are_never_sent() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    let id = create_draft(
+        &app,
+        &serde_json::json!({ "title": "Later", "content": { "html": "<p>Hi</p>", "text": "Hi" } }),
+    )
+    .await;
+    let response = app
+        .post_admin_json(
+            &format!("/newsletters/{}/schedule", id),
+            &serde_json::json!({ "send_at": Utc::now() + Duration::hours(1) }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let cancel = format!("/newsletters/{}/cancel", id);
+    let response = app.post_admin_json(&cancel, &serde_json::json!({})).await;
+    assert_eq!(200, response.status().as_u16());
+    make_due(&app, &id).await;
+    assert_eq!(app.run_scheduler().await, 0);
+    assert_eq!(queued_deliveries(&app).await, 0);
+    assert_eq!(issue_status(&app, &id).await, "cancelled");
+    let response = app.post_admin_json(&cancel, &serde_json::json!({})).await;
+    assert_eq!(409, response.status().as_u16());
+    let response = app
+        .post_admin_json(
+            &format!("/newsletters/{}/cancel", Uuid::new_v4()),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+async fn subscriber_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query_scalar!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[actix_rt::test]
+async fn test_sends_go_only_to_the_given_addresses() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    let ada = subscriber_id(&app, "ada@example.com").await;
+    let id = create_draft(
+        &app,
+        &serde_json::json!({ "title": "Draft", "content": { "markdown": "Hi {{ name }}" } }),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let test = format!("/newsletters/{}/test", id);
+
+    let response = app
+        .post_admin_json(
+            &test,
+            &serde_json::json!({ "emails": ["editor@example.com", "boss@example.com"], "subscriber_id": ada }),
+        )
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let emails = sent_emails(&app).await;
+    let recipients: Vec<_> = emails.iter().map(|e| e["To"].as_str().unwrap()).collect();
+    assert_eq!(recipients, ["editor@example.com", "boss@example.com"]);
+    for email in &emails {
+        assert_eq!(email["Subject"], "[TEST] Draft");
+        assert!(email["TextBody"].as_str().unwrap().contains("Hi Ada"));
+    }
+    // Subscribers' records are left alone
+    let deliveries = sqlx::query_scalar!("SELECT COUNT(*) FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deliveries, Some(0));
+    let tokens = sqlx::query_scalar!("SELECT COUNT(*) FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens, Some(0));
+    assert_eq!(issue_status(&app, &id).await, "draft");
+
+    let emails: Vec<_> = (0..11)
+        .map(|i| format!("editor{}@example.com", i))
+        .collect();
+    let test_cases = [
+        (test.clone(), serde_json::json!({ "emails": [] }), 400),
+        (test.clone(), serde_json::json!({ "emails": emails }), 400),
+        (
+            test.clone(),
+            serde_json::json!({ "emails": ["not-an-email"] }),
+            400,
+        ),
+        (
+            test.clone(),
+            serde_json::json!({ "emails": ["editor@example.com"], "subscriber_id": Uuid::new_v4() }),
+            400,
+        ),
+        (
+            format!("/newsletters/{}/test", Uuid::new_v4()),
+            serde_json::json!({ "emails": ["editor@example.com"] }),
+            404,
+        ),
+    ];
+    for (path, body, expected) in test_cases {
+        let response = app.post_admin_json(&path, &body).await;
+        assert_eq!(expected, response.status().as_u16(), "for {}", body);
+    }
+}
+
+#[actix_rt::test]
+async fn previews_render_the_issue_as_a_chosen_subscriber() {
+    let app = spawn_app().await;
+    create_fields(&app, "newsletter", &company_fields()).await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    sqlx::query!(r#"UPDATE subscriptions SET attributes = '{"company": "ACME"}'::jsonb"#)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let ada = subscriber_id(&app, "ada@example.com").await;
+    let id = create_draft(
+        &app,
+        &serde_json::json!({
+            "title": "Draft",
+            "content": { "markdown": "Hi {{ name }} from {{ attribute.company }}" },
+        }),
+    )
+    .await;
+
+    let response = app
+        .get_admin(&format!(
+            "/newsletters/{}/preview?subscriber_id={}",
+            id, ada
+        ))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Hi Ada from ACME"), "{}", html);
+    assert!(html.contains(&app.postal_address));
+
+    let response = app
+        .get_admin(&format!(
+            "/newsletters/{}/preview?subscriber_id={}&format=text",
+            id, ada
+        ))
+        .await;
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/plain; charset=utf-8"
+    );
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .starts_with("Newsletter\n\nHi Ada from ACME"));
+
+    let response = app.get_admin(&format!("/newsletters/{}/preview", id)).await;
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Hi Test Subscriber from"));
+    let response = app
+        .get_admin(&format!("/newsletters/{}/preview", Uuid::new_v4()))
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+async fn issue_slug(app: &TestApp, newsletter_issue_id: &str) -> Option<String> {
+    let response = app
+        .get_admin(&format!("/newsletters/{}", newsletter_issue_id))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["slug"].as_str().map(Into::into)
+}
+
+async fn get_public(app: &TestApp, path: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}{}", app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[actix_rt::test]
+async fn sent_issues_are_published_in_the_archive_and_the_list_feed() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "title": "Spring update: what's new?",
+        "content": { "markdown": "Hi {{ name | default(\"there\") }}, we <b>shipped</b> it." },
+    });
+
+    let response = app.post_admin_json("/newsletters", &body).await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    let archive_url = format!("{}/archive/spring-update-what-s-new", app.address);
+    let email = &sent_emails(&app).await[0];
+    assert!(email["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("<a href=\"{}\">View in browser</a>", archive_url)));
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("View in browser: {}\n", archive_url)));
+
+    // Nobody is signed in, so there is no one to greet by name
+    let response = get_public(&app, "/archive/spring-update-what-s-new").await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let html = response.text().await.unwrap();
+    assert!(
+        html.contains("<h1>Spring update: what&#x27;s new?</h1>"),
+        "{}",
+        html
+    );
+    assert!(html.contains("Hi there, we <b>shipped</b> it."), "{}", html);
+    assert!(!html.contains("Ada"));
+
+    // The same title again gets a slug of its own
+    let response = app.post_admin_json("/newsletters", &body).await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let second_id = body["newsletter_issue_id"].as_str().unwrap();
+    let second_slug = issue_slug(&app, second_id).await.unwrap();
+    assert_eq!(
+        second_slug,
+        format!("spring-update-what-s-new-{}", &second_id[..8])
+    );
+
+    let response = get_public(&app, "/archive").await;
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains(&format!("<a href=\"{}\">", archive_url)));
+    assert!(html.contains(&second_slug));
+    let response = get_public(&app, "/archive?list=newsletter").await;
+    assert!(response.text().await.unwrap().contains(&archive_url));
+    create_list(&app, "weekly", "Weekly").await;
+    let response = get_public(&app, "/archive?list=weekly").await;
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Nothing has been published yet."));
+
+    let response = get_public(&app, "/lists/newsletter/feed.xml").await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let feed = response.text().await.unwrap();
+    assert!(feed.starts_with(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">"
+    ));
+    assert_eq!(2, feed.matches("<entry>").count());
+    assert!(feed.contains(&format!("<id>{}</id>", archive_url)));
+    // The content is HTML, escaped into the XML
+    assert!(
+        feed.contains("Hi there, we &lt;b&gt;shipped&lt;&#x2f;b&gt; it."),
+        "{}",
+        feed
+    );
+    assert_eq!(
+        404,
+        get_public(&app, "/lists/unknown/feed.xml")
+            .await
+            .status()
+            .as_u16()
+    );
+}
+
+#[actix_rt::test]
+async fn hidden_and_unsent_issues_are_not_in_the_archive() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let draft_id = create_draft(
+        &app,
+        &serde_json::json!({ "title": "Draft", "content": { "markdown": "Not yet" } }),
+    )
+    .await;
+    assert_eq!(None, issue_slug(&app, &draft_id).await);
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Members only",
+                "content": { "markdown": "Just for you" },
+                "hide_from_archive": true,
+            }),
+        )
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let id = body["newsletter_issue_id"].as_str().unwrap();
+    app.dispatch_all_pending_emails().await;
+    // Nothing to view in the browser
+    let email = &sent_emails(&app).await[0];
+    assert!(!email["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("View in browser"));
+    assert!(!email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("View in browser"));
+
+    assert_eq!(
+        404,
+        get_public(&app, "/archive/members-only")
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(
+        404,
+        get_public(&app, "/archive/draft").await.status().as_u16()
+    );
+    let html = get_public(&app, "/archive").await.text().await.unwrap();
+    assert!(html.contains("Nothing has been published yet."));
+    let feed = get_public(&app, "/lists/newsletter/feed.xml")
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(!feed.contains("<entry>"));
+
+    // It can be put back in the archive, and taken out again
+    let response = app
+        .put_admin_json(
+            &format!("/newsletters/{}/archive", id),
+            &serde_json::json!({ "hidden": false }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        200,
+        get_public(&app, "/archive/members-only")
+            .await
+            .status()
+            .as_u16()
+    );
+    app.put_admin_json(
+        &format!("/newsletters/{}/archive", id),
+        &serde_json::json!({ "hidden": true }),
+    )
+    .await;
+    assert_eq!(
+        404,
+        get_public(&app, "/archive/members-only")
+            .await
+            .status()
+            .as_u16()
+    );
+    let response = app
+        .put_admin_json(
+            &format!("/newsletters/{}/archive", Uuid::new_v4()),
+            &serde_json::json!({ "hidden": true }),
+        )
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+// An RSS feed with one item per `(guid, title)`, newest first
+fn rss_feed(items: &[(&str, &str)]) -> String {
+    let items: String = items
+        .iter()
+        .map(|(guid, title)| {
+            // The description is HTML in XML, escaped twice
+            let title = title.replace('<', "&lt;").replace('>', "&gt;");
+            let html_title = title.replace('&', "&amp;");
+            format!(
+                "<item><title>{title}</title><link>https://blog.example.com/{guid}</link><guid>{guid}</guid>\
+                <description>&lt;p&gt;All about &lt;b&gt;{html_title}&lt;/b&gt; &amp;amp; more&lt;/p&gt;</description></item>"
+            )
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\"?><rss version=\"2.0\"><channel><title>Engineering blog</title>\
+        <link>https://blog.example.com</link><description>Posts</description>{}</channel></rss>",
+        items
+    )
+}
+
+async fn serve_feed(blog: &MockServer, items: &[(&str, &str)]) {
+    blog.reset().await;
+    Mock::given(path("/feed.xml"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Type", "application/rss+xml")
+                .set_body_string(rss_feed(items)),
+        )
+        .mount(blog)
+        .await;
+}
+
+// Create the `blog-digest` template and point the default list's feed at the blog
+async fn set_up_feed(app: &TestApp, blog: &MockServer, auto_send: bool) {
+    let response = app
+        .post_admin_json(
+            "/templates",
+            &serde_json::json!({
+                "name": "blog-digest", "kind": "digest",
+                "html": "<h2>New on {{ feed.title }}</h2>{% for entry in entries %}<p>{{ entry.title }}: {{ entry.summary }}</p>{% endfor %}",
+                "text": "New on {{ feed.title }}\n{% for entry in entries %}{{ entry.title }} {{ entry.url }}\n{% endfor %}",
+            }),
+        )
+        .await;
+    assert_eq!(201, response.status().as_u16());
+    let response = app
+        .put_admin_json(
+            "/lists/newsletter/feed",
+            &serde_json::json!({
+                "url": format!("{}/feed.xml", blog.uri()),
+                "template": "blog-digest",
+                "auto_send": auto_send,
+            }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+// Make every feed due without waiting for the poll interval
+async fn make_feeds_due(app: &TestApp) {
+    sqlx::query!("UPDATE feed_sources SET next_poll_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[actix_rt::test]
+async fn admins_can_set_the_feed_of_a_list() {
+    let app = spawn_app().await;
+    let blog = MockServer::start().await;
+    set_up_feed(&app, &blog, false).await;
+
+    let response = app.get_admin("/lists/newsletter/feed").await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["url"], format!("{}/feed.xml", blog.uri()));
+    assert_eq!(body["template"], "blog-digest");
+    assert_eq!(body["layout"], "default");
+    assert_eq!(body["auto_send"], false);
+    assert!(body["last_polled_at"].is_null());
+
+    // Entries only exist in digests
+    let response = app
+        .post_admin_json(
+            "/templates",
+            &serde_json::json!({
+                "name": "not-a-digest", "kind": "issue",
+                "html": "{% for entry in entries %}{{ entry.title }}{% endfor %}", "text": "",
+            }),
+        )
+        .await;
+    assert_eq!(400, response.status().as_u16());
+    for (body, error) in [
+        (
+            serde_json::json!({ "url": "ftp://blog.example.com/feed", "template": "blog-digest" }),
+            "is not an http(s) url",
+        ),
+        (
+            serde_json::json!({ "url": "https://blog.example.com/feed", "template": "default" }),
+            "There is no digest template named `default`.",
+        ),
+        (
+            serde_json::json!({ "url": "https://blog.example.com/feed", "template": "blog-digest", "layout": "blog-digest" }),
+            "There is no layout template named `blog-digest`.",
+        ),
+    ] {
+        let response = app.put_admin_json("/lists/newsletter/feed", &body).await;
+        assert_eq!(400, response.status().as_u16());
+        assert!(response.text().await.unwrap().contains(error));
+    }
+    let response = app
+        .put_admin_json(
+            "/lists/unknown/feed",
+            &serde_json::json!({ "url": "https://blog.example.com/feed", "template": "blog-digest" }),
+        )
+        .await;
+    assert_eq!(404, response.status().as_u16());
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/lists/newsletter/feed", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(204, response.status().as_u16());
+    assert_eq!(
+        404,
+        app.get_admin("/lists/newsletter/feed")
+            .await
+            .status()
+            .as_u16()
+    );
+}
+
+#[actix_rt::test]
+async fn new_feed_entries_become_a_digest_draft_exactly_once() {
+    let app = spawn_app().await;
+    let blog = MockServer::start().await;
+    serve_feed(&blog, &[("old-post", "Old post")]).await;
+    set_up_feed(&app, &blog, false).await;
+
+    // What is already in the feed when it is set up is not news
+    assert_eq!(0, app.poll_feeds().await);
+    assert_eq!(0, app.poll_feeds().await);
+    serve_feed(
+        &blog,
+        &[
+            ("tuning-postgres", "Tuning <Postgres>"),
+            ("old-post", "Old post"),
+        ],
+    )
+    .await;
+    make_feeds_due(&app).await;
+    assert_eq!(1, app.poll_feeds().await);
+
+    let response = app.get_admin("/newsletters?status=draft").await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issues = body["issues"].as_array().unwrap();
+    assert_eq!(1, issues.len());
+    assert_eq!(issues[0]["title"], "Tuning <Postgres>");
+    let id = issues[0]["newsletter_issue_id"].as_str().unwrap();
+    let html = app
+        .get_admin(&format!("/newsletters/{}/preview", id))
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        html.contains("<h2>New on Engineering blog</h2>"),
+        "{}",
+        html
+    );
+    // The summary's markup is dropped, the title is escaped like any value
+    assert!(
+        html.contains(
+            "<p>Tuning &lt;Postgres&gt;: All about Tuning &lt;Postgres&gt; &amp; more</p>"
+        ),
+        "{}",
+        html
+    );
+    assert!(!html.contains("Old post"));
+
+    // Seen entries never make another issue
+    make_feeds_due(&app).await;
+    assert_eq!(0, app.poll_feeds().await);
+    assert_eq!(0, queued_deliveries(&app).await);
+
+    // A feed that cannot be fetched is retried on the next poll
+    blog.reset().await;
+    make_feeds_due(&app).await;
+    assert_eq!(0, app.poll_feeds().await);
+    let body: serde_json::Value = app
+        .get_admin("/lists/newsletter/feed")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert!(body["last_error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Failed to fetch the feed"));
+}
+
+#[actix_rt::test]
+async fn auto_send_feeds_send_the_digest_to_the_list() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ada@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let blog = MockServer::start().await;
+    serve_feed(&blog, &[]).await;
+    set_up_feed(&app, &blog, true).await;
+    assert_eq!(0, app.poll_feeds().await);
+
+    serve_feed(&blog, &[("second", "Second post"), ("first", "First post")]).await;
+    make_feeds_due(&app).await;
+    assert_eq!(1, app.poll_feeds().await);
+    app.dispatch_all_pending_emails().await;
+
+    let email = &sent_emails(&app).await[0];
+    assert_eq!(email["Subject"], "Engineering blog: 2 new posts");
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(
+        text.contains("Second post https://blog.example.com/second\nFirst post https://blog.example.com/first\n"),
+        "{}",
+        text
+    );
+    // Recorded with the issue they went out in
+    let entries = sqlx::query!(
+        "SELECT COUNT(*) AS \"count!\" FROM feed_entries WHERE newsletter_issue_id IS NOT NULL"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(2, entries.count);
+}
+
+// The tracking links of a kind in an email, `o` for the open pixel and `c` for clicks
+fn tracking_links(app: &TestApp, html: &str, kind: &str) -> Vec<String> {
+    let prefix = format!("{}/t/{}/", app.address, kind);
+    html.match_indices(&prefix)
+        .map(|(start, _)| {
+            let link = &html[start..];
+            link[..link.find('"').unwrap()].to_string()
+        })
+        .collect()
+}
+
+// The redirect is what we assert on, not the page it leads to
+async fn get_without_redirects(url: &str) -> reqwest::Response {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(url)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn tracked_issue(lists: &[&str]) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Links",
+        "content": {
+            "html": "<p><a href=\"https://example.com/post?a=1&amp;b=2\">Read</a> <a href='https://example.com/other'>Other</a></p>",
+            "text": "Read https://example.com/post?a=1&b=2",
+        },
+        "lists": lists,
+    })
+}
+
+#[actix_rt::test]
+async fn opens_and_clicks_are_recorded_and_aggregated_per_issue() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("ada@example.com", "Ada", "confirmed", 2),
+            ("bob@example.com", "Bob", "confirmed", 1),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "newsletter",
+        &[
+            ("ada@example.com", "confirmed"),
+            ("bob@example.com", "confirmed"),
+        ],
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_json("/newsletters", &tracked_issue(&["newsletter"]))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id = body["newsletter_issue_id"].as_str().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let mut links = Vec::new();
+    for email in sent_emails(&app).await {
+        let html = email["HtmlBody"].as_str().unwrap();
+        assert!(!html.contains("https://example.com/"), "{}", html);
+        assert_eq!(2, tracking_links(&app, html, "c").len());
+        assert_eq!(1, tracking_links(&app, html, "o").len());
+        // Our own links are not counted as clicks
+        assert!(html.contains(&format!(
+            "{}/lists/newsletter/subscriptions/unsubscribe?subscription_token=",
+            app.address
+        )));
+        assert!(html.contains(&format!("<a href=\"{}/archive/links\">", app.address)));
+        assert!(email["TextBody"]
+            .as_str()
+            .unwrap()
+            .contains("https://example.com/post?a=1&b=2"));
+        let recipient = email["To"].as_str().unwrap().to_string();
+        links.push((recipient, html.to_string()));
+    }
+    links.sort();
+    let (ada, bob) = (&links[0].1, &links[1].1);
+
+    for _ in 0..2 {
+        let response = get_without_redirects(&tracking_links(&app, ada, "o")[0]).await;
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!(response.headers()["Content-Type"], "image/gif");
+    }
+    for html in [ada, bob] {
+        let response = get_without_redirects(&tracking_links(&app, html, "c")[0]).await;
+        assert_eq!(302, response.status().as_u16());
+        assert_eq!(
+            response.headers()["Location"],
+            "https://example.com/post?a=1&b=2"
+        );
+    }
+
+    let response = app
+        .get_admin(&format!("/newsletters/{}/stats", newsletter_issue_id))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let stats: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(stats["deliveries"]["sent"], 2);
+    assert_eq!(stats["deliveries"]["tracked"], 2);
+    assert_eq!(
+        stats["opens"],
+        serde_json::json!({ "total": 2, "unique": 1, "rate": 0.5 })
+    );
+    assert_eq!(
+        stats["clicks"],
+        serde_json::json!({ "total": 2, "unique": 2, "rate": 1.0 })
+    );
+    assert_eq!(
+        stats["links"],
+        serde_json::json!([{ "url": "https://example.com/post?a=1&b=2", "clicks": 2, "unique": 2 }])
+    );
+
+    // Erasing a subscriber keeps their opens and clicks in the counts, but stops counting new ones
+    let ada_id =
+        sqlx::query_scalar!("SELECT id FROM subscriptions WHERE email = 'ada@example.com'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    let response = app
+        .post_admin_json(
+            &format!("/subscribers/{}/erase", ada_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let response = get_without_redirects(&tracking_links(&app, ada, "o")[0]).await;
+    assert_eq!(200, response.status().as_u16());
+    let response = app
+        .get_admin(&format!("/newsletters/{}/stats", newsletter_issue_id))
+        .await;
+    let stats: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        stats["opens"],
+        serde_json::json!({ "total": 2, "unique": 1, "rate": 0.5 })
+    );
+    assert_eq!(stats["clicks"]["total"], 2);
+    let tracked_ada = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM tracking_events JOIN subscriptions ON subscriptions.id = subscriber_id WHERE email LIKE '%ada%'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(tracked_ada, Some(0));
+
+    let response = app
+        .get_admin(&format!("/newsletters/{}/stats", Uuid::new_v4()))
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn tampered_tracking_links_are_not_followed() {
+    let app = spawn_app().await;
+    let token = |url: &str, secret: &str| {
+        TrackingToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: Some(url.into()),
+        }
+        .sign(secret)
+    };
+    let signed = token("https://example.com/", &app.hmac_secret);
+    let forged = token("https://evil.example/", "not-our-secret");
+    let (_, signature) = signed.split_once('.').unwrap();
+    let (payload, _) = forged.split_once('.').unwrap();
+
+    let response = get_without_redirects(&format!("{}/t/c/{}", app.address, signed)).await;
+    assert_eq!(302, response.status().as_u16());
+    assert_eq!(response.headers()["Location"], "https://example.com/");
+    for token in [
+        forged.clone(),
+        format!("{}.{}", payload, signature),
+        "not-a-token".into(),
+    ] {
+        let response = get_without_redirects(&format!("{}/t/c/{}", app.address, token)).await;
+        assert_eq!(404, response.status().as_u16());
+        assert!(response.headers().get("Location").is_none());
+        let response = get_without_redirects(&format!("{}/t/o/{}", app.address, token)).await;
+        assert_eq!(404, response.status().as_u16());
+    }
+    let events = sqlx::query_scalar!("SELECT COUNT(*) FROM tracking_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(Some(0), events);
+}
+
+#[actix_rt::test]
+async fn lists_can_opt_out_of_tracking() {
+    let app = spawn_app().await;
+    let response = app
+        .post_admin_json(
+            "/lists",
+            &serde_json::json!({ "slug": "private", "name": "Private", "tracking": false }),
+        )
+        .await;
+    assert_eq!(201, response.status().as_u16());
+    insert_subscribers(
+        &app,
+        &[
+            ("ada@example.com", "Ada", "confirmed", 2),
+            ("bob@example.com", "Bob", "confirmed", 1),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "newsletter",
+        &[
+            ("ada@example.com", "confirmed"),
+            ("bob@example.com", "confirmed"),
+        ],
+    )
+    .await;
+    add_to_list(&app, "private", &[("ada@example.com", "confirmed")]).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Ada is on a list that opted out, so her email is not tracked at all
+    let response = app
+        .post_admin_json("/newsletters", &tracked_issue(&["newsletter", "private"]))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    for email in sent_emails(&app).await {
+        let html = email["HtmlBody"].as_str().unwrap();
+        let tracked = email["To"] == "bob@example.com";
+        assert_eq!(tracked, !tracking_links(&app, html, "o").is_empty());
+        assert_eq!(tracked, !html.contains("https://example.com/post"));
+    }
+    let tracked = sqlx::query!(
+        r#"
+        SELECT subscriptions.email, issue_deliveries.tracked FROM issue_deliveries
+        JOIN subscriptions ON subscriptions.id = issue_deliveries.subscriber_id
+        ORDER BY subscriptions.email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let tracked: Vec<_> = tracked
+        .iter()
+        .map(|d| (d.email.as_str(), d.tracked))
+        .collect();
+    assert_eq!(
+        tracked,
+        [("ada@example.com", false), ("bob@example.com", true)]
+    );
+
+    let response = app
+        .put_admin_json(
+            "/lists/newsletter/tracking",
+            &serde_json::json!({ "enabled": false }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let response = app.get_admin("/lists").await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["lists"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|list| list["tracking"] == false));
+    let response = app
+        .put_admin_json(
+            "/lists/unknown/tracking",
+            &serde_json::json!({ "enabled": true }),
+        )
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+// Signed the way the email provider signs its webhooks
+async fn post_email_event(app: &TestApp, event: &serde_json::Value) -> reqwest::Response {
+    use base64::Engine;
+    use hmac::Mac;
+    let body = serde_json::to_vec(event).unwrap();
+    let mut mac =
+        hmac::Hmac::<sha2::Sha256>::new_from_slice(app.webhook_secret.as_bytes()).unwrap();
+    mac.update(&body);
+    let signature = base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+    reqwest::Client::new()
+        .post(format!("{}/webhooks/email-events", app.address))
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Signature", signature)
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn bounce(id: u64, kind: &str, email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": id,
+        "Type": kind,
+        "MessageID": Uuid::new_v4().to_string(),
+        "Email": email,
+        "BouncedAt": "2026-10-31T09:00:00Z",
+    })
+}
+
+#[actix_rt::test]
+async fn issues_go_to_the_display_form_and_its_bounces_find_the_subscriber() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_display, name, status, subscribed_at)
+        VALUES ($1, 'Ursula@xn--bcher-kva.de', 'Ursula@Bücher.de', 'Ursula', 'confirmed', now())
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    add_to_list(
+        &app,
+        "newsletter",
+        &[("Ursula@xn--bcher-kva.de", "confirmed")],
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Hello",
+                "content": { "html": "<p>Hi {{ email }}</p>", "text": "Hi {{ email }}" },
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let email = &sent_emails(&app).await[0];
+    assert_eq!(email["To"], "Ursula@Bücher.de");
+    assert!(email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Hi Ursula@Bücher.de"));
+
+    // The provider reports the address we sent to
+    let response = post_email_event(&app, &bounce(1, "HardBounce", "Ursula@Bücher.de")).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        subscriber_status(&app, "Ursula@xn--bcher-kva.de").await,
+        "bounced"
+    );
+    let suppressed = sqlx::query_scalar!("SELECT value FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressed, "ursula@xn--bcher-kva.de");
+}
+
+async fn subscriber_status(app: &TestApp, email: &str) -> String {
+    sqlx::query_scalar!("SELECT status FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[actix_rt::test]
+async fn email_events_without_a_valid_signature_are_rejected() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let body = bounce(1, "HardBounce", "ada@example.com").to_string();
+
+    for signature in [None, Some("not base64"), Some("c2lnbmF0dXJl")] {
+        let mut request = client
+            .post(format!("{}/webhooks/email-events", app.address))
+            .header("Content-Type", "application/json")
+            .body(body.clone());
+        if let Some(signature) = signature {
+            request = request.header("X-Webhook-Signature", signature);
+        }
+        let response = request.send().await.unwrap();
+        assert_eq!(401, response.status().as_u16());
+    }
+    let response = post_email_event(&app, &serde_json::json!({ "RecordType": "Bounce" })).await;
+    assert_eq!(400, response.status().as_u16());
+    let events = sqlx::query_scalar!("SELECT COUNT(*) FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(Some(0), events);
+}
+
+#[actix_rt::test]
+async fn hard_bounces_and_complaints_stop_future_sends() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("ada@example.com", "Ada", "confirmed", 3),
+            ("bob@example.com", "Bob", "confirmed", 2),
+            ("cy@example.com", "Cy", "confirmed", 1),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "newsletter",
+        &[
+            ("ada@example.com", "confirmed"),
+            ("bob@example.com", "confirmed"),
+            ("cy@example.com", "confirmed"),
+        ],
+    )
+    .await;
+    let complaint = serde_json::json!({
+        "RecordType": "SpamComplaint",
+        "ID": 2,
+        "Type": "SpamComplaint",
+        "Email": "bob@example.com",
+    });
+
+    for event in [
+        bounce(1, "HardBounce", "Ada@Example.com"),
+        complaint,
+        // Replayed by the provider
+        bounce(1, "HardBounce", "Ada@Example.com"),
+        // Not one of ours, kept all the same
+        bounce(3, "HardBounce", "stranger@example.com"),
+    ] {
+        let response = post_email_event(&app, &event).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    assert_eq!(subscriber_status(&app, "ada@example.com").await, "bounced");
+    assert_eq!(
+        subscriber_status(&app, "bob@example.com").await,
+        "complained"
+    );
+    assert_eq!(subscriber_status(&app, "cy@example.com").await, "confirmed");
+    let events =
+        sqlx::query!("SELECT kind, subscriber_id FROM email_events ORDER BY provider_event_id")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    let kinds: Vec<_> = events
+        .iter()
+        .map(|e| (e.kind.as_str(), e.subscriber_id.is_some()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("hard_bounce", true),
+            ("hard_bounce", false),
+            ("complaint", true)
+        ]
+    );
+
+    let suppressed = sqlx::query!("SELECT value, reason, source FROM suppressions ORDER BY value")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let suppressed: Vec<_> = suppressed
+        .iter()
+        .map(|s| (s.value.as_str(), s.reason.as_str(), s.source.as_str()))
+        .collect();
+    assert_eq!(
+        suppressed,
+        [
+            ("ada@example.com", "Hard bounce", "webhook"),
+            ("bob@example.com", "Spam complaint", "webhook"),
+            ("stranger@example.com", "Hard bounce", "webhook"),
+        ]
+    );
+
+    // A bounce later on does not make a complaint any milder
+    let response = post_email_event(&app, &bounce(4, "HardBounce", "bob@example.com")).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        subscriber_status(&app, "bob@example.com").await,
+        "complained"
+    );
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Hello",
+                "content": { "html": "<p>Hi</p>", "text": "Hi" },
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(sent_emails(&app).await[0]["To"], "cy@example.com");
+}
+
+#[actix_rt::test]
+async fn soft_bounces_in_a_row_count_as_a_hard_bounce() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    let delivery = serde_json::json!({
+        "RecordType": "Delivery",
+        "MessageID": Uuid::new_v4().to_string(),
+        "Recipient": "ada@example.com",
+        "DeliveredAt": "2026-10-31T09:00:00Z",
+    });
+
+    for event in [
+        bounce(1, "SoftBounce", "ada@example.com"),
+        bounce(2, "Transient", "ada@example.com"),
+        // Getting through starts the count over
+        delivery,
+        bounce(3, "SoftBounce", "ada@example.com"),
+        bounce(4, "DnsError", "ada@example.com"),
+    ] {
+        let response = post_email_event(&app, &event).await;
+        assert_eq!(200, response.status().as_u16());
+        assert_eq!(
+            subscriber_status(&app, "ada@example.com").await,
+            "confirmed"
+        );
+    }
+    let response = post_email_event(&app, &bounce(5, "SoftBounce", "ada@example.com")).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, "ada@example.com").await, "bounced");
+}
+
+#[actix_rt::test]
+async fn the_number_of_soft_bounces_to_count_as_a_hard_bounce_is_configurable() {
+    let app = spawn_app_with(|configuration| {
+        configuration.email_client.max_soft_bounces = 1;
+    })
+    .await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+
+    let response = post_email_event(&app, &bounce(1, "SoftBounce", "ada@example.com")).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, "ada@example.com").await, "bounced");
+}
+
+async fn suppress(app: &TestApp, value: &str) -> reqwest::Response {
+    app.post_admin_json(
+        "/suppressions",
+        &serde_json::json!({ "value": value, "reason": "Legal request" }),
+    )
+    .await
+}
+
+#[actix_rt::test]
+async fn admins_can_manage_the_suppression_list() {
+    let app = spawn_app().await;
+
+    let response = suppress(&app, " Ada@Example.com ").await;
+    assert_eq!(201, response.status().as_u16());
+    let ada: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(ada["kind"], "email");
+    assert_eq!(ada["value"], "ada@example.com");
+    let response = suppress(&app, "@Test.Example").await;
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        (&body["kind"], &body["value"]),
+        (&"domain".into(), &"test.example".into())
+    );
+
+    assert_eq!(
+        409,
+        suppress(&app, "ada@example.com").await.status().as_u16()
+    );
+    assert_eq!(400, suppress(&app, "not a domain").await.status().as_u16());
+    let response = app
+        .post_admin_json(
+            "/suppressions",
+            &serde_json::json!({ "value": "bob@example.com", "reason": " " }),
+        )
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/admin/suppressions/import?reason=Imported",
+            app.address
+        ))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .header("Content-Type", "text/csv")
+        .body("value,reason\nbob@example.com,Complained by phone\nqa.example,\nnot an address,\nADA@example.com,\nbob@example.com,\n")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["accepted"], 2);
+    let lines = |key: &str| -> Vec<u64> {
+        report[key]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["line"].as_u64().unwrap())
+            .collect()
+    };
+    assert_eq!(lines("rejected"), [4]);
+    assert_eq!(lines("duplicates"), [5, 6]);
+
+    let response = app.get_admin("/suppressions?search=example.com").await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let suppressions: Vec<_> = body["suppressions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| {
+            (
+                s["value"].as_str().unwrap(),
+                s["reason"].as_str().unwrap(),
+                s["source"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(suppressions.len(), 2);
+    assert!(suppressions.contains(&("ada@example.com", "Legal request", "admin")));
+    assert!(suppressions.contains(&("bob@example.com", "Complained by phone", "import")));
+
+    let delete = |id: String| {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/suppressions/{}", app.address, id))
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .send()
+    };
+    let id = ada["id"].as_str().unwrap().to_string();
+    assert_eq!(204, delete(id.clone()).await.unwrap().status().as_u16());
+    assert_eq!(404, delete(id).await.unwrap().status().as_u16());
+}
+
+#[actix_rt::test]
+async fn signups_from_suppressed_addresses_are_silently_ignored() {
+    let app = spawn_app().await;
+    assert_eq!(201, suppress(&app, "test.example").await.status().as_u16());
+    assert_eq!(
+        201,
+        suppress(&app, "ada@example.com").await.status().as_u16()
+    );
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for email in ["qa%40mail.test.example", "Ada%40Example.com"] {
+        let response = app
+            .post_list_subscription("newsletter", &format!("name=Someone&email={}", email))
+            .await;
+        // Just like a signup that went through
+        assert_eq!(200, response.status().as_u16());
+    }
+    let subscribers = sqlx::query_scalar!("SELECT COUNT(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(Some(0), subscribers);
+}
+
+#[actix_rt::test]
+async fn suppressions_stop_deliveries_of_issues_already_queued() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("ada@example.com", "Ada", "confirmed", 2),
+            ("bob@example.com", "Bob", "confirmed", 1),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "newsletter",
+        &[
+            ("ada@example.com", "confirmed"),
+            ("bob@example.com", "confirmed"),
+        ],
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Hello",
+                "content": { "html": "<p>Hi</p>", "text": "Hi" },
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id = body["newsletter_issue_id"].as_str().unwrap();
+    // Queued already, not sent yet
+    assert_eq!(
+        201,
+        suppress(&app, "bob@example.com").await.status().as_u16()
+    );
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(sent_emails(&app).await[0]["To"], "ada@example.com");
+    let response = app
+        .get_admin(&format!("/newsletters/{}/stats", newsletter_issue_id))
+        .await;
+    let stats: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(stats["deliveries"]["sent"], 1);
+    assert_eq!(stats["deliveries"]["suppressed"], 1);
+}
+
+fn limit(capacity: u32) -> Option<TokenBucketSettings> {
+    Some(TokenBucketSettings {
+        capacity,
+        per_minute: 1.0,
+    })
+}
+
+// Only `/subscriptions`, with buckets small enough to empty in a test
+async fn spawn_rate_limited_app(
+    per_ip: Option<TokenBucketSettings>,
+    per_email: Option<TokenBucketSettings>,
+    customise: impl FnOnce(&mut Settings),
+) -> TestApp {
+    let app = spawn_app_with(|configuration| {
+        configuration.rate_limit.routes = vec![RouteRateLimit {
+            path: "/subscriptions".into(),
+            method: "POST".into(),
+            per_ip,
+            per_email,
+        }];
+        customise(configuration);
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+async fn post_subscription_from(
+    app: &TestApp,
+    email: &str,
+    forwarded_for: Option<&str>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[("name", "le guin"), ("email", email)]);
+    if let Some(forwarded_for) = forwarded_for {
+        request = request.header("X-Forwarded-For", forwarded_for);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+#[actix_rt::test]
+async fn subscriptions_past_the_per_ip_limit_get_a_429_with_retry_after() {
+    let app = spawn_rate_limited_app(limit(2), None, |_| {}).await;
+
+    for i in 0..2 {
+        let email = format!("reader{}@example.com", i);
+        let response = post_subscription_from(&app, &email, None).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+    // Not behind a trusted proxy, so a made up header changes nothing
+    let response = post_subscription_from(&app, "reader2@example.com", Some("203.0.113.7")).await;
+
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    // One token a minute
+    assert!((1..=60).contains(&retry_after));
+    let subscribers = sqlx::query_scalar!("SELECT COUNT(*) FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(Some(2), subscribers);
+}
+
+#[actix_rt::test]
+async fn clients_behind_a_trusted_proxy_are_limited_on_their_own() {
+    let app = spawn_rate_limited_app(limit(1), None, |configuration| {
+        configuration.rate_limit.trusted_proxies = vec!["127.0.0.0/8".into()];
+    })
+    .await;
+
+    let first = post_subscription_from(&app, "ada@example.com", Some("203.0.113.7")).await;
+    let second = post_subscription_from(&app, "bob@example.com", Some("198.51.100.1")).await;
+    // The client can prepend whatever it likes - only the hop our proxy added counts
+    let third =
+        post_subscription_from(&app, "eve@example.com", Some("198.51.100.9, 203.0.113.7")).await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+    assert_eq!(429, third.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn subscriptions_past_the_per_email_limit_get_a_429_whatever_the_ip() {
+    let app = spawn_rate_limited_app(None, limit(2), |configuration| {
+        configuration.rate_limit.trusted_proxies = vec!["127.0.0.1".into()];
+    })
+    .await;
+
+    let first = post_subscription_from(&app, "ada@example.com", Some("203.0.113.7")).await;
+    let second = post_subscription_from(&app, "Ada@Example.com", Some("198.51.100.1")).await;
+    let third = post_subscription_from(&app, "ADA@example.com", Some("192.0.2.44")).await;
+    let other = post_subscription_from(&app, "bob@example.com", Some("192.0.2.44")).await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(200, second.status().as_u16());
+    assert_eq!(429, third.status().as_u16());
+    assert!(third.headers().contains_key("Retry-After"));
+    assert_eq!(200, other.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn the_postgres_store_keeps_the_buckets_in_the_database() {
+    let app = spawn_rate_limited_app(limit(1), None, |configuration| {
+        configuration.rate_limit.store = RateLimitStore::Postgres;
+    })
+    .await;
+
+    let first = post_subscription_from(&app, "ada@example.com", None).await;
+    let second = post_subscription_from(&app, "bob@example.com", None).await;
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(429, second.status().as_u16());
+    let bucket = sqlx::query!("SELECT key, tokens FROM rate_limit_buckets")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(bucket.key, "/subscriptions:ip:127.0.0.1");
+    assert!(bucket.tokens < 1.0);
+}
+
+#[actix_rt::test]
+async fn buckets_for_an_address_are_keyed_by_its_hash() {
+    use sha2::Digest;
+    let app = spawn_rate_limited_app(None, limit(1), |configuration| {
+        configuration.rate_limit.store = RateLimitStore::Postgres;
+    })
+    .await;
+
+    let response = post_subscription_from(&app, "Ada@Example.com", None).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let key = sqlx::query_scalar!("SELECT key FROM rate_limit_buckets")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        key,
+        format!(
+            "/subscriptions:email:{:x}",
+            sha2::Sha256::digest(b"ada@example.com")
+        )
+    );
+}
+
+#[actix_rt::test]
+async fn percent_encoded_paths_take_from_the_same_buckets() {
+    let app = spawn_rate_limited_app(limit(1), None, |_| {}).await;
+
+    let first = post_subscription_from(&app, "ada@example.com", None).await;
+    let encoded = reqwest::Client::new()
+        .post(format!("{}/%73ubscriptions", app.address))
+        .form(&[("name", "le guin"), ("email", "bob@example.com")])
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, first.status().as_u16());
+    assert_eq!(429, encoded.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn routes_without_a_limit_are_not_rate_limited() {
+    let app = spawn_rate_limited_app(limit(1), limit(1), |_| {}).await;
+
+    for _ in 0..3 {
+        let response = app
+            .post_list_subscription("newsletter", "name=Ada&email=ada%40example.com")
+            .await;
+        assert_ne!(429, response.status().as_u16());
+    }
+}
+
+async fn post_signup(app: &TestApp, form: &[(&str, &str)]) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .form(form)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn get_metrics(app: &TestApp) -> String {
+    let response = app.get_admin("/metrics").await;
+    assert_eq!(200, response.status().as_u16());
+    response.text().await.unwrap()
+}
+
+#[actix_rt::test]
+async fn signups_filling_in_the_honeypot_look_successful_but_are_ignored() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_signup(
+        &app,
+        &[
+            ("name", "le guin"),
+            ("email", "ursula@example.com"),
+            ("website", "https://spam.example"),
+        ],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, subscriber_count(&app).await);
+    assert!(get_metrics(&app)
+        .await
+        .contains("signup_bot_rejections_total{check=\"honeypot\"} 1"));
+}
+
+#[actix_rt::test]
+async fn an_empty_honeypot_does_not_count_as_a_custom_field() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_signup(
+        &app,
+        &[
+            ("name", "le guin"),
+            ("email", "ursula@example.com"),
+            ("website", ""),
+        ],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, subscriber_count(&app).await);
+}
+
+#[actix_rt::test]
+async fn signups_are_ignored_when_the_form_was_filled_in_too_fast_or_too_long_ago() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = reqwest::get(format!("{}/subscriptions/form-token", app.address))
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let fresh = body["form_token"].as_str().unwrap().to_string();
+    let stale = sign_form_token(Utc::now() - Duration::days(2), &app.hmac_secret);
+    let forged = sign_form_token(Utc::now() - Duration::minutes(1), "another-secret");
+    let filled_in = sign_form_token(Utc::now() - Duration::minutes(1), &app.hmac_secret);
+
+    for (email, form_token) in [
+        ("fast@example.com", fresh.as_str()),
+        ("stale@example.com", stale.as_str()),
+        ("forged@example.com", forged.as_str()),
+        ("person@example.com", filled_in.as_str()),
+    ] {
+        let response = post_signup(
+            &app,
+            &[
+                ("name", "le guin"),
+                ("email", email),
+                ("form_token", form_token),
+            ],
+        )
+        .await;
+        assert_eq!(200, response.status().as_u16(), "for {}", email);
+    }
+
+    let emails: Vec<String> = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(emails, vec!["person@example.com"]);
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains("signup_bot_rejections_total{check=\"too_fast\"} 1"));
+    assert!(metrics.contains("signup_bot_rejections_total{check=\"stale_form\"} 1"));
+    assert!(metrics.contains("signup_bot_rejections_total{check=\"form_token\"} 1"));
+}
+
+#[actix_rt::test]
+async fn signups_without_a_form_token_are_ignored_once_it_is_required() {
+    let app = spawn_app_with(|configuration| {
+        configuration.bot_protection.require_form_token = true;
+    })
+    .await;
+
+    let response = post_signup(
+        &app,
+        &[("name", "le guin"), ("email", "ursula@example.com")],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(0, subscriber_count(&app).await);
+}
+
+#[actix_rt::test]
+async fn lists_with_a_captcha_only_take_signups_the_provider_verified() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(wiremock::matchers::body_string_contains("response=passed"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": true })),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(wiremock::matchers::body_string_contains("response=failed"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": false })),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .put_admin_json(
+            "/lists/newsletter/captcha",
+            &serde_json::json!({ "enabled": true }),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let failed = post_signup(
+        &app,
+        &[
+            ("name", "le guin"),
+            ("email", "bot@example.com"),
+            ("h-captcha-response", "failed"),
+        ],
+    )
+    .await;
+    // Without a response, the provider is not even asked
+    let missing = post_signup(&app, &[("name", "le guin"), ("email", "lazy@example.com")]).await;
+    let passed = post_signup(
+        &app,
+        &[
+            ("name", "le guin"),
+            ("email", "ursula@example.com"),
+            ("h-captcha-response", "passed"),
+        ],
+    )
+    .await;
+
+    assert_eq!(200, failed.status().as_u16());
+    assert_eq!(200, missing.status().as_u16());
+    assert_eq!(200, passed.status().as_u16());
+    let emails: Vec<String> = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(emails, vec!["ursula@example.com"]);
+    assert!(get_metrics(&app)
+        .await
+        .contains("signup_bot_rejections_total{check=\"captcha\"} 2"));
+}
+
+#[actix_rt::test]
+async fn lists_without_a_captcha_never_ask_the_provider() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({ "success": false })),
+        )
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_signup(
+        &app,
+        &[
+            ("name", "le guin"),
+            ("email", "ursula@example.com"),
+            ("h-captcha-response", "anything"),
+        ],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(1, subscriber_count(&app).await);
+}
+
+#[actix_rt::test]
+async fn signups_from_disposable_domains_are_rejected_with_the_reason() {
+    let app = spawn_app().await;
+
+    for email in ["someone@mailinator.com", "someone@eu.yopmail.com"] {
+        let response = post_signup(&app, &[("name", "le guin"), ("email", email)]).await;
+
+        assert_eq!(400, response.status().as_u16(), "for {}", email);
+        assert_eq!(
+            response.text().await.unwrap(),
+            "Disposable email addresses are not accepted."
+        );
+    }
+    assert_eq!(0, subscriber_count(&app).await);
+}
+
+#[actix_rt::test]
+async fn disposable_domains_are_only_flagged_when_configured_so() {
+    let app = spawn_app_with(|configuration| {
+        configuration.signup_policy.disposable_domains = PolicyAction::Flag;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_signup(
+        &app,
+        &[("name", "le guin"), ("email", "someone@mailinator.com")],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let policy_flags = sqlx::query_scalar!("SELECT policy_flags FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(policy_flags, vec!["disposable"]);
+}
+
+#[actix_rt::test]
+async fn role_accounts_are_flagged_on_the_subscriber() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = post_signup(
+        &app,
+        &[("name", "Mail"), ("email", "NoReply+news@example.com")],
+    )
+    .await;
+    assert_eq!(200, response.status().as_u16());
+    let response = post_signup(&app, &[("name", "Ada"), ("email", "ada@example.com")]).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = app.get_admin_subscribers("").await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let flags: HashMap<String, serde_json::Value> = body["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| {
+            (
+                s["email"].as_str().unwrap().to_string(),
+                s["policy_flags"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        flags["NoReply+news@example.com"],
+        serde_json::json!(["role_account"])
+    );
+    assert_eq!(flags["ada@example.com"], serde_json::json!([]));
+}
+
+#[actix_rt::test]
+async fn role_accounts_are_rejected_when_configured_so() {
+    let app = spawn_app_with(|configuration| {
+        configuration.signup_policy.role_accounts = PolicyAction::Reject;
+    })
+    .await;
+
+    let response = post_signup(
+        &app,
+        &[("name", "Mail"), ("email", "postmaster@example.com")],
+    )
+    .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(
+        response.text().await.unwrap(),
+        "Role addresses like postmaster@ are not accepted, please use a personal one."
+    );
+    assert_eq!(0, subscriber_count(&app).await);
+}
+
+#[actix_rt::test]
+async fn internal_lists_only_take_signups_from_their_allowed_domains() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_admin_json(
+            "/lists",
+            &serde_json::json!({
+                "slug": "staff",
+                "name": "Staff updates",
+                "allowed_domains": ["OurCompany.com"],
+            }),
+        )
+        .await;
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["allowed_domains"],
+        serde_json::json!(["ourcompany.com"])
+    );
+
+    let outsider = app
+        .post_list_subscription("staff", "name=Eve&email=eve%40example.com")
+        .await;
+    let colleague = app
+        .post_list_subscription("staff", "name=Ada&email=ada%40OurCompany.com")
+        .await;
+    let subdomain = app
+        .post_list_subscription("staff", "name=Bob&email=bob%40eng.ourcompany.com")
+        .await;
+    // Other lists are open to everyone
+    let elsewhere = post_signup(&app, &[("name", "Eve"), ("email", "eve@example.com")]).await;
+
+    assert_eq!(400, outsider.status().as_u16());
+    assert_eq!(
+        outsider.text().await.unwrap(),
+        "This list only accepts addresses at ourcompany.com."
+    );
+    assert_eq!(200, colleague.status().as_u16());
+    assert_eq!(200, subdomain.status().as_u16());
+    assert_eq!(200, elsewhere.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn allowed_domains_must_be_domains() {
+    let app = spawn_app().await;
+
+    let invalid = app
+        .put_admin_json(
+            "/lists/newsletter/allowed-domains",
+            &serde_json::json!({ "domains": ["ada@ourcompany.com"] }),
+        )
+        .await;
+    let missing = app
+        .put_admin_json(
+            "/lists/missing/allowed-domains",
+            &serde_json::json!({ "domains": ["ourcompany.com"] }),
+        )
+        .await;
+    let cleared = app
+        .put_admin_json(
+            "/lists/newsletter/allowed-domains",
+            &serde_json::json!({ "domains": [] }),
+        )
+        .await;
+
+    assert_eq!(400, invalid.status().as_u16());
+    assert_eq!(404, missing.status().as_u16());
+    assert_eq!(200, cleared.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn suppressed_disposable_addresses_get_the_same_policy_rejection() {
+    let app = spawn_app().await;
+    assert_eq!(
+        201,
+        suppress(&app, "someone@mailinator.com")
+            .await
+            .status()
+            .as_u16()
+    );
+
+    let suppressed = post_signup(
+        &app,
+        &[("name", "le guin"), ("email", "someone@mailinator.com")],
+    )
+    .await;
+    let other = post_signup(
+        &app,
+        &[("name", "le guin"), ("email", "other@mailinator.com")],
+    )
+    .await;
+
+    assert_eq!(400, suppressed.status().as_u16());
+    assert_eq!(400, other.status().as_u16());
+    assert_eq!(
+        suppressed.text().await.unwrap(),
+        other.text().await.unwrap()
+    );
+}
+
+// A page with forms, and the CSRF cookie that came with it, if the browser had none yet
+struct FormPage {
+    html: String,
+    cookie: Option<String>,
+}
+
+impl FormPage {
+    // The values of every `<input name="...">`, in the order of the page
+    fn field_values(&self, name: &str) -> Vec<String> {
+        let prefix = format!("name=\"{}\" value=\"", name);
+        self.html
+            .match_indices(&prefix)
+            .map(|(start, _)| {
+                let value = &self.html[start + prefix.len()..];
+                value[..value.find('"').unwrap()].to_string()
+            })
+            .collect()
+    }
+}
+
+// reqwest keeps no cookies, so we hand the CSRF cookie back ourselves
+async fn get_form_page(url: &str, cookie: Option<&str>) -> FormPage {
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    let response = request.send().await.expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    let cookie = response.headers().get("Set-Cookie").map(|cookie| {
+        cookie
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string()
+    });
+    FormPage {
+        html: response.text().await.unwrap(),
+        cookie,
+    }
+}
+
+async fn post_form(url: &str, cookie: Option<&str>, form: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = reqwest::Client::new().post(url).form(form);
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+#[actix_rt::test]
+async fn the_signup_page_renders_the_list_form_with_its_protections() {
+    let app = spawn_app().await;
+    create_list(&app, "weekly", "The <Weekly>").await;
+    create_fields(
+        &app,
+        "weekly",
+        &[serde_json::json!({
+            "key": "role", "label": "Role", "type": "choice", "required": true,
+            "choices": ["engineer", "designer"],
+        })],
+    )
+    .await;
+
+    let response = reqwest::get(format!("{}/lists/weekly/signup", app.address))
+        .await
+        .unwrap();
+    let missing = reqwest::get(format!("{}/lists/missing/signup", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let cookie = response.headers()["Set-Cookie"].to_str().unwrap();
+    assert!(cookie.starts_with("csrf="));
+    assert!(cookie.contains("HttpOnly"));
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Subscribe to The &lt;Weekly&gt;"));
+    assert!(html.contains(&format!("action=\"{}/lists/weekly/signup\"", app.address)));
+    assert!(html.contains("<select name=\"role\" required>"));
+    assert!(html.contains("<option>designer</option>"));
+    assert!(html.contains("name=\"website\""));
+    assert!(html.contains("name=\"form_token\""));
+    assert!(html.contains("name=\"csrf_token\""));
+    // Only lists with a CAPTCHA load the widget
+    assert!(!html.contains("h-captcha"));
+    assert_eq!(404, missing.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn signup_forms_are_only_accepted_with_a_valid_csrf_token() {
+    let app = spawn_app().await;
+    create_list(&app, "weekly", "Weekly").await;
+    create_list(&app, "daily", "Daily").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let signup_url = format!("{}/lists/weekly/signup", app.address);
+    let page = get_form_page(&signup_url, None).await;
+    let cookie = page.cookie.as_deref().unwrap();
+    let csrf_token = &page.field_values("csrf_token")[0];
+    let other_list =
+        get_form_page(&format!("{}/lists/daily/signup", app.address), Some(cookie)).await;
+    // The browser already has a cookie, so it is not handed out again
+    assert!(other_list.cookie.is_none());
+
+    let without_token = post_form(
+        &signup_url,
+        Some(cookie),
+        &[("name", "Eve"), ("email", "eve@example.com")],
+    )
+    .await;
+    let without_cookie = post_form(
+        &signup_url,
+        None,
+        &[
+            ("name", "Eve"),
+            ("email", "eve@example.com"),
+            ("csrf_token", csrf_token),
+        ],
+    )
+    .await;
+    let for_another_list = post_form(
+        &signup_url,
+        Some(cookie),
+        &[
+            ("name", "Eve"),
+            ("email", "eve@example.com"),
+            ("csrf_token", &other_list.field_values("csrf_token")[0]),
+        ],
+    )
+    .await;
+    assert_eq!(403, without_token.status().as_u16());
+    assert_eq!(403, without_cookie.status().as_u16());
+    assert_eq!(403, for_another_list.status().as_u16());
+    assert_eq!(subscriber_count(&app).await, 0);
+
+    let response = post_form(
+        &signup_url,
+        Some(cookie),
+        &[
+            ("name", "Ursula"),
+            ("email", "ursula@example.com"),
+            ("csrf_token", csrf_token),
+        ],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("Check your inbox"));
+    let membership = sqlx::query!(
+        r#"
+        SELECT lists.slug, list_memberships.status
+        FROM list_memberships JOIN lists ON lists.id = list_memberships.list_id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(membership.slug, "weekly");
+    assert_eq!(membership.status, "pending_confirmation");
+}
+
+#[actix_rt::test]
+async fn signup_form_errors_are_shown_on_a_page() {
+    let app = spawn_app().await;
+    let signup_url = format!("{}/lists/newsletter/signup", app.address);
+    let page = get_form_page(&signup_url, None).await;
+
+    let response = post_form(
+        &signup_url,
+        page.cookie.as_deref(),
+        &[
+            ("name", "Ursula"),
+            ("email", "someone@mailinator.com"),
+            ("csrf_token", &page.field_values("csrf_token")[0]),
+        ],
+    )
+    .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Disposable email addresses are not accepted."));
+    assert!(html.contains(&format!("href=\"{}\"", signup_url)));
+}
+
+#[actix_rt::test]
+async fn confirming_a_subscription_shows_a_page_linking_to_the_preference_center() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    post_signup(
+        &app,
+        &[("name", "le guin"), ("email", "ursula@example.com")],
+    )
+    .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let link = app.get_link(email_request);
+
+    let response = reqwest::get(link.clone()).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let html = response.text().await.unwrap();
+    assert!(html.contains("You are subscribed"));
+    assert!(html.contains(&format!(
+        "{}/preferences?subscription_token={}",
+        app.address,
+        subscription_token(&link)
+    )));
+}
+
+// A token for an already inserted subscriber, like the one in their emails
+async fn store_subscription_token(app: &TestApp, email: &str) -> String {
+    let token = Uuid::new_v4().simple().to_string();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, purpose, expires_at)
+        SELECT $1, id, 'preferences', now() + interval '1 day' FROM subscriptions WHERE email = $2
+        "#,
+        token,
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    token
+}
+
+#[actix_rt::test]
+async fn the_preference_center_updates_the_name_delivery_window_and_lists() {
+    let app = spawn_app().await;
+    create_list(&app, "weekly", "Weekly").await;
+    let response = app
+        .post_admin_json(
+            "/lists",
+            &serde_json::json!({
+                "slug": "staff", "name": "Staff updates", "allowed_domains": ["ourcompany.com"],
+            }),
+        )
+        .await;
+    assert_eq!(201, response.status().as_u16());
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ursula@example.com", "confirmed")]).await;
+    let token = store_subscription_token(&app, "ursula@example.com").await;
+    let preferences_url = format!("{}/preferences?subscription_token={}", app.address, token);
+
+    let page = get_form_page(&preferences_url, None).await;
+    assert!(page.html.contains("ursula@example.com"));
+    assert!(page
+        .html
+        .contains("<input type=\"checkbox\" name=\"list.newsletter\" checked>"));
+    assert!(page
+        .html
+        .contains("<input type=\"checkbox\" name=\"list.weekly\">"));
+    // Lists limited to other domains are not on offer
+    assert!(!page.html.contains("list.staff"));
+    assert!(page
+        .html
+        .contains("<option value=\"immediately\" selected>"));
+
+    let response = post_form(
+        &preferences_url,
+        page.cookie.as_deref(),
+        &[
+            ("name", " Ursula K. "),
+            ("delivery_window", "weekly"),
+            ("list.weekly", "on"),
+            ("list.staff", "on"),
+            ("csrf_token", &page.field_values("csrf_token")[0]),
+        ],
+    )
+    .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Your preferences were saved."));
+    assert!(html.contains("<option value=\"weekly\" selected>"));
+    let saved = sqlx::query!("SELECT name, delivery_window FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula K.");
+    assert_eq!(saved.delivery_window, "weekly");
+    let memberships = sqlx::query!(
+        r#"
+        SELECT lists.slug, list_memberships.status, list_memberships.unsubscribed_at
+        FROM list_memberships JOIN lists ON lists.id = list_memberships.list_id
+        ORDER BY lists.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(memberships.len(), 2);
+    assert_eq!(memberships[0].slug, "newsletter");
+    assert_eq!(memberships[0].status, "unsubscribed");
+    assert!(memberships[0].unsubscribed_at.is_some());
+    assert_eq!(memberships[1].slug, "weekly");
+    assert_eq!(memberships[1].status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn the_preference_center_unsubscribes_from_everything() {
+    let app = spawn_app().await;
+    create_list(&app, "weekly", "Weekly").await;
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ursula@example.com", "confirmed")]).await;
+    add_to_list(
+        &app,
+        "weekly",
+        &[("ursula@example.com", "pending_confirmation")],
+    )
+    .await;
+    let token = store_subscription_token(&app, "ursula@example.com").await;
+    let page = get_form_page(
+        &format!("{}/preferences?subscription_token={}", app.address, token),
+        None,
+    )
+    .await;
+    let unsubscribe_url = format!(
+        "{}/preferences/unsubscribe?subscription_token={}",
+        app.address, token
+    );
+    assert!(page
+        .html
+        .contains(&format!("action=\"{}\"", unsubscribe_url)));
+
+    // The token of the other form does not do
+    let wrong_form = post_form(
+        &unsubscribe_url,
+        page.cookie.as_deref(),
+        &[("csrf_token", &page.field_values("csrf_token")[0])],
+    )
+    .await;
+    let response = post_form(
+        &unsubscribe_url,
+        page.cookie.as_deref(),
+        &[("csrf_token", &page.field_values("csrf_token")[1])],
+    )
+    .await;
+
+    assert_eq!(403, wrong_form.status().as_u16());
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You are unsubscribed"));
+    let statuses = sqlx::query_scalar!("SELECT status FROM list_memberships")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(statuses, vec!["unsubscribed", "unsubscribed"]);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "unsubscribed");
+}
+
+#[actix_rt::test]
+async fn the_preference_center_needs_a_valid_token_and_csrf_token() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    let token = store_subscription_token(&app, "ursula@example.com").await;
+    let preferences_url = format!("{}/preferences?subscription_token={}", app.address, token);
+    let page = get_form_page(&preferences_url, None).await;
+
+    let made_up = reqwest::get(format!(
+        "{}/preferences?subscription_token=made-up",
+        app.address
+    ))
+    .await
+    .unwrap();
+    let without_csrf = post_form(
+        &preferences_url,
+        page.cookie.as_deref(),
+        &[("name", "Mallory"), ("delivery_window", "daily")],
+    )
+    .await;
+    let invalid_window = post_form(
+        &preferences_url,
+        page.cookie.as_deref(),
+        &[
+            ("name", "Ursula"),
+            ("delivery_window", "hourly"),
+            ("csrf_token", &page.field_values("csrf_token")[0]),
+        ],
+    )
+    .await;
+
+    assert_eq!(401, made_up.status().as_u16());
+    assert_eq!(403, without_csrf.status().as_u16());
+    assert_eq!(400, invalid_window.status().as_u16());
+    assert!(invalid_window
+        .text()
+        .await
+        .unwrap()
+        .contains("hourly is not a delivery window"));
+    let saved = sqlx::query!("SELECT name, delivery_window FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula");
+    assert_eq!(saved.delivery_window, "immediately");
+}
+
+#[actix_rt::test]
+async fn deliveries_to_daily_and_weekly_subscribers_wait_for_their_slot() {
+    let app = spawn_app().await;
+    insert_subscribers(
+        &app,
+        &[
+            ("now@example.com", "Now", "confirmed", 3),
+            ("daily@example.com", "Daily", "confirmed", 2),
+            ("weekly@example.com", "Weekly", "confirmed", 1),
+        ],
+    )
+    .await;
+    add_to_list(
+        &app,
+        "newsletter",
+        &[
+            ("now@example.com", "confirmed"),
+            ("daily@example.com", "confirmed"),
+            ("weekly@example.com", "confirmed"),
+        ],
+    )
+    .await;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET delivery_window = split_part(email, '@', 1)
+        WHERE email IN ('daily@example.com', 'weekly@example.com')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin_json(
+            "/newsletters",
+            &serde_json::json!({
+                "title": "Release notes",
+                "content": { "html": "<p>Shipped!</p>", "text": "Shipped!" },
+            }),
+        )
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.dispatch_all_pending_emails().await;
+
+    let held_back = sqlx::query!(
+        r#"
+        SELECT subscriptions.email, execute_after AT TIME ZONE 'UTC' AS "execute_after!"
+        FROM issue_delivery_queue
+        JOIN subscriptions ON subscriptions.id = issue_delivery_queue.subscriber_id
+        ORDER BY subscriptions.email
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(held_back.len(), 2);
+    let midnight = chrono::NaiveTime::MIN;
+    let tomorrow = Utc::now().date_naive().succ_opt().unwrap();
+    assert_eq!(held_back[0].email, "daily@example.com");
+    assert_eq!(held_back[0].execute_after, tomorrow.and_time(midnight));
+    assert_eq!(held_back[1].email, "weekly@example.com");
+    assert_eq!(held_back[1].execute_after.weekday(), chrono::Weekday::Mon);
+    assert_eq!(held_back[1].execute_after.time(), midnight);
+    assert!(held_back[1].execute_after > Utc::now().naive_utc());
+}
+
+#[actix_rt::test]
+async fn unknown_paths_get_a_404_without_echoing_the_path() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for path in [
+        "/",
+        "/%3Cscript%3Ealert(1)%3C%2Fscript%3E",
+        "/lists/newsletter/nope",
+    ] {
+        let response = client
+            .get(format!("{}{}", app.address, path))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(404, response.status().as_u16(), "for {}", path);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "not_found");
+        assert!(!body.to_string().contains("script"));
+    }
+}
+
+#[actix_rt::test]
+async fn browsers_get_the_404_as_a_page() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/ursula", app.address))
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(404, response.status().as_u16());
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/html; charset=utf-8"
+    );
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Not Found"));
+    assert!(!html.contains("ursula"));
+}
+
+#[actix_rt::test]
+async fn known_paths_get_a_405_listing_the_methods_they_take() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let health_check = client
+        .delete(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .unwrap();
+    let preferences = client
+        .put(format!("{}/preferences", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(405, health_check.status().as_u16());
+    assert_eq!(health_check.headers()["Allow"], "GET");
+    let body: serde_json::Value = health_check.json().await.unwrap();
+    assert_eq!(body["error"], "method_not_allowed");
+    assert_eq!(405, preferences.status().as_u16());
+    assert_eq!(preferences.headers()["Allow"], "GET, POST");
+}
+
+#[actix_rt::test]
+async fn unknown_admin_paths_still_require_credentials() {
+    let app = spawn_app().await;
+
+    let anonymous = reqwest::Client::new()
+        .get(format!("{}/admin/nope", app.address))
+        .send()
+        .await
+        .unwrap();
+    let signed_in = app.get_admin("/nope").await;
+
+    assert_eq!(401, anonymous.status().as_u16());
+    assert_eq!(404, signed_in.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn admins_can_list_every_exposed_route() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin("/routes").await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let routes = body["routes"].as_array().unwrap();
+    assert!(routes.contains(&serde_json::json!({
+        "method": "GET",
+        "path": "/health_check",
+        "access": "public",
+        "handler": "routes::health_check::health_check",
+    })));
+    assert!(routes.contains(&serde_json::json!({
+        "method": "POST",
+        "path": "/preferences/unsubscribe",
+        "access": "public",
+        "handler": "routes::preferences::unsubscribe_from_everything",
+    })));
+    assert!(routes.contains(&serde_json::json!({
+        "method": "GET",
+        "path": "/admin/routes",
+        "access": "admin",
+        "handler": "routes::admin::routes::list_routes",
+        "scope": "routes:read",
+    })));
+    // Everything under `/admin` is listed as such, and nothing else is - admin routes with a scope
+    for route in routes {
+        let is_admin = route["path"].as_str().unwrap().starts_with("/admin/");
+        assert_eq!(route["access"] == "admin", is_admin, "for {}", route);
+        assert_eq!(route["scope"].is_string(), is_admin, "for {}", route);
+    }
+    assert!(!routes.iter().any(|route| route["path"] == "/{name}"));
+}
+
+// Regenerate the snapshot with `UPDATE_SNAPSHOTS=1 cargo test openapi`, then review its diff
+#[tokio::test]
+async fn the_openapi_spec_matches_the_committed_snapshot() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/openapi.json", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let spec: serde_json::Value = response.json().await.unwrap();
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3.1"));
+    let actual = serde_json::to_string_pretty(&spec).unwrap() + "\n";
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots/openapi.json");
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(path, &actual).unwrap();
+    }
+    let expected = std::fs::read_to_string(path).expect("Failed to read the snapshot.");
+    assert!(
+        expected == actual,
+        "`/openapi.json` drifted from tests/snapshots/openapi.json - \
+         rerun with `UPDATE_SNAPSHOTS=1` if the change is intended:\n{}",
+        actual
+    );
+}
+
+#[tokio::test]
+async fn every_documented_operation_is_served() {
+    let app = spawn_app().await;
+    let spec: serde_json::Value = reqwest::get(format!("{}/openapi.json", app.address))
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap();
+
+    let response = app.get_admin("/routes").await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let routes = body["routes"].as_array().unwrap();
+    let paths = spec["paths"].as_object().unwrap();
+    assert!(paths.contains_key("/subscriptions"));
+    for (path, operations) in paths {
+        for method in operations.as_object().unwrap().keys() {
+            let method = method.to_uppercase();
+            assert!(
+                routes
+                    .iter()
+                    .any(|route| route["path"] == *path && route["method"] == method),
+                "{} {} is documented but not served",
+                method,
+                path
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn the_api_docs_page_loads_the_spec() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/docs", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains(r#"url: "/openapi.json""#));
+}
+
+#[tokio::test]
+async fn the_api_docs_page_can_be_turned_off() {
+    let app = spawn_app_with(|configuration| configuration.application.api_docs = false).await;
+
+    let docs = reqwest::get(format!("{}/docs", app.address))
+        .await
+        .expect("Failed to execute request.");
+    let spec = reqwest::get(format!("{}/openapi.json", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(404, docs.status().as_u16());
+    assert_eq!(200, spec.status().as_u16());
+}
+
+#[tokio::test]
+async fn api_keys_can_call_admin_routes_within_their_scopes() {
+    let app = spawn_app().await;
+    let key = app.mint_api_key(&["lists:read"]).await;
+    assert!(key.starts_with("nlk_"));
+
+    let allowed = app
+        .admin_request_with_key(reqwest::Method::GET, "/lists", &key)
+        .await;
+    let forbidden = app
+        .admin_request_with_key(reqwest::Method::POST, "/lists", &key)
+        .await;
+    // actix routes the decoded path, the scope must hold for it too
+    let encoded = app
+        .admin_request_with_key(reqwest::Method::POST, "/%6Cists", &key)
+        .await;
+
+    assert_eq!(200, allowed.status().as_u16());
+    assert_eq!(403, forbidden.status().as_u16());
+    assert_eq!(403, encoded.status().as_u16());
+    assert_eq!(
+        r#"Bearer realm="admin", error="insufficient_scope", scope="lists:write""#,
+        forbidden.headers()["WWW-Authenticate"]
+    );
+    let lists = sqlx::query!("SELECT slug FROM lists WHERE slug = 'from-the-cms'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(lists.is_none());
+}
+
+#[tokio::test]
+async fn the_secret_of_an_api_key_is_only_shown_once() {
+    let app = spawn_app().await;
+    let key = app.mint_api_key(&["lists:read", "lists:write"]).await;
+
+    let before: serde_json::Value = app.get_admin("/api-keys").await.json().await.unwrap();
+    app.admin_request_with_key(reqwest::Method::GET, "/lists", &key)
+        .await;
+    let after: serde_json::Value = app.get_admin("/api-keys").await.json().await.unwrap();
+
+    let listed = &before["api_keys"][0];
+    assert!(listed.get("key").is_none());
+    assert!(!listed.to_string().contains(&key));
+    assert!(key.starts_with(listed["prefix"].as_str().unwrap()));
+    assert_eq!(
+        listed["scopes"],
+        serde_json::json!(["lists:read", "lists:write"])
+    );
+    assert!(listed["last_used_at"].is_null());
+    assert!(after["api_keys"][0]["last_used_at"].is_string());
+    // Only a hash of it is stored
+    let stored = sqlx::query!("SELECT secret_hash FROM api_keys")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(key, stored.secret_hash);
+}
+
+#[tokio::test]
+async fn revoked_expired_and_unknown_api_keys_are_rejected() {
+    let app = spawn_app().await;
+    let revoked = app.mint_api_key(&["lists:read"]).await;
+    let expired = app.mint_api_key(&["lists:read"]).await;
+    let body: serde_json::Value = app.get_admin("/api-keys").await.json().await.unwrap();
+    let revoked_id = body["api_keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|api_key| revoked.starts_with(api_key["prefix"].as_str().unwrap()))
+        .unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    sqlx::query!(
+        "UPDATE api_keys SET expires_at = $1 WHERE prefix = $2",
+        Utc::now() - Duration::minutes(1),
+        &expired[..12]
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let revocation = reqwest::Client::new()
+        .delete(format!("{}/admin/api-keys/{}", app.address, revoked_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(204, revocation.status().as_u16());
+
+    for key in [
+        revoked.as_str(),
+        expired.as_str(),
+        "nlk_not-a-key-we-minted",
+    ] {
+        let response = app
+            .admin_request_with_key(reqwest::Method::GET, "/lists", key)
+            .await;
+        assert_eq!(401, response.status().as_u16(), "for {}", key);
+        assert_eq!(
+            r#"Bearer realm="admin", error="invalid_token""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn api_keys_are_not_minted_with_bad_scopes_or_expiry() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"name": "CMS", "scopes": []}),
+            "no scopes",
+        ),
+        (
+            serde_json::json!({"name": "CMS", "scopes": ["everything"]}),
+            "an unknown scope",
+        ),
+        (
+            serde_json::json!({"name": "CMS", "scopes": ["api_keys:manage"]}),
+            "a scope only people have",
+        ),
+        (
+            serde_json::json!({"name": " ", "scopes": ["lists:read"]}),
+            "an empty name",
+        ),
+        (
+            serde_json::json!({
+                "name": "CMS",
+                "scopes": ["lists:read"],
+                "expires_at": Utc::now() - Duration::days(1),
+            }),
+            "an expiry in the past",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_admin_json("/api-keys", &body).await;
+
+        assert_eq!(400, response.status().as_u16(), "for {}", description);
+    }
+}
+
+#[tokio::test]
+async fn api_keys_cannot_manage_api_keys() {
+    let app = spawn_app().await;
+    let key = app
+        .mint_api_key(&[
+            "subscribers:read",
+            "subscribers:write",
+            "lists:read",
+            "lists:write",
+            "newsletters:read",
+            "newsletters:write",
+            "newsletters:publish",
+            "stats:read",
+            "routes:read",
+        ])
+        .await;
+
+    let listing = app
+        .admin_request_with_key(reqwest::Method::GET, "/api-keys", &key)
+        .await;
+    let minting = app
+        .admin_request_with_key(reqwest::Method::POST, "/api-keys", &key)
+        .await;
+
+    assert_eq!(403, listing.status().as_u16());
+    assert_eq!(403, minting.status().as_u16());
+}
+
+// Every admin route and the scope it requires - a new route must be added here, and so
+// be given a scope on purpose
+const ADMIN_ROUTE_SCOPES: &[(&str, &str, &str)] = &[
+    ("GET", "/admin/routes", "routes:read"),
+    ("GET", "/admin/users", "users:manage"),
+    ("POST", "/admin/users", "users:manage"),
+    ("PUT", "/admin/users/{user_id}/role", "users:manage"),
+    ("POST", "/admin/users/{user_id}/deactivate", "users:manage"),
+    ("GET", "/admin/api-keys", "api_keys:manage"),
+    ("POST", "/admin/api-keys", "api_keys:manage"),
+    ("DELETE", "/admin/api-keys/{key_id}", "api_keys:manage"),
+    ("GET", "/admin/subscribers", "subscribers:read"),
+    ("GET", "/admin/subscribers/export", "subscribers:read"),
+    ("GET", "/admin/suppressions", "subscribers:read"),
+    ("POST", "/admin/segments/preview", "subscribers:read"),
+    ("POST", "/admin/subscribers/import", "subscribers:write"),
+    (
+        "POST",
+        "/admin/subscribers/{subscriber_id}/erase",
+        "subscribers:write",
+    ),
+    (
+        "POST",
+        "/admin/subscribers/{subscriber_id}/tags",
+        "subscribers:write",
+    ),
+    (
+        "DELETE",
+        "/admin/subscribers/{subscriber_id}/tags/{tag}",
+        "subscribers:write",
+    ),
+    ("POST", "/admin/suppressions", "subscribers:write"),
+    ("POST", "/admin/suppressions/import", "subscribers:write"),
+    (
+        "DELETE",
+        "/admin/suppressions/{suppression_id}",
+        "subscribers:write",
+    ),
+    ("GET", "/admin/metrics", "stats:read"),
+    (
+        "GET",
+        "/admin/newsletters/{newsletter_issue_id}/stats",
+        "stats:read",
+    ),
+    ("GET", "/admin/lists", "lists:read"),
+    ("GET", "/admin/lists/{slug}/feed", "lists:read"),
+    ("GET", "/admin/lists/{slug}/fields", "lists:read"),
+    ("POST", "/admin/lists", "lists:write"),
+    ("PUT", "/admin/lists/{slug}/feed", "lists:write"),
+    ("DELETE", "/admin/lists/{slug}/feed", "lists:write"),
+    ("PUT", "/admin/lists/{slug}/tracking", "lists:write"),
+    ("PUT", "/admin/lists/{slug}/captcha", "lists:write"),
+    ("PUT", "/admin/lists/{slug}/allowed-domains", "lists:write"),
+    ("POST", "/admin/lists/{slug}/fields", "lists:write"),
+    ("DELETE", "/admin/lists/{slug}/fields/{key}", "lists:write"),
+    ("GET", "/admin/templates", "newsletters:read"),
+    ("GET", "/admin/newsletters", "newsletters:read"),
+    ("POST", "/admin/newsletters/preview", "newsletters:read"),
+    (
+        "GET",
+        "/admin/newsletters/{newsletter_issue_id}",
+        "newsletters:read",
+    ),
+    (
+        "GET",
+        "/admin/newsletters/{newsletter_issue_id}/preview",
+        "newsletters:read",
+    ),
+    ("POST", "/admin/templates", "newsletters:write"),
+    ("PUT", "/admin/templates/{name}", "newsletters:write"),
+    ("POST", "/admin/newsletters/drafts", "newsletters:write"),
+    (
+        "PUT",
+        "/admin/newsletters/{newsletter_issue_id}",
+        "newsletters:write",
+    ),
+    (
+        "DELETE",
+        "/admin/newsletters/{newsletter_issue_id}",
+        "newsletters:write",
+    ),
+    (
+        "POST",
+        "/admin/newsletters/{newsletter_issue_id}/test",
+        "newsletters:write",
+    ),
+    ("POST", "/admin/newsletters", "newsletters:publish"),
+    (
+        "POST",
+        "/admin/newsletters/{newsletter_issue_id}/schedule",
+        "newsletters:publish",
+    ),
+    (
+        "POST",
+        "/admin/newsletters/{newsletter_issue_id}/cancel",
+        "newsletters:publish",
+    ),
+    (
+        "PUT",
+        "/admin/newsletters/{newsletter_issue_id}/archive",
+        "newsletters:publish",
+    ),
+];
+
+// The scopes each role grants, as documented in the README
+fn role_grants(role: &str, scope: &str) -> bool {
+    match role {
+        "owner" => true,
+        "editor" => !matches!(scope, "newsletters:publish" | "users:manage"),
+        "viewer" => matches!(
+            scope,
+            "subscribers:read"
+                | "lists:read"
+                | "newsletters:read"
+                | "stats:read"
+                | "api_keys:manage"
+        ),
+        "analyst" => matches!(
+            scope,
+            "lists:read" | "newsletters:read" | "stats:read" | "api_keys:manage"
+        ),
+        _ => false,
+    }
+}
+
+#[tokio::test]
+async fn every_admin_route_requires_the_expected_scope() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin("/routes").await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    let mut listed: Vec<(String, String, String)> = body["routes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|route| route["access"] == "admin")
+        .map(|route| {
+            (
+                route["method"].as_str().unwrap().to_string(),
+                route["path"].as_str().unwrap().to_string(),
+                route["scope"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    let mut expected: Vec<(String, String, String)> = ADMIN_ROUTE_SCOPES
+        .iter()
+        .map(|(method, path, scope)| (method.to_string(), path.to_string(), scope.to_string()))
+        .collect();
+    listed.sort();
+    expected.sort();
+    assert_eq!(expected, listed);
+}
+
+#[tokio::test]
+async fn each_role_reaches_exactly_the_admin_routes_its_scopes_grant() {
+    let app = spawn_app().await;
+
+    for role in ["owner", "editor", "viewer", "analyst"] {
+        let user = app.store_user(role).await;
+        for (method, path, scope) in ADMIN_ROUTE_SCOPES {
+            // Placeholders get values nothing matches - allowed requests end in a 4xx of the handler
+            let path = path
+                .trim_start_matches("/admin")
+                .replace("{slug}", "no-such-list")
+                .replace("{tag}", "no-such-tag")
+                .replace("{name}", "no-such-template")
+                .replace("{key}", "no_such_field")
+                .replace(['{', '}'], "")
+                .replace("user_id", &Uuid::new_v4().to_string())
+                .replace("key_id", &Uuid::new_v4().to_string())
+                .replace("subscriber_id", &Uuid::new_v4().to_string())
+                .replace("suppression_id", &Uuid::new_v4().to_string())
+                .replace("newsletter_issue_id", &Uuid::new_v4().to_string());
+            let method = reqwest::Method::from_bytes(method.as_bytes()).unwrap();
+
+            let response = app.admin_request_as(&user, method.clone(), &path).await;
+
+            assert_eq!(
+                !role_grants(role, scope),
+                response.status().as_u16() == 403,
+                "{} {} {} got a {}",
+                role,
+                method,
+                path,
+                response.status()
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn editors_cannot_publish_or_mint_keys_that_publish() {
+    let app = spawn_app().await;
+    let editor = app.store_user("editor").await;
+
+    let publish = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", app.address))
+        .basic_auth(&editor.username, Some(&editor.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Newsletter body as plain text", "html": "<p>Newsletter body as HTML</p>"},
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let minting = reqwest::Client::new()
+        .post(format!("{}/admin/api-keys", app.address))
+        .basic_auth(&editor.username, Some(&editor.password))
+        .json(&serde_json::json!({ "name": "CMS", "scopes": ["newsletters:publish"] }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(403, publish.status().as_u16());
+    assert_eq!(400, minting.status().as_u16());
+}
+
+#[tokio::test]
+async fn api_keys_lose_the_scopes_their_user_loses() {
+    let app = spawn_app().await;
+    let owner = app.store_user("owner").await;
+    let minted = reqwest::Client::new()
+        .post(format!("{}/admin/api-keys", app.address))
+        .basic_auth(&owner.username, Some(&owner.password))
+        .json(&serde_json::json!({ "name": "CMS", "scopes": ["lists:read", "lists:write"] }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let body: serde_json::Value = minted.json().await.unwrap();
+    let key = body["key"].as_str().unwrap();
+
+    let demotion = app
+        .put_admin_json(
+            &format!("/users/{}/role", owner.user_id),
+            &serde_json::json!({ "role": "viewer" }),
+        )
+        .await;
+    assert_eq!(200, demotion.status().as_u16());
+    let read = app
+        .admin_request_with_key(reqwest::Method::GET, "/lists", key)
+        .await;
+    let write = app
+        .admin_request_with_key(reqwest::Method::POST, "/lists", key)
+        .await;
+    assert_eq!(200, read.status().as_u16());
+    assert_eq!(403, write.status().as_u16());
+
+    let deactivation = app
+        .post_admin_json(
+            &format!("/users/{}/deactivate", owner.user_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(204, deactivation.status().as_u16());
+    let read = app
+        .admin_request_with_key(reqwest::Method::GET, "/lists", key)
+        .await;
+    let signed_in = app
+        .admin_request_as(&owner, reqwest::Method::GET, "/lists")
+        .await;
+    assert_eq!(401, read.status().as_u16());
+    assert_eq!(401, signed_in.status().as_u16());
+}
+
+#[tokio::test]
+async fn owners_invite_users_who_can_sign_in_with_their_role() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_admin_json(
+            "/users",
+            &serde_json::json!({ "username": "intern", "role": "viewer" }),
+        )
+        .await;
+
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!("viewer", body["role"]);
+    let password = body["password"].as_str().unwrap();
+    let client = reqwest::Client::new();
+    let lists = client
+        .get(format!("{}/admin/lists", app.address))
+        .basic_auth("intern", Some(password))
+        .send()
+        .await
+        .unwrap();
+    let users = client
+        .get(format!("{}/admin/users", app.address))
+        .basic_auth("intern", Some(password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, lists.status().as_u16());
+    assert_eq!(403, users.status().as_u16());
+    // The password is not stored as such, nor listed
+    let listing: serde_json::Value = app.get_admin("/users").await.json().await.unwrap();
+    assert!(!listing.to_string().contains(password));
+    let taken = app
+        .post_admin_json(
+            "/users",
+            &serde_json::json!({ "username": "intern", "role": "editor" }),
+        )
+        .await;
+    assert_eq!(409, taken.status().as_u16());
+    let bad_role = app
+        .post_admin_json(
+            "/users",
+            &serde_json::json!({ "username": "boss", "role": "superuser" }),
+        )
+        .await;
+    assert_eq!(400, bad_role.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_last_active_owner_cannot_be_demoted_or_deactivated() {
+    let app = spawn_app().await;
+    // The seeded `admin` user is an owner too
+    sqlx::query!("UPDATE users SET deactivated_at = now() WHERE username = 'admin'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let owner_id = app.test_user.user_id;
+
+    let demotion = app
+        .put_admin_json(
+            &format!("/users/{}/role", owner_id),
+            &serde_json::json!({ "role": "editor" }),
+        )
+        .await;
+    let deactivation = app
+        .post_admin_json(
+            &format!("/users/{}/deactivate", owner_id),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_eq!(409, demotion.status().as_u16());
+    assert_eq!(409, deactivation.status().as_u16());
+    // With a second owner, the first may step down
+    let second_owner = app.store_user("owner").await;
+    let demotion = app
+        .put_admin_json(
+            &format!("/users/{}/role", owner_id),
+            &serde_json::json!({ "role": "editor" }),
+        )
+        .await;
+    assert_eq!(200, demotion.status().as_u16());
+    let unknown = app
+        .admin_request_as(
+            &second_owner,
+            reqwest::Method::POST,
+            &format!("/users/{}/deactivate", Uuid::new_v4()),
+        )
+        .await;
+    assert_eq!(404, unknown.status().as_u16());
+}
+
+#[tokio::test]
+async fn percent_encoded_admin_paths_still_require_their_scope() {
+    let app = spawn_app().await;
+    let viewer = app.store_user("viewer").await;
+    let client = reqwest::Client::new();
+
+    let invite = client
+        .post(format!("{}/admin/%75sers", app.address))
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&serde_json::json!({ "username": "sneaky", "role": "owner" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let publish = client
+        .post(format!("{}/admin/%6Eewsletters", app.address))
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Newsletter body as plain text", "html": "<p>Newsletter body as HTML</p>"},
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(403, invite.status().as_u16());
+    assert_eq!(403, publish.status().as_u16());
+    let invited = sqlx::query!("SELECT user_id FROM users WHERE username = 'sneaky'")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(invited.is_none());
+}
+
+#[actix_rt::test]
+async fn only_a_fresh_data_access_token_reads_or_erases_subscriber_data() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    // As in every newsletter, and forwarded along with it
+    let preferences_token = store_subscription_token(&app, "ursula@example.com").await;
+    let link = get_data_access_link(&app, "ursula@example.com").await;
+    let client = reqwest::Client::new();
+
+    let data = client
+        .get(format!(
+            "{}/subscriptions/me/data?subscription_token={}",
+            app.address, preferences_token
+        ))
+        .send()
+        .await
+        .unwrap();
+    let erasure = client
+        .post(format!("{}/subscriptions/me/erase", app.address))
+        .form(&[("subscription_token", &preferences_token)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, data.status().as_u16());
+    assert_eq!(401, erasure.status().as_u16());
+
+    // The emailed link only works for a while
+    sqlx::query!(
+        "UPDATE subscription_tokens SET expires_at = now() - interval '1 second' WHERE subscription_token = $1",
+        subscription_token(&link)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let expired = client.get(link).send().await.unwrap();
+    assert_eq!(401, expired.status().as_u16());
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[actix_rt::test]
+async fn a_feed_whose_first_poll_fails_is_seeded_by_the_first_one_that_succeeds() {
+    let app = spawn_app().await;
+    let blog = MockServer::start().await;
+    set_up_feed(&app, &blog, true).await;
+
+    // Nothing is served yet
+    assert_eq!(0, app.poll_feeds().await);
+    let body: serde_json::Value = app
+        .get_admin("/lists/newsletter/feed")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert!(body["last_polled_at"].is_null());
+    assert!(body["last_error"].is_string());
+
+    // The back catalogue is not news, even though it is the first time we see it
+    serve_feed(&blog, &[("second", "Second post"), ("first", "First post")]).await;
+    make_feeds_due(&app).await;
+    assert_eq!(0, app.poll_feeds().await);
+    assert_eq!(0, queued_deliveries(&app).await);
+    let body: serde_json::Value = app
+        .get_admin("/lists/newsletter/feed")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert!(body["last_polled_at"].is_string());
+    assert!(body["last_error"].is_null());
+}
+
+#[actix_rt::test]
+async fn signing_up_a_confirmed_address_again_changes_nothing_about_it() {
+    let app = spawn_app().await;
+    create_fields(&app, "newsletter", &company_fields()).await;
+    insert_subscribers(&app, &[("ursula@example.com", "Ursula", "confirmed", 1)]).await;
+    add_to_list(&app, "newsletter", &[("ursula@example.com", "confirmed")]).await;
+    sqlx::query!(r#"UPDATE subscriptions SET attributes = '{"company": "Earthsea"}'::jsonb"#)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Anyone can post the address, nothing proves it is hers
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .form(&[
+            ("name", "Not Ursula"),
+            ("email", "ursula@example.com"),
+            ("company", "Mallory Inc"),
+            ("country", "de"),
+            ("tags", "vip"),
+        ])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.attributes,
+        serde_json::json!({ "company": "Earthsea" })
+    );
+    let tags = sqlx::query_scalar!("SELECT COUNT(*) FROM subscriber_tags")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tags, Some(0));
+}
+
+#[actix_rt::test]
+async fn erasing_a_subscriber_keeps_their_email_events_without_the_address() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    let ada_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let response = post_email_event(&app, &bounce(1, "SoftBounce", "ada@example.com")).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let response = app
+        .post_admin_json(
+            &format!("/subscribers/{}/erase", ada_id),
+            &serde_json::json!({}),
+        )
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let event = sqlx::query!("SELECT kind, email, subscriber_id, payload FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.kind, "soft_bounce");
+    assert_eq!(event.subscriber_id, Some(ada_id));
+    assert!(event.email.is_none());
+    assert!(!event.payload.to_string().contains("ada"));
+}
+
+#[actix_rt::test]
+async fn a_form_token_signs_up_one_address_only() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let filled_in = sign_form_token(Utc::now() - Duration::minutes(1), &app.hmac_secret);
+
+    for email in ["ursula@example.com", "octavia@example.com"] {
+        let response = post_signup(
+            &app,
+            &[
+                ("name", "le guin"),
+                ("email", email),
+                ("form_token", filled_in.as_str()),
+            ],
+        )
+        .await;
+        assert_eq!(200, response.status().as_u16(), "for {}", email);
+    }
+
+    let emails: Vec<String> = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(emails, vec!["ursula@example.com"]);
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains("signup_bot_rejections_total{check=\"reused_form_token\"} 1"));
+}
+
+#[test]
+fn signups_need_a_form_token_by_default() {
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    assert!(configuration.bot_protection.require_form_token);
+}
+
+#[actix_rt::test]
+async fn issues_with_the_same_title_published_at_once_all_get_a_slug() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "title": "Launch day",
+        "content": { "markdown": "It is out." },
+    });
+
+    let (first, second, third) = tokio::join!(
+        app.post_admin_json("/newsletters", &body),
+        app.post_admin_json("/newsletters", &body),
+        app.post_admin_json("/newsletters", &body),
+    );
+
+    for response in [first, second, third] {
+        assert_eq!(202, response.status().as_u16());
+    }
+    let mut slugs = sqlx::query_scalar!(r#"SELECT slug AS "slug!" FROM newsletter_issues"#)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    slugs.sort();
+    slugs.dedup();
+    assert_eq!(3, slugs.len());
+    assert!(slugs.contains(&"launch-day".to_string()));
+}
+
+#[actix_rt::test]
+async fn bounces_for_an_erased_subscriber_do_not_add_a_suppression() {
+    let app = spawn_app().await;
+    insert_subscribers(&app, &[("ada@example.com", "Ada", "confirmed", 1)]).await;
+    let ada_id = sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let response = app
+        .post_admin_json(
+            &format!("/subscribers/{}/erase", ada_id),
+            &serde_json::json!({}),
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    // Whatever address the row has now, the event is about it
+    let address = sqlx::query_scalar!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = post_email_event(&app, &bounce(1, "HardBounce", &address)).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, &address).await, "erased");
+    let suppressions = sqlx::query_scalar!("SELECT COUNT(*) FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressions, Some(0));
+}